@@ -0,0 +1,478 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    num::NonZeroUsize,
+    pin::Pin,
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll, ready},
+};
+
+use lru::LruCache;
+use pin_project_lite::pin_project;
+use vfs::{Error, ErrorKind, Operation, SeekFrom, VFile};
+
+const DEFAULT_READAHEAD: usize = 4;
+
+/// Identifies the file a block belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum FileKey {
+    Anonymous(u64),
+    Named(Arc<str>),
+}
+
+/// The cached blocks, along with the numbers of the blocks of each file, so the
+/// blocks of a file can be dropped without going through the whole cache
+struct Blocks {
+    lru: LruCache<(FileKey, u64), Arc<[u8]>>,
+    files: HashMap<FileKey, BTreeSet<u64>>,
+}
+
+impl Blocks {
+    fn new(capacity: NonZeroUsize) -> Blocks {
+        Blocks {
+            lru: LruCache::new(capacity),
+            files: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, file: &FileKey, block: u64) -> Option<Arc<[u8]>> {
+        self.lru.get(&(file.clone(), block)).cloned()
+    }
+
+    fn contains(&self, file: &FileKey, block: u64) -> bool {
+        self.lru.contains(&(file.clone(), block))
+    }
+
+    fn put(&mut self, file: &FileKey, block: u64, data: Arc<[u8]>) {
+        self.files.entry(file.clone()).or_default().insert(block);
+        // The block itself is returned when it was cached already
+        if let Some((evicted, _)) = self.lru.push((file.clone(), block), data)
+            && evicted != (file.clone(), block)
+        {
+            self.forget(&evicted.0, evicted.1);
+        }
+    }
+
+    /// Drop the blocks of `file` for which `func` returns true
+    fn remove(&mut self, file: &FileKey, mut func: impl FnMut(u64, &[u8]) -> bool) {
+        let Some(blocks) = self.files.get(file) else {
+            return;
+        };
+
+        let dropped: Vec<u64> = blocks
+            .iter()
+            .copied()
+            .filter(|&block| {
+                self.lru
+                    .peek(&(file.clone(), block))
+                    .is_some_and(|data| func(block, data))
+            })
+            .collect();
+
+        for block in dropped {
+            self.lru.pop(&(file.clone(), block));
+            self.forget(file, block);
+        }
+    }
+
+    fn forget(&mut self, file: &FileKey, block: u64) {
+        if let Some(blocks) = self.files.get_mut(file) {
+            blocks.remove(&block);
+            if blocks.is_empty() {
+                self.files.remove(file);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.lru.clear();
+        self.files.clear();
+    }
+}
+
+struct Inner {
+    block_size: usize,
+    readahead: AtomicUsize,
+    next_id: AtomicU64,
+    blocks: Mutex<Blocks>,
+}
+
+/// A cache of fixed size blocks read from files, shared by all [`CachedFile`]s
+/// created from it. The least recently used blocks are dropped first.
+#[derive(Clone)]
+pub struct BlockCache {
+    inner: Arc<Inner>,
+}
+
+impl core::fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let blocks = self.lock();
+        f.debug_struct("BlockCache")
+            .field("block_size", &self.inner.block_size)
+            .field("blocks", &blocks.lru.len())
+            .field("capacity", &blocks.lru.cap())
+            .finish()
+    }
+}
+
+impl BlockCache {
+    /// Create a cache holding at most `capacity` blocks of `block_size` bytes
+    pub fn new(block_size: usize, capacity: usize) -> BlockCache {
+        BlockCache {
+            inner: Arc::new(Inner {
+                block_size: block_size.max(1),
+                readahead: AtomicUsize::new(DEFAULT_READAHEAD),
+                next_id: AtomicU64::new(0),
+                blocks: Mutex::new(Blocks::new(
+                    NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN),
+                )),
+            }),
+        }
+    }
+
+    /// The number of blocks to read ahead when a file is read sequentially.
+    /// Defaults to 4
+    pub fn readahead(self, blocks: usize) -> Self {
+        self.inner.readahead.store(blocks, Ordering::Relaxed);
+        self
+    }
+
+    /// The size of the cached blocks in bytes
+    pub fn block_size(&self) -> usize {
+        self.inner.block_size
+    }
+
+    /// Cache the blocks of `file`. They are only used by the returned file
+    pub fn file<T: VFile>(&self, file: T) -> CachedFile<T> {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        CachedFile::new(self.clone(), FileKey::Anonymous(id), file)
+    }
+
+    /// Cache the blocks of `file` under `key`, usually its path. Files opened with the
+    /// same key share their blocks, so the key must change when the content does,
+    /// or the blocks have to be dropped with [`invalidate`](BlockCache::invalidate)
+    pub fn file_with_key<T: VFile>(&self, file: T, key: impl Into<Arc<str>>) -> CachedFile<T> {
+        CachedFile::new(self.clone(), FileKey::Named(key.into()), file)
+    }
+
+    /// Drop all blocks cached under `key`
+    pub fn invalidate(&self, key: &str) {
+        self.lock().remove(&FileKey::Named(key.into()), |_, _| true);
+    }
+
+    /// Drop all cached blocks
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Blocks> {
+        self.inner
+            .blocks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get(&self, file: &FileKey, block: u64) -> Option<Arc<[u8]>> {
+        self.lock().get(file, block)
+    }
+
+    fn contains(&self, file: &FileKey, block: u64) -> bool {
+        self.lock().contains(file, block)
+    }
+}
+
+/// Blocks being read from the inner file
+struct Fetch {
+    block: u64,
+    buf: Vec<u8>,
+    filled: usize,
+}
+
+pin_project! {
+    /// A file whose reads are served from the blocks of a [`BlockCache`].
+    ///
+    /// Missing blocks are read from the inner file, along with the blocks following them
+    /// when the file is read sequentially. Writes go straight to the inner file and drop
+    /// the blocks they overlap, so files opened for appending should not be wrapped.
+    pub struct CachedFile<T> {
+        #[pin]
+        file: T,
+        cache: BlockCache,
+        key: FileKey,
+        // The position seen by the user of the file
+        pos: u64,
+        // The position of the inner file, when known
+        inner_pos: Option<u64>,
+        // The last block served, to detect sequential reads
+        last_block: Option<u64>,
+        fetch: Option<Fetch>,
+    }
+
+    impl<T> PinnedDrop for CachedFile<T> {
+        fn drop(this: Pin<&mut Self>) {
+            // No one else can use the blocks of an anonymous file
+            if let FileKey::Anonymous(_) = this.key {
+                this.cache.lock().remove(&this.key, |_, _| true);
+            }
+        }
+    }
+}
+
+impl<T> core::fmt::Debug for CachedFile<T>
+where
+    T: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CachedFile")
+            .field("file", &self.file)
+            .field("pos", &self.pos)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> CachedFile<T> {
+    fn new(cache: BlockCache, key: FileKey, file: T) -> CachedFile<T> {
+        CachedFile {
+            file,
+            cache,
+            key,
+            pos: 0,
+            inner_pos: None,
+            last_block: None,
+            fetch: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.file
+    }
+}
+
+impl<T: VFile> CachedFile<T> {
+    /// Move the inner file to `target`
+    fn poll_inner_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        target: u64,
+    ) -> Poll<Result<(), Error>> {
+        let this = self.project();
+        if *this.inner_pos != Some(target) {
+            *this.inner_pos = None;
+            let pos = ready!(this.file.poll_seek(cx, SeekFrom::Start(target)))?;
+            *this.inner_pos = Some(pos);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Read the blocks of the current fetch from the inner file and store them in the cache
+    fn poll_fetch(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let block_size = self.cache.block_size() as u64;
+        let fetch = self.fetch.as_ref().expect("no blocks to fetch");
+        let pos = fetch.block * block_size + fetch.filled as u64;
+        ready!(self.as_mut().poll_inner_seek(cx, pos))?;
+
+        let mut this = self.project();
+        let fetch = this.fetch.as_mut().unwrap();
+        while fetch.filled < fetch.buf.len() {
+            let n = ready!(
+                this.file
+                    .as_mut()
+                    .poll_read(cx, &mut fetch.buf[fetch.filled..])
+            )
+            .inspect_err(|_| *this.inner_pos = None)?;
+            if n == 0 {
+                break;
+            }
+            fetch.filled += n;
+            *this.inner_pos = this.inner_pos.map(|pos| pos + n as u64);
+        }
+
+        let fetch = this.fetch.take().unwrap();
+        let mut blocks = this.cache.lock();
+        let mut chunks = fetch.buf[..fetch.filled].chunks(block_size as usize);
+        // An empty first block marks the end of the file
+        let first = chunks.next().unwrap_or_default();
+
+        // The block asked for goes in last, as the most recently used
+        for (block, chunk) in (fetch.block + 1..).zip(chunks) {
+            blocks.put(this.key, block, Arc::from(chunk));
+        }
+        blocks.put(this.key, fetch.block, Arc::from(first));
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: VFile> VFile for CachedFile<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let block_size = self.cache.block_size() as u64;
+
+        loop {
+            let block = self.pos / block_size;
+
+            if let Some(data) = self.cache.get(&self.key, block) {
+                let this = self.as_mut().project();
+                // Positions past a short block are past the end of the file
+                let data = data
+                    .get((*this.pos % block_size) as usize..)
+                    .unwrap_or_default();
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                *this.pos += len as u64;
+                *this.last_block = Some(block);
+                return Poll::Ready(Ok(len));
+            }
+
+            if self.fetch.as_ref().is_none_or(|fetch| fetch.block != block) {
+                let sequential = block > 0 && self.last_block == Some(block - 1);
+                let mut count = 1;
+                if sequential {
+                    // Leave room for the block asked for
+                    let readahead = self
+                        .cache
+                        .inner
+                        .readahead
+                        .load(Ordering::Relaxed)
+                        .min(self.cache.lock().lru.cap().get() - 1)
+                        as u64;
+                    while count <= readahead && !self.cache.contains(&self.key, block + count) {
+                        count += 1;
+                    }
+                }
+
+                *self.as_mut().project().fetch = Some(Fetch {
+                    block,
+                    buf: vec![0; (count * block_size) as usize],
+                    filled: 0,
+                });
+            }
+
+            ready!(self.as_mut().poll_fetch(cx))
+                .map_err(|err| err.with_operation(Operation::Read))?;
+        }
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        let this = self.project();
+        let target = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(offset) => this.pos.checked_add_signed(offset),
+            SeekFrom::End(_) => {
+                // The length is only known by the inner file
+                *this.inner_pos = None;
+                let pos = ready!(this.file.poll_seek(cx, pos))?;
+                *this.inner_pos = Some(pos);
+                Some(pos)
+            }
+        };
+
+        match target {
+            Some(target) => {
+                *this.pos = target;
+                Poll::Ready(Ok(target))
+            }
+            None => Poll::Ready(Err(Error::new_const(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+            .with_operation(Operation::Seek))),
+        }
+    }
+
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let pos = self.pos;
+        ready!(self.as_mut().poll_inner_seek(cx, pos))?;
+
+        let this = self.project();
+        *this.fetch = None;
+        let ret = ready!(this.file.poll_write(cx, buf));
+        *this.inner_pos = None;
+
+        // The file may have changed even when the write failed. Short blocks are
+        // dropped as well, since they no longer mark the end of the file
+        let block_size = this.cache.block_size();
+        let first = pos / block_size as u64;
+        let last = (pos + buf.len() as u64) / block_size as u64;
+        this.cache.lock().remove(this.key, |block, data| {
+            (first..=last).contains(&block) || data.len() < block_size
+        });
+
+        let n = ret?;
+        *this.pos += n as u64;
+        *this.inner_pos = Some(*this.pos);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.project().file.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.project().file.poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(len: usize) -> Arc<[u8]> {
+        vec![0; len].into()
+    }
+
+    fn indexed(blocks: &Blocks, file: &FileKey) -> Vec<u64> {
+        blocks
+            .files
+            .get(file)
+            .map_or_else(Vec::new, |set| set.iter().copied().collect())
+    }
+
+    #[test]
+    fn index_follows_the_lru() {
+        let a = FileKey::Named("a".into());
+        let b = FileKey::Anonymous(0);
+        let mut blocks = Blocks::new(NonZeroUsize::new(3).unwrap());
+        blocks.put(&a, 0, data(4));
+        blocks.put(&a, 1, data(4));
+        blocks.put(&b, 0, data(4));
+        assert_eq!(indexed(&blocks, &a), [0, 1]);
+
+        // Evicted blocks leave the index, as do files without blocks
+        blocks.put(&b, 1, data(2));
+        assert_eq!(indexed(&blocks, &a), [1]);
+        blocks.put(&b, 2, data(4));
+        assert!(!blocks.files.contains_key(&a));
+        assert_eq!(indexed(&blocks, &b), [0, 1, 2]);
+
+        // Putting a block again does not evict anything
+        blocks.put(&b, 1, data(4));
+        assert_eq!(indexed(&blocks, &b), [0, 1, 2]);
+
+        blocks.remove(&b, |block, data| block == 0 || data.len() < 4);
+        assert_eq!(indexed(&blocks, &b), [1, 2]);
+        assert!(blocks.get(&b, 0).is_none());
+        assert!(blocks.contains(&b, 1));
+
+        blocks.remove(&b, |_, _| true);
+        assert!(blocks.files.is_empty());
+        assert_eq!(blocks.lru.len(), 0);
+    }
+}
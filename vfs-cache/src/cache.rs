@@ -0,0 +1,144 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+use vfs::{DirEntry, Metadata, VPathStr};
+
+/// Cached results of a single path, keyed by its virtual path
+struct Entry<P> {
+    metadata: Option<(Instant, Metadata)>,
+    listing: Option<(Instant, Arc<[DirEntry<P>]>)>,
+}
+
+impl<P> Default for Entry<P> {
+    fn default() -> Self {
+        Entry {
+            metadata: None,
+            listing: None,
+        }
+    }
+}
+
+pub(crate) struct Cache<P> {
+    ttl: Duration,
+    entries: LruCache<String, Entry<P>>,
+    /// Bumped by every invalidation, so results fetched before a change
+    /// are not stored after it
+    generation: u64,
+}
+
+/// The cache shared by a filesystem and all its paths and files
+pub(crate) struct Shared<P>(Mutex<Cache<P>>);
+
+impl<P> Shared<P> {
+    pub fn new(ttl: Duration, max_entries: NonZeroUsize) -> Shared<P> {
+        Shared(Mutex::new(Cache {
+            ttl,
+            entries: LruCache::new(max_entries),
+            generation: 0,
+        }))
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Cache<P>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<P> Cache<P> {
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = ttl;
+    }
+
+    pub fn set_max_entries(&mut self, max_entries: NonZeroUsize) {
+        self.entries.resize(max_entries);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn metadata(&mut self, key: &str) -> Option<Metadata> {
+        let ttl = self.ttl;
+        let entry = self.entries.get_mut(key)?;
+        match entry.metadata {
+            Some((time, metadata)) if time.elapsed() < ttl => Some(metadata),
+            _ => {
+                entry.metadata = None;
+                None
+            }
+        }
+    }
+
+    pub fn listing(&mut self, key: &str) -> Option<Arc<[DirEntry<P>]>> {
+        let ttl = self.ttl;
+        let entry = self.entries.get_mut(key)?;
+        match &entry.listing {
+            Some((time, listing)) if time.elapsed() < ttl => Some(listing.clone()),
+            _ => {
+                entry.listing = None;
+                None
+            }
+        }
+    }
+
+    pub fn insert_metadata(&mut self, key: String, generation: u64, metadata: Metadata) {
+        if generation == self.generation && !self.ttl.is_zero() {
+            let entry = self.entries.get_or_insert_mut(key, Entry::default);
+            entry.metadata = Some((Instant::now(), metadata));
+        }
+    }
+
+    pub fn insert_listing(&mut self, key: String, generation: u64, listing: Arc<[DirEntry<P>]>) {
+        if generation == self.generation && !self.ttl.is_zero() {
+            let entry = self.entries.get_or_insert_mut(key, Entry::default);
+            entry.listing = Some((Instant::now(), listing));
+        }
+    }
+
+    /// Forget everything known about `key` and the listing of its parent.
+    /// When `recursive` is true, everything below `key` is forgotten as well
+    pub fn invalidate(&mut self, key: &str, recursive: bool) {
+        self.generation += 1;
+        self.entries.pop(key);
+
+        if let Some(parent) = parent(key) {
+            self.entries.pop(parent);
+        }
+
+        if recursive {
+            let prefix = if key.ends_with('/') {
+                String::from(key)
+            } else {
+                format!("{key}/")
+            };
+
+            let children: Vec<String> = self
+                .entries
+                .iter()
+                .filter(|(path, _)| path.starts_with(&prefix))
+                .map(|(path, _)| path.clone())
+                .collect();
+
+            for child in children {
+                self.entries.pop(&child);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.generation += 1;
+        self.entries.clear();
+    }
+}
+
+/// The parent of a virtual path, `None` for the root
+fn parent(key: &str) -> Option<&str> {
+    VPathStr::new(key).parent().map(VPathStr::as_str)
+}
@@ -0,0 +1,97 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, ready},
+};
+
+use pin_project_lite::pin_project;
+use vfs::{Error, SeekFrom, VFile};
+
+use crate::cache::Shared;
+
+pin_project! {
+    /// A file opened through a [`CachedFS`](crate::CachedFS). Writing to it
+    /// invalidates the cached metadata of the file and the listing of its directory
+    pub struct TrackedFile<F, P> {
+        #[pin]
+        file: F,
+        cache: Arc<Shared<P>>,
+        key: String,
+    }
+}
+
+impl<F, P> TrackedFile<F, P> {
+    pub(crate) fn new(file: F, cache: Arc<Shared<P>>, key: String) -> TrackedFile<F, P> {
+        TrackedFile { file, cache, key }
+    }
+
+    /// The file of the inner filesystem
+    pub fn get_ref(&self) -> &F {
+        &self.file
+    }
+
+    /// The file of the inner filesystem.
+    /// Changes made through it are not tracked by the cache
+    pub fn get_mut(&mut self) -> &mut F {
+        &mut self.file
+    }
+
+    pub fn into_inner(self) -> F {
+        self.file
+    }
+}
+
+impl<F, P> core::fmt::Debug for TrackedFile<F, P>
+where
+    F: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TrackedFile")
+            .field("file", &self.file)
+            .field("path", &self.key)
+            .finish()
+    }
+}
+
+impl<F, P> VFile for TrackedFile<F, P>
+where
+    F: VFile,
+    P: Send + Sync,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.project().file.poll_read(cx, buf)
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        self.project().file.poll_seek(cx, pos)
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.project();
+        let ret = ready!(this.file.poll_write(cx, buf));
+        if matches!(ret, Ok(n) if n > 0) {
+            this.cache.lock().invalidate(this.key, false);
+        }
+        Poll::Ready(ret)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.project().file.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.project().file.poll_close(cx)
+    }
+}
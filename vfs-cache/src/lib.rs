@@ -0,0 +1,401 @@
+//! Caching layers for filesystems which are slow to query, such as remote
+//! storage or archives.
+//!
+//! [`CachedFS`] remembers the results of [`VPath::metadata`] and [`VPath::read_dir`]
+//! for a limited time, while [`BlockCache`] keeps the content of files read through
+//! [`CachedFile`].
+mod block;
+mod cache;
+mod file;
+
+pub use self::{
+    block::{BlockCache, CachedFile},
+    file::TrackedFile,
+};
+
+use std::{num::NonZeroUsize, sync::Arc, time::Duration};
+
+use futures::{StreamExt, TryStreamExt};
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use vfs::{
+    Capabilities, DirEntry, Error, FileTimes, Metadata, OpenOptions, Permissions, SendVFS, VFS,
+    VPath, VPathStr, WatchEvent,
+};
+
+use self::cache::Shared;
+
+const DEFAULT_TTL: Duration = Duration::from_secs(5);
+
+const DEFAULT_MAX_ENTRIES: NonZeroUsize = NonZeroUsize::new(4096).unwrap();
+
+/// A filesystem which caches the metadata and directory listings of another filesystem.
+///
+/// Results are kept until they are older than the [ttl](CachedFS::ttl) or pushed out by
+/// newer ones once [`max_entries`](CachedFS::max_entries) paths are cached. Changes made through
+/// the cached filesystem, including writes to files opened through it, drop the affected
+/// entries right away. Changes made by others are only seen once the entries expire,
+/// unless they are reported by a [watcher](VPath::watch) of the cached filesystem
+/// or dropped with [`invalidate`](CachedFS::invalidate).
+pub struct CachedFS<F: VFS> {
+    fs: F,
+    cache: Arc<Shared<F::Path>>,
+}
+
+impl<F: VFS + Clone> Clone for CachedFS<F> {
+    fn clone(&self) -> Self {
+        CachedFS {
+            fs: self.fs.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<F: VFS + core::fmt::Debug> core::fmt::Debug for CachedFS<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CachedFS")
+            .field("fs", &self.fs)
+            .field("entries", &self.cache.lock().len())
+            .finish()
+    }
+}
+
+impl<F: SendVFS> CachedFS<F> {
+    /// Cache `fs`, keeping results for 5 seconds and at most 4096 paths
+    pub fn new(fs: F) -> CachedFS<F> {
+        CachedFS {
+            fs,
+            cache: Arc::new(Shared::new(DEFAULT_TTL, DEFAULT_MAX_ENTRIES)),
+        }
+    }
+
+    /// How long results are kept. A zero duration disables the cache
+    pub fn ttl(self, ttl: Duration) -> Self {
+        self.cache.lock().set_ttl(ttl);
+        self
+    }
+
+    /// The maximum number of paths to keep results for.
+    /// The least recently used paths are dropped first
+    pub fn max_entries(self, max_entries: usize) -> Self {
+        let max_entries = NonZeroUsize::new(max_entries).unwrap_or(NonZeroUsize::MIN);
+        self.cache.lock().set_max_entries(max_entries);
+        self
+    }
+
+    /// The wrapped filesystem
+    pub fn get_ref(&self) -> &F {
+        &self.fs
+    }
+
+    /// Drop all cached results
+    pub fn flush(&self) {
+        self.cache.lock().clear();
+    }
+
+    /// Drop the cached results of `path`, everything below it and the listing
+    /// of its parent directory, for when it was changed outside of the cache
+    pub fn invalidate(&self, path: impl AsRef<VPathStr>) -> Result<(), Error> {
+        let key = self.fs.path(path)?.virtual_path();
+        self.cache.lock().invalidate(&key, true);
+        Ok(())
+    }
+}
+
+impl<F: SendVFS> VFS for CachedFS<F> {
+    type Path = CachedPath<F>;
+
+    type Stats = F::Stats;
+
+    fn path(&self, path: impl AsRef<VPathStr>) -> Result<Self::Path, Error> {
+        Ok(CachedPath::new(self.cache.clone(), self.fs.path(path)?))
+    }
+
+    fn stats(&self) -> Self::Stats {
+        self.fs.stats()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.fs.capabilities()
+    }
+}
+
+/// A path of a [`CachedFS`]
+pub struct CachedPath<F: VFS> {
+    cache: Arc<Shared<F::Path>>,
+    inner: F::Path,
+}
+
+impl<F: VFS> Clone for CachedPath<F>
+where
+    F::Path: Clone,
+{
+    fn clone(&self) -> Self {
+        CachedPath {
+            cache: self.cache.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<F: VFS> core::fmt::Debug for CachedPath<F>
+where
+    F::Path: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("CachedPath").field(&self.inner).finish()
+    }
+}
+
+impl<F: VFS> PartialEq for CachedPath<F>
+where
+    F::Path: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cache, &other.cache) && self.inner == other.inner
+    }
+}
+
+impl<F: VFS> Eq for CachedPath<F> where F::Path: Eq {}
+
+impl<F: VFS> core::hash::Hash for CachedPath<F>
+where
+    F::Path: core::hash::Hash,
+{
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.inner.hash(state)
+    }
+}
+
+impl<F: SendVFS> CachedPath<F> {
+    fn new(cache: Arc<Shared<F::Path>>, inner: F::Path) -> CachedPath<F> {
+        CachedPath { cache, inner }
+    }
+
+    fn wrap(&self, inner: F::Path) -> CachedPath<F> {
+        CachedPath::new(self.cache.clone(), inner)
+    }
+
+    /// The path of the inner filesystem
+    pub fn get_ref(&self) -> &F::Path {
+        &self.inner
+    }
+
+    /// Run a mutation of the inner filesystem, then drop what it may have changed
+    fn mutate<T: Send + 'static>(
+        &self,
+        future: impl Future<Output = Result<T, Error>> + Send + 'static,
+        recursive: bool,
+    ) -> BoxFuture<'static, Result<T, Error>> {
+        let cache = self.cache.clone();
+        let key = self.inner.virtual_path();
+        Box::pin(async move {
+            let ret = future.await;
+            // Even a failed operation may have changed part of the tree
+            cache.lock().invalidate(&key, recursive);
+            ret
+        })
+    }
+}
+
+impl<F: SendVFS> VPath for CachedPath<F> {
+    type FS = CachedFS<F>;
+
+    type File = TrackedFile<<F::Path as VPath>::File, F::Path>;
+
+    type ListDir = BoxStream<'static, Result<DirEntry<Self>, Error>>;
+
+    type Metadata = BoxFuture<'static, Result<Metadata, Error>>;
+
+    type Open = BoxFuture<'static, Result<Self::File, Error>>;
+
+    type CreateDir = BoxFuture<'static, Result<(), Error>>;
+
+    type Remove = BoxFuture<'static, Result<(), Error>>;
+
+    type ReadDir = BoxFuture<'static, Result<Self::ListDir, Error>>;
+
+    type SetPermissions = BoxFuture<'static, Result<(), Error>>;
+
+    type SetTimes = BoxFuture<'static, Result<(), Error>>;
+
+    type SetOwner = BoxFuture<'static, Result<(), Error>>;
+
+    type GetXattr = <F::Path as VPath>::GetXattr;
+
+    type SetXattr = <F::Path as VPath>::SetXattr;
+
+    type ListXattr = <F::Path as VPath>::ListXattr;
+
+    type RemoveXattr = <F::Path as VPath>::RemoveXattr;
+
+    type Watcher = BoxStream<'static, Result<WatchEvent<Self>, Error>>;
+
+    type Watch = BoxFuture<'static, Result<Self::Watcher, Error>>;
+
+    type Canonicalize = BoxFuture<'static, Result<Self, Error>>;
+
+    fn fs(&self) -> Self::FS {
+        CachedFS {
+            fs: self.inner.fs(),
+            cache: self.cache.clone(),
+        }
+    }
+
+    fn virtual_path(&self) -> String {
+        self.inner.virtual_path()
+    }
+
+    fn to_string(&self) -> String {
+        self.inner.to_string()
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        self.inner.file_name()
+    }
+
+    fn extension(&self) -> Option<&str> {
+        self.inner.extension()
+    }
+
+    fn resolve(&self, path: &str) -> Result<Self, Error> {
+        Ok(self.wrap(self.inner.resolve(path)?))
+    }
+
+    fn parent(&self) -> Option<Self> {
+        Some(self.wrap(self.inner.parent()?))
+    }
+
+    fn canonicalize(&self) -> Self::Canonicalize {
+        let this = self.clone();
+        let future = self.inner.canonicalize();
+        Box::pin(async move { Ok(this.wrap(future.await?)) })
+    }
+
+    fn metadata(&self) -> Self::Metadata {
+        let key = self.inner.virtual_path();
+        let generation = {
+            let mut cache = self.cache.lock();
+            if let Some(metadata) = cache.metadata(&key) {
+                return Box::pin(async move { Ok(metadata) });
+            }
+            cache.generation()
+        };
+
+        let cache = self.cache.clone();
+        let future = self.inner.metadata();
+        Box::pin(async move {
+            let metadata = future.await?;
+            cache.lock().insert_metadata(key, generation, metadata);
+            Ok(metadata)
+        })
+    }
+
+    fn open(&self, options: OpenOptions) -> Self::Open {
+        let cache = self.cache.clone();
+        let key = self.inner.virtual_path();
+        let future = self.inner.open(options);
+        let changes = options.write || options.append || options.create || options.truncate;
+        Box::pin(async move {
+            let file = future.await;
+            if changes {
+                cache.lock().invalidate(&key, false);
+            }
+            Ok(TrackedFile::new(file?, cache, key))
+        })
+    }
+
+    fn read_dir(&self) -> Self::ReadDir {
+        let this = self.clone();
+        let key = self.inner.virtual_path();
+        let cached = {
+            let mut cache = self.cache.lock();
+            cache.listing(&key).ok_or(cache.generation())
+        };
+
+        Box::pin(async move {
+            let listing = match cached {
+                Ok(listing) => listing,
+                Err(generation) => {
+                    let entries: Arc<[DirEntry<F::Path>]> = this
+                        .inner
+                        .read_dir()
+                        .await?
+                        .try_collect::<Vec<_>>()
+                        .await?
+                        .into();
+                    this.cache
+                        .lock()
+                        .insert_listing(key, generation, entries.clone());
+                    entries
+                }
+            };
+
+            let entries = (0..listing.len()).map(move |idx| {
+                let entry = listing[idx].clone();
+                Ok(entry.map(|path| this.wrap(path)))
+            });
+            Ok(futures::stream::iter(entries).boxed())
+        })
+    }
+
+    fn create_dir(&self) -> Self::CreateDir {
+        self.mutate(self.inner.create_dir(), false)
+    }
+
+    fn rm(&self) -> Self::Remove {
+        self.mutate(self.inner.rm(), true)
+    }
+
+    fn set_permissions(&self, permissions: Permissions) -> Self::SetPermissions {
+        self.mutate(self.inner.set_permissions(permissions), false)
+    }
+
+    fn set_times(&self, times: FileTimes) -> Self::SetTimes {
+        self.mutate(self.inner.set_times(times), false)
+    }
+
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> Self::SetOwner {
+        self.mutate(self.inner.set_owner(uid, gid), false)
+    }
+
+    fn supports_xattr(&self) -> bool {
+        self.inner.supports_xattr()
+    }
+
+    fn get_xattr(&self, name: &str) -> Self::GetXattr {
+        self.inner.get_xattr(name)
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8]) -> Self::SetXattr {
+        self.inner.set_xattr(name, value)
+    }
+
+    fn list_xattr(&self) -> Self::ListXattr {
+        self.inner.list_xattr()
+    }
+
+    fn remove_xattr(&self, name: &str) -> Self::RemoveXattr {
+        self.inner.remove_xattr(name)
+    }
+
+    /// Changes reported by the watcher also drop the cached results of the changed paths
+    fn watch(&self, recursive: bool) -> Self::Watch {
+        let this = self.clone();
+        let future = self.inner.watch(recursive);
+        Box::pin(async move {
+            let watcher = future.await?.map_ok(move |event| {
+                let mut cache = this.cache.lock();
+                match &event {
+                    WatchEvent::Rename { from, to } => {
+                        cache.invalidate(&from.virtual_path(), true);
+                        cache.invalidate(&to.virtual_path(), true);
+                    }
+                    event => cache.invalidate(&event.path().virtual_path(), true),
+                }
+                drop(cache);
+                event.map(|path| this.wrap(path))
+            });
+            Ok(watcher.boxed())
+        })
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use futures::executor::block_on;
+use vfs::{Error, OpenOptions, SeekFrom, VFS, VFile, VFileExt, VPath};
+use vfs_cache::{BlockCache, CachedFile};
+use vfs_memory::{MemoryFS, MemoryFile};
+use vfs_test::{pattern, write_file};
+
+const BLOCK: usize = 100;
+
+/// A file counting the reads which reach it
+struct Counted {
+    file: MemoryFile,
+    reads: Arc<AtomicUsize>,
+}
+
+impl VFile for Counted {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        Pin::new(&mut self.file).poll_read(cx, buf)
+    }
+
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        Pin::new(&mut self.file).poll_seek(cx, pos)
+    }
+
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Pin::new(&mut self.file).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.file).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.file).poll_close(cx)
+    }
+}
+
+struct Fixture {
+    fs: MemoryFS,
+    reads: Arc<AtomicUsize>,
+}
+
+impl Fixture {
+    async fn new(files: &[(&str, usize)]) -> Fixture {
+        let fs = MemoryFS::new();
+        for &(name, len) in files {
+            write_file(&fs.path(name).unwrap(), &pattern(len)).await;
+        }
+        Fixture {
+            fs,
+            reads: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    async fn open(&self, cache: &BlockCache, name: &str, write: bool) -> CachedFile<Counted> {
+        let options = OpenOptions::new().read(true).write(write);
+        let file = Counted {
+            file: self.fs.path(name).unwrap().open(options).await.unwrap(),
+            reads: self.reads.clone(),
+        };
+        cache.file_with_key(file, name)
+    }
+
+    /// The reads which reached the inner files since the last call
+    fn reads(&self) -> usize {
+        self.reads.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// Read `len` bytes at `pos`, checking them against the pattern
+async fn read_at(file: &mut CachedFile<Counted>, pos: usize, len: usize) {
+    file.seek(SeekFrom::Start(pos as u64)).await.unwrap();
+    let mut buf = vec![0; len];
+    file.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, pattern(pos + len)[pos..]);
+}
+
+#[test]
+fn blocks_are_shared_by_key() {
+    block_on(async {
+        let fixture = Fixture::new(&[("/file", BLOCK * 3)]).await;
+        let cache = BlockCache::new(BLOCK, 16).readahead(0);
+
+        let mut file = fixture.open(&cache, "/file", false).await;
+        read_at(&mut file, 150, 100).await;
+        assert!(fixture.reads() > 0);
+        read_at(&mut file, 120, 60).await;
+        assert_eq!(fixture.reads(), 0);
+
+        // A file opened with the same key reads the same blocks
+        let mut other = fixture.open(&cache, "/file", false).await;
+        read_at(&mut other, 100, 200).await;
+        assert_eq!(fixture.reads(), 0);
+        read_at(&mut file, 0, 300).await;
+        assert!(fixture.reads() > 0);
+        read_at(&mut other, 0, 300).await;
+        assert_eq!(fixture.reads(), 0);
+
+        // The short last block marks the end of the file
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(250)).await.unwrap();
+        file.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, pattern(300)[250..]);
+        assert_eq!(fixture.reads(), 1);
+    })
+}
+
+#[test]
+fn least_recently_used_blocks_are_dropped() {
+    block_on(async {
+        let fixture = Fixture::new(&[("/file", BLOCK * 4)]).await;
+        let cache = BlockCache::new(BLOCK, 2).readahead(0);
+        let mut file = fixture.open(&cache, "/file", false).await;
+
+        read_at(&mut file, 0, 1).await;
+        read_at(&mut file, BLOCK, 1).await;
+        read_at(&mut file, 0, 1).await;
+        fixture.reads();
+
+        // Block 1 was used least recently when block 2 was read
+        read_at(&mut file, BLOCK * 2, 1).await;
+        assert!(fixture.reads() > 0);
+        read_at(&mut file, 0, 1).await;
+        assert_eq!(fixture.reads(), 0);
+        read_at(&mut file, BLOCK, 1).await;
+        assert!(fixture.reads() > 0);
+    })
+}
+
+#[test]
+fn sequential_reads_read_ahead() {
+    block_on(async {
+        let fixture = Fixture::new(&[("/file", BLOCK * 12)]).await;
+        let cache = BlockCache::new(BLOCK, 16).readahead(3);
+        let mut file = fixture.open(&cache, "/file", false).await;
+
+        read_at(&mut file, 0, BLOCK).await;
+        read_at(&mut file, BLOCK, BLOCK).await;
+        fixture.reads();
+
+        // Reading block 1 after block 0 fetched blocks 2 to 4 as well
+        read_at(&mut file, BLOCK * 2, BLOCK * 3).await;
+        assert_eq!(fixture.reads(), 0);
+        read_at(&mut file, BLOCK * 5, 1).await;
+        assert!(fixture.reads() > 0);
+
+        // Other reads only fetch the block asked for
+        read_at(&mut file, BLOCK * 10, 1).await;
+        fixture.reads();
+        read_at(&mut file, BLOCK * 11, 1).await;
+        assert!(fixture.reads() > 0);
+    })
+}
+
+#[test]
+fn writes_drop_the_blocks_they_change() {
+    block_on(async {
+        let fixture = Fixture::new(&[("/file", BLOCK * 2 + 50)]).await;
+        let cache = BlockCache::new(BLOCK, 16).readahead(0);
+        let mut file = fixture.open(&cache, "/file", true).await;
+        read_at(&mut file, 0, BLOCK * 2 + 50).await;
+        fixture.reads();
+
+        file.seek(SeekFrom::Start(BLOCK as u64 + 10)).await.unwrap();
+        file.write_all(b"changed").await.unwrap();
+        read_at(&mut file, 0, BLOCK).await;
+        assert_eq!(fixture.reads(), 0);
+
+        file.seek(SeekFrom::Start(BLOCK as u64 + 10)).await.unwrap();
+        let mut buf = [0; 7];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"changed");
+        assert!(fixture.reads() > 0);
+
+        // The short last block is dropped once the file grows past it
+        file.seek(SeekFrom::Start(BLOCK as u64 * 3)).await.unwrap();
+        file.write_all(b"end").await.unwrap();
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(BLOCK as u64 * 3)).await.unwrap();
+        file.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"end");
+    })
+}
+
+#[test]
+fn invalidate_drops_the_blocks_of_a_key() {
+    block_on(async {
+        let fixture = Fixture::new(&[("/a", BLOCK * 2), ("/b", BLOCK * 2)]).await;
+        let cache = BlockCache::new(BLOCK, 16).readahead(0);
+        let mut a = fixture.open(&cache, "/a", false).await;
+        let mut b = fixture.open(&cache, "/b", false).await;
+        read_at(&mut a, 0, BLOCK * 2).await;
+        read_at(&mut b, 0, BLOCK * 2).await;
+        fixture.reads();
+
+        cache.invalidate("/a");
+        read_at(&mut b, 0, BLOCK * 2).await;
+        assert_eq!(fixture.reads(), 0);
+        read_at(&mut a, 0, BLOCK * 2).await;
+        assert!(fixture.reads() > 0);
+
+        cache.clear();
+        read_at(&mut b, 0, 1).await;
+        assert!(fixture.reads() > 0);
+    })
+}
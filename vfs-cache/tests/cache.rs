@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use futures::executor::block_on;
+use vfs::{OpenOptions, SeekFrom, VFS, VFileExt, VPath};
+use vfs_cache::CachedFS;
+use vfs_memory::MemoryFS;
+use vfs_test::{names, write_file};
+
+async fn size<P: VPath>(path: &P) -> u64 {
+    path.metadata().await.unwrap().size
+}
+
+#[test]
+fn results_expire() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        write_file(&inner.path("/file").unwrap(), b"old").await;
+        let fs = CachedFS::new(inner.clone()).ttl(Duration::from_millis(100));
+        let path = fs.path("/file").unwrap();
+        assert_eq!(size(&path).await, 3);
+        assert_eq!(names(&fs.path("/").unwrap()).await, ["file"]);
+
+        // Changes of the inner filesystem are only seen once the results expire
+        write_file(&inner.path("/file").unwrap(), b"changed").await;
+        write_file(&inner.path("/other").unwrap(), b"other").await;
+        assert_eq!(size(&path).await, 3);
+        assert_eq!(names(&fs.path("/").unwrap()).await, ["file"]);
+
+        std::thread::sleep(Duration::from_millis(150));
+        assert_eq!(size(&path).await, 7);
+        assert_eq!(names(&fs.path("/").unwrap()).await, ["file", "other"]);
+
+        // A zero ttl disables the cache
+        let fs = CachedFS::new(inner.clone()).ttl(Duration::ZERO);
+        let path = fs.path("/file").unwrap();
+        assert_eq!(size(&path).await, 7);
+        write_file(&inner.path("/file").unwrap(), b"new").await;
+        assert_eq!(size(&path).await, 3);
+    })
+}
+
+#[test]
+fn least_recently_used_paths_are_dropped() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        for name in ["/a", "/b", "/c"] {
+            write_file(&inner.path(name).unwrap(), b"old").await;
+        }
+        let fs = CachedFS::new(inner.clone()).max_entries(2);
+        for name in ["/a", "/b", "/a", "/c"] {
+            assert_eq!(size(&fs.path(name).unwrap()).await, 3);
+        }
+
+        for name in ["/a", "/b", "/c"] {
+            write_file(&inner.path(name).unwrap(), b"changed").await;
+        }
+
+        // /b was used least recently when /c was added
+        assert_eq!(size(&fs.path("/a").unwrap()).await, 3);
+        assert_eq!(size(&fs.path("/c").unwrap()).await, 3);
+        assert_eq!(size(&fs.path("/b").unwrap()).await, 7);
+    })
+}
+
+#[test]
+fn changes_drop_results() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        inner.path("/dir").unwrap().create_dir().await.unwrap();
+        write_file(&inner.path("/dir/file").unwrap(), b"old").await;
+        let fs = CachedFS::new(inner.clone());
+        let dir = fs.path("/dir").unwrap();
+        let path = fs.path("/dir/file").unwrap();
+        assert_eq!(size(&path).await, 3);
+        assert_eq!(names(&dir).await, ["file"]);
+
+        // Writes through the cache drop the file and the listing of its directory
+        let mut file = path.open(OpenOptions::new().write(true)).await.unwrap();
+        file.seek(SeekFrom::End(0)).await.unwrap();
+        file.write_all(b" and new").await.unwrap();
+        file.close().await.unwrap();
+        assert_eq!(size(&path).await, 11);
+
+        write_file(&fs.path("/dir/new").unwrap(), b"new").await;
+        assert_eq!(names(&dir).await, ["file", "new"]);
+
+        // Removing a directory drops everything below it
+        dir.rm().await.unwrap();
+        assert!(path.metadata().await.is_err());
+
+        // Changes made outside of the cache are seen once they are invalidated
+        inner.path("/dir").unwrap().create_dir().await.unwrap();
+        write_file(&inner.path("/dir/file").unwrap(), b"outside").await;
+        assert_eq!(size(&path).await, 7);
+        assert_eq!(names(&dir).await, ["file"]);
+
+        write_file(&inner.path("/dir/file").unwrap(), b"outside again").await;
+        write_file(&inner.path("/dir/other").unwrap(), b"other").await;
+        assert_eq!(size(&path).await, 7);
+        fs.invalidate("/dir").unwrap();
+        assert_eq!(size(&path).await, 13);
+        assert_eq!(names(&dir).await, ["file", "other"]);
+    })
+}
+
+#[test]
+fn results_fetched_before_a_change_are_not_kept() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        write_file(&inner.path("/file").unwrap(), b"old").await;
+        let fs = CachedFS::new(inner.clone());
+        let path = fs.path("/file").unwrap();
+
+        // The lookups start before the file is changed and complete after
+        let metadata = path.metadata();
+        let listing = fs.path("/").unwrap().read_dir();
+        write_file(&path, b"changed").await;
+        write_file(&fs.path("/new").unwrap(), b"new").await;
+        drop(listing.await.unwrap());
+        metadata.await.unwrap();
+
+        write_file(&inner.path("/file").unwrap(), b"changed again").await;
+        write_file(&inner.path("/other").unwrap(), b"other").await;
+        assert_eq!(size(&path).await, 13);
+        assert_eq!(
+            names(&fs.path("/").unwrap()).await,
+            ["file", "new", "other"]
+        );
+    })
+}
@@ -0,0 +1,324 @@
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, ready},
+};
+
+use fastcdc::v2020;
+use futures_core::future::BoxFuture;
+use vfs::{
+    ContentHash, Error, ErrorKind, OpenOptions, Operation, SeekFrom, SendVFS, VFile, VFileExt,
+    VPath,
+};
+
+use crate::{manifest::Entry, objects::finalize, store::Store};
+
+/// Identifies a chunk list and the version of its format
+const MAGIC: &[u8; 16] = b"vfs-cas chunks 1";
+
+/// The hash and size of a chunk in a chunk list
+const ENTRY_LEN: usize = 36;
+
+/// The sizes of the chunks files are split into.
+///
+/// Chunk boundaries are placed by a rolling hash of the content (FastCDC), so
+/// they move along with inserted or removed data, and a change to a large file
+/// only stores the few chunks around it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunking {
+    min: u32,
+    avg: u32,
+    max: u32,
+}
+
+impl Default for Chunking {
+    /// Chunks of 16 KiB to 256 KiB, 64 KiB on average
+    fn default() -> Self {
+        Chunking {
+            min: 16 * 1024,
+            avg: 64 * 1024,
+            max: 256 * 1024,
+        }
+    }
+}
+
+impl Chunking {
+    /// Chunks of `min` to `max` bytes, `avg` bytes on average.
+    ///
+    /// The minimum has to be between 64 bytes and 1 MiB, the average between
+    /// 256 bytes and 4 MiB and the maximum between 1 KiB and 16 MiB
+    pub fn new(min: u32, avg: u32, max: u32) -> Result<Chunking, Error> {
+        let valid = (v2020::MINIMUM_MIN..=v2020::MINIMUM_MAX).contains(&min)
+            && (v2020::AVERAGE_MIN..=v2020::AVERAGE_MAX).contains(&avg)
+            && (v2020::MAXIMUM_MIN..=v2020::MAXIMUM_MAX).contains(&max)
+            && min <= avg
+            && avg <= max;
+
+        if !valid {
+            return Err(Error::new_const(
+                ErrorKind::InvalidInput,
+                "invalid chunk sizes",
+            ));
+        }
+
+        Ok(Chunking { min, avg, max })
+    }
+
+    pub fn min(&self) -> u32 {
+        self.min
+    }
+
+    pub fn avg(&self) -> u32 {
+        self.avg
+    }
+
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    /// The length of the first chunk of `data`, which has to hold at least
+    /// the maximum chunk size unless it is the end of the file
+    fn cut(&self, data: &[u8]) -> usize {
+        // Normalization level 1, the default of FastCDC
+        let bits = v2020::logarithm2(self.avg);
+        let mask_s = v2020::MASKS[bits as usize + 1];
+        let mask_l = v2020::MASKS[bits as usize - 1];
+        let (_, len) = v2020::cut(
+            data,
+            self.min as usize,
+            self.avg as usize,
+            self.max as usize,
+            mask_s,
+            mask_l,
+            mask_s << 1,
+            mask_l << 1,
+        );
+        len
+    }
+}
+
+/// The chunks of a file stored in chunks, stored as an object itself.
+///
+/// Chunk lists start with a magic followed by the hash and the size of
+/// every chunk, as a 32 bit little endian integer.
+#[derive(Debug, Default)]
+pub(crate) struct ChunkList {
+    hashes: Vec<ContentHash>,
+    /// The offset of the end of every chunk
+    ends: Vec<u64>,
+}
+
+impl ChunkList {
+    pub fn parse(data: &[u8]) -> Result<ChunkList, Error> {
+        let entries = data.strip_prefix(MAGIC).ok_or_else(invalid)?;
+        if entries.len() % ENTRY_LEN != 0 {
+            return Err(invalid());
+        }
+
+        let mut list = ChunkList::default();
+        for entry in entries.chunks_exact(ENTRY_LEN) {
+            let hash = ContentHash::from_bytes(entry[..32].try_into().unwrap());
+            let len = u32::from_le_bytes(entry[32..].try_into().unwrap());
+            list.push(hash, len);
+        }
+        Ok(list)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(MAGIC.len() + self.hashes.len() * ENTRY_LEN);
+        out.extend_from_slice(MAGIC);
+        let mut start = 0;
+        for (hash, end) in self.hashes.iter().zip(&self.ends) {
+            out.extend_from_slice(hash.as_bytes());
+            out.extend_from_slice(&((end - start) as u32).to_le_bytes());
+            start = *end;
+        }
+        out
+    }
+
+    fn push(&mut self, hash: ContentHash, len: u32) {
+        self.ends.push(self.size() + len as u64);
+        self.hashes.push(hash);
+    }
+
+    pub fn size(&self) -> u64 {
+        self.ends.last().copied().unwrap_or(0)
+    }
+
+    /// The start of the chunk with `index`
+    fn start(&self, index: usize) -> u64 {
+        match index {
+            0 => 0,
+            _ => self.ends[index - 1],
+        }
+    }
+
+    /// The index of the chunk holding the byte at `pos`
+    fn find(&self, pos: u64) -> Option<usize> {
+        let index = self.ends.partition_point(|end| *end <= pos);
+        (index < self.ends.len()).then_some(index)
+    }
+}
+
+/// Split the file at `path` into chunks and store them and their chunk list.
+/// Returns the entry of the file, which has the hash of the whole content
+pub(crate) async fn put_chunks<F: SendVFS>(
+    store: &Store<F>,
+    path: &F::Path,
+    chunking: Chunking,
+) -> Result<Entry, Error> {
+    let file = path.open(OpenOptions::new().read(true)).await?;
+    let mut file = core::pin::pin!(file);
+
+    let mut list = ChunkList::default();
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0; chunking.max as usize];
+    let mut filled = 0;
+    let mut eof = false;
+    loop {
+        // The cut point of a chunk is only known once its maximum size is available
+        while !eof && filled < buf.len() {
+            let n = file.read(&mut buf[filled..]).await?;
+            eof = n == 0;
+            filled += n;
+        }
+        if filled == 0 {
+            break;
+        }
+
+        let len = chunking.cut(&buf[..filled]);
+        hasher.update(&buf[..len]);
+        list.push(store.objects().put(&buf[..len]).await?, len as u32);
+        buf.copy_within(len..filled, 0);
+        filled -= len;
+    }
+
+    let chunks = store.objects().put(&list.to_bytes()).await?;
+    Ok(Entry::chunks(finalize(&hasher), list.size(), chunks))
+}
+
+type Load = BoxFuture<'static, Result<(usize, Vec<u8>), Error>>;
+
+/// Reads a file stored in chunks, loading one chunk at a time
+pub(crate) struct ChunkReader<F> {
+    store: Arc<Store<F>>,
+    chunks: ChunkList,
+    pos: u64,
+    /// The index and content of the chunk read last
+    chunk: Option<(usize, Vec<u8>)>,
+    // Holds futures of the inner filesystem, which need not be Sync
+    loading: Option<Mutex<Load>>,
+}
+
+impl<F: SendVFS> ChunkReader<F> {
+    pub async fn open(store: Arc<Store<F>>, hash: ContentHash) -> Result<ChunkReader<F>, Error> {
+        let chunks = ChunkList::parse(&store.objects().get(hash).await?)?;
+        Ok(ChunkReader {
+            store,
+            chunks,
+            pos: 0,
+            chunk: None,
+            loading: None,
+        })
+    }
+
+    fn load(&self, index: usize) -> Load {
+        let store = self.store.clone();
+        let hash = self.chunks.hashes[index];
+        let len = self.chunks.ends[index] - self.chunks.start(index);
+        Box::pin(async move {
+            let data = store.objects().get(hash).await?;
+            if data.len() as u64 != len {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("chunk {hash} has the wrong size"),
+                ));
+            }
+            Ok((index, data))
+        })
+    }
+}
+
+impl<F: SendVFS> VFile for ChunkReader<F> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(loading) = &mut this.loading {
+                let chunk = ready!(loading.get_mut().unwrap().as_mut().poll(cx));
+                this.loading = None;
+                this.chunk = Some(chunk.map_err(|err| err.with_operation(Operation::Read))?);
+            }
+
+            let Some(index) = this.chunks.find(this.pos).filter(|_| !buf.is_empty()) else {
+                return Poll::Ready(Ok(0));
+            };
+
+            match &this.chunk {
+                Some((current, data)) if *current == index => {
+                    let offset = (this.pos - this.chunks.start(index)) as usize;
+                    let n = buf.len().min(data.len() - offset);
+                    buf[..n].copy_from_slice(&data[offset..offset + n]);
+                    this.pos += n as u64;
+                    return Poll::Ready(Ok(n));
+                }
+                _ => this.loading = Some(Mutex::new(this.load(index))),
+            }
+        }
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        let this = self.get_mut();
+        let target = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(offset) => this.pos.checked_add_signed(offset),
+            SeekFrom::End(offset) => this.chunks.size().checked_add_signed(offset),
+        };
+
+        let Some(target) = target else {
+            return Poll::Ready(Err(Error::new_const(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+            .with_operation(Operation::Seek)));
+        };
+
+        // Chunks are loaded by the next read
+        this.pos = target;
+        Poll::Ready(Ok(target))
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Poll::Ready(Err(Error::new_const(
+            ErrorKind::PermissionDenied,
+            "the file is not opened for writing",
+        )
+        .with_operation(Operation::Write)))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        this.chunk = None;
+        this.loading = None;
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn invalid() -> Error {
+    Error::new_const(ErrorKind::InvalidData, "invalid chunk list")
+}
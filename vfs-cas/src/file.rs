@@ -0,0 +1,249 @@
+use std::{
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll, ready},
+};
+
+use futures_core::future::BoxFuture;
+use vfs::{Error, ErrorKind, Operation, SeekFrom, SendVFS, VFS, VFile, VFileExt, VPath};
+
+use crate::{
+    CasPath,
+    chunk::{ChunkReader, put_chunks},
+    manifest::Entry,
+    objects::{finalize, hash_file},
+};
+
+type InnerFile<F> = <<F as VFS>::Path as VPath>::File;
+
+/// The stored content of a file
+pub(crate) enum Content<F: VFS> {
+    /// An object, or the temporary file of a file being written
+    Object(Pin<Box<InnerFile<F>>>),
+    Chunks(ChunkReader<F>),
+}
+
+impl<F: SendVFS> Content<F> {
+    pub fn as_mut(&mut self) -> Pin<&mut dyn VFile> {
+        match self {
+            Content::Object(file) => file.as_mut(),
+            Content::Chunks(chunks) => {
+                let chunks: Pin<&mut ChunkReader<F>> = Pin::new(chunks);
+                chunks
+            }
+        }
+    }
+}
+
+/// The state of a file opened for writing. Its content is written to a
+/// temporary file, which is hashed and stored as an object when closed
+pub(crate) struct Writer<F: VFS> {
+    path: CasPath<F>,
+    temp: F::Path,
+    /// Hashes the content as long as it is written sequentially
+    hasher: Option<blake3::Hasher>,
+    hashed: u64,
+    pos: u64,
+    len: u64,
+    append: bool,
+}
+
+impl<F: SendVFS> Writer<F> {
+    pub fn new(
+        path: CasPath<F>,
+        temp: F::Path,
+        hasher: blake3::Hasher,
+        len: u64,
+        append: bool,
+    ) -> Writer<F> {
+        Writer {
+            path,
+            temp,
+            hasher: Some(hasher),
+            hashed: len,
+            pos: 0,
+            len,
+            append,
+        }
+    }
+
+    async fn finish(self, mut file: Pin<Box<InnerFile<F>>>) -> Result<(), Error> {
+        let store = &self.path.fs.store;
+        let result = async {
+            file.close().await?;
+
+            let entry = match self.path.fs.chunking {
+                Some(chunking) => put_chunks(store, &self.temp, chunking).await?,
+                None => {
+                    let (hash, size) = match &self.hasher {
+                        Some(hasher) if self.hashed == self.len => (finalize(hasher), self.len),
+                        _ => hash_file(&self.temp).await?,
+                    };
+                    store.objects().put_file(&self.temp, hash, size).await?;
+                    Entry::file(hash, size)
+                }
+            };
+            self.path.commit(entry).await
+        }
+        .await;
+
+        // The temporary file is of no use anymore, whether the file was stored or not
+        self.temp.rm().await.ok();
+        result
+    }
+}
+
+/// A file of a [`CasFS`](crate::CasFS).
+///
+/// Files opened for reading read the stored object or chunks directly. Files opened
+/// for writing are written to a temporary file, which replaces the file once it is closed.
+pub struct CasFile<F: VFS> {
+    file: Option<Content<F>>,
+    read: bool,
+    writer: Option<Box<Writer<F>>>,
+    // Holds futures of the inner filesystem, which need not be Sync
+    closing: Option<Mutex<BoxFuture<'static, Result<(), Error>>>>,
+}
+
+impl<F: VFS> core::fmt::Debug for CasFile<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CasFile")
+            .field("write", &self.writer.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: SendVFS> CasFile<F> {
+    pub(crate) fn reader(content: Content<F>) -> CasFile<F> {
+        CasFile {
+            file: Some(content),
+            read: true,
+            writer: None,
+            closing: None,
+        }
+    }
+
+    pub(crate) fn writer(
+        file: Pin<Box<InnerFile<F>>>,
+        read: bool,
+        writer: Writer<F>,
+    ) -> CasFile<F> {
+        CasFile {
+            file: Some(Content::Object(file)),
+            read,
+            writer: Some(Box::new(writer)),
+            closing: None,
+        }
+    }
+
+    fn file(&mut self, operation: Operation) -> Result<Pin<&mut dyn VFile>, Error> {
+        match &mut self.file {
+            Some(file) => Ok(file.as_mut()),
+            None => Err(closed(operation)),
+        }
+    }
+}
+
+impl<F: SendVFS> VFile for CasFile<F> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        if !this.read {
+            return Poll::Ready(Err(Error::new_const(
+                ErrorKind::PermissionDenied,
+                "the file is not opened for reading",
+            )
+            .with_operation(Operation::Read)));
+        }
+
+        let n = ready!(this.file(Operation::Read)?.poll_read(cx, buf))?;
+        if let Some(writer) = &mut this.writer {
+            writer.pos += n as u64;
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        let this = self.get_mut();
+        let pos = ready!(this.file(Operation::Seek)?.poll_seek(cx, pos))?;
+        if let Some(writer) = &mut this.writer {
+            writer.pos = pos;
+        }
+        Poll::Ready(Ok(pos))
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        let Some(file) = &mut this.file else {
+            return Poll::Ready(Err(closed(Operation::Write)));
+        };
+        let mut file = file.as_mut();
+        let Some(writer) = &mut this.writer else {
+            return Poll::Ready(Err(Error::new_const(
+                ErrorKind::PermissionDenied,
+                "the file is not opened for writing",
+            )
+            .with_operation(Operation::Write)));
+        };
+
+        if writer.append && writer.pos != writer.len {
+            writer.pos = ready!(file.as_mut().poll_seek(cx, SeekFrom::Start(writer.len)))?;
+        }
+
+        let n = ready!(file.poll_write(cx, buf))?;
+        match &mut writer.hasher {
+            Some(hasher) if writer.pos == writer.hashed => {
+                hasher.update(&buf[..n]);
+                writer.hashed += n as u64;
+            }
+            // Overwritten or skipped content is hashed when the file is closed
+            _ => writer.hasher = None,
+        }
+        writer.pos += n as u64;
+        writer.len = writer.len.max(writer.pos);
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        this.file(Operation::Flush)?.poll_flush(cx)
+    }
+
+    /// Stores the content of a written file and replaces the file with it
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+
+        if let Some(writer) = this.writer.take()
+            && let Some(Content::Object(file)) = this.file.take()
+        {
+            this.closing = Some(Mutex::new(Box::pin(writer.finish(file))));
+        }
+
+        if let Some(closing) = &mut this.closing {
+            let result = ready!(closing.get_mut().unwrap().as_mut().poll(cx));
+            this.closing = None;
+            return Poll::Ready(result);
+        }
+
+        match &mut this.file {
+            Some(file) => file.as_mut().poll_close(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+fn closed(operation: Operation) -> Error {
+    Error::new_const(ErrorKind::InvalidInput, "the file is closed").with_operation(operation)
+}
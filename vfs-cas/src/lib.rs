@@ -0,0 +1,561 @@
+//! A content addressed [`VFS`], which stores files as objects named by the
+//! BLAKE3 hash of their content in another filesystem.
+//!
+//! Directories are stored as manifests listing the name, hash and size of their
+//! entries, which are objects as well. Identical files and directories are
+//! therefore stored only once, and the hash of a directory identifies its whole
+//! tree. The hashes are exposed as [`Metadata::hash`].
+//!
+//! The inner filesystem holds the `objects` directory, a `tmp` directory for files
+//! being written, and a `root` file referencing the manifest of the root directory,
+//! which is written to `root.new` first while it is replaced.
+//! Objects are never removed, so replaced and removed content stays in the store.
+//!
+//! With [`CasFS::chunking`], files are split into chunks by their content, which
+//! are stored as objects along with a list of the chunks of the file. Chunks are
+//! shared by all files, so a small change to a large file only stores the chunks
+//! around the change.
+//!
+//! The objects on their own are available as [`Objects`], for other stores of
+//! content by its hash.
+mod chunk;
+mod file;
+mod manifest;
+mod objects;
+mod store;
+
+pub use self::{
+    chunk::Chunking,
+    file::CasFile,
+    objects::{Objects, copy, hash_file, read_all},
+};
+
+use std::sync::Arc;
+
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use vfs::{
+    Capabilities, DirEntry, Error, ErrorKind, FileTimes, FileType, Metadata, OpenOptions,
+    Operation, Permissions, SendVFS, VFS, VFileExt, VPath, VPathStr, WatchEvent, WatchHub,
+};
+
+use self::{
+    chunk::ChunkReader,
+    file::{Content, Writer},
+    manifest::{Entry, Manifest},
+    store::Store,
+};
+
+/// Manifests kept in memory by default
+const DEFAULT_CACHE_SIZE: usize = 1024;
+
+/// A filesystem storing files and directories by the hash of their content.
+///
+/// Files are immutable objects, so a file opened for writing is written to a
+/// temporary file, which replaces the file when closed. Permissions, times,
+/// ownership and extended attributes are not stored.
+///
+/// Opening an existing file for writing without truncating it copies all of its
+/// content to the temporary file first, and the whole file is hashed again when
+/// it is closed. Changing a few bytes of a large file therefore costs reading and
+/// writing it completely, though with [`chunking`](CasFS::chunking) only the chunks
+/// around the change are stored again.
+pub struct CasFS<F> {
+    store: Arc<Store<F>>,
+    hub: WatchHub<CasPath<F>>,
+    /// How written files are split into chunks, if they are
+    chunking: Option<Chunking>,
+}
+
+impl<F> Clone for CasFS<F> {
+    fn clone(&self) -> Self {
+        CasFS {
+            store: self.store.clone(),
+            hub: self.hub.clone(),
+            chunking: self.chunking,
+        }
+    }
+}
+
+impl<F> core::fmt::Debug for CasFS<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CasFS")
+            .field("chunking", &self.chunking)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: SendVFS> CasFS<F> {
+    /// Open the store kept in `fs`, starting with an empty root directory
+    /// if `fs` holds no store yet
+    pub async fn new(fs: F) -> Result<CasFS<F>, Error> {
+        CasFS::with_cache_size(fs, DEFAULT_CACHE_SIZE).await
+    }
+
+    /// Open the store kept in `fs`, keeping up to `cache_size` directory manifests in memory
+    pub async fn with_cache_size(fs: F, cache_size: usize) -> Result<CasFS<F>, Error> {
+        Ok(CasFS {
+            store: Arc::new(Store::open(fs, cache_size).await?),
+            hub: WatchHub::new(),
+            chunking: None,
+        })
+    }
+
+    /// Store files written from now on in chunks of the given sizes.
+    ///
+    /// Files are read the same way whether they are stored in chunks or not, so
+    /// stores can switch at any time.
+    pub fn chunking(mut self, chunking: Chunking) -> Self {
+        self.chunking = Some(chunking);
+        self
+    }
+
+    /// The filesystem holding the store
+    pub fn get_ref(&self) -> &F {
+        self.store.fs()
+    }
+}
+
+impl<F: SendVFS> VFS for CasFS<F> {
+    type Path = CasPath<F>;
+
+    type Stats = F::Stats;
+
+    fn path(&self, path: impl AsRef<VPathStr>) -> Result<Self::Path, Error> {
+        Ok(CasPath::new(
+            self.clone(),
+            VPathStr::new("").resolve(path).into_string(),
+        ))
+    }
+
+    /// The space used on the inner filesystem
+    fn stats(&self) -> Self::Stats {
+        self.store.fs().stats()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            symlinks: false,
+            atomic_rename: false,
+            xattr: false,
+            seek: true,
+            write: self.store.fs().capabilities().write,
+            case_sensitive: true,
+            max_name_len: None,
+        }
+    }
+}
+
+/// A path of a [`CasFS`]
+pub struct CasPath<F> {
+    fs: CasFS<F>,
+    /// Normalized path without leading slash, empty for the root directory
+    path: String,
+}
+
+impl<F> Clone for CasPath<F> {
+    fn clone(&self) -> Self {
+        CasPath {
+            fs: self.fs.clone(),
+            path: self.path.clone(),
+        }
+    }
+}
+
+impl<F> core::fmt::Debug for CasPath<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("CasPath").field(&self.path).finish()
+    }
+}
+
+/// Paths are equal when they point into the same store
+impl<F> PartialEq for CasPath<F> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.fs.store, &other.fs.store) && self.path == other.path
+    }
+}
+
+impl<F> Eq for CasPath<F> {}
+
+impl<F> core::hash::Hash for CasPath<F> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.path.hash(state)
+    }
+}
+
+impl<F: SendVFS> CasPath<F> {
+    fn new(fs: CasFS<F>, path: String) -> CasPath<F> {
+        CasPath { fs, path }
+    }
+
+    fn child(&self, name: &str) -> CasPath<F> {
+        let path = VPathStr::new(&self.path).join(name).into_string();
+        CasPath::new(self.fs.clone(), path)
+    }
+
+    fn components(&self) -> Vec<&str> {
+        let path = VPathStr::new(&self.path);
+        path.components().map(|name| name.as_str()).collect()
+    }
+
+    fn context(&self, err: Error, operation: Operation) -> Error {
+        err.with_operation(operation)
+            .with_path(format!("/{}", self.path))
+    }
+
+    async fn lookup(&self) -> Result<Entry, Error> {
+        let root = self.fs.store.root().await;
+        self.fs.store.lookup(root, &self.components()).await
+    }
+
+    /// Replace the file with the stored file `entry`
+    async fn commit(&self, entry: Entry) -> Result<(), Error> {
+        let old = self
+            .fs
+            .store
+            .update(&self.components(), false, |old| match old {
+                Some(old) if old.kind == FileType::Dir => Err(ErrorKind::IsADirectory.into()),
+                _ => Ok(Some(entry)),
+            })
+            .await
+            .map_err(|err| self.context(err, Operation::Close))?;
+
+        self.fs.hub.emit(match old {
+            Some(_) => WatchEvent::Modify(self.clone()),
+            None => WatchEvent::Create(self.clone()),
+        });
+        Ok(())
+    }
+
+    /// Open the stored content of the file `entry` for reading
+    async fn open_content(&self, entry: Entry) -> Result<Content<F>, Error> {
+        let store = &self.fs.store;
+        match entry.chunks {
+            Some(chunks) => Ok(Content::Chunks(
+                ChunkReader::open(store.clone(), chunks).await?,
+            )),
+            None => {
+                let file = store.objects().open(entry.hash).await?;
+                Ok(Content::Object(Box::pin(file)))
+            }
+        }
+    }
+
+    /// Open a temporary file holding the current content, which replaces the file when closed
+    async fn open_writer(&self, options: OpenOptions) -> Result<CasFile<F>, Error> {
+        let existing = match self.lookup().await {
+            Ok(entry) if entry.kind == FileType::Dir => return Err(ErrorKind::IsADirectory.into()),
+            Ok(entry) => Some(entry),
+            Err(err) if err.kind() == ErrorKind::NotFound && options.create => {
+                // The parent has to exist for the file to be stored
+                let parent = self.parent().ok_or(ErrorKind::IsADirectory)?;
+                if parent.lookup().await?.kind != FileType::Dir {
+                    return Err(ErrorKind::NotADirectory.into());
+                }
+                None
+            }
+            Err(err) => return Err(err),
+        };
+
+        let store = &self.fs.store;
+        let temp = store.temp_path()?;
+        let file = temp
+            .open(
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true),
+            )
+            .await?;
+        let mut file = Box::pin(file);
+
+        // Continue from the current content, unless it is replaced
+        let mut hasher = blake3::Hasher::new();
+        let mut len = 0;
+        if let Some(entry) = existing.filter(|_| !options.truncate) {
+            let mut content = self.open_content(entry).await?;
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let n = content.as_mut().read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+                file.write_all(&buf[..n]).await?;
+                len += n as u64;
+            }
+            file.seek(vfs::SeekFrom::Start(0)).await?;
+        }
+
+        let writer = Writer::new(self.clone(), temp, hasher, len, options.append);
+        Ok(CasFile::writer(file, options.read, writer))
+    }
+}
+
+fn metadata(entry: &Entry) -> Metadata {
+    Metadata {
+        size: entry.size,
+        kind: entry.kind,
+        permissions: None,
+        accessed: None,
+        modified: None,
+        created: None,
+        uid: None,
+        gid: None,
+        hash: Some(entry.hash),
+    }
+}
+
+impl<F: SendVFS> VPath for CasPath<F> {
+    type FS = CasFS<F>;
+
+    type File = CasFile<F>;
+
+    type ListDir = BoxStream<'static, Result<DirEntry<Self>, Error>>;
+
+    type Metadata = BoxFuture<'static, Result<Metadata, Error>>;
+
+    type Open = BoxFuture<'static, Result<CasFile<F>, Error>>;
+
+    type CreateDir = BoxFuture<'static, Result<(), Error>>;
+
+    type Remove = BoxFuture<'static, Result<(), Error>>;
+
+    type ReadDir = BoxFuture<'static, Result<Self::ListDir, Error>>;
+
+    type SetPermissions = BoxFuture<'static, Result<(), Error>>;
+
+    type SetTimes = BoxFuture<'static, Result<(), Error>>;
+
+    type SetOwner = BoxFuture<'static, Result<(), Error>>;
+
+    type GetXattr = BoxFuture<'static, Result<Option<Vec<u8>>, Error>>;
+
+    type SetXattr = BoxFuture<'static, Result<(), Error>>;
+
+    type ListXattr = BoxFuture<'static, Result<Vec<String>, Error>>;
+
+    type RemoveXattr = BoxFuture<'static, Result<(), Error>>;
+
+    type Watcher = BoxStream<'static, Result<WatchEvent<Self>, Error>>;
+
+    type Watch = BoxFuture<'static, Result<Self::Watcher, Error>>;
+
+    type Canonicalize = BoxFuture<'static, Result<Self, Error>>;
+
+    fn fs(&self) -> Self::FS {
+        self.fs.clone()
+    }
+
+    fn virtual_path(&self) -> String {
+        format!("/{}", self.path)
+    }
+
+    fn to_string(&self) -> String {
+        self.virtual_path()
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        VPathStr::new(&self.path).file_name()
+    }
+
+    fn extension(&self) -> Option<&str> {
+        VPathStr::new(&self.path).extension()
+    }
+
+    fn resolve(&self, path: &str) -> Result<Self, Error> {
+        Ok(CasPath::new(
+            self.fs.clone(),
+            VPathStr::new(&self.path).resolve(path).into_string(),
+        ))
+    }
+
+    fn parent(&self) -> Option<Self> {
+        let parent = VPathStr::new(&self.path).parent()?;
+        Some(CasPath::new(self.fs.clone(), parent.as_str().into()))
+    }
+
+    /// There are no symbolic links, so this only checks that the path exists
+    fn canonicalize(&self) -> Self::Canonicalize {
+        let this = self.clone();
+        Box::pin(async move {
+            this.lookup()
+                .await
+                .map_err(|err| this.context(err, Operation::Canonicalize))?;
+            Ok(this)
+        })
+    }
+
+    /// The hash of a file is the hash of its whole content, also when it is
+    /// stored in chunks, and the hash of a directory the hash of its manifest,
+    /// which covers everything below it
+    fn metadata(&self) -> Self::Metadata {
+        let this = self.clone();
+        Box::pin(async move {
+            let entry = this
+                .lookup()
+                .await
+                .map_err(|err| this.context(err, Operation::Metadata))?;
+            Ok(metadata(&entry))
+        })
+    }
+
+    fn open(&self, options: OpenOptions) -> Self::Open {
+        let this = self.clone();
+        Box::pin(async move {
+            if options.write || options.append {
+                return this
+                    .open_writer(options)
+                    .await
+                    .map_err(|err| this.context(err, Operation::Open));
+            }
+
+            let entry = this
+                .lookup()
+                .await
+                .map_err(|err| this.context(err, Operation::Open))?;
+            if entry.kind == FileType::Dir {
+                return Err(this.context(ErrorKind::IsADirectory.into(), Operation::Open));
+            }
+
+            let content = this
+                .open_content(entry)
+                .await
+                .map_err(|err| this.context(err, Operation::Open))?;
+            Ok(CasFile::reader(content))
+        })
+    }
+
+    fn read_dir(&self) -> Self::ReadDir {
+        let this = self.clone();
+        Box::pin(async move {
+            let manifest = async {
+                let entry = this.lookup().await?;
+                if entry.kind != FileType::Dir {
+                    return Err(ErrorKind::NotADirectory.into());
+                }
+                this.fs.store.manifest(entry.hash).await
+            }
+            .await
+            .map_err(|err| this.context(err, Operation::ReadDir))?;
+
+            // Manifests hold everything known about an entry
+            let entries = manifest
+                .iter()
+                .map(|(name, entry)| Ok(DirEntry::with_metadata(this.child(name), metadata(entry))))
+                .collect::<Vec<_>>();
+
+            Ok(Box::pin(futures::stream::iter(entries)) as Self::ListDir)
+        })
+    }
+
+    fn create_dir(&self) -> Self::CreateDir {
+        let this = self.clone();
+        Box::pin(async move {
+            let components = this.components();
+            if components.is_empty() {
+                return Ok(());
+            }
+
+            let store = &this.fs.store;
+            let old = async {
+                let empty = Manifest::default().to_bytes();
+                let entry = Entry::dir(store.objects().put(&empty).await?, empty.len() as u64);
+                store
+                    .update(&components, true, |old| match old {
+                        Some(old) if old.kind == FileType::Dir => Ok(Some(old)),
+                        Some(_) => Err(Error::new_const(
+                            ErrorKind::AlreadyExists,
+                            "a file with the same name exists",
+                        )),
+                        None => Ok(Some(entry)),
+                    })
+                    .await
+            }
+            .await
+            .map_err(|err| this.context(err, Operation::CreateDir))?;
+
+            if old.is_none() {
+                this.fs.hub.emit(WatchEvent::Create(this.clone()));
+            }
+            Ok(())
+        })
+    }
+
+    fn rm(&self) -> Self::Remove {
+        let this = self.clone();
+        Box::pin(async move {
+            this.fs
+                .store
+                .update(&this.components(), false, |old| {
+                    old.ok_or(Error::from(ErrorKind::NotFound)).map(|_| None)
+                })
+                .await
+                .map_err(|err| this.context(err, Operation::Remove))?;
+
+            this.fs.hub.emit(WatchEvent::Remove(this.clone()));
+            Ok(())
+        })
+    }
+
+    fn set_permissions(&self, _permissions: Permissions) -> Self::SetPermissions {
+        let err = self.context(
+            unsupported("content addressed storage keeps no permissions"),
+            Operation::SetPermissions,
+        );
+        Box::pin(async move { Err(err) })
+    }
+
+    fn set_times(&self, _times: FileTimes) -> Self::SetTimes {
+        let err = self.context(
+            unsupported("content addressed storage keeps no times"),
+            Operation::SetTimes,
+        );
+        Box::pin(async move { Err(err) })
+    }
+
+    fn set_owner(&self, _uid: Option<u32>, _gid: Option<u32>) -> Self::SetOwner {
+        let err = self.context(
+            unsupported("content addressed storage keeps no ownership"),
+            Operation::SetOwner,
+        );
+        Box::pin(async move { Err(err) })
+    }
+
+    fn supports_xattr(&self) -> bool {
+        false
+    }
+
+    fn get_xattr(&self, _name: &str) -> Self::GetXattr {
+        let err = self.context(no_xattr(), Operation::GetXattr);
+        Box::pin(async move { Err(err) })
+    }
+
+    fn set_xattr(&self, _name: &str, _value: &[u8]) -> Self::SetXattr {
+        let err = self.context(no_xattr(), Operation::SetXattr);
+        Box::pin(async move { Err(err) })
+    }
+
+    fn list_xattr(&self) -> Self::ListXattr {
+        let err = self.context(no_xattr(), Operation::ListXattr);
+        Box::pin(async move { Err(err) })
+    }
+
+    fn remove_xattr(&self, _name: &str) -> Self::RemoveXattr {
+        let err = self.context(no_xattr(), Operation::RemoveXattr);
+        Box::pin(async move { Err(err) })
+    }
+
+    fn watch(&self, recursive: bool) -> Self::Watch {
+        let watcher = self.fs.hub.subscribe(self, recursive);
+        Box::pin(async move { Ok(Box::pin(watcher) as Self::Watcher) })
+    }
+}
+
+const fn unsupported(message: &'static str) -> Error {
+    Error::new_const(ErrorKind::Unsupported, message)
+}
+
+const fn no_xattr() -> Error {
+    unsupported("content addressed storage keeps no extended attributes")
+}
@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+
+use vfs::{ContentHash, Error, ErrorKind, FileType};
+
+/// Identifies a manifest and the version of its format
+const HEADER: &str = "vfs-cas manifest 1";
+
+/// An entry of a directory manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub kind: FileType,
+    /// The hash of the file content or the manifest of the directory
+    pub hash: ContentHash,
+    /// The size of the file or the manifest of the directory
+    pub size: u64,
+    /// The hash of the chunk list of a file stored in chunks
+    pub chunks: Option<ContentHash>,
+}
+
+impl Entry {
+    pub fn dir(hash: ContentHash, size: u64) -> Entry {
+        Entry {
+            kind: FileType::Dir,
+            hash,
+            size,
+            chunks: None,
+        }
+    }
+
+    pub fn file(hash: ContentHash, size: u64) -> Entry {
+        Entry {
+            kind: FileType::File,
+            hash,
+            size,
+            chunks: None,
+        }
+    }
+
+    pub fn chunks(hash: ContentHash, size: u64, chunks: ContentHash) -> Entry {
+        Entry {
+            kind: FileType::File,
+            hash,
+            size,
+            chunks: Some(chunks),
+        }
+    }
+}
+
+/// The listing of a directory, stored as an object like file contents.
+///
+/// Manifests are text with a header line followed by one line per entry
+/// holding the kind, hash, size and name of the entry, sorted by name. The kind
+/// is `d` for directories, `f` for files and `c` for files stored in chunks,
+/// whose lines hold the hash of their chunk list before the name.
+/// Equal directories have equal manifests, so they are stored only once.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Manifest {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Manifest {
+    pub fn parse(data: &[u8]) -> Result<Manifest, Error> {
+        let data = core::str::from_utf8(data).map_err(|_| invalid())?;
+        // Names can contain carriage returns, so lines only end in line feeds
+        let mut lines = data.split_terminator('\n');
+        if lines.next() != Some(HEADER) {
+            return Err(invalid());
+        }
+
+        let mut entries = BTreeMap::new();
+        for line in lines {
+            let mut parts = line.splitn(4, ' ');
+            let (Some(kind), Some(hash), Some(size), Some(rest)) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return Err(invalid());
+            };
+
+            let (kind, chunks, name) = match kind {
+                "d" => (FileType::Dir, None, rest),
+                "f" => (FileType::File, None, rest),
+                "c" => {
+                    let (chunks, name) = rest.split_once(' ').ok_or_else(invalid)?;
+                    (
+                        FileType::File,
+                        Some(chunks.parse().map_err(|_| invalid())?),
+                        name,
+                    )
+                }
+                _ => return Err(invalid()),
+            };
+            let entry = Entry {
+                kind,
+                hash: hash.parse().map_err(|_| invalid())?,
+                size: size.parse().map_err(|_| invalid())?,
+                chunks,
+            };
+            entries.insert(name.to_string(), entry);
+        }
+
+        Ok(Manifest { entries })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = String::from(HEADER);
+        out.push('\n');
+        for (name, entry) in &self.entries {
+            let (hash, size) = (entry.hash, entry.size);
+            let line = match (entry.kind, entry.chunks) {
+                (FileType::Dir, _) => format!("d {hash} {size} {name}\n"),
+                (_, Some(chunks)) => format!("c {hash} {size} {chunks} {name}\n"),
+                _ => format!("f {hash} {size} {name}\n"),
+            };
+            out.push_str(&line);
+        }
+        out.into_bytes()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Entry> {
+        self.entries.get(name)
+    }
+
+    pub fn insert(&mut self, name: &str, entry: Entry) -> Result<(), Error> {
+        if name.contains('\n') {
+            return Err(Error::new_const(
+                ErrorKind::InvalidFilename,
+                "file names cannot contain line breaks",
+            ));
+        }
+        self.entries.insert(name.to_string(), entry);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Entry> {
+        self.entries.remove(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Entry)> {
+        self.entries
+            .iter()
+            .map(|(name, entry)| (name.as_str(), entry))
+    }
+}
+
+fn invalid() -> Error {
+    Error::new_const(ErrorKind::InvalidData, "invalid directory manifest")
+}
@@ -0,0 +1,143 @@
+use vfs::{ContentHash, Error, ErrorKind, OpenOptions, VFS, VFile, VFileExt, VPath};
+
+type InnerFile<F> = <<F as VFS>::Path as VPath>::File;
+
+const COPY_SIZE: usize = 64 * 1024;
+
+pub(crate) fn hash(data: &[u8]) -> ContentHash {
+    ContentHash::from_bytes(*blake3::hash(data).as_bytes())
+}
+
+pub(crate) fn finalize(hasher: &blake3::Hasher) -> ContentHash {
+    ContentHash::from_bytes(*hasher.finalize().as_bytes())
+}
+
+/// Contents named by their BLAKE3 hash, kept in a directory of another
+/// filesystem and fanned out into directories by the first byte of the hash.
+///
+/// Objects are written in place, so an object cut short by a crash is
+/// detected by its size and written again
+pub struct Objects<F> {
+    fs: F,
+    dir: String,
+}
+
+impl<F: VFS> Objects<F> {
+    /// The objects kept in the directory `dir` of `fs`, which is created if needed
+    pub async fn new(fs: F, dir: impl Into<String>) -> Result<Objects<F>, Error> {
+        let dir = dir.into();
+        fs.path(&dir)?.create_dir().await?;
+        Ok(Objects { fs, dir })
+    }
+
+    /// The location of the object with `hash`, whether it is stored or not
+    pub fn path(&self, hash: ContentHash) -> Result<F::Path, Error> {
+        let hex = hash.to_string();
+        self.fs
+            .path(format!("{}/{}/{}", self.dir, &hex[..2], &hex[2..]))
+    }
+
+    /// Open the object with `hash` for reading
+    pub async fn open(&self, hash: ContentHash) -> Result<InnerFile<F>, Error> {
+        self.path(hash)?.open(OpenOptions::new().read(true)).await
+    }
+
+    /// Returns true if the object with `hash` is stored completely
+    pub async fn contains(&self, hash: ContentHash, size: u64) -> Result<bool, Error> {
+        let stored = self.path(hash)?.metadata().await;
+        Ok(stored.is_ok_and(|metadata| metadata.is_file() && metadata.size == size))
+    }
+
+    /// Store `data` unless it is stored already
+    pub async fn put(&self, data: &[u8]) -> Result<ContentHash, Error> {
+        let hash = hash(data);
+        if !self.contains(hash, data.len() as u64).await? {
+            let mut file = self.create(hash).await?;
+            file.write_all(data).await?;
+            file.close().await?;
+        }
+        Ok(hash)
+    }
+
+    /// Store the content of the file at `path`, which has `hash` and `size`,
+    /// unless it is stored already
+    pub async fn put_file(
+        &self,
+        path: &F::Path,
+        hash: ContentHash,
+        size: u64,
+    ) -> Result<(), Error> {
+        if self.contains(hash, size).await? {
+            return Ok(());
+        }
+
+        let from = path.open(OpenOptions::new().read(true)).await?;
+        let mut from = core::pin::pin!(from);
+        let mut to = self.create(hash).await?;
+        copy(from.as_mut(), to.as_mut()).await?;
+        to.close().await
+    }
+
+    /// Read the object with `hash`, checking that its content matches the hash
+    pub async fn get(&self, hash: ContentHash) -> Result<Vec<u8>, Error> {
+        let data = read_all(&self.path(hash)?).await?;
+        if self::hash(&data) != hash {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("object {hash} is corrupt"),
+            ));
+        }
+        Ok(data)
+    }
+
+    async fn create(&self, hash: ContentHash) -> Result<core::pin::Pin<Box<InnerFile<F>>>, Error> {
+        let path = self.path(hash)?;
+        if let Some(parent) = path.parent() {
+            parent.create_dir().await?;
+        }
+        let options = OpenOptions::new().write(true).create(true).truncate(true);
+        Ok(Box::pin(path.open(options).await?))
+    }
+}
+
+/// Hash the content of the file at `path`, returning the hash and the size
+pub async fn hash_file<P: VPath>(path: &P) -> Result<(ContentHash, u64), Error> {
+    let file = path.open(OpenOptions::new().read(true)).await?;
+    let mut file = core::pin::pin!(file);
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0; COPY_SIZE];
+    let mut size = 0;
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((finalize(&hasher), size))
+}
+
+/// Read the whole file at `path`
+pub async fn read_all<P: VPath>(path: &P) -> Result<Vec<u8>, Error> {
+    let file = path.open(OpenOptions::new().read(true)).await?;
+    let mut file = core::pin::pin!(file);
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).await?;
+    Ok(data)
+}
+
+/// Copy the rest of `from` to `to`
+pub async fn copy<R: VFile + ?Sized, W: VFile + ?Sized>(
+    mut from: core::pin::Pin<&mut R>,
+    mut to: core::pin::Pin<&mut W>,
+) -> Result<(), Error> {
+    let mut buf = vec![0; COPY_SIZE];
+    loop {
+        let n = from.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        to.write_all(&buf[..n]).await?;
+    }
+}
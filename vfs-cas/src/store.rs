@@ -0,0 +1,247 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use async_lock::Mutex as AsyncMutex;
+use lru::LruCache;
+use vfs::{ContentHash, Error, ErrorKind, FileType, OpenOptions, SendVFS, VFileExt, VPath};
+
+use crate::{
+    manifest::{Entry, Manifest},
+    objects::{Objects, hash, read_all},
+};
+
+/// The file holding the hash of the root manifest
+const ROOT: &str = "root";
+
+/// The new root reference, written before the root reference is replaced
+const ROOT_NEW: &str = "root.new";
+
+/// The directory of the objects
+const OBJECTS: &str = "objects";
+
+/// The directory of files being written
+const TEMP: &str = "tmp";
+
+/// The objects and the root reference kept in the inner filesystem
+pub(crate) struct Store<F> {
+    fs: F,
+    objects: Objects<F>,
+    /// The hash of the root manifest. Locked while the tree is changed,
+    /// so changes are applied one at a time
+    root: AsyncMutex<ContentHash>,
+    manifests: Mutex<LruCache<ContentHash, Arc<Manifest>>>,
+    temp_id: AtomicU64,
+}
+
+impl<F: SendVFS> Store<F> {
+    /// Open the store kept in `fs`, initializing it with an empty root directory if needed
+    pub async fn open(fs: F, cache_size: usize) -> Result<Store<F>, Error> {
+        let objects = Objects::new(fs.clone(), OBJECTS).await?;
+        fs.path(TEMP)?.create_dir().await?;
+
+        let store = Store {
+            objects,
+            root: AsyncMutex::new(hash(b"")),
+            manifests: Mutex::new(LruCache::new(
+                NonZeroUsize::new(cache_size).unwrap_or(NonZeroUsize::MIN),
+            )),
+            temp_id: AtomicU64::new(0),
+            fs,
+        };
+
+        let root = match store.read_root().await? {
+            Some(root) => root,
+            None => {
+                let root = store.objects.put(&Manifest::default().to_bytes()).await?;
+                store.write_root(root).await?;
+                root
+            }
+        };
+
+        *store.root.lock().await = root;
+        Ok(store)
+    }
+
+    pub fn fs(&self) -> &F {
+        &self.fs
+    }
+
+    pub fn objects(&self) -> &Objects<F> {
+        &self.objects
+    }
+
+    pub async fn root(&self) -> ContentHash {
+        *self.root.lock().await
+    }
+
+    /// A new path for a file being written
+    pub fn temp_path(&self) -> Result<F::Path, Error> {
+        // Unique among processes sharing the inner filesystem as well
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&std::process::id().to_le_bytes());
+        hasher.update(&self.temp_id.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        hasher.update(&now.as_nanos().to_le_bytes());
+
+        let name = hasher.finalize().to_hex();
+        self.fs.path(format!("{TEMP}/{}", &name[..32]))
+    }
+
+    pub async fn manifest(&self, hash: ContentHash) -> Result<Arc<Manifest>, Error> {
+        if let Some(manifest) = self.manifests.lock().unwrap().get(&hash) {
+            return Ok(manifest.clone());
+        }
+
+        let manifest = Arc::new(Manifest::parse(&self.objects.get(hash).await?)?);
+        self.manifests.lock().unwrap().put(hash, manifest.clone());
+        Ok(manifest)
+    }
+
+    /// Find the entry at `components` in the tree with the root manifest `root`
+    pub async fn lookup(&self, root: ContentHash, components: &[&str]) -> Result<Entry, Error> {
+        let mut entry = Entry::dir(root, 0);
+
+        for name in components {
+            if entry.kind != FileType::Dir {
+                return Err(ErrorKind::NotADirectory.into());
+            }
+            let manifest = self.manifest(entry.hash).await?;
+            entry = *manifest.get(name).ok_or(ErrorKind::NotFound)?;
+        }
+
+        Ok(entry)
+    }
+
+    /// Replace the entry at `components` with the result of `change`, which is
+    /// given the current entry, and store the manifests of all its parents.
+    /// Missing parents are created if `create_parents` is true.
+    /// Returns the entry which was replaced
+    pub async fn update(
+        &self,
+        components: &[&str],
+        create_parents: bool,
+        change: impl FnOnce(Option<Entry>) -> Result<Option<Entry>, Error>,
+    ) -> Result<Option<Entry>, Error> {
+        let Some((name, parents)) = components.split_last() else {
+            return Err(Error::new_const(
+                ErrorKind::InvalidInput,
+                "the root directory cannot be replaced",
+            ));
+        };
+
+        let mut root = self.root.lock().await;
+
+        // The manifests of the root and every parent of the entry
+        let mut manifests = Vec::with_capacity(components.len());
+        manifests.push(Manifest::clone(&*self.manifest(*root).await?));
+        for parent in parents {
+            let manifest = match manifests.last().unwrap().get(parent) {
+                Some(entry) if entry.kind == FileType::Dir => {
+                    Manifest::clone(&*self.manifest(entry.hash).await?)
+                }
+                Some(_) => return Err(ErrorKind::NotADirectory.into()),
+                None if create_parents => Manifest::default(),
+                None => return Err(ErrorKind::NotFound.into()),
+            };
+            manifests.push(manifest);
+        }
+
+        let dir = manifests.last_mut().unwrap();
+        let old = dir.get(name).copied();
+        let new = change(old)?;
+        if new == old {
+            return Ok(old);
+        }
+        match new {
+            Some(entry) => dir.insert(name, entry)?,
+            None => {
+                dir.remove(name);
+            }
+        }
+
+        let mut child = None;
+        for (idx, mut manifest) in manifests.into_iter().enumerate().rev() {
+            if let Some(entry) = child {
+                manifest.insert(parents[idx], entry)?;
+            }
+            let data = manifest.to_bytes();
+            child = Some(Entry::dir(
+                self.objects.put(&data).await?,
+                data.len() as u64,
+            ));
+        }
+
+        let hash = child.unwrap().hash;
+        self.write_root(hash).await?;
+        *root = hash;
+
+        Ok(old)
+    }
+
+    /// Read the root reference, or the new one if the root reference was cut short
+    /// while being replaced. Returns `None` for a new store
+    async fn read_root(&self) -> Result<Option<ContentHash>, Error> {
+        let mut found = false;
+        for name in [ROOT, ROOT_NEW] {
+            let data = match read_all(&self.fs.path(name)?).await {
+                Ok(data) => data,
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            found = true;
+
+            let hash = core::str::from_utf8(&data)
+                .ok()
+                .and_then(|hex| hex.trim().parse().ok());
+            // A reference cut short points at no object
+            if let Some(hash) = hash
+                && let Ok(metadata) = self.objects.path(hash)?.metadata().await
+                && metadata.is_file()
+            {
+                return Ok(Some(hash));
+            }
+        }
+
+        if found {
+            Err(Error::new_const(
+                ErrorKind::InvalidData,
+                "invalid root reference",
+            ))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Point the root reference at the manifest with `hash`.
+    ///
+    /// Files cannot be renamed over each other, so the reference is written to
+    /// `root.new` first and then over the old reference, which has the same length.
+    /// The root reference is never empty, and one of both is complete after a crash
+    async fn write_root(&self, hash: ContentHash) -> Result<(), Error> {
+        let line = format!("{hash}\n");
+        for (name, truncate) in [(ROOT_NEW, true), (ROOT, false)] {
+            let file = self
+                .fs
+                .path(name)?
+                .open(
+                    OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(truncate),
+                )
+                .await?;
+            let mut file = core::pin::pin!(file);
+            file.write_all(line.as_bytes()).await?;
+            file.close().await?;
+        }
+
+        self.fs.path(ROOT_NEW)?.rm().await
+    }
+}
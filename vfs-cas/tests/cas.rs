@@ -0,0 +1,138 @@
+use futures::executor::block_on;
+use vfs::{ErrorKind, OpenOptions, VFS, VFileExt, VPath};
+use vfs_cas::{CasFS, Chunking};
+use vfs_memory::MemoryFS;
+use vfs_test::{names, noise, read_file, write_file};
+
+#[test]
+fn carriage_return_in_name() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        let fs = CasFS::new(inner.clone()).await.unwrap();
+        write_file(&fs.path("/a\r").unwrap(), b"cr").await;
+        write_file(&fs.path("/a").unwrap(), b"plain").await;
+
+        let fs = CasFS::new(inner).await.unwrap();
+        assert_eq!(names(&fs.path("/").unwrap()).await, ["a", "a\r"]);
+        assert_eq!(read_file(&fs.path("/a\r").unwrap()).await, b"cr");
+        assert_eq!(read_file(&fs.path("/a").unwrap()).await, b"plain");
+
+        // Line feeds still end the entries, so they are refused
+        let options = OpenOptions::new().write(true).create(true);
+        let path = fs.path("/a\nb").unwrap();
+        let err = match path.open(options).await {
+            Ok(mut file) => file.close().await.unwrap_err(),
+            Err(err) => err,
+        };
+        assert_eq!(err.kind(), ErrorKind::InvalidFilename);
+    })
+}
+
+#[test]
+fn root_reference_cut_short() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        let fs = CasFS::new(inner.clone()).await.unwrap();
+        write_file(&fs.path("/file").unwrap(), b"data").await;
+
+        let root = read_file(&inner.path("/root").unwrap()).await;
+        assert_eq!(root.len(), 65);
+        assert!(inner.path("/root.new").unwrap().metadata().await.is_err());
+
+        // A crash while the reference was overwritten, after the new one was written
+        let mut cut = root.clone();
+        cut[40..].fill(b'0');
+        write_file(&inner.path("/root").unwrap(), &cut).await;
+        write_file(&inner.path("/root.new").unwrap(), &root).await;
+        let fs = CasFS::new(inner.clone()).await.unwrap();
+        assert_eq!(read_file(&fs.path("/file").unwrap()).await, b"data");
+
+        // Without the new reference the store can not be opened
+        inner.path("/root.new").unwrap().rm().await.unwrap();
+        write_file(&inner.path("/root").unwrap(), &cut).await;
+        let err = CasFS::new(inner).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    })
+}
+
+async fn objects(inner: &MemoryFS) -> usize {
+    let mut count = 0;
+    for dir in names(&inner.path("/objects").unwrap()).await {
+        count += names(&inner.path(format!("/objects/{dir}")).unwrap())
+            .await
+            .len();
+    }
+    count
+}
+
+#[test]
+fn hash_is_content_hash() {
+    block_on(async {
+        let data = noise(200_000, 1);
+        let hash = vfs::ContentHash::from_bytes(*blake3::hash(&data).as_bytes());
+
+        let plain = CasFS::new(MemoryFS::new()).await.unwrap();
+        let chunking = Chunking::new(1024, 4096, 16 * 1024).unwrap();
+        let chunked = CasFS::new(MemoryFS::new())
+            .await
+            .unwrap()
+            .chunking(chunking);
+
+        for fs in [plain, chunked] {
+            let path = fs.path("/file").unwrap();
+            write_file(&path, &data).await;
+            let metadata = path.metadata().await.unwrap();
+            assert_eq!(metadata.hash, Some(hash));
+            assert_eq!(metadata.size, data.len() as u64);
+            assert_eq!(read_file(&path).await, data);
+        }
+    })
+}
+
+#[test]
+fn identical_content_is_stored_once() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        let fs = CasFS::new(inner.clone()).await.unwrap();
+        let data = noise(10_000, 2);
+
+        write_file(&fs.path("/a").unwrap(), &data).await;
+        let before = objects(&inner).await;
+        fs.path("/dir").unwrap().create_dir().await.unwrap();
+        write_file(&fs.path("/dir/b").unwrap(), &data).await;
+
+        let a = fs.path("/a").unwrap().metadata().await.unwrap();
+        let b = fs.path("/dir/b").unwrap().metadata().await.unwrap();
+        assert_eq!(a.hash, b.hash);
+        // Only manifests were added: the root holding the new directory, and
+        // the directory holding the file along with the root holding that
+        assert_eq!(objects(&inner).await, before + 3);
+    })
+}
+
+#[test]
+fn chunked_change_stores_few_chunks() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        let chunking = Chunking::new(1024, 4096, 16 * 1024).unwrap();
+        let fs = CasFS::new(inner.clone()).await.unwrap().chunking(chunking);
+        let mut data = noise(1_000_000, 3);
+
+        let path = fs.path("/file").unwrap();
+        write_file(&path, &data).await;
+        let before = objects(&inner).await;
+
+        // Overwrite a few bytes in the middle
+        let options = OpenOptions::new().write(true);
+        let mut file = path.open(options).await.unwrap();
+        file.seek(vfs::SeekFrom::Start(500_000)).await.unwrap();
+        file.write_all(b"changed").await.unwrap();
+        file.close().await.unwrap();
+        data[500_000..500_007].copy_from_slice(b"changed");
+
+        assert_eq!(read_file(&path).await, data);
+        // The changed chunks, the chunk list and the root manifest
+        let added = objects(&inner).await - before;
+        assert!((3..10).contains(&added), "{added} objects added");
+    })
+}
@@ -0,0 +1,204 @@
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use std::io::Write;
+
+use vfs::{Error, ErrorKind};
+
+/// The compression algorithm of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    Gzip,
+    Zstd,
+}
+
+impl Codec {
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Codec::Gzip => 1,
+            Codec::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Result<Codec, Error> {
+        match id {
+            1 => Ok(Codec::Gzip),
+            2 => Ok(Codec::Zstd),
+            _ => Err(Error::new_const(
+                ErrorKind::InvalidData,
+                "unknown compression of file",
+            )),
+        }
+    }
+
+    /// Returns true if files compressed with this algorithm can be read and written
+    pub fn is_supported(self) -> bool {
+        match self {
+            Codec::Gzip => cfg!(feature = "gzip"),
+            Codec::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+}
+
+/// Compresses a single frame, collecting the output in memory
+pub(crate) enum Encoder {
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    /// Lets the methods match exhaustively when no codec is enabled
+    #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+    #[allow(dead_code)]
+    None(core::convert::Infallible),
+}
+
+impl Encoder {
+    /// Start a frame. `level` is the algorithm specific compression level
+    pub fn new(codec: Codec, level: Option<i32>) -> Result<Encoder, Error> {
+        match codec {
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => {
+                let level = level.map_or(flate2::Compression::default(), |level| {
+                    flate2::Compression::new(level.clamp(0, 9) as u32)
+                });
+                Ok(Encoder::Gzip(flate2::write::GzEncoder::new(
+                    Vec::new(),
+                    level,
+                )))
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => {
+                let mut encoder = zstd::stream::write::Encoder::new(
+                    Vec::new(),
+                    level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL),
+                )?;
+                encoder.include_checksum(true)?;
+                Ok(Encoder::Zstd(encoder))
+            }
+            #[allow(unreachable_patterns)]
+            _ => {
+                let _ = level;
+                Err(unsupported(codec))
+            }
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => Ok(encoder.write_all(data)?),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => Ok(encoder.write_all(data)?),
+            #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+            Encoder::None(never) => {
+                let _ = data;
+                match *never {}
+            }
+        }
+    }
+
+    /// Take the compressed output produced so far
+    pub fn take(&mut self) -> Vec<u8> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => core::mem::take(encoder.get_mut()),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => core::mem::take(encoder.get_mut()),
+            #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+            Encoder::None(never) => match *never {},
+        }
+    }
+
+    /// End the frame, returning the remaining output
+    pub fn finish(self) -> Result<Vec<u8>, Error> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => Ok(encoder.finish()?),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => Ok(encoder.finish()?),
+            #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+            Encoder::None(never) => match never {},
+        }
+    }
+}
+
+/// Decompresses a single frame, collecting the output in memory
+pub(crate) enum Decoder {
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzDecoder<Vec<u8>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Decoder<'static, Vec<u8>>),
+    /// Lets the methods match exhaustively when no codec is enabled
+    #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+    #[allow(dead_code)]
+    None(core::convert::Infallible),
+}
+
+impl Decoder {
+    pub fn new(codec: Codec) -> Result<Decoder, Error> {
+        match codec {
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => Ok(Decoder::Gzip(flate2::write::GzDecoder::new(Vec::new()))),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Ok(Decoder::Zstd(
+                zstd::stream::write::Decoder::new(Vec::new())?,
+            )),
+            #[allow(unreachable_patterns)]
+            _ => Err(unsupported(codec)),
+        }
+    }
+
+    /// Decompress `data`, returning the output it completed
+    pub fn decode(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(decoder) => {
+                decoder.write_all(data).map_err(corrupt)?;
+                decoder.flush().map_err(corrupt)?;
+                Ok(core::mem::take(decoder.get_mut()))
+            }
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(decoder) => {
+                decoder.write_all(data).map_err(corrupt)?;
+                decoder.flush().map_err(corrupt)?;
+                Ok(core::mem::take(decoder.get_mut()))
+            }
+            #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+            Decoder::None(never) => {
+                let _ = data;
+                match *never {}
+            }
+        }
+    }
+
+    /// End the frame, returning the remaining output
+    pub fn finish(&mut self) -> Result<Vec<u8>, Error> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(decoder) => {
+                decoder.try_finish().map_err(corrupt)?;
+                Ok(core::mem::take(decoder.get_mut()))
+            }
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(decoder) => {
+                decoder.flush().map_err(corrupt)?;
+                Ok(core::mem::take(decoder.get_mut()))
+            }
+            #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+            Decoder::None(never) => match *never {},
+        }
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+fn corrupt(err: std::io::Error) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("failed to decompress file: {err}"),
+    )
+}
+
+fn unsupported(codec: Codec) -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!("{codec:?} compressed files are not supported"),
+    )
+}
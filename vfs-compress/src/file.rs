@@ -0,0 +1,552 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use vfs::{Error, ErrorKind, Operation, SeekFrom, VFile, VFileExt};
+
+use crate::codec::{Codec, Decoder, Encoder};
+
+/// Identifies the footer of a compressed file
+const MAGIC: [u8; 4] = *b"VFSZ";
+
+const VERSION: u8 = 1;
+
+/// frame count, uncompressed size, codec, version, two reserved bytes and the magic
+pub(crate) const FOOTER_LEN: u64 = 24;
+
+/// The compressed and uncompressed size of a frame
+const ENTRY_LEN: u64 = 16;
+
+/// Compressed bytes read from the inner file at once
+const READ_SIZE: usize = 64 * 1024;
+
+/// Compressed output written to the inner file before taking more input
+const WRITE_SIZE: usize = 64 * 1024;
+
+/// The trailer of a compressed file.
+///
+/// A file consists of independently compressed frames, followed by a table
+/// of the compressed and uncompressed size of each frame and this footer.
+/// The table lets readers start decompressing at the frame containing
+/// the position they seek to.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Footer {
+    frames: u64,
+    pub size: u64,
+    codec: Codec,
+}
+
+impl Footer {
+    fn parse(buf: &[u8; FOOTER_LEN as usize]) -> Result<Footer, Error> {
+        if buf[20..] != MAGIC || buf[17] != VERSION {
+            return Err(not_compressed());
+        }
+
+        Ok(Footer {
+            frames: u64::from_le_bytes(buf[0..8].try_into().unwrap()),
+            size: u64::from_le_bytes(buf[8..16].try_into().unwrap()),
+            codec: Codec::from_id(buf[16])?,
+        })
+    }
+
+    fn to_bytes(self) -> [u8; FOOTER_LEN as usize] {
+        let mut buf = [0; FOOTER_LEN as usize];
+        buf[0..8].copy_from_slice(&self.frames.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.size.to_le_bytes());
+        buf[16] = self.codec.id();
+        buf[17] = VERSION;
+        buf[20..].copy_from_slice(&MAGIC);
+        buf
+    }
+
+    /// Read the footer of a file, which has a length of `len` bytes.
+    /// An empty file is read as an empty compressed file
+    pub async fn read<T: VFile + Unpin>(file: &mut T, len: u64) -> Result<Option<Footer>, Error> {
+        if len == 0 {
+            return Ok(None);
+        }
+
+        if len < FOOTER_LEN {
+            return Err(not_compressed());
+        }
+
+        let mut buf = [0; FOOTER_LEN as usize];
+        file.seek(SeekFrom::Start(len - FOOTER_LEN)).await?;
+        file.read_exact(&mut buf).await?;
+        Footer::parse(&buf).map(Some)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    /// Offset of the compressed data in the inner file
+    offset: u64,
+    compressed: u64,
+    /// Offset of the uncompressed data
+    start: u64,
+    len: u64,
+}
+
+/// Read the frame table of a file with a length of `len` bytes
+async fn read_frames<T: VFile + Unpin>(
+    file: &mut T,
+    len: u64,
+) -> Result<(Codec, u64, Vec<Frame>), Error> {
+    let Some(footer) = Footer::read(file, len).await? else {
+        return Ok((Codec::Zstd, 0, Vec::new()));
+    };
+
+    let table_len = footer
+        .frames
+        .checked_mul(ENTRY_LEN)
+        .filter(|table| table + FOOTER_LEN <= len)
+        .ok_or_else(not_compressed)?;
+
+    let mut table = vec![0; table_len as usize];
+    file.seek(SeekFrom::Start(len - FOOTER_LEN - table_len))
+        .await?;
+    file.read_exact(&mut table).await?;
+
+    let (mut offset, mut start) = (0u64, 0u64);
+    let mut frames = Vec::with_capacity(footer.frames as usize);
+    for entry in table.chunks_exact(ENTRY_LEN as usize) {
+        let compressed = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+        let frame_len = u64::from_le_bytes(entry[8..16].try_into().unwrap());
+        frames.push(Frame {
+            offset,
+            compressed,
+            start,
+            len: frame_len,
+        });
+        offset = offset.checked_add(compressed).ok_or_else(not_compressed)?;
+        start = start.checked_add(frame_len).ok_or_else(not_compressed)?;
+    }
+
+    if offset + table_len + FOOTER_LEN != len || start != footer.size {
+        return Err(not_compressed());
+    }
+
+    Ok((footer.codec, footer.size, frames))
+}
+
+/// Compression settings of files being written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Options {
+    pub codec: Codec,
+    pub level: Option<i32>,
+    /// The uncompressed size of each frame, or `None` for a single frame
+    pub frame_size: Option<u64>,
+}
+
+struct Reader {
+    codec: Codec,
+    size: u64,
+    frames: Vec<Frame>,
+    pos: u64,
+    /// The frame being decompressed
+    frame: usize,
+    decoder: Option<Decoder>,
+    /// Compressed bytes of the frame which have not been read yet
+    remaining: u64,
+    /// Decompressed data, starting at the uncompressed offset `decoded`
+    out: Vec<u8>,
+    out_pos: usize,
+    decoded: u64,
+    input: Box<[u8]>,
+    inner_pos: Option<u64>,
+}
+
+struct Writer {
+    options: Options,
+    encoder: Option<Encoder>,
+    /// Compressed and uncompressed size of the current frame
+    compressed: u64,
+    len: u64,
+    frames: Vec<(u64, u64)>,
+    size: u64,
+    /// Output which still has to be written to the inner file
+    pending: Vec<u8>,
+    written: usize,
+    closed: bool,
+}
+
+enum Mode {
+    Read(Box<Reader>),
+    Write(Box<Writer>),
+}
+
+/// A file of a [`CompressedFS`](crate::CompressedFS), which decompresses it while reading
+/// or compresses it while writing.
+///
+/// Written files are only complete once they are closed. The trailer is written
+/// when closing, so a file dropped before that is left without one, and opening
+/// it or reading its metadata fails with [`ErrorKind::InvalidData`].
+pub struct CompressedFile<T> {
+    file: Pin<Box<T>>,
+    mode: Mode,
+}
+
+impl<T> core::fmt::Debug for CompressedFile<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mode = match self.mode {
+            Mode::Read(_) => "read",
+            Mode::Write(_) => "write",
+        };
+        f.debug_struct("CompressedFile")
+            .field("mode", &mode)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: VFile> CompressedFile<T> {
+    pub(crate) async fn reader(file: T) -> Result<CompressedFile<T>, Error> {
+        let mut file = Box::pin(file);
+        let len = file.seek(SeekFrom::End(0)).await?;
+        let (codec, size, frames) = read_frames(&mut file, len).await?;
+
+        Ok(CompressedFile {
+            file,
+            mode: Mode::Read(Box::new(Reader {
+                codec,
+                size,
+                frames,
+                pos: 0,
+                frame: 0,
+                decoder: None,
+                remaining: 0,
+                out: Vec::new(),
+                out_pos: 0,
+                decoded: 0,
+                input: vec![0; READ_SIZE].into_boxed_slice(),
+                inner_pos: Some(len),
+            })),
+        })
+    }
+
+    pub(crate) fn writer(file: T, options: Options) -> CompressedFile<T> {
+        CompressedFile {
+            file: Box::pin(file),
+            mode: Mode::Write(Box::new(Writer {
+                options,
+                encoder: None,
+                compressed: 0,
+                len: 0,
+                frames: Vec::new(),
+                size: 0,
+                pending: Vec::new(),
+                written: 0,
+                closed: false,
+            })),
+        }
+    }
+}
+
+impl Reader {
+    /// Make the decompressed output start at the current position
+    fn poll_fill<T: VFile>(
+        &mut self,
+        file: &mut Pin<Box<T>>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Error>> {
+        loop {
+            let frame = self.frames[self.frame];
+            let available = (self.out.len() - self.out_pos) as u64;
+
+            if self.pos < self.decoded + available {
+                self.out_pos += (self.pos - self.decoded) as usize;
+                self.decoded = self.pos;
+                return Poll::Ready(Ok(()));
+            }
+
+            // Everything decompressed so far lies before the position
+            self.decoded += available;
+            self.out.clear();
+            self.out_pos = 0;
+
+            let Some(decoder) = self.decoder.as_mut() else {
+                return Poll::Ready(Err(corrupt()));
+            };
+
+            let offset = frame.offset + frame.compressed - self.remaining;
+            if self.inner_pos != Some(offset) {
+                self.inner_pos = None;
+                ready!(file.as_mut().poll_seek(cx, SeekFrom::Start(offset)))?;
+                self.inner_pos = Some(offset);
+            }
+
+            let len = self.input.len().min(self.remaining as usize);
+            let n = ready!(file.as_mut().poll_read(cx, &mut self.input[..len]))
+                .inspect_err(|_| self.inner_pos = None)?;
+            if n == 0 {
+                return Poll::Ready(Err(corrupt()));
+            }
+
+            self.remaining -= n as u64;
+            self.inner_pos = Some(offset + n as u64);
+            self.out = decoder.decode(&self.input[..n])?;
+
+            // Finish the frame right away, so its checksum is verified even
+            // when the reader stops at the end of the frame
+            if self.remaining == 0 {
+                let tail = decoder.finish()?;
+                self.out.extend_from_slice(&tail);
+                self.decoder = None;
+                if self.decoded + self.out.len() as u64 != frame.start + frame.len {
+                    return Poll::Ready(Err(corrupt()));
+                }
+            }
+        }
+    }
+
+    fn poll_read<T: VFile>(
+        &mut self,
+        file: &mut Pin<Box<T>>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let current = self.frames[self.frame];
+        let in_frame = (current.start..current.start + current.len).contains(&self.pos);
+        if !in_frame
+            || self.pos < self.decoded
+            || self.decoder.is_none()
+                && self.pos >= self.decoded + (self.out.len() - self.out_pos) as u64
+        {
+            // Start over at the frame containing the position
+            let idx = self
+                .frames
+                .partition_point(|frame| frame.start + frame.len <= self.pos);
+            let frame = self.frames[idx];
+            self.frame = idx;
+            self.decoder = Some(Decoder::new(self.codec)?);
+            self.remaining = frame.compressed;
+            self.out.clear();
+            self.out_pos = 0;
+            self.decoded = frame.start;
+        }
+
+        ready!(self.poll_fill(file, cx))?;
+
+        let data = &self.out[self.out_pos..];
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        self.out_pos += len;
+        self.decoded += len as u64;
+        self.pos += len as u64;
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl Writer {
+    /// Write the pending output to the inner file
+    fn poll_drain<T: VFile>(
+        &mut self,
+        file: &mut Pin<Box<T>>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Error>> {
+        while self.written < self.pending.len() {
+            let n = ready!(file.as_mut().poll_write(cx, &self.pending[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    fn end_frame(&mut self) -> Result<(), Error> {
+        if let Some(encoder) = self.encoder.take() {
+            let output = encoder.finish()?;
+            self.compressed += output.len() as u64;
+            self.pending.extend_from_slice(&output);
+            self.frames.push((self.compressed, self.len));
+            self.compressed = 0;
+            self.len = 0;
+        }
+        Ok(())
+    }
+
+    fn poll_write<T: VFile>(
+        &mut self,
+        file: &mut Pin<Box<T>>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        if self.pending.len() >= WRITE_SIZE {
+            ready!(self.poll_drain(file, cx))?;
+        }
+
+        if self.closed {
+            return Poll::Ready(Err(Error::new_const(
+                ErrorKind::InvalidInput,
+                "the file is closed",
+            )));
+        }
+
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let encoder = match &mut self.encoder {
+            Some(encoder) => encoder,
+            encoder => encoder.insert(Encoder::new(self.options.codec, self.options.level)?),
+        };
+
+        let room = self
+            .options
+            .frame_size
+            .map_or(u64::MAX, |frame_size| frame_size - self.len);
+        let n = buf.len().min(room.try_into().unwrap_or(usize::MAX));
+        encoder.write(&buf[..n])?;
+        let output = encoder.take();
+        self.compressed += output.len() as u64;
+        self.pending.extend_from_slice(&output);
+        self.len += n as u64;
+        self.size += n as u64;
+
+        if Some(self.len) == self.options.frame_size {
+            self.end_frame()?;
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_close<T: VFile>(
+        &mut self,
+        file: &mut Pin<Box<T>>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Error>> {
+        if !self.closed {
+            self.end_frame()?;
+            for (compressed, len) in &self.frames {
+                self.pending.extend_from_slice(&compressed.to_le_bytes());
+                self.pending.extend_from_slice(&len.to_le_bytes());
+            }
+
+            let footer = Footer {
+                frames: self.frames.len() as u64,
+                size: self.size,
+                codec: self.options.codec,
+            };
+            self.pending.extend_from_slice(&footer.to_bytes());
+            self.closed = true;
+        }
+
+        ready!(self.poll_drain(file, cx))?;
+        file.as_mut().poll_close(cx)
+    }
+}
+
+impl<T: VFile> VFile for CompressedFile<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        match &mut this.mode {
+            Mode::Read(reader) => reader.poll_read(&mut this.file, cx, buf),
+            Mode::Write(_) => Poll::Ready(Err(write_only(Operation::Read))),
+        }
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        let this = self.get_mut();
+        let (current, size) = match &this.mode {
+            Mode::Read(reader) => (reader.pos, reader.size),
+            Mode::Write(writer) => (writer.size, writer.size),
+        };
+
+        let target = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(offset) => current.checked_add_signed(offset),
+            SeekFrom::End(offset) => size.checked_add_signed(offset),
+        };
+
+        let Some(target) = target else {
+            return Poll::Ready(Err(Error::new_const(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+            .with_operation(Operation::Seek)));
+        };
+
+        match &mut this.mode {
+            Mode::Read(reader) => reader.pos = target,
+            Mode::Write(_) if target != current => {
+                return Poll::Ready(Err(Error::new_const(
+                    ErrorKind::Unsupported,
+                    "compressed files can only be written sequentially",
+                )
+                .with_operation(Operation::Seek)));
+            }
+            Mode::Write(_) => {}
+        }
+
+        Poll::Ready(Ok(target))
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        match &mut this.mode {
+            Mode::Write(writer) => writer.poll_write(&mut this.file, cx, buf),
+            Mode::Read(_) => Poll::Ready(Err(Error::new_const(
+                ErrorKind::PermissionDenied,
+                "the file is not opened for writing",
+            )
+            .with_operation(Operation::Write))),
+        }
+    }
+
+    /// Writes the compressed output produced so far. Frames are only
+    /// completed once they are full or the file is closed
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        if let Mode::Write(writer) = &mut this.mode {
+            ready!(writer.poll_drain(&mut this.file, cx))?;
+        }
+        this.file.as_mut().poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        match &mut this.mode {
+            Mode::Write(writer) => writer.poll_close(&mut this.file, cx),
+            Mode::Read(_) => this.file.as_mut().poll_close(cx),
+        }
+    }
+}
+
+fn write_only(operation: Operation) -> Error {
+    Error::new_const(
+        ErrorKind::PermissionDenied,
+        "the file is not opened for reading",
+    )
+    .with_operation(operation)
+}
+
+pub(crate) fn not_compressed() -> Error {
+    Error::new_const(
+        ErrorKind::InvalidData,
+        "not a compressed file, or one which was not closed after writing",
+    )
+}
+
+fn corrupt() -> Error {
+    Error::new_const(ErrorKind::InvalidData, "compressed file is corrupt")
+}
@@ -0,0 +1,365 @@
+//! A [`VFS`] which stores its files compressed in another filesystem.
+//!
+//! Files are compressed with gzip or zstd, each behind the cargo feature of the
+//! same name, and carry a small trailer with their uncompressed size and a table of
+//! their compressed frames. Directories and all other properties are those of the
+//! inner filesystem.
+mod codec;
+mod file;
+
+pub use self::{codec::Codec, file::CompressedFile};
+
+use futures::{StreamExt, TryStreamExt};
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use vfs::{
+    Capabilities, DirEntry, Error, ErrorKind, FileTimes, Metadata, OpenOptions, Operation,
+    Permissions, SendVFS, VFS, VPath, VPathStr, WatchEvent,
+};
+
+use self::file::{Footer, Options};
+
+/// A filesystem which compresses files written to it and decompresses them when read.
+///
+/// By default each file is compressed as a single frame, so seeking backwards
+/// decompresses the file from its start again. Use [`seekable`](CompressedFS::seekable)
+/// to split files into frames which can be decompressed on their own.
+///
+/// Files can be read, or written from scratch by opening them with
+/// [`truncate`](OpenOptions::truncate). Appending and opening for both reading
+/// and writing are not supported.
+pub struct CompressedFS<F> {
+    fs: F,
+    options: Options,
+}
+
+impl<F: Clone> Clone for CompressedFS<F> {
+    fn clone(&self) -> Self {
+        CompressedFS {
+            fs: self.fs.clone(),
+            options: self.options,
+        }
+    }
+}
+
+impl<F: core::fmt::Debug> core::fmt::Debug for CompressedFS<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CompressedFS")
+            .field("fs", &self.fs)
+            .field("codec", &self.options.codec)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: SendVFS> CompressedFS<F> {
+    /// Store the files of `fs` compressed with `codec`
+    pub fn new(fs: F, codec: Codec) -> CompressedFS<F> {
+        CompressedFS {
+            fs,
+            options: Options {
+                codec,
+                level: None,
+                frame_size: None,
+            },
+        }
+    }
+
+    /// The compression level of written files, from 0 to 9 for gzip and from 1 to 22 for zstd.
+    /// Defaults to the default level of the codec
+    pub fn level(mut self, level: i32) -> Self {
+        self.options.level = Some(level);
+        self
+    }
+
+    /// Compress written files in frames of `frame_size` uncompressed bytes, so reads
+    /// after a seek only decompress the frame containing the new position.
+    /// Smaller frames make seeking cheaper at the cost of compressing worse
+    pub fn seekable(mut self, frame_size: usize) -> Self {
+        self.options.frame_size = Some(frame_size.max(1) as u64);
+        self
+    }
+
+    /// The wrapped filesystem
+    pub fn get_ref(&self) -> &F {
+        &self.fs
+    }
+}
+
+impl<F: SendVFS> VFS for CompressedFS<F> {
+    type Path = CompressedPath<F>;
+
+    type Stats = F::Stats;
+
+    fn path(&self, path: impl AsRef<VPathStr>) -> Result<Self::Path, Error> {
+        Ok(CompressedPath::new(self.options, self.fs.path(path)?))
+    }
+
+    /// The space used on the inner filesystem, which is the compressed size of the files
+    fn stats(&self) -> Self::Stats {
+        self.fs.stats()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            seek: true,
+            ..self.fs.capabilities()
+        }
+    }
+}
+
+/// A path of a [`CompressedFS`]
+pub struct CompressedPath<F: VFS> {
+    options: Options,
+    inner: F::Path,
+}
+
+impl<F: VFS> Clone for CompressedPath<F>
+where
+    F::Path: Clone,
+{
+    fn clone(&self) -> Self {
+        CompressedPath {
+            options: self.options,
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<F: VFS> core::fmt::Debug for CompressedPath<F>
+where
+    F::Path: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("CompressedPath").field(&self.inner).finish()
+    }
+}
+
+impl<F: VFS> PartialEq for CompressedPath<F>
+where
+    F::Path: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<F: VFS> Eq for CompressedPath<F> where F::Path: Eq {}
+
+impl<F: VFS> core::hash::Hash for CompressedPath<F>
+where
+    F::Path: core::hash::Hash,
+{
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.inner.hash(state)
+    }
+}
+
+impl<F: SendVFS> CompressedPath<F> {
+    fn new(options: Options, inner: F::Path) -> CompressedPath<F> {
+        CompressedPath { options, inner }
+    }
+
+    fn wrap(&self, inner: F::Path) -> CompressedPath<F> {
+        CompressedPath::new(self.options, inner)
+    }
+
+    /// The path of the inner filesystem
+    pub fn get_ref(&self) -> &F::Path {
+        &self.inner
+    }
+
+    fn context(&self, err: Error, operation: Operation) -> Error {
+        err.with_operation(operation)
+            .with_path(self.inner.virtual_path())
+    }
+}
+
+impl<F: SendVFS> VPath for CompressedPath<F> {
+    type FS = CompressedFS<F>;
+
+    type File = CompressedFile<<F::Path as VPath>::File>;
+
+    type ListDir = BoxStream<'static, Result<DirEntry<Self>, Error>>;
+
+    type Metadata = BoxFuture<'static, Result<Metadata, Error>>;
+
+    type Open = BoxFuture<'static, Result<Self::File, Error>>;
+
+    type CreateDir = <F::Path as VPath>::CreateDir;
+
+    type Remove = <F::Path as VPath>::Remove;
+
+    type ReadDir = BoxFuture<'static, Result<Self::ListDir, Error>>;
+
+    type SetPermissions = <F::Path as VPath>::SetPermissions;
+
+    type SetTimes = <F::Path as VPath>::SetTimes;
+
+    type SetOwner = <F::Path as VPath>::SetOwner;
+
+    type GetXattr = <F::Path as VPath>::GetXattr;
+
+    type SetXattr = <F::Path as VPath>::SetXattr;
+
+    type ListXattr = <F::Path as VPath>::ListXattr;
+
+    type RemoveXattr = <F::Path as VPath>::RemoveXattr;
+
+    type Watcher = BoxStream<'static, Result<WatchEvent<Self>, Error>>;
+
+    type Watch = BoxFuture<'static, Result<Self::Watcher, Error>>;
+
+    type Canonicalize = BoxFuture<'static, Result<Self, Error>>;
+
+    fn fs(&self) -> Self::FS {
+        CompressedFS {
+            fs: self.inner.fs(),
+            options: self.options,
+        }
+    }
+
+    fn virtual_path(&self) -> String {
+        self.inner.virtual_path()
+    }
+
+    fn to_string(&self) -> String {
+        self.inner.to_string()
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        self.inner.file_name()
+    }
+
+    fn extension(&self) -> Option<&str> {
+        self.inner.extension()
+    }
+
+    fn resolve(&self, path: &str) -> Result<Self, Error> {
+        Ok(self.wrap(self.inner.resolve(path)?))
+    }
+
+    fn parent(&self) -> Option<Self> {
+        Some(self.wrap(self.inner.parent()?))
+    }
+
+    fn canonicalize(&self) -> Self::Canonicalize {
+        let this = self.clone();
+        let future = self.inner.canonicalize();
+        Box::pin(async move { Ok(this.wrap(future.await?)) })
+    }
+
+    /// The size of files is their uncompressed size, read from the trailer of the file.
+    /// Files without a trailer fail with [`ErrorKind::InvalidData`], as they do when
+    /// opened. A hash known by the inner filesystem is that of the compressed
+    /// content, so it is left out
+    fn metadata(&self) -> Self::Metadata {
+        let this = self.clone();
+        Box::pin(async move {
+            let mut metadata = this.inner.metadata().await?;
+            metadata.hash = None;
+            if metadata.is_file() && metadata.size > 0 {
+                let file = this.inner.open(OpenOptions::new().read(true)).await?;
+                let mut file = core::pin::pin!(file);
+                let footer = Footer::read(&mut file, metadata.size)
+                    .await
+                    .map_err(|err| this.context(err, Operation::Metadata))?;
+                metadata.size = footer.map_or(0, |footer| footer.size);
+            }
+            Ok(metadata)
+        })
+    }
+
+    fn open(&self, options: OpenOptions) -> Self::Open {
+        let this = self.clone();
+        Box::pin(async move {
+            if options.append || options.read && options.write {
+                let err = Error::new_const(
+                    ErrorKind::Unsupported,
+                    "compressed files can only be opened for either reading or writing",
+                );
+                return Err(this.context(err, Operation::Open));
+            }
+
+            if options.write && !options.truncate {
+                let err = Error::new_const(
+                    ErrorKind::Unsupported,
+                    "compressed files can only be written after truncating them",
+                );
+                return Err(this.context(err, Operation::Open));
+            }
+
+            let file = this.inner.open(options).await?;
+            if options.write {
+                Ok(CompressedFile::writer(file, this.options))
+            } else {
+                CompressedFile::reader(file)
+                    .await
+                    .map_err(|err| this.context(err, Operation::Open))
+            }
+        })
+    }
+
+    fn read_dir(&self) -> Self::ReadDir {
+        let this = self.clone();
+        let future = self.inner.read_dir();
+        Box::pin(async move {
+            // Sizes known by the inner filesystem are compressed sizes,
+            // so only the file type of the entries is kept
+            let entries = future.await?.map_ok(move |entry| {
+                let kind = entry.file_type();
+                DirEntry::new(this.wrap(entry.into_path()), kind)
+            });
+            Ok(entries.boxed())
+        })
+    }
+
+    fn create_dir(&self) -> Self::CreateDir {
+        self.inner.create_dir()
+    }
+
+    fn rm(&self) -> Self::Remove {
+        self.inner.rm()
+    }
+
+    fn set_permissions(&self, permissions: Permissions) -> Self::SetPermissions {
+        self.inner.set_permissions(permissions)
+    }
+
+    fn set_times(&self, times: FileTimes) -> Self::SetTimes {
+        self.inner.set_times(times)
+    }
+
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> Self::SetOwner {
+        self.inner.set_owner(uid, gid)
+    }
+
+    fn supports_xattr(&self) -> bool {
+        self.inner.supports_xattr()
+    }
+
+    fn get_xattr(&self, name: &str) -> Self::GetXattr {
+        self.inner.get_xattr(name)
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8]) -> Self::SetXattr {
+        self.inner.set_xattr(name, value)
+    }
+
+    fn list_xattr(&self) -> Self::ListXattr {
+        self.inner.list_xattr()
+    }
+
+    fn remove_xattr(&self, name: &str) -> Self::RemoveXattr {
+        self.inner.remove_xattr(name)
+    }
+
+    fn watch(&self, recursive: bool) -> Self::Watch {
+        let this = self.clone();
+        let future = self.inner.watch(recursive);
+        Box::pin(async move {
+            let watcher = future
+                .await?
+                .map_ok(move |event| event.map(|path| this.wrap(path)));
+            Ok(watcher.boxed())
+        })
+    }
+}
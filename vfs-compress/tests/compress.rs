@@ -0,0 +1,116 @@
+use futures::executor::block_on;
+use vfs::{ErrorKind, OpenOptions, SeekFrom, VFS, VFileExt, VPath};
+use vfs_compress::{Codec, CompressedFS};
+use vfs_memory::MemoryFS;
+use vfs_test::{noise, pattern, read_file, write_file};
+
+fn round_trip(codec: Codec) {
+    block_on(async {
+        let inner = MemoryFS::new();
+        let fs = CompressedFS::new(inner.clone(), codec);
+        let data = pattern(300_000);
+
+        let path = fs.path("/file").unwrap();
+        write_file(&path, &data).await;
+
+        assert_eq!(read_file(&path).await, data);
+        assert_eq!(path.metadata().await.unwrap().size, data.len() as u64);
+
+        let stored = inner.path("/file").unwrap().metadata().await.unwrap();
+        assert!(stored.size < data.len() as u64);
+    })
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn round_trip_zstd() {
+    round_trip(Codec::Zstd);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn round_trip_gzip() {
+    round_trip(Codec::Gzip);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn seek_between_frames() {
+    block_on(async {
+        let fs = CompressedFS::new(MemoryFS::new(), Codec::Zstd).seekable(4096);
+        let data = pattern(50_000);
+
+        let path = fs.path("/file").unwrap();
+        write_file(&path, &data).await;
+
+        let mut file = path.open(OpenOptions::new().read(true)).await.unwrap();
+        for offset in [40_000, 5, 12_345, 49_990] {
+            file.seek(SeekFrom::Start(offset)).await.unwrap();
+            let mut buf = [0; 10];
+            file.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, data[offset as usize..offset as usize + 10]);
+        }
+    })
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn uncompressed_inner_files() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        let fs = CompressedFS::new(inner.clone(), Codec::Zstd);
+
+        // Shorter and longer than the trailer of a compressed file
+        for (name, len) in [("/short", 5), ("/long", 1000)] {
+            write_file(&inner.path(name).unwrap(), &pattern(len)).await;
+
+            let path = fs.path(name).unwrap();
+            let err = path.metadata().await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+            let err = path.open(OpenOptions::new().read(true)).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+
+        write_file(&inner.path("/empty").unwrap(), &[]).await;
+        let path = fs.path("/empty").unwrap();
+        assert_eq!(path.metadata().await.unwrap().size, 0);
+        assert!(read_file(&path).await.is_empty());
+    })
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn dropped_writer_is_detected() {
+    block_on(async {
+        let fs = CompressedFS::new(MemoryFS::new(), Codec::Zstd).seekable(1000);
+        let path = fs.path("/file").unwrap();
+        write_file(&path, &pattern(500)).await;
+
+        let options = OpenOptions::new().write(true).truncate(true);
+        let mut file = path.open(options).await.unwrap();
+        // Incompressible data, so some of the frames reach the inner file
+        file.write_all(&noise(200_000, 1)).await.unwrap();
+        drop(file);
+
+        let err = path.metadata().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = path.open(OpenOptions::new().read(true)).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    })
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn hash_of_inner_file_is_hidden() {
+    block_on(async {
+        let inner = vfs_cas::CasFS::new(MemoryFS::new()).await.unwrap();
+        let fs = CompressedFS::new(inner.clone(), Codec::Zstd);
+        write_file(&fs.path("/file").unwrap(), &pattern(1000)).await;
+
+        let stored = inner.path("/file").unwrap().metadata().await.unwrap();
+        assert!(stored.hash.is_some());
+        let metadata = fs.path("/file").unwrap().metadata().await.unwrap();
+        assert_eq!(metadata.hash, None);
+    })
+}
@@ -0,0 +1,615 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, ready},
+};
+
+use chacha20poly1305::{
+    AeadCore, AeadInPlace, Tag, XChaCha20Poly1305, XNonce,
+    aead::{OsRng, rand_core::RngCore},
+};
+use vfs::{Error, ErrorKind, OpenOptions, Operation, SeekFrom, VFile, VFileExt};
+
+/// Identifies the header of an encrypted file
+const MAGIC: [u8; 4] = *b"VFSE";
+
+const VERSION: u8 = 1;
+
+const NONCE_LEN: usize = 24;
+
+const TAG_LEN: usize = 16;
+
+/// The bytes a chunk takes on top of its content
+const OVERHEAD: u64 = (NONCE_LEN + TAG_LEN) as u64;
+
+/// Magic, version, three reserved bytes, chunk size and file id,
+/// followed by the sealed size of the file
+pub(crate) const HEADER_LEN: u64 = 28 + NONCE_LEN as u64 + 8 + TAG_LEN as u64;
+
+/// The plain part of the header, which is authenticated along with the size
+const PREFIX_LEN: usize = 28;
+
+pub(crate) const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+
+/// The header at the start of an encrypted file.
+///
+/// The header is followed by the chunks of the file, each consisting of a random
+/// nonce, the encrypted content and the authentication tag. Chunks are bound to
+/// the id of their file and their index, so they can not be swapped or moved
+/// between files, and the size sealed in the header prevents truncation.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Header {
+    chunk_size: u32,
+    file_id: [u8; 16],
+    pub size: u64,
+}
+
+impl Header {
+    fn new(chunk_size: u32) -> Header {
+        let mut file_id = [0; 16];
+        OsRng.fill_bytes(&mut file_id);
+        Header {
+            chunk_size,
+            file_id,
+            size: 0,
+        }
+    }
+
+    fn prefix(&self) -> [u8; PREFIX_LEN] {
+        let mut buf = [0; PREFIX_LEN];
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4] = VERSION;
+        buf[8..12].copy_from_slice(&self.chunk_size.to_le_bytes());
+        buf[12..28].copy_from_slice(&self.file_id);
+        buf
+    }
+
+    fn seal(&self, cipher: &XChaCha20Poly1305) -> Result<Vec<u8>, Error> {
+        let prefix = self.prefix();
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut size = self.size.to_le_bytes();
+        let tag = cipher
+            .encrypt_in_place_detached(&nonce, &prefix, &mut size)
+            .map_err(|_| encrypt_failed())?;
+
+        let mut buf = Vec::with_capacity(HEADER_LEN as usize);
+        buf.extend_from_slice(&prefix);
+        buf.extend_from_slice(&nonce);
+        buf.extend_from_slice(&size);
+        buf.extend_from_slice(&tag);
+        Ok(buf)
+    }
+
+    fn open(buf: &[u8; HEADER_LEN as usize], cipher: &XChaCha20Poly1305) -> Result<Header, Error> {
+        if buf[0..4] != MAGIC || buf[4] != VERSION {
+            return Err(not_encrypted());
+        }
+
+        let (prefix, rest) = buf.split_at(PREFIX_LEN);
+        let (nonce, rest) = rest.split_at(NONCE_LEN);
+        let (size, tag) = rest.split_at(8);
+        let mut size: [u8; 8] = size.try_into().unwrap();
+        cipher
+            .decrypt_in_place_detached(
+                XNonce::from_slice(nonce),
+                prefix,
+                &mut size,
+                Tag::from_slice(tag),
+            )
+            .map_err(|_| corrupt())?;
+
+        let chunk_size = u32::from_le_bytes(buf[8..12].try_into().unwrap());
+        if chunk_size == 0 {
+            return Err(corrupt());
+        }
+
+        Ok(Header {
+            chunk_size,
+            file_id: buf[12..28].try_into().unwrap(),
+            size: u64::from_le_bytes(size),
+        })
+    }
+
+    /// Read the header of a file, which has a length of `len` bytes.
+    /// An empty file is read as an empty encrypted file
+    pub async fn read<T: VFile + Unpin>(
+        file: &mut T,
+        len: u64,
+        cipher: &XChaCha20Poly1305,
+    ) -> Result<Option<Header>, Error> {
+        if len == 0 {
+            return Ok(None);
+        }
+
+        if len < HEADER_LEN {
+            return Err(not_encrypted());
+        }
+
+        let mut buf = [0; HEADER_LEN as usize];
+        file.seek(SeekFrom::Start(0)).await?;
+        file.read_exact(&mut buf).await?;
+        let header = Header::open(&buf, cipher)?;
+
+        // Chunks are not checked until they are read, but their total
+        // length has to match the size of the file
+        let chunks = header.size.div_ceil(header.chunk_size as u64);
+        let expected = chunks
+            .checked_mul(OVERHEAD)
+            .and_then(|overhead| overhead.checked_add(header.size + HEADER_LEN));
+        if expected != Some(len) {
+            return Err(corrupt());
+        }
+
+        Ok(Some(header))
+    }
+
+    fn chunk_size(&self) -> u64 {
+        self.chunk_size as u64
+    }
+
+    fn chunk_offset(&self, index: u64) -> u64 {
+        HEADER_LEN + index * (self.chunk_size() + OVERHEAD)
+    }
+
+    /// The associated data of a chunk
+    fn chunk_aad(&self, index: u64) -> [u8; 24] {
+        let mut aad = [0; 24];
+        aad[..16].copy_from_slice(&self.file_id);
+        aad[16..].copy_from_slice(&index.to_le_bytes());
+        aad
+    }
+}
+
+/// A chunk of the file, decrypted
+struct Chunk {
+    index: u64,
+    data: Vec<u8>,
+    /// Whether the chunk was written since it was stored
+    dirty: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum IoKind {
+    Load(u64),
+    Store(u64),
+    Header,
+}
+
+/// A read or write of the inner file, which is driven to completion
+/// before the file does anything else
+struct Io {
+    kind: IoKind,
+    offset: u64,
+    buf: Vec<u8>,
+    done: usize,
+    seeked: bool,
+}
+
+impl Io {
+    fn new(kind: IoKind, offset: u64, buf: Vec<u8>) -> Io {
+        Io {
+            kind,
+            offset,
+            buf,
+            done: 0,
+            seeked: false,
+        }
+    }
+
+    fn poll<T: VFile>(
+        &mut self,
+        file: &mut Pin<Box<T>>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Error>> {
+        if !self.seeked {
+            ready!(file.as_mut().poll_seek(cx, SeekFrom::Start(self.offset)))?;
+            self.seeked = true;
+        }
+
+        while self.done < self.buf.len() {
+            let n = match self.kind {
+                IoKind::Load(_) => ready!(file.as_mut().poll_read(cx, &mut self.buf[self.done..]))?,
+                IoKind::Store(_) | IoKind::Header => {
+                    ready!(file.as_mut().poll_write(cx, &self.buf[self.done..]))?
+                }
+            };
+
+            if n == 0 {
+                return Poll::Ready(Err(match self.kind {
+                    IoKind::Load(_) => corrupt(),
+                    _ => ErrorKind::WriteZero.into(),
+                }));
+            }
+            self.done += n;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A file of an [`EncryptedFS`](crate::EncryptedFS), which decrypts it while reading
+/// and encrypts it while writing.
+///
+/// The file is read and written a chunk at a time, so seeking only decrypts the
+/// chunk containing the new position. Written data is only stored in the inner
+/// file once a different chunk is accessed or the file is flushed or closed. The
+/// header is stored along with the chunk when the file grew, so a file dropped
+/// without flushing still holds everything stored up to then.
+pub struct EncryptedFile<T> {
+    file: Pin<Box<T>>,
+    cipher: Arc<XChaCha20Poly1305>,
+    header: Header,
+    header_dirty: bool,
+    read: bool,
+    write: bool,
+    append: bool,
+    pos: u64,
+    chunk: Option<Chunk>,
+    io: Option<Io>,
+}
+
+impl<T> core::fmt::Debug for EncryptedFile<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EncryptedFile")
+            .field("size", &self.header.size)
+            .field("pos", &self.pos)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: VFile> EncryptedFile<T> {
+    pub(crate) async fn open(
+        file: T,
+        cipher: Arc<XChaCha20Poly1305>,
+        options: OpenOptions,
+        chunk_size: u32,
+    ) -> Result<EncryptedFile<T>, Error> {
+        let mut file = Box::pin(file);
+        let len = file.seek(SeekFrom::End(0)).await?;
+        let header = Header::read(&mut file, len, &cipher).await?;
+        let write = options.write || options.append;
+
+        Ok(EncryptedFile {
+            file,
+            header: header.unwrap_or_else(|| Header::new(chunk_size)),
+            // A new file gets its header written even if nothing is written to it
+            header_dirty: header.is_none() && write,
+            cipher,
+            read: options.read,
+            write,
+            append: options.append,
+            pos: 0,
+            chunk: None,
+            io: None,
+        })
+    }
+
+    /// Finish the pending read or write of the inner file
+    fn poll_io(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let Some(io) = self.io.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+
+        let result = ready!(io.poll(&mut self.file, cx));
+        let io = self.io.take().unwrap();
+        result?;
+
+        match io.kind {
+            IoKind::Load(index) => {
+                let data = self.open_chunk(index, io.buf)?;
+                self.chunk = Some(Chunk {
+                    index,
+                    data,
+                    dirty: false,
+                });
+            }
+            IoKind::Store(index) => {
+                if let Some(chunk) = self.chunk.as_mut().filter(|chunk| chunk.index == index) {
+                    chunk.dirty = false;
+                }
+            }
+            IoKind::Header => self.header_dirty = false,
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn open_chunk(&self, index: u64, mut buf: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let tag = buf.split_off(buf.len() - TAG_LEN);
+        let mut data = buf.split_off(NONCE_LEN);
+        self.cipher
+            .decrypt_in_place_detached(
+                XNonce::from_slice(&buf),
+                &self.header.chunk_aad(index),
+                &mut data,
+                Tag::from_slice(&tag),
+            )
+            .map_err(|_| corrupt())?;
+        Ok(data)
+    }
+
+    /// Start storing the current chunk with a fresh nonce
+    fn store_chunk(&mut self) -> Result<(), Error> {
+        let chunk = self.chunk.as_ref().unwrap();
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        let mut buf = Vec::with_capacity(chunk.data.len() + OVERHEAD as usize);
+        buf.extend_from_slice(&nonce);
+        buf.extend_from_slice(&chunk.data);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(
+                &nonce,
+                &self.header.chunk_aad(chunk.index),
+                &mut buf[NONCE_LEN..],
+            )
+            .map_err(|_| encrypt_failed())?;
+        buf.extend_from_slice(&tag);
+
+        let offset = self.header.chunk_offset(chunk.index);
+        self.io = Some(Io::new(IoKind::Store(chunk.index), offset, buf));
+        Ok(())
+    }
+
+    /// Make the chunk with `index` the current chunk, storing the current one if it was written
+    fn poll_chunk(&mut self, cx: &mut Context<'_>, index: u64) -> Poll<Result<(), Error>> {
+        loop {
+            ready!(self.poll_io(cx))?;
+
+            match &self.chunk {
+                Some(chunk) if chunk.index == index => return Poll::Ready(Ok(())),
+                Some(chunk) if chunk.dirty => {
+                    self.store_chunk()?;
+                    continue;
+                }
+                // The stored chunks have to add up to the size sealed in the header,
+                // or the file can not be opened again if it is never flushed
+                _ if self.header_dirty => {
+                    let header = self.header.seal(&self.cipher)?;
+                    self.io = Some(Io::new(IoKind::Header, 0, header));
+                    continue;
+                }
+                _ => {}
+            }
+
+            // The file only grows at its end, so all chunks before the
+            // end are stored with their full size
+            let chunk_size = self.header.chunk_size();
+            let start = index * chunk_size;
+            if start >= self.header.size {
+                self.chunk = Some(Chunk {
+                    index,
+                    data: Vec::new(),
+                    dirty: false,
+                });
+                return Poll::Ready(Ok(()));
+            }
+
+            let len = chunk_size.min(self.header.size - start) + OVERHEAD;
+            let offset = self.header.chunk_offset(index);
+            self.io = Some(Io::new(IoKind::Load(index), offset, vec![0; len as usize]));
+        }
+    }
+
+    /// Store the written chunk and the header
+    fn poll_store(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        loop {
+            ready!(self.poll_io(cx))?;
+
+            if self.chunk.as_ref().is_some_and(|chunk| chunk.dirty) {
+                self.store_chunk()?;
+            } else if self.header_dirty {
+                let header = self.header.seal(&self.cipher)?;
+                self.io = Some(Io::new(IoKind::Header, 0, header));
+            } else {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    /// Write `buf` into the chunk containing the position, which is at most the size of the file
+    fn poll_write_chunk(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, Error>> {
+        let chunk_size = self.header.chunk_size();
+        let index = self.pos / chunk_size;
+        ready!(self.poll_chunk(cx, index))?;
+
+        let offset = (self.pos - index * chunk_size) as usize;
+        let len = buf.len().min(chunk_size as usize - offset);
+        let chunk = self.chunk.as_mut().unwrap();
+        if chunk.data.len() < offset + len {
+            chunk.data.resize(offset + len, 0);
+        }
+        chunk.data[offset..offset + len].copy_from_slice(&buf[..len]);
+        chunk.dirty = true;
+
+        self.pos += len as u64;
+        if self.pos > self.header.size {
+            self.header.size = self.pos;
+            self.header_dirty = true;
+        }
+
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl<T: VFile> VFile for EncryptedFile<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        if !this.read {
+            return Poll::Ready(Err(Error::new_const(
+                ErrorKind::PermissionDenied,
+                "the file is not opened for reading",
+            )
+            .with_operation(Operation::Read)));
+        }
+
+        if this.pos >= this.header.size || buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let chunk_size = this.header.chunk_size();
+        let index = this.pos / chunk_size;
+        ready!(this.poll_chunk(cx, index))?;
+
+        let chunk = this.chunk.as_ref().unwrap();
+        let offset = (this.pos - index * chunk_size) as usize;
+        let data = chunk.data.get(offset..).unwrap_or_default();
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        this.pos += len as u64;
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        let this = self.get_mut();
+        let target = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(offset) => this.pos.checked_add_signed(offset),
+            SeekFrom::End(offset) => this.header.size.checked_add_signed(offset),
+        };
+
+        let Some(target) = target else {
+            return Poll::Ready(Err(Error::new_const(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+            .with_operation(Operation::Seek)));
+        };
+
+        this.pos = target;
+        Poll::Ready(Ok(target))
+    }
+
+    /// Writing past the end of the file fills the gap with zeros
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        if !this.write {
+            return Poll::Ready(Err(Error::new_const(
+                ErrorKind::PermissionDenied,
+                "the file is not opened for writing",
+            )
+            .with_operation(Operation::Write)));
+        }
+
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        if this.append {
+            this.pos = this.header.size;
+        }
+
+        // Extend the file with zeros up to the position first
+        let chunk_size = this.header.chunk_size();
+        while this.pos > this.header.size {
+            let index = this.header.size / chunk_size;
+            ready!(this.poll_chunk(cx, index))?;
+
+            let end = (this.pos - index * chunk_size).min(chunk_size);
+            let chunk = this.chunk.as_mut().unwrap();
+            chunk.data.resize(end as usize, 0);
+            chunk.dirty = true;
+            this.header.size = index * chunk_size + end;
+            this.header_dirty = true;
+        }
+
+        this.poll_write_chunk(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_store(cx))?;
+        this.file.as_mut().poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_store(cx))?;
+        this.file.as_mut().poll_close(cx)
+    }
+}
+
+/// Authenticated along with extended attribute values, followed by the name
+/// of the attribute, so values can not be moved between attributes
+const XATTR_AAD: &[u8] = b"VFSE xattr ";
+
+fn xattr_aad(name: &str) -> Vec<u8> {
+    [XATTR_AAD, name.as_bytes()].concat()
+}
+
+/// Seal the value of the extended attribute `name` as a random nonce, the
+/// encrypted value and the authentication tag
+pub(crate) fn seal_xattr(
+    cipher: &XChaCha20Poly1305,
+    name: &str,
+    value: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut data = value.to_vec();
+    let tag = cipher
+        .encrypt_in_place_detached(&nonce, &xattr_aad(name), &mut data)
+        .map_err(|_| encrypt_failed())?;
+
+    let mut buf = Vec::with_capacity(value.len() + OVERHEAD as usize);
+    buf.extend_from_slice(&nonce);
+    buf.extend_from_slice(&data);
+    buf.extend_from_slice(&tag);
+    Ok(buf)
+}
+
+/// Open the value of the extended attribute `name` sealed by [`seal_xattr`]
+pub(crate) fn open_xattr(
+    cipher: &XChaCha20Poly1305,
+    name: &str,
+    mut buf: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    if buf.len() < OVERHEAD as usize {
+        return Err(corrupt_xattr());
+    }
+
+    let tag = buf.split_off(buf.len() - TAG_LEN);
+    let mut data = buf.split_off(NONCE_LEN);
+    cipher
+        .decrypt_in_place_detached(
+            XNonce::from_slice(&buf),
+            &xattr_aad(name),
+            &mut data,
+            Tag::from_slice(&tag),
+        )
+        .map_err(|_| corrupt_xattr())?;
+    Ok(data)
+}
+
+pub(crate) fn not_encrypted() -> Error {
+    Error::new_const(ErrorKind::InvalidData, "not an encrypted file")
+}
+
+fn corrupt() -> Error {
+    Error::new_const(
+        ErrorKind::InvalidData,
+        "encrypted file is corrupt or was encrypted with another key",
+    )
+}
+
+fn corrupt_xattr() -> Error {
+    Error::new_const(
+        ErrorKind::InvalidData,
+        "encrypted attribute is corrupt or was encrypted with another key",
+    )
+}
+
+fn encrypt_failed() -> Error {
+    Error::new_const(ErrorKind::Other, "failed to encrypt file")
+}
@@ -0,0 +1,457 @@
+//! A [`VFS`] which stores its files encrypted in another filesystem.
+//!
+//! File contents are split into chunks which are encrypted and authenticated with
+//! XChaCha20-Poly1305, so files can be read and written at random positions. File
+//! and directory names can be encrypted as well. The values of extended attributes
+//! are sealed with the key of the contents, but their names are not. Directories
+//! and all other properties, such as permissions and times, are stored by the
+//! inner filesystem unencrypted.
+mod file;
+mod name;
+
+pub use self::file::EncryptedFile;
+
+use std::sync::Arc;
+
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305};
+use futures::{StreamExt, TryStreamExt};
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use vfs::{
+    Capabilities, DirEntry, Error, FileTimes, Metadata, OpenOptions, Operation, Permissions,
+    SendVFS, VFS, VPath, VPathStr, WatchEvent,
+};
+
+use self::{
+    file::{DEFAULT_CHUNK_SIZE, Header, open_xattr, seal_xattr},
+    name::NameCipher,
+};
+
+#[derive(Clone)]
+struct Config {
+    content: Arc<XChaCha20Poly1305>,
+    names: Option<Arc<NameCipher>>,
+    chunk_size: u32,
+}
+
+/// A filesystem which encrypts files written to it and decrypts them when read.
+///
+/// The keys are 256 bit keys supplied by the caller, who is responsible for
+/// generating and keeping them. Files can only be read with the key they were
+/// written with.
+pub struct EncryptedFS<F> {
+    fs: F,
+    config: Config,
+}
+
+impl<F: Clone> Clone for EncryptedFS<F> {
+    fn clone(&self) -> Self {
+        EncryptedFS {
+            fs: self.fs.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<F: core::fmt::Debug> core::fmt::Debug for EncryptedFS<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EncryptedFS")
+            .field("fs", &self.fs)
+            .field("encrypt_names", &self.config.names.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: SendVFS> EncryptedFS<F> {
+    /// Store the files of `fs` with their contents encrypted with `key`
+    pub fn new(fs: F, key: [u8; 32]) -> EncryptedFS<F> {
+        EncryptedFS {
+            fs,
+            config: Config {
+                content: Arc::new(XChaCha20Poly1305::new(&key.into())),
+                names: None,
+                chunk_size: DEFAULT_CHUNK_SIZE,
+            },
+        }
+    }
+
+    /// Encrypt file and directory names with `key`, which should differ from the key
+    /// of the contents. Names which are not encrypted with the key are left out of
+    /// directory listings and watch events.
+    ///
+    /// The names are encrypted deterministically, so equal names are visible
+    /// as such, and longer names run into the name length limit of the inner
+    /// filesystem sooner
+    pub fn encrypt_names(mut self, key: [u8; 32]) -> Self {
+        self.config.names = Some(Arc::new(NameCipher::new(&key)));
+        self
+    }
+
+    /// The plain size of the chunks of files created from now on. Defaults to 64 KiB.
+    /// Each chunk takes 40 bytes more in the inner filesystem
+    pub fn chunk_size(mut self, size: u32) -> Self {
+        self.config.chunk_size = size.max(1);
+        self
+    }
+
+    /// The wrapped filesystem
+    pub fn get_ref(&self) -> &F {
+        &self.fs
+    }
+}
+
+impl<F: SendVFS> VFS for EncryptedFS<F> {
+    type Path = EncryptedPath<F>;
+
+    type Stats = F::Stats;
+
+    fn path(&self, path: impl AsRef<VPathStr>) -> Result<Self::Path, Error> {
+        let inner = match &self.config.names {
+            Some(names) => self.fs.path(names.encrypt_path(path.as_ref()))?,
+            None => self.fs.path(path)?,
+        };
+        EncryptedPath::new(self.config.clone(), inner)
+    }
+
+    fn stats(&self) -> Self::Stats {
+        self.fs.stats()
+    }
+
+    /// Encrypted names are longer than the plain names, so the name length
+    /// limit is that of the longest name which fits the inner filesystem
+    fn capabilities(&self) -> Capabilities {
+        let inner = self.fs.capabilities();
+        let max_name_len = match &self.config.names {
+            Some(_) => inner.max_name_len.map(NameCipher::max_plain_len),
+            None => inner.max_name_len,
+        };
+
+        Capabilities {
+            seek: true,
+            max_name_len,
+            ..inner
+        }
+    }
+}
+
+/// A path of an [`EncryptedFS`]
+pub struct EncryptedPath<F: VFS> {
+    config: Config,
+    /// The decrypted path, if names are encrypted
+    plain: Option<String>,
+    inner: F::Path,
+}
+
+impl<F: VFS> Clone for EncryptedPath<F>
+where
+    F::Path: Clone,
+{
+    fn clone(&self) -> Self {
+        EncryptedPath {
+            config: self.config.clone(),
+            plain: self.plain.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<F: VFS> core::fmt::Debug for EncryptedPath<F>
+where
+    F::Path: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self.plain {
+            Some(plain) => f.debug_tuple("EncryptedPath").field(plain).finish(),
+            None => f.debug_tuple("EncryptedPath").field(&self.inner).finish(),
+        }
+    }
+}
+
+impl<F: VFS> PartialEq for EncryptedPath<F>
+where
+    F::Path: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<F: VFS> Eq for EncryptedPath<F> where F::Path: Eq {}
+
+impl<F: VFS> core::hash::Hash for EncryptedPath<F>
+where
+    F::Path: core::hash::Hash,
+{
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.inner.hash(state)
+    }
+}
+
+impl<F: SendVFS> EncryptedPath<F> {
+    fn new(config: Config, inner: F::Path) -> Result<EncryptedPath<F>, Error> {
+        let plain = match &config.names {
+            Some(names) => {
+                let path = names.decrypt_path(VPathStr::new(&inner.virtual_path()))?;
+                Some(path.into_string())
+            }
+            None => None,
+        };
+        Ok(EncryptedPath {
+            config,
+            plain,
+            inner,
+        })
+    }
+
+    fn wrap(&self, inner: F::Path) -> Result<EncryptedPath<F>, Error> {
+        EncryptedPath::new(self.config.clone(), inner)
+    }
+
+    /// The path of the inner filesystem
+    pub fn get_ref(&self) -> &F::Path {
+        &self.inner
+    }
+
+    fn context(&self, err: Error, operation: Operation) -> Error {
+        err.with_operation(operation).with_path(self.virtual_path())
+    }
+}
+
+/// Decrypt the paths of a watch event, or `None` if one of them is not encrypted
+fn decrypt_event<F: SendVFS>(
+    this: &EncryptedPath<F>,
+    event: WatchEvent<F::Path>,
+) -> Option<WatchEvent<EncryptedPath<F>>> {
+    Some(match event {
+        WatchEvent::Create(path) => WatchEvent::Create(this.wrap(path).ok()?),
+        WatchEvent::Modify(path) => WatchEvent::Modify(this.wrap(path).ok()?),
+        WatchEvent::Remove(path) => WatchEvent::Remove(this.wrap(path).ok()?),
+        WatchEvent::Rename { from, to } => WatchEvent::Rename {
+            from: this.wrap(from).ok()?,
+            to: this.wrap(to).ok()?,
+        },
+    })
+}
+
+impl<F: SendVFS> VPath for EncryptedPath<F> {
+    type FS = EncryptedFS<F>;
+
+    type File = EncryptedFile<<F::Path as VPath>::File>;
+
+    type ListDir = BoxStream<'static, Result<DirEntry<Self>, Error>>;
+
+    type Metadata = BoxFuture<'static, Result<Metadata, Error>>;
+
+    type Open = BoxFuture<'static, Result<Self::File, Error>>;
+
+    type CreateDir = <F::Path as VPath>::CreateDir;
+
+    type Remove = <F::Path as VPath>::Remove;
+
+    type ReadDir = BoxFuture<'static, Result<Self::ListDir, Error>>;
+
+    type SetPermissions = <F::Path as VPath>::SetPermissions;
+
+    type SetTimes = <F::Path as VPath>::SetTimes;
+
+    type SetOwner = <F::Path as VPath>::SetOwner;
+
+    type GetXattr = BoxFuture<'static, Result<Option<Vec<u8>>, Error>>;
+
+    type SetXattr = BoxFuture<'static, Result<(), Error>>;
+
+    type ListXattr = <F::Path as VPath>::ListXattr;
+
+    type RemoveXattr = <F::Path as VPath>::RemoveXattr;
+
+    type Watcher = BoxStream<'static, Result<WatchEvent<Self>, Error>>;
+
+    type Watch = BoxFuture<'static, Result<Self::Watcher, Error>>;
+
+    type Canonicalize = BoxFuture<'static, Result<Self, Error>>;
+
+    fn fs(&self) -> Self::FS {
+        EncryptedFS {
+            fs: self.inner.fs(),
+            config: self.config.clone(),
+        }
+    }
+
+    fn virtual_path(&self) -> String {
+        match &self.plain {
+            Some(plain) => plain.clone(),
+            None => self.inner.virtual_path(),
+        }
+    }
+
+    /// The path in the inner filesystem, which contains the encrypted names
+    fn to_string(&self) -> String {
+        self.inner.to_string()
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        match &self.plain {
+            Some(plain) => VPathStr::new(plain).file_name(),
+            None => self.inner.file_name(),
+        }
+    }
+
+    fn extension(&self) -> Option<&str> {
+        match &self.plain {
+            Some(plain) => VPathStr::new(plain).extension(),
+            None => self.inner.extension(),
+        }
+    }
+
+    fn resolve(&self, path: &str) -> Result<Self, Error> {
+        let inner = match &self.config.names {
+            Some(names) => self
+                .inner
+                .resolve(names.encrypt_path(VPathStr::new(path)).as_str())?,
+            None => self.inner.resolve(path)?,
+        };
+        self.wrap(inner)
+    }
+
+    fn parent(&self) -> Option<Self> {
+        self.wrap(self.inner.parent()?).ok()
+    }
+
+    fn canonicalize(&self) -> Self::Canonicalize {
+        let this = self.clone();
+        let future = self.inner.canonicalize();
+        Box::pin(async move {
+            let path = future.await?;
+            this.wrap(path)
+                .map_err(|err| this.context(err, Operation::Canonicalize))
+        })
+    }
+
+    /// The size of files is their plain size, read from the header of the file.
+    /// A hash known by the inner filesystem is that of the encrypted content,
+    /// so it is left out
+    fn metadata(&self) -> Self::Metadata {
+        let this = self.clone();
+        Box::pin(async move {
+            let mut metadata = this.inner.metadata().await?;
+            metadata.hash = None;
+            if metadata.is_file() {
+                let file = this.inner.open(OpenOptions::new().read(true)).await?;
+                let mut file = core::pin::pin!(file);
+                let header = Header::read(&mut file, metadata.size, &this.config.content)
+                    .await
+                    .map_err(|err| this.context(err, Operation::Metadata))?;
+                metadata.size = header.map_or(0, |header| header.size);
+            }
+            Ok(metadata)
+        })
+    }
+
+    /// Files are always opened for reading in the inner filesystem, as writes
+    /// have to read the chunks they change
+    fn open(&self, options: OpenOptions) -> Self::Open {
+        let this = self.clone();
+        Box::pin(async move {
+            let write = options.write || options.append;
+            let inner = OpenOptions {
+                read: true,
+                write,
+                append: false,
+                ..options
+            };
+
+            let file = this.inner.open(inner).await?;
+            EncryptedFile::open(
+                file,
+                this.config.content.clone(),
+                options,
+                this.config.chunk_size,
+            )
+            .await
+            .map_err(|err| this.context(err, Operation::Open))
+        })
+    }
+
+    /// Entries whose names are not encrypted with the key of the filesystem are skipped
+    fn read_dir(&self) -> Self::ReadDir {
+        let this = self.clone();
+        let future = self.inner.read_dir();
+        Box::pin(async move {
+            // Sizes known by the inner filesystem are encrypted sizes,
+            // so only the file type of the entries is kept
+            let entries = future.await?.try_filter_map(move |entry| {
+                let kind = entry.file_type();
+                let entry = this
+                    .wrap(entry.into_path())
+                    .ok()
+                    .map(|path| DirEntry::new(path, kind));
+                futures::future::ready(Ok(entry))
+            });
+            Ok(entries.boxed())
+        })
+    }
+
+    fn create_dir(&self) -> Self::CreateDir {
+        self.inner.create_dir()
+    }
+
+    fn rm(&self) -> Self::Remove {
+        self.inner.rm()
+    }
+
+    fn set_permissions(&self, permissions: Permissions) -> Self::SetPermissions {
+        self.inner.set_permissions(permissions)
+    }
+
+    fn set_times(&self, times: FileTimes) -> Self::SetTimes {
+        self.inner.set_times(times)
+    }
+
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> Self::SetOwner {
+        self.inner.set_owner(uid, gid)
+    }
+
+    fn supports_xattr(&self) -> bool {
+        self.inner.supports_xattr()
+    }
+
+    fn get_xattr(&self, name: &str) -> Self::GetXattr {
+        let this = self.clone();
+        let future = self.inner.get_xattr(name);
+        let name = name.to_string();
+        Box::pin(async move {
+            let Some(sealed) = future.await? else {
+                return Ok(None);
+            };
+            open_xattr(&this.config.content, &name, sealed)
+                .map(Some)
+                .map_err(|err| this.context(err, Operation::GetXattr))
+        })
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8]) -> Self::SetXattr {
+        let sealed = seal_xattr(&self.config.content, name, value)
+            .map_err(|err| self.context(err, Operation::SetXattr));
+        let this = self.clone();
+        let name = name.to_string();
+        Box::pin(async move { this.inner.set_xattr(&name, &sealed?).await })
+    }
+
+    fn list_xattr(&self) -> Self::ListXattr {
+        self.inner.list_xattr()
+    }
+
+    fn remove_xattr(&self, name: &str) -> Self::RemoveXattr {
+        self.inner.remove_xattr(name)
+    }
+
+    fn watch(&self, recursive: bool) -> Self::Watch {
+        let this = self.clone();
+        let future = self.inner.watch(recursive);
+        Box::pin(async move {
+            let watcher = future.await?.try_filter_map(move |event| {
+                futures::future::ready(Ok(decrypt_event(&this, event)))
+            });
+            Ok(watcher.boxed())
+        })
+    }
+}
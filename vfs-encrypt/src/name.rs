@@ -0,0 +1,73 @@
+use aes_gcm_siv::{Aes256GcmSiv, KeyInit, Nonce, aead::Aead};
+use data_encoding::BASE32HEX_NOPAD;
+use vfs::{Component, Error, ErrorKind, VPathBuf, VPathStr};
+
+/// Encrypts file names deterministically, so a name always maps to the same
+/// encrypted name and paths can be looked up without listing directories.
+///
+/// AES-GCM-SIV stays secure when a nonce is reused, apart from revealing which names
+/// are equal, so all names are encrypted with the same nonce. Encrypted names are
+/// encoded as lowercase base32, which survives case insensitive filesystems.
+pub(crate) struct NameCipher(Aes256GcmSiv);
+
+/// The bytes the authentication tag adds to an encrypted name, before encoding
+const TAG_LEN: usize = 16;
+
+impl NameCipher {
+    pub fn new(key: &[u8; 32]) -> NameCipher {
+        NameCipher(Aes256GcmSiv::new(key.into()))
+    }
+
+    pub fn encrypt(&self, name: &str) -> String {
+        let encrypted = self
+            .0
+            .encrypt(&Nonce::default(), name.as_bytes())
+            .expect("file names are far below the size limit of AES-GCM-SIV");
+        BASE32HEX_NOPAD.encode(&encrypted).to_ascii_lowercase()
+    }
+
+    pub fn decrypt(&self, name: &str) -> Result<String, Error> {
+        let encrypted = BASE32HEX_NOPAD
+            .decode(name.to_ascii_uppercase().as_bytes())
+            .map_err(|_| not_encrypted(name))?;
+        let name = self
+            .0
+            .decrypt(&Nonce::default(), encrypted.as_slice())
+            .map_err(|_| not_encrypted(name))?;
+        String::from_utf8(name).map_err(|_| {
+            Error::new_const(ErrorKind::InvalidData, "decrypted file name is not UTF-8")
+        })
+    }
+
+    /// The longest name which encrypts to a name of at most `len` bytes
+    pub fn max_plain_len(len: usize) -> usize {
+        // Base32 takes 8 characters for every 5 bytes
+        (len * 5 / 8).saturating_sub(TAG_LEN)
+    }
+
+    /// Encrypt the names in `path`, keeping the root and `.` and `..` components
+    pub fn encrypt_path(&self, path: &VPathStr) -> VPathBuf {
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) => self.encrypt(name),
+                component => component.as_str().to_string(),
+            })
+            .collect()
+    }
+
+    pub fn decrypt_path(&self, path: &VPathStr) -> Result<VPathBuf, Error> {
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) => self.decrypt(name),
+                component => Ok(component.as_str().to_string()),
+            })
+            .collect()
+    }
+}
+
+fn not_encrypted(name: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidFilename,
+        format!("'{name}' is not an encrypted file name"),
+    )
+}
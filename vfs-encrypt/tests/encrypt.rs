@@ -0,0 +1,179 @@
+use vfs::{ErrorKind, OpenOptions, SeekFrom, VFS, VFileExt, VPath};
+use vfs_encrypt::EncryptedFS;
+use vfs_memory::MemoryFS;
+use vfs_test::{TempDir, pattern, read_file, try_read_file, write_file};
+use vfs_tokio::FS;
+
+const KEY: [u8; 32] = [7; 32];
+
+const CHUNK: usize = 1000;
+
+async fn mount(dir: &TempDir) -> EncryptedFS<FS> {
+    let fs = FS::new(dir.path().to_path_buf()).await.unwrap();
+    EncryptedFS::new(fs, KEY).chunk_size(CHUNK as u32)
+}
+
+#[tokio::test]
+async fn round_trip() {
+    let dir = TempDir::new("encrypt-round-trip");
+    let fs = mount(&dir).await;
+    let data = pattern(CHUNK * 3 + 123);
+
+    let path = fs.path("/file").unwrap();
+    write_file(&path, &data).await;
+    assert_eq!(read_file(&path).await, data);
+    assert_eq!(path.metadata().await.unwrap().size, data.len() as u64);
+
+    let stored = std::fs::read(dir.path().join("file")).unwrap();
+    assert!(!stored.windows(64).any(|window| window == &data[..64]));
+
+    let other = EncryptedFS::new(FS::new(dir.path().to_path_buf()).await.unwrap(), [8; 32]);
+    let err = try_read_file(&other.path("/file").unwrap())
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn tampering_is_detected() {
+    let dir = TempDir::new("encrypt-tamper");
+    let fs = mount(&dir).await;
+    let path = fs.path("/file").unwrap();
+    write_file(&path, &pattern(CHUNK * 2)).await;
+    let stored = std::fs::read(dir.path().join("file")).unwrap();
+
+    // A flipped bit in the header, in a chunk and a truncated file
+    for (offset, truncate) in [(40, false), (stored.len() - 100, false), (0, true)] {
+        let mut changed = stored.clone();
+        if truncate {
+            changed.truncate(stored.len() - 1);
+        } else {
+            changed[offset] ^= 1;
+        }
+        std::fs::write(dir.path().join("file"), &changed).unwrap();
+
+        let err = try_read_file(&path).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
+
+#[tokio::test]
+async fn seek_and_overwrite() {
+    let dir = TempDir::new("encrypt-seek");
+    let fs = mount(&dir).await;
+    let mut data = pattern(CHUNK * 4);
+
+    let path = fs.path("/file").unwrap();
+    write_file(&path, &data).await;
+
+    let options = OpenOptions::new().read(true).write(true);
+    let mut file = path.open(options).await.unwrap();
+    for offset in [CHUNK * 3 + 10, 5, CHUNK - 2] {
+        file.seek(SeekFrom::Start(offset as u64)).await.unwrap();
+        let mut buf = [0; 4];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, data[offset..offset + 4]);
+
+        file.seek(SeekFrom::Start(offset as u64)).await.unwrap();
+        file.write_all(b"abcd").await.unwrap();
+        data[offset..offset + 4].copy_from_slice(b"abcd");
+    }
+
+    // Past the end, which fills the gap with zeros
+    file.seek(SeekFrom::End(CHUNK as i64 + 1)).await.unwrap();
+    file.write_all(b"end").await.unwrap();
+    data.resize(data.len() + CHUNK + 1, 0);
+    data.extend_from_slice(b"end");
+
+    file.close().await.unwrap();
+    assert_eq!(read_file(&path).await, data);
+}
+
+#[tokio::test]
+async fn dropped_writer_leaves_readable_file() {
+    let dir = TempDir::new("encrypt-dropped");
+    let fs = mount(&dir).await;
+    let data = pattern(CHUNK * 2 + CHUNK / 2);
+
+    let path = fs.path("/file").unwrap();
+    let options = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true);
+    let mut file = path.open(options).await.unwrap();
+    file.write_all(&data).await.unwrap();
+
+    // Reading the first chunk stores the last one, then the file is never flushed
+    file.seek(SeekFrom::Start(0)).await.unwrap();
+    let mut buf = [0; 1];
+    file.read_exact(&mut buf).await.unwrap();
+    drop(file);
+
+    assert_eq!(read_file(&path).await, data);
+}
+
+#[tokio::test]
+async fn encrypted_names() {
+    let dir = TempDir::new("encrypt-names");
+    let fs = mount(&dir).await.encrypt_names([9; 32]);
+
+    let Some(max) = fs.capabilities().max_name_len else {
+        return;
+    };
+    let inner = FS::new(dir.path().to_path_buf()).await.unwrap();
+    assert!(max < inner.capabilities().max_name_len.unwrap());
+
+    let longest = "n".repeat(max);
+    write_file(&fs.path(format!("/{longest}")).unwrap(), b"data").await;
+    assert_eq!(
+        read_file(&fs.path(format!("/{longest}")).unwrap()).await,
+        b"data"
+    );
+
+    let name = std::fs::read_dir(dir.path())
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_ne!(name.file_name().to_str().unwrap(), longest);
+
+    let options = OpenOptions::new().write(true).create(true);
+    let too_long = fs.path(format!("/{longest}n")).unwrap();
+    assert!(too_long.open(options).await.is_err());
+}
+
+#[tokio::test]
+async fn sealed_xattr() {
+    let inner = MemoryFS::new();
+    let fs = EncryptedFS::new(inner.clone(), KEY);
+    let path = fs.path("/file").unwrap();
+    write_file(&path, b"data").await;
+
+    path.set_xattr("user.tag", b"secret value").await.unwrap();
+    path.set_xattr("user.other", b"other").await.unwrap();
+    assert_eq!(
+        path.get_xattr("user.tag").await.unwrap().unwrap(),
+        b"secret value"
+    );
+    assert!(path.get_xattr("user.missing").await.unwrap().is_none());
+
+    let stored = inner.path("/file").unwrap();
+    let sealed = stored.get_xattr("user.tag").await.unwrap().unwrap();
+    assert!(!sealed.windows(6).any(|window| window == b"secret"));
+
+    // Values are bound to their name and key
+    let other = stored.get_xattr("user.other").await.unwrap().unwrap();
+    stored.set_xattr("user.tag", &other).await.unwrap();
+    let err = path.get_xattr("user.tag").await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let other = EncryptedFS::new(inner, [8; 32]);
+    let err = other
+        .path("/file")
+        .unwrap()
+        .get_xattr("user.other")
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
@@ -0,0 +1,644 @@
+use alloc::{string::String, vec, vec::Vec};
+
+use vfs::{Error, ErrorKind, Timestamp, VFile};
+
+use crate::{inner::Inner, time};
+
+pub(crate) const ATTR_READ_ONLY: u8 = 0x01;
+pub(crate) const ATTR_VOLUME_ID: u8 = 0x08;
+pub(crate) const ATTR_DIRECTORY: u8 = 0x10;
+pub(crate) const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+const ENTRY_SIZE: usize = 32;
+const DELETED: u8 = 0xE5;
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+/// Long names are limited to 255 UTF-16 units, which take 20 slots
+const MAX_LFN_SLOTS: usize = 20;
+
+/// Byte offsets of the UCS-2 characters within a long name entry
+const LFN_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// A directory has at most 65536 entries
+const MAX_ENTRIES: usize = 65536;
+
+/// Where the entries of a directory are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DirLoc {
+    /// The fixed root directory region of FAT12 and FAT16
+    Root,
+    /// A directory in the data region, starting at the given cluster
+    Cluster(u32),
+}
+
+/// The raw entries of a directory, read into memory
+pub(crate) struct Dir {
+    pub loc: DirLoc,
+    clusters: Vec<u32>,
+    data: Vec<u8>,
+}
+
+/// A parsed short directory entry along with its long name
+#[derive(Debug, Clone)]
+pub(crate) struct Entry {
+    pub name: String,
+    pub raw: [u8; ENTRY_SIZE],
+    /// Device offset of the short entry
+    pub offset: u64,
+    /// Slot index of the first long name entry, or the short entry
+    first_slot: usize,
+    slot: usize,
+}
+
+impl Entry {
+    pub fn attr(&self) -> u8 {
+        self.raw[11]
+    }
+
+    pub fn set_attr(&mut self, attr: u8) {
+        self.raw[11] = attr;
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attr() & ATTR_DIRECTORY != 0
+    }
+
+    pub fn readonly(&self) -> bool {
+        self.attr() & ATTR_READ_ONLY != 0
+    }
+
+    pub fn first_cluster(&self) -> u32 {
+        let hi = u16::from_le_bytes([self.raw[20], self.raw[21]]) as u32;
+        let lo = u16::from_le_bytes([self.raw[26], self.raw[27]]) as u32;
+        (hi << 16) | lo
+    }
+
+    pub fn set_first_cluster(&mut self, cluster: u32) {
+        self.raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        self.raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    }
+
+    pub fn size(&self) -> u32 {
+        u32::from_le_bytes([self.raw[28], self.raw[29], self.raw[30], self.raw[31]])
+    }
+
+    pub fn set_size(&mut self, size: u32) {
+        self.raw[28..32].copy_from_slice(&size.to_le_bytes());
+    }
+
+    /// The location of the entries if this is a directory
+    pub fn dir_loc(&self, root: DirLoc) -> DirLoc {
+        match self.first_cluster() {
+            // ".." entries refer to the root directory with cluster 0
+            0 => root,
+            cluster => DirLoc::Cluster(cluster),
+        }
+    }
+
+    pub fn created(&self) -> Option<Timestamp> {
+        time::decode(self.u16_at(16), self.u16_at(14), self.raw[13])
+    }
+
+    pub fn accessed(&self) -> Option<Timestamp> {
+        time::decode(self.u16_at(18), 0, 0)
+    }
+
+    pub fn modified(&self) -> Option<Timestamp> {
+        time::decode(self.u16_at(24), self.u16_at(22), 0)
+    }
+
+    pub fn set_created(&mut self, timestamp: Timestamp) {
+        let (date, time, tenths) = time::encode(timestamp);
+        self.raw[13] = tenths;
+        self.raw[14..16].copy_from_slice(&time.to_le_bytes());
+        self.raw[16..18].copy_from_slice(&date.to_le_bytes());
+    }
+
+    pub fn set_accessed(&mut self, timestamp: Timestamp) {
+        let (date, _, _) = time::encode(timestamp);
+        self.raw[18..20].copy_from_slice(&date.to_le_bytes());
+    }
+
+    pub fn set_modified(&mut self, timestamp: Timestamp) {
+        let (date, time, _) = time::encode(timestamp);
+        self.raw[22..24].copy_from_slice(&time.to_le_bytes());
+        self.raw[24..26].copy_from_slice(&date.to_le_bytes());
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.raw[offset], self.raw[offset + 1]])
+    }
+}
+
+impl Dir {
+    fn slots(&self) -> usize {
+        self.data.len() / ENTRY_SIZE
+    }
+
+    fn slot(&self, slot: usize) -> &[u8] {
+        &self.data[slot * ENTRY_SIZE..(slot + 1) * ENTRY_SIZE]
+    }
+
+    fn slot_offset<F>(&self, inner: &Inner<F>, slot: usize) -> u64 {
+        let layout = &inner.layout;
+        let offset = (slot * ENTRY_SIZE) as u64;
+        match self.loc {
+            DirLoc::Root => layout.root_dir_offset + offset,
+            DirLoc::Cluster(_) => {
+                let cluster_size = layout.cluster_size as u64;
+                let cluster = self.clusters[(offset / cluster_size) as usize];
+                layout.cluster_offset(cluster) + offset % cluster_size
+            }
+        }
+    }
+
+    /// All entries of the directory, excluding `.`, `..` and the volume label
+    pub fn entries<F>(&self, inner: &Inner<F>) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let mut lfn = LongName::default();
+
+        for slot in 0..self.slots() {
+            let raw = self.slot(slot);
+            match raw[0] {
+                0 => break,
+                DELETED => {
+                    lfn = LongName::default();
+                    continue;
+                }
+                _ => {}
+            }
+
+            let attr = raw[11];
+            if attr & 0x3F == ATTR_LONG_NAME {
+                lfn.push(slot, raw);
+                continue;
+            }
+
+            let lfn = core::mem::take(&mut lfn);
+            if attr & ATTR_VOLUME_ID != 0 || raw[0] == b'.' {
+                continue;
+            }
+
+            let mut short = [0; 11];
+            short.copy_from_slice(&raw[..11]);
+
+            let (name, first_slot) = match lfn.finish(&short) {
+                Some((name, first)) => (name, first),
+                None => (short_display(&short, raw[12]), slot),
+            };
+
+            entries.push(Entry {
+                name,
+                raw: raw.try_into().unwrap(),
+                offset: self.slot_offset(inner, slot),
+                first_slot,
+                slot,
+            });
+        }
+
+        entries
+    }
+
+    /// Find an entry by name. Names are compared case insensitively
+    pub fn find<F>(&self, inner: &Inner<F>, name: &str) -> Option<Entry> {
+        self.entries(inner)
+            .into_iter()
+            .find(|entry| eq_ignore_case(&entry.name, name))
+    }
+
+    fn short_names(&self) -> Vec<[u8; 11]> {
+        (0..self.slots())
+            .map(|slot| self.slot(slot))
+            .take_while(|raw| raw[0] != 0)
+            .filter(|raw| raw[0] != DELETED && raw[11] & 0x3F != ATTR_LONG_NAME)
+            .map(|raw| raw[..11].try_into().unwrap())
+            .collect()
+    }
+
+    /// Index of the first run of `count` free slots
+    fn free_run(&self, count: usize) -> Option<usize> {
+        let end = (0..self.slots())
+            .find(|&slot| self.slot(slot)[0] == 0)
+            .unwrap_or(self.slots());
+
+        let mut run = 0;
+        for slot in 0..self.slots() {
+            if slot >= end || self.slot(slot)[0] == DELETED {
+                run += 1;
+                if run == count {
+                    return Some(slot + 1 - count);
+                }
+            } else {
+                run = 0;
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Default)]
+struct LongName {
+    first_slot: usize,
+    checksum: u8,
+    expected: u8,
+    parts: Vec<u16>,
+    valid: bool,
+}
+
+impl LongName {
+    fn push(&mut self, slot: usize, raw: &[u8]) {
+        let seq = raw[0] & 0x1F;
+
+        // An orphaned or corrupt slot, the sequence runs from 1 to 20
+        if seq == 0 || seq as usize > MAX_LFN_SLOTS {
+            self.valid = false;
+            return;
+        }
+
+        if raw[0] & LFN_LAST != 0 {
+            *self = LongName {
+                first_slot: slot,
+                checksum: raw[13],
+                expected: seq,
+                parts: vec![0xFFFF; seq as usize * LFN_CHARS],
+                valid: true,
+            };
+        } else if !self.valid || seq + 1 != self.expected || raw[13] != self.checksum {
+            self.valid = false;
+            return;
+        }
+
+        self.expected = seq;
+        let start = (seq as usize - 1) * LFN_CHARS;
+        for (i, offset) in LFN_OFFSETS.iter().enumerate() {
+            self.parts[start + i] = u16::from_le_bytes([raw[*offset], raw[offset + 1]]);
+        }
+    }
+
+    /// The long name if it is complete and belongs to the short entry
+    fn finish(self, short: &[u8; 11]) -> Option<(String, usize)> {
+        if !self.valid || self.expected != 1 || self.checksum != checksum(short) {
+            return None;
+        }
+
+        let units = self
+            .parts
+            .iter()
+            .copied()
+            .take_while(|&unit| unit != 0 && unit != 0xFFFF);
+
+        let name = char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+
+        Some((name, self.first_slot))
+    }
+}
+
+fn checksum(short: &[u8; 11]) -> u8 {
+    short.iter().fold(0u8, |sum, &b| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(b)
+    })
+}
+
+/// Display form of a short name, honouring the lower case flags set by Windows NT
+fn short_display(short: &[u8; 11], flags: u8) -> String {
+    let convert = |bytes: &[u8], lower: bool| {
+        let mut out = String::new();
+        for (i, &b) in bytes.iter().enumerate() {
+            // 0x05 is stored in place of a leading 0xE5
+            let b = if i == 0 && b == 0x05 { DELETED } else { b };
+            let c = b as char;
+            out.push(if lower { c.to_ascii_lowercase() } else { c });
+        }
+        String::from(out.trim_end_matches(' '))
+    };
+
+    let mut name = convert(&short[..8], flags & 0x08 != 0);
+    let ext = convert(&short[8..], flags & 0x10 != 0);
+    if !ext.is_empty() {
+        name.push('.');
+        name.push_str(&ext);
+    }
+
+    name
+}
+
+fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .eq(b.chars().flat_map(char::to_lowercase))
+}
+
+fn is_short_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || b"$%'-_@~`!(){}^#&".contains(&c)
+}
+
+/// Check that a name can be stored in a directory
+pub(crate) fn validate_name(name: &str) -> Result<(), Error> {
+    let invalid = name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.ends_with(['.', ' '])
+        || name.encode_utf16().count() > 255
+        || name
+            .chars()
+            .any(|c| (c as u32) < 0x20 || "\"*/:<>?\\|".contains(c));
+
+    if invalid {
+        return Err(Error::new_const(
+            ErrorKind::InvalidFilename,
+            "name cannot be stored on a FAT volume",
+        ));
+    }
+
+    Ok(())
+}
+
+/// The short name and lower case flags if `name` can be stored as an 8.3 name
+/// without a long name. Parts in mixed case need a long name to keep their case
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+
+    let case = |part: &str, max: usize, flag: u8| {
+        let valid =
+            part.len() <= max && part.bytes().all(|b| is_short_char(b.to_ascii_uppercase()));
+        let lower = part.bytes().any(|b| b.is_ascii_lowercase());
+        let upper = part.bytes().any(|b| b.is_ascii_uppercase());
+
+        match (valid, lower, upper) {
+            (false, _, _) | (_, true, true) => None,
+            (_, true, false) => Some(flag),
+            _ => Some(0),
+        }
+    };
+
+    if base.is_empty() {
+        return None;
+    }
+
+    let flags = case(base, 8, 0x08)? | case(ext, 3, 0x10)?;
+
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.to_ascii_uppercase().as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+    Some((short, flags))
+}
+
+/// Generate a unique short name with a numeric tail for a long name
+fn generate_short_name(name: &str, existing: &[[u8; 11]]) -> Result<[u8; 11], Error> {
+    let convert = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match u8::try_from(c.to_ascii_uppercase()) {
+                Ok(b) if is_short_char(b) => b,
+                _ => b'_',
+            })
+            .collect()
+    };
+
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(idx) => (convert(&trimmed[..idx]), convert(&trimmed[idx + 1..])),
+        None => (convert(trimmed), Vec::new()),
+    };
+
+    let base = if base.is_empty() { vec![b'_'] } else { base };
+
+    for n in 1..1_000_000u32 {
+        let mut tail = [0u8; 7];
+        let mut len = 0;
+        let mut rest = n;
+        while rest > 0 {
+            tail[len] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            len += 1;
+        }
+
+        let keep = base.len().min(8 - len - 1);
+        let mut short = [b' '; 11];
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep] = b'~';
+        for i in 0..len {
+            short[keep + 1 + i] = tail[len - 1 - i];
+        }
+        let ext_len = ext.len().min(3);
+        short[8..8 + ext_len].copy_from_slice(&ext[..ext_len]);
+
+        if !existing.contains(&short) {
+            return Ok(short);
+        }
+    }
+
+    Err(Error::new_const(
+        ErrorKind::StorageFull,
+        "no unique short name available",
+    ))
+}
+
+impl<F> Inner<F>
+where
+    F: VFile + Unpin,
+{
+    pub fn root_loc(&self) -> DirLoc {
+        match self.layout.fat_type {
+            crate::FatType::Fat32 => DirLoc::Cluster(self.layout.root_cluster),
+            _ => DirLoc::Root,
+        }
+    }
+
+    pub async fn read_dir(&mut self, loc: DirLoc) -> Result<Dir, Error> {
+        match loc {
+            DirLoc::Root => {
+                let mut data = vec![0; self.layout.root_entries as usize * ENTRY_SIZE];
+                self.read_at(self.layout.root_dir_offset, &mut data).await?;
+                Ok(Dir {
+                    loc,
+                    clusters: Vec::new(),
+                    data,
+                })
+            }
+            DirLoc::Cluster(first) => {
+                let clusters = self.chain(first).await?;
+                let cluster_size = self.layout.cluster_size as usize;
+                let mut data = vec![0; clusters.len() * cluster_size];
+                for (idx, &cluster) in clusters.iter().enumerate() {
+                    let offset = self.layout.cluster_offset(cluster);
+                    self.read_at(
+                        offset,
+                        &mut data[idx * cluster_size..(idx + 1) * cluster_size],
+                    )
+                    .await?;
+                }
+                Ok(Dir {
+                    loc,
+                    clusters,
+                    data,
+                })
+            }
+        }
+    }
+
+    /// Allocate a cluster and fill it with zeros
+    pub async fn alloc_zeroed(&mut self, prev: Option<u32>) -> Result<u32, Error> {
+        let cluster = self.alloc_cluster(prev).await?;
+        let offset = self.layout.cluster_offset(cluster);
+        self.zero(offset, self.layout.cluster_size as u64).await?;
+        Ok(cluster)
+    }
+
+    /// Grow a directory in the data region by one cluster
+    async fn extend_dir(&mut self, dir: &mut Dir) -> Result<(), Error> {
+        let cluster_size = self.layout.cluster_size as usize;
+        if dir.loc == DirLoc::Root || dir.data.len() + cluster_size > MAX_ENTRIES * ENTRY_SIZE {
+            return Err(Error::new_const(
+                ErrorKind::StorageFull,
+                "directory cannot hold any more entries",
+            ));
+        }
+
+        let cluster = self.alloc_zeroed(dir.clusters.last().copied()).await?;
+        dir.clusters.push(cluster);
+        dir.data.resize(dir.data.len() + cluster_size, 0);
+
+        Ok(())
+    }
+
+    /// Write a raw slot of a directory to the device
+    async fn write_slot(&mut self, dir: &mut Dir, slot: usize, raw: &[u8]) -> Result<(), Error> {
+        let offset = dir.slot_offset(self, slot);
+        self.write_at(offset, raw).await?;
+        dir.data[slot * ENTRY_SIZE..(slot + 1) * ENTRY_SIZE].copy_from_slice(raw);
+        Ok(())
+    }
+
+    /// Persist the short entry of `entry`
+    pub async fn write_entry(&mut self, entry: &Entry) -> Result<(), Error> {
+        self.write_at(entry.offset, &entry.raw).await
+    }
+
+    /// Add a new entry to a directory
+    pub async fn create_entry(
+        &mut self,
+        dir: &mut Dir,
+        name: &str,
+        attr: u8,
+        first_cluster: u32,
+    ) -> Result<Entry, Error> {
+        validate_name(name)?;
+
+        let (short, flags, long) = match exact_short_name(name) {
+            Some((short, flags)) if !dir.short_names().contains(&short) => (short, flags, None),
+            _ => {
+                let short = generate_short_name(name, &dir.short_names())?;
+                let units: Vec<u16> = name.encode_utf16().collect();
+                (short, 0, Some(units))
+            }
+        };
+
+        let lfn_slots = long
+            .as_ref()
+            .map(|units| units.len().div_ceil(LFN_CHARS))
+            .unwrap_or(0);
+        let count = lfn_slots + 1;
+
+        let first_slot = loop {
+            match dir.free_run(count) {
+                Some(slot) => break slot,
+                None => self.extend_dir(dir).await?,
+            }
+        };
+
+        if let Some(units) = &long {
+            let sum = checksum(&short);
+            for i in 0..lfn_slots {
+                let seq = lfn_slots - i;
+                let mut raw = [0u8; ENTRY_SIZE];
+                raw[0] = seq as u8 | if i == 0 { LFN_LAST } else { 0 };
+                raw[11] = ATTR_LONG_NAME;
+                raw[13] = sum;
+
+                for (j, offset) in LFN_OFFSETS.iter().enumerate() {
+                    let idx = (seq - 1) * LFN_CHARS + j;
+                    let unit = match idx.cmp(&units.len()) {
+                        core::cmp::Ordering::Less => units[idx],
+                        core::cmp::Ordering::Equal => 0,
+                        core::cmp::Ordering::Greater => 0xFFFF,
+                    };
+                    raw[*offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+                }
+
+                self.write_slot(dir, first_slot + i, &raw).await?;
+            }
+        }
+
+        let slot = first_slot + lfn_slots;
+        let mut entry = Entry {
+            name: String::from(name),
+            raw: [0; ENTRY_SIZE],
+            offset: dir.slot_offset(self, slot),
+            first_slot,
+            slot,
+        };
+
+        let now = self.now();
+        entry.raw[..11].copy_from_slice(&short);
+        entry.raw[12] = flags;
+        entry.set_attr(attr);
+        entry.set_first_cluster(first_cluster);
+        entry.set_created(now);
+        entry.set_accessed(now);
+        entry.set_modified(now);
+
+        let raw = entry.raw;
+        self.write_slot(dir, slot, &raw).await?;
+
+        Ok(entry)
+    }
+
+    /// Mark an entry and its long name as deleted. The clusters are not released
+    pub async fn remove_entry(&mut self, dir: &mut Dir, entry: &Entry) -> Result<(), Error> {
+        for slot in entry.first_slot..=entry.slot {
+            let mut raw: [u8; ENTRY_SIZE] = dir.slot(slot).try_into().unwrap();
+            raw[0] = DELETED;
+            self.write_slot(dir, slot, &raw).await?;
+        }
+        Ok(())
+    }
+
+    /// Create the cluster of a new directory, containing the `.` and `..` entries
+    pub async fn init_dir(&mut self, parent: DirLoc) -> Result<u32, Error> {
+        let cluster = self.alloc_zeroed(None).await?;
+        let now = self.now();
+
+        let parent_cluster = match parent {
+            DirLoc::Cluster(cluster) if parent != self.root_loc() => cluster,
+            _ => 0,
+        };
+
+        for (idx, (name, target)) in [(".", cluster), ("..", parent_cluster)]
+            .into_iter()
+            .enumerate()
+        {
+            let mut entry = Entry {
+                name: String::new(),
+                raw: [0; ENTRY_SIZE],
+                offset: self.layout.cluster_offset(cluster) + (idx * ENTRY_SIZE) as u64,
+                first_slot: 0,
+                slot: 0,
+            };
+            entry.raw[..11].copy_from_slice(b"           ");
+            entry.raw[..name.len()].copy_from_slice(name.as_bytes());
+            entry.set_attr(ATTR_DIRECTORY);
+            entry.set_first_cluster(target);
+            entry.set_created(now);
+            entry.set_accessed(now);
+            entry.set_modified(now);
+            self.write_entry(&entry).await?;
+        }
+
+        Ok(cluster)
+    }
+}
@@ -0,0 +1,395 @@
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::{
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use vfs::{Error, ErrorKind, OpenOptions, Operation, SeekFrom, VFile};
+
+use crate::{FatFS, dir::ATTR_ARCHIVE, dir::Entry, inner::Inner};
+
+/// The position and cluster chain of an open file
+pub(crate) struct FileState {
+    entry: Entry,
+    clusters: Vec<u32>,
+    pos: u64,
+    options: OpenOptions,
+    dirty: bool,
+    /// First cluster as last stored in the directory entry
+    stored_cluster: u32,
+    /// The entry was removed while the file was open
+    removed: bool,
+}
+
+impl FileState {
+    pub fn new(entry: Entry, clusters: Vec<u32>, options: OpenOptions) -> FileState {
+        FileState {
+            stored_cluster: entry.first_cluster(),
+            entry,
+            clusters,
+            pos: 0,
+            options,
+            dirty: false,
+            removed: false,
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.entry.size() as u64
+    }
+}
+
+enum Outcome {
+    Read(Vec<u8>),
+    Written(usize),
+    Flushed,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OpKind {
+    Read,
+    Write,
+    Flush,
+}
+
+type OpFuture = Pin<Box<dyn Future<Output = (FileState, Result<Outcome, Error>)> + Send + Sync>>;
+
+/// A file on a FAT volume.
+///
+/// The size and modification time of the file are stored in its directory
+/// entry when the file is flushed or closed, so written files must be closed
+/// for the changes to be visible.
+pub struct FatFile<F> {
+    fs: FatFS<F>,
+    path: String,
+    state: Option<FileState>,
+    op: Option<(OpKind, OpFuture)>,
+}
+
+impl<F> FatFile<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    pub(crate) fn new(fs: FatFS<F>, path: String, state: FileState) -> FatFile<F> {
+        FatFile {
+            fs,
+            path,
+            state: Some(state),
+            op: None,
+        }
+    }
+
+    fn context(&self, err: Error, operation: Operation) -> Error {
+        err.with_operation(operation)
+            .with_path(alloc::format!("/{}", self.path))
+    }
+
+    fn state(&self) -> &FileState {
+        self.state
+            .as_ref()
+            .expect("file state is only taken while an operation is in progress")
+    }
+
+    /// Drive a pending operation to completion, discarding its result
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some((_, future)) = &mut self.op {
+            let (state, _) = ready!(future.as_mut().poll(cx));
+            self.op = None;
+            self.state = Some(state);
+        }
+        Poll::Ready(())
+    }
+
+    fn poll_op(
+        &mut self,
+        cx: &mut Context<'_>,
+        kind: OpKind,
+        start: impl FnOnce(FatFS<F>, String, FileState) -> OpFuture,
+    ) -> Poll<Result<Outcome, Error>> {
+        // An operation of another kind was abandoned while pending
+        if self
+            .op
+            .as_ref()
+            .is_some_and(|(pending, _)| *pending != kind)
+        {
+            ready!(self.poll_idle(cx));
+        }
+
+        if self.op.is_none() {
+            let state = self.state.take().expect("file state missing");
+            self.op = Some((kind, start(self.fs.clone(), self.path.clone(), state)));
+        }
+
+        let (_, future) = self.op.as_mut().unwrap();
+        let (state, ret) = ready!(future.as_mut().poll(cx));
+        self.op = None;
+        self.state = Some(state);
+
+        Poll::Ready(ret)
+    }
+}
+
+/// Device ranges covered by `len` bytes at `pos` of a file, merging contiguous clusters
+fn segments<F>(inner: &Inner<F>, clusters: &[u32], pos: u64, len: usize) -> Vec<(u64, usize)> {
+    let cluster_size = inner.layout.cluster_size as u64;
+    let mut segments: Vec<(u64, usize)> = Vec::new();
+    let mut pos = pos;
+    let mut remaining = len;
+
+    while remaining > 0 {
+        let cluster = clusters[(pos / cluster_size) as usize];
+        let within = pos % cluster_size;
+        let n = ((cluster_size - within) as usize).min(remaining);
+        let offset = inner.layout.cluster_offset(cluster) + within;
+
+        match segments.last_mut() {
+            Some((start, len)) if *start + *len as u64 == offset => *len += n,
+            _ => segments.push((offset, n)),
+        }
+
+        pos += n as u64;
+        remaining -= n;
+    }
+
+    segments
+}
+
+impl<F> Inner<F>
+where
+    F: VFile + Unpin,
+{
+    async fn file_read(&mut self, state: &mut FileState, len: usize) -> Result<Outcome, Error> {
+        let len = state.size().saturating_sub(state.pos).min(len as u64) as usize;
+        let mut data = vec![0; len];
+
+        let mut filled = 0;
+        for (offset, n) in segments(self, &state.clusters, state.pos, len) {
+            self.read_at(offset, &mut data[filled..filled + n]).await?;
+            filled += n;
+        }
+
+        state.pos += len as u64;
+
+        Ok(Outcome::Read(data))
+    }
+
+    async fn file_write(&mut self, state: &mut FileState, data: Vec<u8>) -> Result<Outcome, Error> {
+        if state.options.append {
+            state.pos = state.size();
+        }
+
+        let end = state.pos + data.len() as u64;
+        if end > u32::MAX as u64 {
+            return Err(Error::new_const(
+                ErrorKind::FileTooLarge,
+                "files on a FAT volume are limited to 4 GiB",
+            ));
+        }
+
+        let needed = end.div_ceil(self.layout.cluster_size as u64) as usize;
+        while state.clusters.len() < needed {
+            let cluster = self.alloc_cluster(state.clusters.last().copied()).await?;
+            if state.clusters.is_empty() {
+                state.entry.set_first_cluster(cluster);
+            }
+            state.clusters.push(cluster);
+            state.dirty = true;
+        }
+
+        // Writing past the end leaves a gap, which reads back as zeros
+        if state.pos > state.size() {
+            let gap = (state.pos - state.size()) as usize;
+            for (offset, n) in segments(self, &state.clusters, state.size(), gap) {
+                self.zero(offset, n as u64).await?;
+            }
+        }
+
+        let mut written = 0;
+        for (offset, n) in segments(self, &state.clusters, state.pos, data.len()) {
+            self.write_at(offset, &data[written..written + n]).await?;
+            written += n;
+        }
+
+        state.pos = end;
+        if end > state.size() {
+            state.entry.set_size(end as u32);
+        }
+        state.dirty = true;
+
+        Ok(Outcome::Written(data.len()))
+    }
+
+    /// Store the size and modification time in the directory entry. The entry
+    /// is read back first, so attributes and times set through the path while
+    /// the file was open are kept, and a removed entry is left alone
+    async fn file_flush(&mut self, state: &mut FileState) -> Result<bool, Error> {
+        let dirty = state.dirty;
+        if dirty {
+            let mut entry = state.entry.clone();
+            self.read_at(entry.offset, &mut entry.raw).await?;
+
+            // A deleted slot, or one reused by another entry
+            state.removed |= entry.raw[..11] != state.entry.raw[..11]
+                || entry.first_cluster() != state.stored_cluster;
+
+            if state.removed {
+                // Removing the entry released the chain it pointed to, but not
+                // clusters allocated since the file was empty
+                if state.stored_cluster == 0 {
+                    self.free_clusters(&state.clusters).await?;
+                }
+                state.clusters.clear();
+                state.entry.set_first_cluster(0);
+                state.entry.set_size(0);
+                state.stored_cluster = 0;
+            } else {
+                let now = self.now();
+                entry.set_size(state.entry.size());
+                entry.set_first_cluster(state.entry.first_cluster());
+                entry.set_modified(now);
+                entry.set_accessed(now);
+                entry.set_attr(entry.attr() | ATTR_ARCHIVE);
+                self.write_entry(&entry).await?;
+
+                state.stored_cluster = entry.first_cluster();
+                state.entry = entry;
+            }
+            state.dirty = false;
+        }
+
+        self.flush().await?;
+
+        Ok(dirty)
+    }
+}
+
+impl<F> VFile for FatFile<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+
+        if this.op.is_none() && !this.state().options.read {
+            let err = Error::new_const(ErrorKind::PermissionDenied, "file not opened for reading");
+            return Poll::Ready(Err(this.context(err, Operation::Read)));
+        }
+
+        let len = buf.len();
+        let ret = ready!(this.poll_op(cx, OpKind::Read, |fs, _, mut state| {
+            Box::pin(async move {
+                let mut inner = fs.inner.lock().await;
+                let ret = inner.file_read(&mut state, len).await;
+                (state, ret)
+            })
+        }));
+
+        Poll::Ready(match ret {
+            Ok(Outcome::Read(data)) => {
+                let n = data.len().min(buf.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                Ok(n)
+            }
+            Ok(_) => unreachable!(),
+            Err(err) => Err(this.context(err, Operation::Read)),
+        })
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_idle(cx));
+
+        let state = this.state.as_mut().expect("file state missing");
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => state.size().checked_add_signed(offset),
+            SeekFrom::Current(offset) => state.pos.checked_add_signed(offset),
+        };
+
+        Poll::Ready(match pos {
+            Some(pos) => {
+                state.pos = pos;
+                Ok(pos)
+            }
+            None => {
+                let err = Error::new_const(
+                    ErrorKind::InvalidInput,
+                    "invalid seek to a negative or overflowing position",
+                );
+                Err(this.context(err, Operation::Seek))
+            }
+        })
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+
+        if this.op.is_none() {
+            let options = this.state().options;
+            if !options.write && !options.append {
+                let err =
+                    Error::new_const(ErrorKind::PermissionDenied, "file not opened for writing");
+                return Poll::Ready(Err(this.context(err, Operation::Write)));
+            }
+        }
+
+        let data = buf.to_vec();
+        let ret = ready!(this.poll_op(cx, OpKind::Write, |fs, _, mut state| {
+            Box::pin(async move {
+                let mut inner = fs.inner.lock().await;
+                let ret = inner.file_write(&mut state, data).await;
+                (state, ret)
+            })
+        }));
+
+        Poll::Ready(match ret {
+            Ok(Outcome::Written(n)) => Ok(n),
+            Ok(_) => unreachable!(),
+            Err(err) => Err(this.context(err, Operation::Write)),
+        })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+
+        let ret = ready!(this.poll_op(cx, OpKind::Flush, |fs, path, mut state| {
+            Box::pin(async move {
+                let mut inner = fs.inner.lock().await;
+                let ret = inner.file_flush(&mut state).await;
+                drop(inner);
+
+                #[cfg(feature = "std")]
+                if let Ok(true) = ret {
+                    let path = crate::FatPath::new(fs.clone(), path);
+                    fs.hub.emit(vfs::WatchEvent::Modify(path));
+                }
+                #[cfg(not(feature = "std"))]
+                let _ = path;
+
+                (state, ret.map(|_| Outcome::Flushed))
+            })
+        }));
+
+        Poll::Ready(
+            ret.map(|_| ())
+                .map_err(|err| this.context(err, Operation::Flush)),
+        )
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.poll_flush(cx)
+            .map_err(|err| err.with_operation(Operation::Close))
+    }
+}
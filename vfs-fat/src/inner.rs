@@ -0,0 +1,328 @@
+use alloc::{vec, vec::Vec};
+use core::pin::Pin;
+
+use futures::future::poll_fn;
+use vfs::{Error, ErrorKind, SeekFrom, Timestamp, VFile, VFileExt};
+
+use crate::layout::{FatType, Layout};
+
+/// Size of the window of the FAT kept in memory on FAT16 and FAT32.
+/// The FAT12 table is small enough to be kept in memory as a whole
+const FAT_WINDOW: u64 = 4096;
+
+/// Lead and structure signatures of the FAT32 FSInfo sector
+const FSINFO_LEAD: u32 = 0x4161_5252;
+const FSINFO_STRUCT: u32 = 0x6141_7272;
+
+/// The mounted device and the cached part of the allocation table
+pub(crate) struct Inner<F> {
+    device: F,
+    pub layout: Layout,
+    pub clock: fn() -> Timestamp,
+    fat: Vec<u8>,
+    fat_window: Option<u64>,
+    fat_dirty: bool,
+    next_free: u32,
+    free_count: Option<u32>,
+    fsinfo_dirty: bool,
+}
+
+impl<F> Inner<F>
+where
+    F: VFile + Unpin,
+{
+    pub async fn mount(mut device: F, clock: fn() -> Timestamp) -> Result<Inner<F>, Error> {
+        let mut boot = [0; 512];
+        device.seek(SeekFrom::Start(0)).await?;
+        device.read_exact(&mut boot).await?;
+
+        let layout = Layout::parse(&boot)?;
+
+        Ok(Inner {
+            device,
+            layout,
+            clock,
+            fat: Vec::new(),
+            fat_window: None,
+            fat_dirty: false,
+            next_free: 2,
+            free_count: None,
+            fsinfo_dirty: false,
+        })
+    }
+
+    pub fn now(&self) -> Timestamp {
+        (self.clock)()
+    }
+
+    pub async fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), Error> {
+        self.device.seek(SeekFrom::Start(offset)).await?;
+        self.device.read_exact(buf).await
+    }
+
+    pub async fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<(), Error> {
+        self.device.seek(SeekFrom::Start(offset)).await?;
+        self.device.write_all(buf).await
+    }
+
+    /// Fill a range of the device with zeros
+    pub async fn zero(&mut self, offset: u64, len: u64) -> Result<(), Error> {
+        let zeros = [0u8; 512];
+        self.device.seek(SeekFrom::Start(offset)).await?;
+
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(zeros.len() as u64) as usize;
+            self.device.write_all(&zeros[..n]).await?;
+            remaining -= n as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Byte offset of a cluster's entry within the FAT
+    fn fat_entry_offset(&self, cluster: u32) -> u64 {
+        let cluster = cluster as u64;
+        match self.layout.fat_type {
+            FatType::Fat12 => cluster + cluster / 2,
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4,
+        }
+    }
+
+    /// Make sure the part of the FAT containing `offset` is in memory,
+    /// and return the offset relative to the loaded window
+    async fn load_fat(&mut self, offset: u64) -> Result<usize, Error> {
+        let window = match self.layout.fat_type {
+            FatType::Fat12 => self.layout.fat_size,
+            _ => FAT_WINDOW.min(self.layout.fat_size),
+        };
+        let start = offset - offset % window;
+
+        if self.fat_window != Some(start) {
+            self.flush_fat().await?;
+
+            let len = window.min(self.layout.fat_size - start) as usize;
+            let mut fat = vec![0; len];
+            self.read_at(self.layout.fat_offset + start, &mut fat)
+                .await?;
+            self.fat = fat;
+            self.fat_window = Some(start);
+        }
+
+        Ok((offset - start) as usize)
+    }
+
+    pub async fn fat_get(&mut self, cluster: u32) -> Result<u32, Error> {
+        let offset = self.fat_entry_offset(cluster);
+        let idx = self.load_fat(offset).await?;
+        let fat = &self.fat;
+
+        let value = match self.layout.fat_type {
+            FatType::Fat12 => {
+                let value = u16::from_le_bytes([fat[idx], fat[idx + 1]]);
+                if cluster & 1 == 1 {
+                    (value >> 4) as u32
+                } else {
+                    (value & 0xFFF) as u32
+                }
+            }
+            FatType::Fat16 => u16::from_le_bytes([fat[idx], fat[idx + 1]]) as u32,
+            FatType::Fat32 => {
+                u32::from_le_bytes([fat[idx], fat[idx + 1], fat[idx + 2], fat[idx + 3]])
+                    & 0x0FFF_FFFF
+            }
+        };
+
+        Ok(value)
+    }
+
+    pub async fn fat_set(&mut self, cluster: u32, value: u32) -> Result<(), Error> {
+        let offset = self.fat_entry_offset(cluster);
+        let idx = self.load_fat(offset).await?;
+        let fat = &mut self.fat;
+
+        match self.layout.fat_type {
+            FatType::Fat12 => {
+                let old = u16::from_le_bytes([fat[idx], fat[idx + 1]]);
+                let value = value as u16 & 0xFFF;
+                let new = if cluster & 1 == 1 {
+                    (old & 0x000F) | (value << 4)
+                } else {
+                    (old & 0xF000) | value
+                };
+                fat[idx..idx + 2].copy_from_slice(&new.to_le_bytes());
+            }
+            FatType::Fat16 => {
+                fat[idx..idx + 2].copy_from_slice(&(value as u16).to_le_bytes());
+            }
+            FatType::Fat32 => {
+                // The upper four bits are reserved and must be preserved
+                let old = u32::from_le_bytes([fat[idx], fat[idx + 1], fat[idx + 2], fat[idx + 3]]);
+                let new = (old & 0xF000_0000) | (value & 0x0FFF_FFFF);
+                fat[idx..idx + 4].copy_from_slice(&new.to_le_bytes());
+            }
+        }
+
+        self.fat_dirty = true;
+
+        Ok(())
+    }
+
+    /// Write the cached part of the FAT back to every copy of the table
+    async fn flush_fat(&mut self) -> Result<(), Error> {
+        let Some(start) = self.fat_window.filter(|_| self.fat_dirty) else {
+            return Ok(());
+        };
+
+        let fat = core::mem::take(&mut self.fat);
+        let mut ret = Ok(());
+        for copy in 0..self.layout.num_fats as u64 {
+            let offset = self.layout.fat_offset + copy * self.layout.fat_size + start;
+            ret = self.write_at(offset, &fat).await;
+            if ret.is_err() {
+                break;
+            }
+        }
+        self.fat = fat;
+
+        ret?;
+        self.fat_dirty = false;
+
+        Ok(())
+    }
+
+    /// Returns true if the FAT value terminates a chain
+    fn is_end(&self, value: u32) -> bool {
+        value >= self.layout.end_of_chain() - 7
+    }
+
+    /// All clusters in the chain starting at `first`
+    pub async fn chain(&mut self, first: u32) -> Result<Vec<u32>, Error> {
+        let mut clusters = Vec::new();
+        if first == 0 {
+            return Ok(clusters);
+        }
+
+        let mut cluster = first;
+        loop {
+            if !self.layout.is_data_cluster(cluster) {
+                return Err(Error::new_const(
+                    ErrorKind::InvalidData,
+                    "cluster chain points outside of the volume",
+                ));
+            }
+
+            if clusters.len() > self.layout.cluster_count as usize {
+                return Err(Error::new_const(
+                    ErrorKind::InvalidData,
+                    "cluster chain contains a cycle",
+                ));
+            }
+
+            clusters.push(cluster);
+
+            let next = self.fat_get(cluster).await?;
+            if self.is_end(next) {
+                return Ok(clusters);
+            }
+            cluster = next;
+        }
+    }
+
+    /// Allocate a free cluster, and append it to the chain ending in `prev`
+    pub async fn alloc_cluster(&mut self, prev: Option<u32>) -> Result<u32, Error> {
+        let count = self.layout.cluster_count;
+        let start = if self.layout.is_data_cluster(self.next_free) {
+            self.next_free
+        } else {
+            2
+        };
+
+        for i in 0..count {
+            let cluster = 2 + (start - 2 + i) % count;
+            if self.fat_get(cluster).await? != 0 {
+                continue;
+            }
+
+            self.fat_set(cluster, self.layout.end_of_chain()).await?;
+            if let Some(prev) = prev {
+                self.fat_set(prev, cluster).await?;
+            }
+
+            self.next_free = cluster + 1;
+            self.free_count = self.free_count.map(|n| n.saturating_sub(1));
+            self.fsinfo_dirty = true;
+
+            return Ok(cluster);
+        }
+
+        Err(Error::new_const(
+            ErrorKind::StorageFull,
+            "no free clusters left on the volume",
+        ))
+    }
+
+    /// Release all clusters in a chain
+    pub async fn free_chain(&mut self, first: u32) -> Result<(), Error> {
+        let clusters = self.chain(first).await?;
+        self.free_clusters(&clusters).await
+    }
+
+    /// Release the given clusters
+    pub async fn free_clusters(&mut self, clusters: &[u32]) -> Result<(), Error> {
+        for &cluster in clusters {
+            self.fat_set(cluster, 0).await?;
+        }
+
+        if let Some(&first) = clusters.iter().min() {
+            self.next_free = self.next_free.min(first);
+        }
+        self.free_count = self.free_count.map(|n| n + clusters.len() as u32);
+        self.fsinfo_dirty = true;
+
+        Ok(())
+    }
+
+    /// Number of free clusters on the volume
+    pub async fn count_free(&mut self) -> Result<u32, Error> {
+        if let Some(count) = self.free_count {
+            return Ok(count);
+        }
+
+        let mut count = 0;
+        for cluster in 2..self.layout.cluster_count + 2 {
+            if self.fat_get(cluster).await? == 0 {
+                count += 1;
+            }
+        }
+
+        self.free_count = Some(count);
+        self.fsinfo_dirty = true;
+
+        Ok(count)
+    }
+
+    /// Write all cached state to the device and flush it
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        self.flush_fat().await?;
+
+        if let Some(offset) = self.layout.fsinfo_offset.filter(|_| self.fsinfo_dirty) {
+            let mut sector = [0; 512];
+            self.read_at(offset, &mut sector).await?;
+
+            let lead = u32::from_le_bytes([sector[0], sector[1], sector[2], sector[3]]);
+            let sig = u32::from_le_bytes([sector[484], sector[485], sector[486], sector[487]]);
+
+            if lead == FSINFO_LEAD && sig == FSINFO_STRUCT {
+                let free = self.free_count.unwrap_or(u32::MAX);
+                sector[488..492].copy_from_slice(&free.to_le_bytes());
+                sector[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+                self.write_at(offset, &sector).await?;
+            }
+        }
+        self.fsinfo_dirty = false;
+
+        poll_fn(|cx| Pin::new(&mut self.device).poll_flush(cx)).await
+    }
+}
@@ -0,0 +1,151 @@
+use vfs::{Error, ErrorKind};
+
+/// The FAT variant of a volume, determined by its cluster count
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// Geometry of a volume, parsed from the boot sector
+#[derive(Debug, Clone)]
+pub(crate) struct Layout {
+    pub fat_type: FatType,
+    pub cluster_size: u32,
+    pub num_fats: u32,
+    /// Byte offset of the first FAT
+    pub fat_offset: u64,
+    /// Size of a single FAT in bytes
+    pub fat_size: u64,
+    /// Byte offset of the fixed root directory on FAT12/16
+    pub root_dir_offset: u64,
+    pub root_entries: u32,
+    /// Byte offset of cluster 2
+    pub data_offset: u64,
+    /// Number of data clusters
+    pub cluster_count: u32,
+    /// First cluster of the root directory on FAT32
+    pub root_cluster: u32,
+    /// Byte offset of the FAT32 FSInfo sector
+    pub fsinfo_offset: Option<u64>,
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+const fn invalid(message: &'static str) -> Error {
+    Error::new_const(ErrorKind::InvalidData, message)
+}
+
+impl Layout {
+    pub fn parse(boot: &[u8; 512]) -> Result<Layout, Error> {
+        if boot[510] != 0x55 || boot[511] != 0xAA {
+            return Err(invalid("missing boot sector signature"));
+        }
+
+        let bytes_per_sector = u16_at(boot, 11) as u32;
+        let sectors_per_cluster = boot[13] as u32;
+        let reserved_sectors = u16_at(boot, 14) as u32;
+        let num_fats = boot[16] as u32;
+        let root_entries = u16_at(boot, 17) as u32;
+        let total_sectors_16 = u16_at(boot, 19) as u32;
+        let fat_size_16 = u16_at(boot, 22) as u32;
+        let total_sectors_32 = u32_at(boot, 32);
+
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096) {
+            return Err(invalid("invalid bytes per sector"));
+        }
+
+        if sectors_per_cluster == 0 || !sectors_per_cluster.is_power_of_two() {
+            return Err(invalid("invalid sectors per cluster"));
+        }
+
+        if num_fats == 0 || reserved_sectors == 0 {
+            return Err(invalid("invalid number of FATs or reserved sectors"));
+        }
+
+        let fat_sectors = if fat_size_16 != 0 {
+            fat_size_16
+        } else {
+            u32_at(boot, 36)
+        };
+
+        let total_sectors = if total_sectors_16 != 0 {
+            total_sectors_16
+        } else {
+            total_sectors_32
+        };
+
+        let root_dir_sectors = (root_entries * 32).div_ceil(bytes_per_sector);
+        let first_data_sector = reserved_sectors + num_fats * fat_sectors + root_dir_sectors;
+
+        if fat_sectors == 0 || total_sectors <= first_data_sector {
+            return Err(invalid("invalid volume size"));
+        }
+
+        let cluster_count = (total_sectors - first_data_sector) / sectors_per_cluster;
+
+        let fat_type = if cluster_count < 4085 {
+            FatType::Fat12
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+
+        let (root_cluster, fsinfo_offset) = if fat_type == FatType::Fat32 {
+            let fsinfo = u16_at(boot, 48) as u64;
+            let fsinfo =
+                (fsinfo != 0 && fsinfo != 0xFFFF).then_some(fsinfo * bytes_per_sector as u64);
+            (u32_at(boot, 44), fsinfo)
+        } else {
+            (0, None)
+        };
+
+        let sector = bytes_per_sector as u64;
+
+        Ok(Layout {
+            fat_type,
+            cluster_size: bytes_per_sector * sectors_per_cluster,
+            num_fats,
+            fat_offset: reserved_sectors as u64 * sector,
+            fat_size: fat_sectors as u64 * sector,
+            root_dir_offset: (reserved_sectors + num_fats * fat_sectors) as u64 * sector,
+            root_entries,
+            data_offset: first_data_sector as u64 * sector,
+            cluster_count,
+            root_cluster,
+            fsinfo_offset,
+        })
+    }
+
+    /// Byte offset of a data cluster
+    pub fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_offset + (cluster as u64 - 2) * self.cluster_size as u64
+    }
+
+    /// Returns true if the cluster number refers to a data cluster
+    pub fn is_data_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.cluster_count + 2
+    }
+
+    /// The FAT value marking the end of a cluster chain
+    pub fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+}
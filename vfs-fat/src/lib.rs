@@ -0,0 +1,491 @@
+//! A [`VFS`] backed by a FAT12, FAT16 or FAT32 volume, read from any [`VFile`]
+//! like an image file or a block device.
+//!
+//! The crate only needs `alloc`. With the `std` feature enabled, changes made
+//! through the filesystem can be watched.
+#![no_std]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+extern crate std;
+
+mod dir;
+mod file;
+mod inner;
+mod layout;
+mod ops;
+mod time;
+
+pub use self::{file::FatFile, layout::FatType};
+
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+
+use async_lock::Mutex;
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use vfs::{
+    Capabilities, DirEntry, Error, ErrorKind, FileTimes, FsStats, Metadata, OpenOptions, Operation,
+    Permissions, Timestamp, VFS, VFile, VPath, VPathStr, WatchEvent,
+};
+
+use self::inner::Inner;
+
+#[cfg(feature = "std")]
+fn default_clock() -> Timestamp {
+    std::time::SystemTime::now().into()
+}
+
+/// Without a clock, entries are stamped with the earliest date FAT supports
+#[cfg(not(feature = "std"))]
+fn default_clock() -> Timestamp {
+    Timestamp::UNIX_EPOCH
+}
+
+/// A mounted FAT volume
+pub struct FatFS<F> {
+    inner: Arc<Mutex<Inner<F>>>,
+    fat_type: FatType,
+    #[cfg(feature = "std")]
+    hub: vfs::WatchHub<FatPath<F>>,
+}
+
+impl<F> Clone for FatFS<F> {
+    fn clone(&self) -> Self {
+        FatFS {
+            inner: self.inner.clone(),
+            fat_type: self.fat_type,
+            #[cfg(feature = "std")]
+            hub: self.hub.clone(),
+        }
+    }
+}
+
+impl<F> core::fmt::Debug for FatFS<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FatFS")
+            .field("fat_type", &self.fat_type)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F> FatFS<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    /// Mount the volume starting at the beginning of `device`
+    pub async fn new(device: F) -> Result<FatFS<F>, Error> {
+        FatFS::with_clock(device, default_clock).await
+    }
+
+    /// Mount the volume with a custom source of the current time,
+    /// used to stamp created and modified entries
+    pub async fn with_clock(device: F, clock: fn() -> Timestamp) -> Result<FatFS<F>, Error> {
+        let inner = Inner::mount(device, clock).await?;
+
+        Ok(FatFS {
+            fat_type: inner.layout.fat_type,
+            inner: Arc::new(Mutex::new(inner)),
+            #[cfg(feature = "std")]
+            hub: Default::default(),
+        })
+    }
+
+    /// The FAT variant of the volume
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    /// Write all cached metadata to the device and flush it
+    pub async fn flush(&self) -> Result<(), Error> {
+        self.inner.lock().await.flush().await
+    }
+
+    #[cfg(feature = "std")]
+    fn emit(&self, event: WatchEvent<FatPath<F>>) {
+        self.hub.emit(event);
+    }
+}
+
+impl<F> VFS for FatFS<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    type Path = FatPath<F>;
+
+    type Stats = BoxFuture<'static, Result<FsStats, Error>>;
+
+    fn path(&self, path: impl AsRef<VPathStr>) -> Result<Self::Path, Error> {
+        Ok(FatPath::new(
+            self.clone(),
+            ops::join("", path.as_ref().as_str()),
+        ))
+    }
+
+    fn stats(&self) -> Self::Stats {
+        let fs = self.clone();
+        Box::pin(async move {
+            let mut inner = fs.inner.lock().await;
+            let free = inner
+                .count_free()
+                .await
+                .map_err(|err| err.with_operation(Operation::Stats))?;
+
+            let cluster_size = inner.layout.cluster_size as u64;
+            Ok(FsStats {
+                block_size: cluster_size,
+                total: inner.layout.cluster_count as u64 * cluster_size,
+                free: free as u64 * cluster_size,
+                available: free as u64 * cluster_size,
+            })
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            symlinks: false,
+            atomic_rename: false,
+            xattr: false,
+            seek: true,
+            write: true,
+            case_sensitive: false,
+            max_name_len: Some(255),
+        }
+    }
+}
+
+/// A path on a FAT volume
+pub struct FatPath<F> {
+    fs: FatFS<F>,
+    /// Path without leading slash, empty for the root directory. Any `..`
+    /// left in it is resolved by the lookup, which checks that it follows a
+    /// directory. Names and comparisons go by the normalized form
+    path: String,
+}
+
+impl<F> Clone for FatPath<F> {
+    fn clone(&self) -> Self {
+        FatPath {
+            fs: self.fs.clone(),
+            path: self.path.clone(),
+        }
+    }
+}
+
+impl<F> core::fmt::Debug for FatPath<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("FatPath").field(&self.path).finish()
+    }
+}
+
+/// Paths are equal when they point into the same mounted volume
+impl<F> PartialEq for FatPath<F> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.fs.inner, &other.fs.inner)
+            && ops::normalize(&self.path) == ops::normalize(&other.path)
+    }
+}
+
+impl<F> Eq for FatPath<F> {}
+
+impl<F> core::hash::Hash for FatPath<F> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        ops::normalize(&self.path).hash(state)
+    }
+}
+
+impl<F> FatPath<F> {
+    fn new(fs: FatFS<F>, path: String) -> FatPath<F> {
+        FatPath { fs, path }
+    }
+
+    fn child(&self, name: &str) -> FatPath<F> {
+        let path = if self.path.is_empty() {
+            String::from(name)
+        } else {
+            format!("{}/{}", self.path, name)
+        };
+        FatPath::new(self.fs.clone(), path)
+    }
+
+    fn context(&self, err: Error, operation: Operation) -> Error {
+        err.with_operation(operation)
+            .with_path(format!("/{}", self.path))
+    }
+}
+
+impl<F> VPath for FatPath<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    type FS = FatFS<F>;
+
+    type File = FatFile<F>;
+
+    type ListDir = BoxStream<'static, Result<DirEntry<Self>, Error>>;
+
+    type Metadata = BoxFuture<'static, Result<Metadata, Error>>;
+
+    type Open = BoxFuture<'static, Result<FatFile<F>, Error>>;
+
+    type CreateDir = BoxFuture<'static, Result<(), Error>>;
+
+    type Remove = BoxFuture<'static, Result<(), Error>>;
+
+    type ReadDir = BoxFuture<'static, Result<Self::ListDir, Error>>;
+
+    type SetPermissions = BoxFuture<'static, Result<(), Error>>;
+
+    type SetTimes = BoxFuture<'static, Result<(), Error>>;
+
+    type SetOwner = BoxFuture<'static, Result<(), Error>>;
+
+    type GetXattr = BoxFuture<'static, Result<Option<Vec<u8>>, Error>>;
+
+    type SetXattr = BoxFuture<'static, Result<(), Error>>;
+
+    type ListXattr = BoxFuture<'static, Result<Vec<String>, Error>>;
+
+    type RemoveXattr = BoxFuture<'static, Result<(), Error>>;
+
+    type Watcher = BoxStream<'static, Result<WatchEvent<Self>, Error>>;
+
+    type Watch = BoxFuture<'static, Result<Self::Watcher, Error>>;
+
+    type Canonicalize = BoxFuture<'static, Result<Self, Error>>;
+
+    fn fs(&self) -> Self::FS {
+        self.fs.clone()
+    }
+
+    fn virtual_path(&self) -> String {
+        format!("/{}", ops::normalize(&self.path).join("/"))
+    }
+
+    fn to_string(&self) -> String {
+        self.virtual_path()
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        ops::normalize(&self.path).pop()
+    }
+
+    fn extension(&self) -> Option<&str> {
+        let name = self.file_name()?;
+        match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => Some(ext),
+            _ => None,
+        }
+    }
+
+    fn resolve(&self, path: &str) -> Result<Self, Error> {
+        Ok(FatPath::new(self.fs.clone(), ops::join(&self.path, path)))
+    }
+
+    fn parent(&self) -> Option<Self> {
+        let mut names = ops::normalize(&self.path);
+        names.pop()?;
+        Some(FatPath::new(self.fs.clone(), names.join("/")))
+    }
+
+    /// FAT has no symbolic links, so this only restores the case of every
+    /// name as stored on the volume
+    fn canonicalize(&self) -> Self::Canonicalize {
+        let this = self.clone();
+        Box::pin(async move {
+            let mut inner = this.fs.inner.lock().await;
+            let path = inner
+                .canonical(&ops::components(&this.path))
+                .await
+                .map_err(|err| this.context(err, Operation::Canonicalize))?;
+            drop(inner);
+            Ok(FatPath::new(this.fs, path))
+        })
+    }
+
+    fn metadata(&self) -> Self::Metadata {
+        let this = self.clone();
+        Box::pin(async move {
+            let mut inner = this.fs.inner.lock().await;
+            inner
+                .metadata(&ops::components(&this.path))
+                .await
+                .map_err(|err| this.context(err, Operation::Metadata))
+        })
+    }
+
+    fn open(&self, options: OpenOptions) -> Self::Open {
+        let this = self.clone();
+        Box::pin(async move {
+            let mut inner = this.fs.inner.lock().await;
+            let (state, created) = inner
+                .open(&ops::components(&this.path), options)
+                .await
+                .map_err(|err| this.context(err, Operation::Open))?;
+            drop(inner);
+
+            #[cfg(feature = "std")]
+            if created {
+                this.fs.emit(WatchEvent::Create(this.clone()));
+            }
+            #[cfg(not(feature = "std"))]
+            let _ = created;
+
+            Ok(FatFile::new(this.fs, this.path, state))
+        })
+    }
+
+    fn read_dir(&self) -> Self::ReadDir {
+        let this = self.clone();
+        Box::pin(async move {
+            let mut inner = this.fs.inner.lock().await;
+            let entries = inner
+                .list(&ops::components(&this.path))
+                .await
+                .map_err(|err| this.context(err, Operation::ReadDir))?;
+
+            // Directories are read as a whole, so the metadata of each entry comes for free
+            let entries = entries
+                .into_iter()
+                .map(|entry| {
+                    let path = this.child(&entry.name);
+                    Ok(DirEntry::with_metadata(path, ops::entry_metadata(&entry)))
+                })
+                .collect::<Vec<_>>();
+
+            Ok(Box::pin(futures::stream::iter(entries)) as Self::ListDir)
+        })
+    }
+
+    fn create_dir(&self) -> Self::CreateDir {
+        let this = self.clone();
+        Box::pin(async move {
+            let mut inner = this.fs.inner.lock().await;
+            let components = inner
+                .resolve(&ops::components(&this.path))
+                .await
+                .map_err(|err| this.context(err, Operation::CreateDir))?;
+            let existing = inner
+                .create_dir_all(&components)
+                .await
+                .map_err(|err| this.context(err, Operation::CreateDir))?;
+            drop(inner);
+
+            #[cfg(feature = "std")]
+            for depth in existing..components.len() {
+                let path = components[..=depth].join("/");
+                this.fs
+                    .emit(WatchEvent::Create(FatPath::new(this.fs.clone(), path)));
+            }
+            #[cfg(not(feature = "std"))]
+            let _ = existing;
+
+            Ok(())
+        })
+    }
+
+    fn rm(&self) -> Self::Remove {
+        let this = self.clone();
+        Box::pin(async move {
+            let mut inner = this.fs.inner.lock().await;
+            inner
+                .remove(&ops::components(&this.path))
+                .await
+                .map_err(|err| this.context(err, Operation::Remove))?;
+            drop(inner);
+
+            #[cfg(feature = "std")]
+            this.fs.emit(WatchEvent::Remove(this.clone()));
+
+            Ok(())
+        })
+    }
+
+    fn set_permissions(&self, permissions: Permissions) -> Self::SetPermissions {
+        let this = self.clone();
+        Box::pin(async move {
+            let mut inner = this.fs.inner.lock().await;
+            inner
+                .set_permissions(&ops::components(&this.path), permissions)
+                .await
+                .map_err(|err| this.context(err, Operation::SetPermissions))?;
+            drop(inner);
+
+            #[cfg(feature = "std")]
+            this.fs.emit(WatchEvent::Modify(this.clone()));
+
+            Ok(())
+        })
+    }
+
+    fn set_times(&self, times: FileTimes) -> Self::SetTimes {
+        let this = self.clone();
+        Box::pin(async move {
+            let mut inner = this.fs.inner.lock().await;
+            inner
+                .set_times(&ops::components(&this.path), times)
+                .await
+                .map_err(|err| this.context(err, Operation::SetTimes))?;
+            drop(inner);
+
+            #[cfg(feature = "std")]
+            this.fs.emit(WatchEvent::Modify(this.clone()));
+
+            Ok(())
+        })
+    }
+
+    fn set_owner(&self, _uid: Option<u32>, _gid: Option<u32>) -> Self::SetOwner {
+        let err = self.context(
+            unsupported("FAT has no file ownership"),
+            Operation::SetOwner,
+        );
+        Box::pin(async move { Err(err) })
+    }
+
+    fn supports_xattr(&self) -> bool {
+        false
+    }
+
+    fn get_xattr(&self, _name: &str) -> Self::GetXattr {
+        let err = self.context(no_xattr(), Operation::GetXattr);
+        Box::pin(async move { Err(err) })
+    }
+
+    fn set_xattr(&self, _name: &str, _value: &[u8]) -> Self::SetXattr {
+        let err = self.context(no_xattr(), Operation::SetXattr);
+        Box::pin(async move { Err(err) })
+    }
+
+    fn list_xattr(&self) -> Self::ListXattr {
+        let err = self.context(no_xattr(), Operation::ListXattr);
+        Box::pin(async move { Err(err) })
+    }
+
+    fn remove_xattr(&self, _name: &str) -> Self::RemoveXattr {
+        let err = self.context(no_xattr(), Operation::RemoveXattr);
+        Box::pin(async move { Err(err) })
+    }
+
+    #[cfg(feature = "std")]
+    fn watch(&self, recursive: bool) -> Self::Watch {
+        let watcher = self.fs.hub.subscribe(self, recursive);
+        Box::pin(async move { Ok(Box::pin(watcher) as Self::Watcher) })
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn watch(&self, _recursive: bool) -> Self::Watch {
+        let err = self.context(
+            unsupported("watching requires the std feature"),
+            Operation::Watch,
+        );
+        Box::pin(async move { Err(err) })
+    }
+}
+
+const fn unsupported(message: &'static str) -> Error {
+    Error::new_const(ErrorKind::Unsupported, message)
+}
+
+const fn no_xattr() -> Error {
+    unsupported("FAT has no extended attributes")
+}
@@ -0,0 +1,370 @@
+use alloc::{string::String, vec::Vec};
+
+use vfs::{Error, ErrorKind, FileTimes, FileType, Metadata, OpenOptions, Permissions, VFile};
+
+use crate::{
+    dir::{ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_READ_ONLY, Dir, DirLoc, Entry},
+    file::FileState,
+    inner::Inner,
+};
+
+pub(crate) fn entry_metadata(entry: &Entry) -> Metadata {
+    let (kind, mode) = if entry.is_dir() {
+        (FileType::Dir, 0o755)
+    } else {
+        (FileType::File, 0o644)
+    };
+
+    let mut permissions = Permissions::from_mode(mode);
+    permissions.set_readonly(entry.readonly());
+
+    Metadata {
+        size: if entry.is_dir() {
+            0
+        } else {
+            entry.size() as u64
+        },
+        kind,
+        permissions: Some(permissions),
+        accessed: entry.accessed(),
+        modified: entry.modified(),
+        created: entry.created(),
+        uid: None,
+        gid: None,
+        hash: None,
+    }
+}
+
+fn root_metadata() -> Metadata {
+    Metadata {
+        size: 0,
+        kind: FileType::Dir,
+        permissions: Some(Permissions::from_mode(0o755)),
+        accessed: None,
+        modified: None,
+        created: None,
+        uid: None,
+        gid: None,
+        hash: None,
+    }
+}
+
+impl<F> Inner<F>
+where
+    F: VFile + Unpin,
+{
+    /// Apply the `..` components of a path. Without symbolic links `..` leads
+    /// to the parent of the name before it, which has to be a directory
+    pub async fn resolve<'a>(&mut self, components: &[&'a str]) -> Result<Vec<&'a str>, Error> {
+        let mut resolved = Vec::with_capacity(components.len());
+
+        for &name in components {
+            if name == ".." {
+                self.lookup_dir(&resolved).await?;
+                resolved.pop();
+            } else {
+                resolved.push(name);
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    /// Walk the directories leading up to the last component
+    async fn parent_dir(&mut self, components: &[&str]) -> Result<Dir, Error> {
+        let mut dir = self.read_dir(self.root_loc()).await?;
+
+        for name in components.iter().take(components.len().saturating_sub(1)) {
+            let entry = dir.find(self, name).ok_or(ErrorKind::NotFound)?;
+            if !entry.is_dir() {
+                return Err(ErrorKind::NotADirectory.into());
+            }
+            dir = self.read_dir(entry.dir_loc(self.root_loc())).await?;
+        }
+
+        Ok(dir)
+    }
+
+    /// Find the entry of a path along with the directory containing it.
+    /// Returns `None` for the root directory, which has no entry
+    async fn lookup(&mut self, components: &[&str]) -> Result<Option<(Dir, Entry)>, Error> {
+        let Some(name) = components.last() else {
+            return Ok(None);
+        };
+
+        let dir = self.parent_dir(components).await?;
+        let entry = dir.find(self, name).ok_or(ErrorKind::NotFound)?;
+
+        Ok(Some((dir, entry)))
+    }
+
+    async fn lookup_dir(&mut self, components: &[&str]) -> Result<Dir, Error> {
+        let loc = match self.lookup(components).await? {
+            None => self.root_loc(),
+            Some((_, entry)) if entry.is_dir() => entry.dir_loc(self.root_loc()),
+            Some(_) => return Err(ErrorKind::NotADirectory.into()),
+        };
+
+        self.read_dir(loc).await
+    }
+
+    /// The path with every component spelled as stored on the volume
+    pub async fn canonical(&mut self, components: &[&str]) -> Result<String, Error> {
+        let components = &self.resolve(components).await?;
+        let mut dir = self.read_dir(self.root_loc()).await?;
+        let mut names = Vec::with_capacity(components.len());
+
+        for (idx, name) in components.iter().enumerate() {
+            let entry = dir.find(self, name).ok_or(ErrorKind::NotFound)?;
+            if idx + 1 < components.len() {
+                if !entry.is_dir() {
+                    return Err(ErrorKind::NotADirectory.into());
+                }
+                dir = self.read_dir(entry.dir_loc(self.root_loc())).await?;
+            }
+            names.push(entry.name);
+        }
+
+        Ok(names.join("/"))
+    }
+
+    pub async fn metadata(&mut self, components: &[&str]) -> Result<Metadata, Error> {
+        let components = &self.resolve(components).await?;
+        Ok(match self.lookup(components).await? {
+            Some((_, entry)) => entry_metadata(&entry),
+            None => root_metadata(),
+        })
+    }
+
+    pub async fn list(&mut self, components: &[&str]) -> Result<Vec<Entry>, Error> {
+        let components = &self.resolve(components).await?;
+        let dir = self.lookup_dir(components).await?;
+        Ok(dir.entries(self))
+    }
+
+    /// Create a directory and all missing parents. Returns the number of
+    /// leading components which existed already. `components` have to be
+    /// [resolved](Inner::resolve) first
+    pub async fn create_dir_all(&mut self, components: &[&str]) -> Result<usize, Error> {
+        let mut dir = self.read_dir(self.root_loc()).await?;
+        let mut existing = components.len();
+
+        for (idx, name) in components.iter().enumerate() {
+            let entry = match dir.find(self, name) {
+                Some(entry) if entry.is_dir() => entry,
+                Some(_) if idx + 1 == components.len() => {
+                    return Err(Error::new_const(
+                        ErrorKind::AlreadyExists,
+                        "a file with the same name exists",
+                    ));
+                }
+                Some(_) => return Err(ErrorKind::NotADirectory.into()),
+                None => {
+                    existing = existing.min(idx);
+                    let cluster = self.init_dir(dir.loc).await?;
+                    match self
+                        .create_entry(&mut dir, name, ATTR_DIRECTORY, cluster)
+                        .await
+                    {
+                        Ok(entry) => entry,
+                        Err(err) => {
+                            self.free_chain(cluster).await?;
+                            return Err(err);
+                        }
+                    }
+                }
+            };
+
+            dir = self.read_dir(entry.dir_loc(self.root_loc())).await?;
+        }
+
+        self.flush().await?;
+
+        Ok(existing)
+    }
+
+    /// Release the clusters of everything below a directory. The whole tree is
+    /// read before anything is freed, since a subdirectory can only be listed
+    /// while its chain is intact
+    async fn free_tree(&mut self, loc: DirLoc) -> Result<(), Error> {
+        let mut stack = Vec::from([loc]);
+        let mut chains = Vec::new();
+
+        while let Some(loc) = stack.pop() {
+            let dir = self.read_dir(loc).await?;
+            for entry in dir.entries(self) {
+                if entry.is_dir() {
+                    stack.push(entry.dir_loc(self.root_loc()));
+                }
+                chains.push(entry.first_cluster());
+            }
+        }
+
+        // Deepest first, so an interrupted removal leaves no entries pointing
+        // at freed clusters above those still in use
+        for first in chains.into_iter().rev() {
+            self.free_chain(first).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn remove(&mut self, components: &[&str]) -> Result<(), Error> {
+        let components = &self.resolve(components).await?;
+        let Some((mut dir, entry)) = self.lookup(components).await? else {
+            return Err(Error::new_const(
+                ErrorKind::InvalidInput,
+                "the root directory cannot be removed",
+            ));
+        };
+
+        if entry.is_dir() {
+            self.free_tree(entry.dir_loc(self.root_loc())).await?;
+        }
+
+        self.remove_entry(&mut dir, &entry).await?;
+        self.free_chain(entry.first_cluster()).await?;
+
+        self.flush().await
+    }
+
+    /// Apply a change to the entry of a path. The root directory has no entry
+    /// to store attributes in, so changes to it are ignored
+    async fn update_entry(
+        &mut self,
+        components: &[&str],
+        update: impl FnOnce(&mut Entry),
+    ) -> Result<(), Error> {
+        let components = &self.resolve(components).await?;
+        if let Some((_, mut entry)) = self.lookup(components).await? {
+            update(&mut entry);
+            self.write_entry(&entry).await?;
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn set_permissions(
+        &mut self,
+        components: &[&str],
+        permissions: Permissions,
+    ) -> Result<(), Error> {
+        self.update_entry(components, |entry| {
+            let attr = entry.attr() & !ATTR_READ_ONLY;
+            if permissions.readonly() {
+                entry.set_attr(attr | ATTR_READ_ONLY);
+            } else {
+                entry.set_attr(attr);
+            }
+        })
+        .await
+    }
+
+    pub async fn set_times(&mut self, components: &[&str], times: FileTimes) -> Result<(), Error> {
+        self.update_entry(components, |entry| {
+            if let Some(accessed) = times.accessed {
+                entry.set_accessed(accessed);
+            }
+            if let Some(modified) = times.modified {
+                entry.set_modified(modified);
+            }
+        })
+        .await
+    }
+
+    /// Open or create a file. Returns the file state and whether the file was created
+    pub async fn open(
+        &mut self,
+        components: &[&str],
+        options: OpenOptions,
+    ) -> Result<(FileState, bool), Error> {
+        let components = &self.resolve(components).await?;
+        let writable = options.write || options.append;
+        if (options.create || options.truncate) && !writable {
+            return Err(Error::new_const(
+                ErrorKind::InvalidInput,
+                "creating or truncating a file requires write access",
+            ));
+        }
+
+        let Some(name) = components.last() else {
+            return Err(Error::new_const(
+                ErrorKind::IsADirectory,
+                "the root directory cannot be opened as a file",
+            ));
+        };
+
+        let mut dir = self.parent_dir(components).await?;
+
+        let (mut entry, created) = match dir.find(self, name) {
+            Some(entry) => (entry, false),
+            None if options.create => {
+                let entry = self.create_entry(&mut dir, name, ATTR_ARCHIVE, 0).await?;
+                self.flush().await?;
+                (entry, true)
+            }
+            None => return Err(ErrorKind::NotFound.into()),
+        };
+
+        if entry.is_dir() {
+            return Err(Error::new_const(ErrorKind::IsADirectory, "is a directory"));
+        }
+
+        if writable && entry.readonly() {
+            return Err(Error::new_const(
+                ErrorKind::PermissionDenied,
+                "the file is read only",
+            ));
+        }
+
+        if options.truncate && entry.first_cluster() != 0 {
+            self.free_chain(entry.first_cluster()).await?;
+            entry.set_first_cluster(0);
+            entry.set_size(0);
+            entry.set_modified(self.now());
+            self.write_entry(&entry).await?;
+            self.flush().await?;
+        }
+
+        let clusters = self.chain(entry.first_cluster()).await?;
+
+        Ok((FileState::new(entry, clusters, options), created))
+    }
+}
+
+/// Split a normalized path into its components
+pub(crate) fn components(path: &str) -> Vec<&str> {
+    path.split('/').filter(|part| !part.is_empty()).collect()
+}
+
+/// The names a path leads to once every `..` steps out of the name before
+/// it, which is where the lookup ends up as FAT has no symbolic links
+pub(crate) fn normalize(path: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    for part in components(path) {
+        match part {
+            ".." => {
+                names.pop();
+            }
+            name => names.push(name),
+        }
+    }
+    names
+}
+
+/// Resolve `path` against the path `base`. `..` is kept for the lookup,
+/// which checks that it follows a directory. A `..` with nothing before it
+/// stays at the root and is dropped
+pub(crate) fn join(base: &str, path: &str) -> String {
+    let mut parts = components(base);
+
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if parts.is_empty() => {}
+            part => parts.push(part),
+        }
+    }
+
+    parts.join("/")
+}
@@ -0,0 +1,80 @@
+use core::time::Duration;
+
+use vfs::Timestamp;
+
+/// Seconds between the unix epoch and 1980-01-01, the earliest FAT date
+const FAT_EPOCH: u64 = 315_532_800;
+
+/// Seconds past the unix epoch of 2107-12-31 23:59:58, the latest FAT date
+const FAT_END: u64 = 4_354_819_198;
+
+/// Days since the unix epoch of a proleptic gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Gregorian date of a number of days since the unix epoch
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Decode a FAT date, time and 10ms resolution offset.
+/// Returns `None` for the zero date used by entries without a timestamp
+pub(crate) fn decode(date: u16, time: u16, tenths: u8) -> Option<Timestamp> {
+    if date == 0 {
+        return None;
+    }
+
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xF).clamp(1, 12) as u32;
+    let day = (date & 0x1F).max(1) as u32;
+
+    let seconds = (time >> 11) as u64 * 3600
+        + ((time >> 5) & 0x3F) as u64 * 60
+        + (time & 0x1F) as u64 * 2
+        + tenths as u64 / 100;
+
+    let days = days_from_civil(year, month, day) as u64;
+    let millis = (tenths as u64 % 100) * 10;
+
+    Some(Timestamp::from_unix(
+        Duration::from_secs(days * 86_400 + seconds) + Duration::from_millis(millis),
+    ))
+}
+
+/// Encode a timestamp as FAT date, time and 10ms resolution offset.
+/// Times outside of the range FAT can represent are clamped
+pub(crate) fn encode(timestamp: Timestamp) -> (u16, u16, u8) {
+    let unix = timestamp.as_unix();
+    let secs = unix.as_secs().clamp(FAT_EPOCH, FAT_END);
+    let millis = if secs == unix.as_secs() {
+        unix.subsec_millis()
+    } else {
+        0
+    };
+
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let rem = secs % 86_400;
+
+    let date = (((year - 1980) as u16) << 9) | ((month as u16) << 5) | day as u16;
+    let time =
+        (((rem / 3600) as u16) << 11) | ((((rem / 60) % 60) as u16) << 5) | ((rem % 60) / 2) as u16;
+    let tenths = ((rem % 2) * 100) as u8 + (millis / 10) as u8;
+
+    (date, time, tenths)
+}
@@ -0,0 +1,297 @@
+use futures::{TryStreamExt, executor::block_on};
+use vfs::{ErrorKind, FileType, OpenOptions, Permissions, VFS, VFileExt, VPath};
+use vfs_fat::{FatFS, FatType};
+use vfs_test::{Device, names, read_file, write_file};
+
+const SECTOR: usize = 512;
+
+/// Geometry of a formatted test image
+struct Image {
+    data: Vec<u8>,
+    /// Byte offset of the fixed root directory on FAT12/16
+    root_dir: usize,
+}
+
+/// Format an image with one sector per cluster, sized so that the cluster
+/// count selects `fat_type`
+fn format(fat_type: FatType) -> Image {
+    let (total, reserved, root_entries, bits) = match fat_type {
+        FatType::Fat12 => (2048, 1, 512, 12),
+        FatType::Fat16 => (16384, 1, 512, 16),
+        FatType::Fat32 => (70000, 32, 0, 32),
+    };
+    let root_sectors = root_entries * 32 / SECTOR;
+
+    // The FAT covers the clusters left over after the FATs themselves
+    let mut fat_sectors = 1;
+    loop {
+        let clusters = total - reserved - 2 * fat_sectors - root_sectors;
+        let needed = ((clusters + 2) * bits / 8).div_ceil(SECTOR);
+        if needed <= fat_sectors {
+            break;
+        }
+        fat_sectors = needed;
+    }
+
+    let mut data = vec![0u8; total * SECTOR];
+    let boot = &mut data[..SECTOR];
+    boot[..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+    boot[3..11].copy_from_slice(b"MSWIN4.1");
+    boot[11..13].copy_from_slice(&(SECTOR as u16).to_le_bytes());
+    boot[13] = 1;
+    boot[14..16].copy_from_slice(&(reserved as u16).to_le_bytes());
+    boot[16] = 2;
+    boot[17..19].copy_from_slice(&(root_entries as u16).to_le_bytes());
+    boot[21] = 0xF8;
+    if total < 0x10000 {
+        boot[19..21].copy_from_slice(&(total as u16).to_le_bytes());
+    } else {
+        boot[32..36].copy_from_slice(&(total as u32).to_le_bytes());
+    }
+    if fat_type == FatType::Fat32 {
+        boot[36..40].copy_from_slice(&(fat_sectors as u32).to_le_bytes());
+        boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+        boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+    } else {
+        boot[22..24].copy_from_slice(&(fat_sectors as u16).to_le_bytes());
+    }
+    boot[510] = 0x55;
+    boot[511] = 0xAA;
+
+    if fat_type == FatType::Fat32 {
+        let fsinfo = &mut data[SECTOR..2 * SECTOR];
+        fsinfo[..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+        fsinfo[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+        fsinfo[488..496].fill(0xFF);
+        fsinfo[508..512].copy_from_slice(&0xAA55_0000u32.to_le_bytes());
+    }
+
+    // Media descriptor and end of chain markers, along with the root
+    // directory cluster on FAT32
+    let reserved_entries: &[u8] = match fat_type {
+        FatType::Fat12 => &[0xF8, 0xFF, 0xFF],
+        FatType::Fat16 => &[0xF8, 0xFF, 0xFF, 0xFF],
+        FatType::Fat32 => &[
+            0xF8, 0xFF, 0xFF, 0x0F, 0xFF, 0xFF, 0xFF, 0x0F, 0xFF, 0xFF, 0xFF, 0x0F,
+        ],
+    };
+    for fat in 0..2 {
+        let start = (reserved + fat * fat_sectors) * SECTOR;
+        data[start..start + reserved_entries.len()].copy_from_slice(reserved_entries);
+    }
+
+    Image {
+        data,
+        root_dir: (reserved + 2 * fat_sectors) * SECTOR,
+    }
+}
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+        .collect()
+}
+
+async fn free(fs: &FatFS<Device>) -> u64 {
+    fs.stats().await.unwrap().free
+}
+
+fn round_trip(fat_type: FatType) {
+    block_on(async {
+        let device = Device::new(format(fat_type).data);
+        let fs = FatFS::new(device.clone()).await.unwrap();
+        assert_eq!(fs.fat_type(), fat_type);
+
+        let empty = free(&fs).await;
+
+        // A file spanning several clusters, grown over two writes
+        let big = pattern(5000, 1);
+        let path = fs.path("/docs/A rather long file name.txt").unwrap();
+        fs.path("/docs").unwrap().create_dir().await.unwrap();
+        let options = OpenOptions::new().write(true).create(true);
+        let mut file = path.open(options).await.unwrap();
+        file.write_all(&big[..700]).await.unwrap();
+        file.write_all(&big[700..]).await.unwrap();
+        file.close().await.unwrap();
+
+        let small = pattern(100, 2);
+        write_file(&fs.path("/README.TXT").unwrap(), &small).await;
+
+        let before_tree = free(&fs).await;
+
+        // A tree with a directory spanning several clusters, to be removed whole
+        fs.path("/tree/sub/deep")
+            .unwrap()
+            .create_dir()
+            .await
+            .unwrap();
+        for i in 0..40 {
+            let path = fs
+                .path(format!("/tree/sub/entry with a long name {i}"))
+                .unwrap();
+            write_file(&path, &pattern(600, i)).await;
+        }
+        write_file(&fs.path("/tree/sub/deep/leaf").unwrap(), &big).await;
+        assert_eq!(names(&fs.path("/tree/sub").unwrap()).await.len(), 41);
+
+        fs.path("/tree").unwrap().rm().await.unwrap();
+        assert_eq!(free(&fs).await, before_tree);
+
+        fs.flush().await.unwrap();
+        drop(path);
+        drop(fs);
+
+        let fs = FatFS::new(device.reopen()).await.unwrap();
+        assert_eq!(fs.fat_type(), fat_type);
+        assert_eq!(names(&fs.path("/").unwrap()).await, ["README.TXT", "docs"]);
+        assert_eq!(
+            names(&fs.path("/docs").unwrap()).await,
+            ["A rather long file name.txt"]
+        );
+
+        let path = fs.path("/docs/a rather LONG file name.txt").unwrap();
+        assert_eq!(read_file(&path).await, big);
+        assert_eq!(path.metadata().await.unwrap().size, 5000);
+        assert_eq!(read_file(&fs.path("/readme.txt").unwrap()).await, small);
+
+        let err = fs.path("/tree").unwrap().metadata().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(free(&fs).await, before_tree);
+
+        fs.path("/docs").unwrap().rm().await.unwrap();
+        fs.path("/README.TXT").unwrap().rm().await.unwrap();
+        assert_eq!(free(&fs).await, empty);
+    });
+}
+
+#[test]
+fn round_trip_fat12() {
+    round_trip(FatType::Fat12);
+}
+
+#[test]
+fn round_trip_fat16() {
+    round_trip(FatType::Fat16);
+}
+
+#[test]
+fn round_trip_fat32() {
+    round_trip(FatType::Fat32);
+}
+
+#[test]
+fn flush_keeps_attributes_set_while_open() {
+    block_on(async {
+        let fs = FatFS::new(Device::new(format(FatType::Fat16).data))
+            .await
+            .unwrap();
+        let path = fs.path("/file").unwrap();
+
+        let options = OpenOptions::new().write(true).create(true);
+        let mut file = path.open(options).await.unwrap();
+        file.write_all(&pattern(1500, 3)).await.unwrap();
+
+        let mut permissions = Permissions::from_mode(0o644);
+        permissions.set_readonly(true);
+        path.set_permissions(permissions).await.unwrap();
+        file.close().await.unwrap();
+
+        let metadata = path.metadata().await.unwrap();
+        assert_eq!(metadata.size, 1500);
+        assert!(metadata.permissions.unwrap().readonly());
+    });
+}
+
+#[test]
+fn flush_after_remove_leaves_entry_removed() {
+    block_on(async {
+        let fs = FatFS::new(Device::new(format(FatType::Fat12).data))
+            .await
+            .unwrap();
+        let empty = free(&fs).await;
+
+        for name in ["/empty", "/written"] {
+            let path = fs.path(name).unwrap();
+            if name == "/written" {
+                write_file(&path, &pattern(700, 4)).await;
+            }
+
+            let options = OpenOptions::new().write(true).create(true).append(true);
+            let mut file = path.open(options).await.unwrap();
+            file.write_all(&pattern(2000, 5)).await.unwrap();
+            path.rm().await.unwrap();
+            file.close().await.unwrap();
+
+            let err = path.metadata().await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::NotFound);
+            assert_eq!(free(&fs).await, empty);
+        }
+
+        assert!(names(&fs.path("/").unwrap()).await.is_empty());
+    });
+}
+
+#[test]
+fn orphaned_long_name_slot() {
+    block_on(async {
+        let image = format(FatType::Fat16);
+        let root_dir = image.root_dir;
+        let device = Device::new(image.data);
+
+        let fs = FatFS::new(device.clone()).await.unwrap();
+        write_file(&fs.path("/a long name.txt").unwrap(), b"data").await;
+        drop(fs);
+
+        // Mark the only long name slot as the last one with sequence number 0
+        device.data()[root_dir] = 0x40;
+
+        let fs = FatFS::new(device.reopen()).await.unwrap();
+        let entries: Vec<_> = fs
+            .path("/")
+            .unwrap()
+            .read_dir()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].file_type(), FileType::File);
+        assert_ne!(entries[0].name(), "a long name.txt");
+        assert_eq!(read_file(entries[0].path()).await, b"data");
+    });
+}
+
+#[test]
+fn parent_components() {
+    block_on(async {
+        let fs = FatFS::new(Device::new(format(FatType::Fat16).data))
+            .await
+            .unwrap();
+        fs.path("/docs/sub").unwrap().create_dir().await.unwrap();
+        write_file(&fs.path("/file").unwrap(), b"top").await;
+
+        let path = fs.path("/docs/sub/../../file").unwrap();
+        assert_eq!(path.virtual_path(), "/file");
+        assert_eq!(path, fs.path("/file").unwrap());
+        assert_eq!(read_file(&path).await, b"top");
+        assert_eq!(path.canonicalize().await.unwrap().virtual_path(), "/file");
+
+        // The name and parent are those of where the path leads
+        let path = fs.path("/docs/sub/..").unwrap();
+        assert_eq!(path.file_name(), Some("docs"));
+        assert_eq!(path.parent().unwrap().virtual_path(), "/");
+        assert!(fs.path("/docs/..").unwrap().parent().is_none());
+
+        assert_eq!(fs.path("/../file").unwrap().virtual_path(), "/file");
+        assert_eq!(names(&fs.path("/docs/..").unwrap()).await, ["docs", "file"]);
+
+        fs.path("/docs/../new").unwrap().create_dir().await.unwrap();
+        assert_eq!(names(&fs.path("/").unwrap()).await, ["docs", "file", "new"]);
+
+        let err = fs.path("/file/../docs").unwrap().metadata().await;
+        assert_eq!(err.unwrap_err().kind(), ErrorKind::NotADirectory);
+        let err = fs.path("/missing/../docs").unwrap().metadata().await;
+        assert_eq!(err.unwrap_err().kind(), ErrorKind::NotFound);
+    });
+}
@@ -0,0 +1,146 @@
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use core::{
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use vfs::{Error, ErrorKind, Operation, SeekFrom, VFile};
+
+use crate::volume::Volume;
+
+/// Upper bound of a single read from the device
+const MAX_READ: u64 = 64 * 1024;
+
+type ReadFuture = Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>> + Send + Sync>>;
+
+/// A file in an ISO 9660 image
+pub struct IsoFile<F> {
+    volume: Arc<Volume<F>>,
+    path: String,
+    extents: Vec<(u64, u64)>,
+    size: u64,
+    pos: u64,
+    read: Option<ReadFuture>,
+}
+
+impl<F> IsoFile<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    pub(crate) fn new(
+        volume: Arc<Volume<F>>,
+        path: String,
+        extents: Vec<(u64, u64)>,
+    ) -> IsoFile<F> {
+        IsoFile {
+            volume,
+            path,
+            size: extents.iter().map(|(_, len)| len).sum(),
+            extents,
+            pos: 0,
+            read: None,
+        }
+    }
+
+    fn context(&self, err: Error, operation: Operation) -> Error {
+        err.with_operation(operation)
+            .with_path(format!("/{}", self.path))
+    }
+
+    /// The device range holding the data at the current position
+    fn segment(&self, len: usize) -> Option<(u64, u64)> {
+        let mut start = 0;
+        for &(offset, extent) in &self.extents {
+            if self.pos < start + extent {
+                let within = self.pos - start;
+                let len = (extent - within).min(len as u64).min(MAX_READ);
+                return Some((offset + within, len));
+            }
+            start += extent;
+        }
+        None
+    }
+}
+
+impl<F> VFile for IsoFile<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+
+        if this.read.is_none() {
+            let Some((offset, len)) = this.segment(buf.len()).filter(|_| !buf.is_empty()) else {
+                return Poll::Ready(Ok(0));
+            };
+
+            let volume = this.volume.clone();
+            this.read = Some(Box::pin(async move {
+                volume.read_at(offset, len as usize).await
+            }));
+        }
+
+        let ret = ready!(this.read.as_mut().unwrap().as_mut().poll(cx));
+        this.read = None;
+
+        Poll::Ready(match ret {
+            Ok(data) => {
+                let n = data.len().min(buf.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                this.pos += n as u64;
+                Ok(n)
+            }
+            Err(err) => Err(this.context(err, Operation::Read)),
+        })
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        let this = self.get_mut();
+        // A pending read has no side effects and can be abandoned
+        this.read = None;
+
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => this.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => this.pos.checked_add_signed(offset),
+        };
+
+        Poll::Ready(match pos {
+            Some(pos) => {
+                this.pos = pos;
+                Ok(pos)
+            }
+            None => {
+                let err = Error::new_const(
+                    ErrorKind::InvalidInput,
+                    "invalid seek to a negative or overflowing position",
+                );
+                Err(this.context(err, Operation::Seek))
+            }
+        })
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Poll::Ready(Err(self.context(crate::read_only(), Operation::Write)))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
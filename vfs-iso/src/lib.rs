@@ -0,0 +1,518 @@
+//! A read-only [`VFS`] backed by an ISO 9660 image, read from any [`VFile`].
+//!
+//! Rock Ridge names, permissions, owners, times and symbolic links are used
+//! when present, otherwise Joliet names are preferred over plain ISO 9660 names.
+#![no_std]
+
+extern crate alloc;
+
+mod file;
+mod volume;
+
+pub use self::file::IsoFile;
+
+use alloc::{boxed::Box, collections::VecDeque, format, string::String, sync::Arc, vec::Vec};
+
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use vfs::{
+    Capabilities, DirEntry, Error, ErrorKind, FileTimes, FileType, FsStats, Metadata, OpenOptions,
+    Operation, Permissions, VFS, VFile, VPath, VPathStr, WatchEvent,
+};
+
+use self::volume::{Names, Record, Volume};
+
+/// Symbolic links followed while resolving a single path
+const MAX_SYMLINKS: usize = 40;
+
+/// A mounted ISO 9660 image
+pub struct IsoFS<F> {
+    volume: Arc<Volume<F>>,
+}
+
+impl<F> Clone for IsoFS<F> {
+    fn clone(&self) -> Self {
+        IsoFS {
+            volume: self.volume.clone(),
+        }
+    }
+}
+
+impl<F> core::fmt::Debug for IsoFS<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("IsoFS")
+            .field("label", &self.volume.label)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F> IsoFS<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    /// Mount the image starting at the beginning of `device`
+    pub async fn new(device: F) -> Result<IsoFS<F>, Error> {
+        Ok(IsoFS {
+            volume: Arc::new(Volume::open(device).await?),
+        })
+    }
+
+    /// The volume identifier
+    pub fn label(&self) -> &str {
+        &self.volume.label
+    }
+
+    /// Returns true if the image carries Rock Ridge extensions
+    pub fn rock_ridge(&self) -> bool {
+        matches!(self.volume.names, Names::RockRidge(_))
+    }
+}
+
+impl<F> VFS for IsoFS<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    type Path = IsoPath<F>;
+
+    type Stats = BoxFuture<'static, Result<FsStats, Error>>;
+
+    fn path(&self, path: impl AsRef<VPathStr>) -> Result<Self::Path, Error> {
+        Ok(IsoPath::new(self.clone(), join("", path.as_ref().as_str())))
+    }
+
+    fn stats(&self) -> Self::Stats {
+        let total = self.volume.size;
+        Box::pin(async move {
+            Ok(FsStats {
+                block_size: 2048,
+                total,
+                free: 0,
+                available: 0,
+            })
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let rock_ridge = self.rock_ridge();
+        Capabilities {
+            symlinks: rock_ridge,
+            atomic_rename: false,
+            xattr: false,
+            seek: true,
+            write: false,
+            case_sensitive: rock_ridge,
+            max_name_len: Some(match self.volume.names {
+                Names::RockRidge(_) => 255,
+                Names::Joliet => 64,
+                Names::Iso => 30,
+            }),
+        }
+    }
+}
+
+impl<F> Volume<F>
+where
+    F: VFile + Unpin,
+{
+    fn name_matches(&self, record: &Record, name: &str) -> bool {
+        match self.names {
+            Names::RockRidge(_) => record.name == name,
+            _ => record.name.eq_ignore_ascii_case(name),
+        }
+    }
+
+    /// Find the record of a path, following symbolic links
+    async fn lookup(&self, path: &str) -> Result<Record, Error> {
+        Ok(self.walk(path).await?.1)
+    }
+
+    /// Find the record of a path, following symbolic links, along with the
+    /// canonical path leading to it
+    async fn walk(&self, path: &str) -> Result<(String, Record), Error> {
+        let mut stack = Vec::from([self.root.clone()]);
+        let mut names: Vec<String> = Vec::new();
+        let mut queue: VecDeque<String> = components(path).map(String::from).collect();
+        let mut links = 0;
+
+        while let Some(name) = queue.pop_front() {
+            match name.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    if !stack.last().unwrap().is_dir() {
+                        return Err(Error::new_const(
+                            ErrorKind::NotADirectory,
+                            "not a directory",
+                        ));
+                    }
+                    if stack.len() > 1 {
+                        stack.pop();
+                        names.pop();
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            let dir = stack.last().unwrap();
+            if !dir.is_dir() {
+                return Err(Error::new_const(
+                    ErrorKind::NotADirectory,
+                    "not a directory",
+                ));
+            }
+
+            let record = self
+                .read_dir(dir)
+                .await?
+                .into_iter()
+                .find(|record| self.name_matches(record, &name))
+                .ok_or(Error::new_const(
+                    ErrorKind::NotFound,
+                    "no such file or directory",
+                ))?;
+
+            match record.target {
+                Some(target) => {
+                    links += 1;
+                    if links > MAX_SYMLINKS {
+                        return Err(Error::new_const(
+                            ErrorKind::FilesystemLoop,
+                            "too many levels of symbolic links",
+                        ));
+                    }
+
+                    if target.starts_with('/') {
+                        stack.truncate(1);
+                        names.clear();
+                    }
+
+                    for part in target.split('/').rev() {
+                        queue.push_front(part.into());
+                    }
+                }
+                None => {
+                    names.push(record.name.clone());
+                    stack.push(record);
+                }
+            }
+        }
+
+        Ok((names.join("/"), stack.pop().unwrap()))
+    }
+}
+
+/// A path in an ISO 9660 image
+pub struct IsoPath<F> {
+    fs: IsoFS<F>,
+    /// Path without leading slash, empty for the root directory. Any `..`
+    /// left in it is resolved by the lookup
+    path: String,
+}
+
+impl<F> Clone for IsoPath<F> {
+    fn clone(&self) -> Self {
+        IsoPath {
+            fs: self.fs.clone(),
+            path: self.path.clone(),
+        }
+    }
+}
+
+impl<F> core::fmt::Debug for IsoPath<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("IsoPath").field(&self.path).finish()
+    }
+}
+
+/// Paths are equal when they point into the same mounted image
+impl<F> PartialEq for IsoPath<F> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.fs.volume, &other.fs.volume) && self.path == other.path
+    }
+}
+
+impl<F> Eq for IsoPath<F> {}
+
+impl<F> core::hash::Hash for IsoPath<F> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.path.hash(state)
+    }
+}
+
+impl<F> IsoPath<F> {
+    fn new(fs: IsoFS<F>, path: String) -> IsoPath<F> {
+        IsoPath { fs, path }
+    }
+
+    fn child(&self, name: &str) -> IsoPath<F> {
+        let path = if self.path.is_empty() {
+            String::from(name)
+        } else {
+            format!("{}/{}", self.path, name)
+        };
+        IsoPath::new(self.fs.clone(), path)
+    }
+
+    fn context(&self, err: Error, operation: Operation) -> Error {
+        err.with_operation(operation)
+            .with_path(format!("/{}", self.path))
+    }
+
+    fn fail<T: Send + 'static>(
+        &self,
+        err: Error,
+        operation: Operation,
+    ) -> BoxFuture<'static, Result<T, Error>> {
+        let err = self.context(err, operation);
+        Box::pin(async move { Err(err) })
+    }
+}
+
+impl<F> VPath for IsoPath<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    type FS = IsoFS<F>;
+
+    type File = IsoFile<F>;
+
+    type ListDir = BoxStream<'static, Result<DirEntry<Self>, Error>>;
+
+    type Metadata = BoxFuture<'static, Result<Metadata, Error>>;
+
+    type Open = BoxFuture<'static, Result<IsoFile<F>, Error>>;
+
+    type CreateDir = BoxFuture<'static, Result<(), Error>>;
+
+    type Remove = BoxFuture<'static, Result<(), Error>>;
+
+    type ReadDir = BoxFuture<'static, Result<Self::ListDir, Error>>;
+
+    type SetPermissions = BoxFuture<'static, Result<(), Error>>;
+
+    type SetTimes = BoxFuture<'static, Result<(), Error>>;
+
+    type SetOwner = BoxFuture<'static, Result<(), Error>>;
+
+    type GetXattr = BoxFuture<'static, Result<Option<Vec<u8>>, Error>>;
+
+    type SetXattr = BoxFuture<'static, Result<(), Error>>;
+
+    type ListXattr = BoxFuture<'static, Result<Vec<String>, Error>>;
+
+    type RemoveXattr = BoxFuture<'static, Result<(), Error>>;
+
+    type Watcher = BoxStream<'static, Result<WatchEvent<Self>, Error>>;
+
+    type Watch = BoxFuture<'static, Result<Self::Watcher, Error>>;
+
+    type Canonicalize = BoxFuture<'static, Result<Self, Error>>;
+
+    fn fs(&self) -> Self::FS {
+        self.fs.clone()
+    }
+
+    fn virtual_path(&self) -> String {
+        format!("/{}", self.path)
+    }
+
+    fn to_string(&self) -> String {
+        self.virtual_path()
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        self.path
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty() && *name != "..")
+    }
+
+    fn extension(&self) -> Option<&str> {
+        let name = self.file_name()?;
+        match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => Some(ext),
+            _ => None,
+        }
+    }
+
+    fn resolve(&self, path: &str) -> Result<Self, Error> {
+        Ok(IsoPath::new(self.fs.clone(), join(&self.path, path)))
+    }
+
+    fn parent(&self) -> Option<Self> {
+        if self.path.is_empty() {
+            return None;
+        }
+
+        let parent = match self.path.rsplit_once('/') {
+            // Only the lookup knows where `..` leads, so step out of it too
+            _ if self.file_name().is_none() => join(&self.path, ".."),
+            Some((parent, _)) => String::from(parent),
+            None => String::new(),
+        };
+
+        Some(IsoPath::new(self.fs.clone(), parent))
+    }
+
+    fn canonicalize(&self) -> Self::Canonicalize {
+        let this = self.clone();
+        Box::pin(async move {
+            let (path, _) = this
+                .fs
+                .volume
+                .walk(&this.path)
+                .await
+                .map_err(|err| this.context(err, Operation::Canonicalize))?;
+            Ok(IsoPath::new(this.fs, path))
+        })
+    }
+
+    fn metadata(&self) -> Self::Metadata {
+        let this = self.clone();
+        Box::pin(async move {
+            let record = this
+                .fs
+                .volume
+                .lookup(&this.path)
+                .await
+                .map_err(|err| this.context(err, Operation::Metadata))?;
+            Ok(record.metadata())
+        })
+    }
+
+    fn open(&self, options: OpenOptions) -> Self::Open {
+        if options.write || options.append || options.create || options.truncate {
+            return self.fail(read_only(), Operation::Open);
+        }
+
+        let this = self.clone();
+        Box::pin(async move {
+            let record = this
+                .fs
+                .volume
+                .lookup(&this.path)
+                .await
+                .map_err(|err| this.context(err, Operation::Open))?;
+
+            if record.is_dir() {
+                let err = Error::new_const(ErrorKind::IsADirectory, "is a directory");
+                return Err(this.context(err, Operation::Open));
+            }
+
+            Ok(IsoFile::new(this.fs.volume, this.path, record.extents))
+        })
+    }
+
+    fn read_dir(&self) -> Self::ReadDir {
+        let this = self.clone();
+        Box::pin(async move {
+            let volume = &this.fs.volume;
+            let records = async {
+                let dir = volume.lookup(&this.path).await?;
+                if !dir.is_dir() {
+                    return Err(Error::new_const(
+                        ErrorKind::NotADirectory,
+                        "not a directory",
+                    ));
+                }
+                volume.read_dir(&dir).await
+            }
+            .await
+            .map_err(|err| this.context(err, Operation::ReadDir))?;
+
+            let entries = records
+                .into_iter()
+                .map(|record| {
+                    let path = this.child(&record.name);
+                    // Links are followed when asking for the metadata of the entry
+                    Ok(match record.kind {
+                        FileType::Symlink => DirEntry::new(path, FileType::Symlink),
+                        _ => DirEntry::with_metadata(path, record.metadata()),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            Ok(Box::pin(futures::stream::iter(entries)) as Self::ListDir)
+        })
+    }
+
+    fn create_dir(&self) -> Self::CreateDir {
+        self.fail(read_only(), Operation::CreateDir)
+    }
+
+    fn rm(&self) -> Self::Remove {
+        self.fail(read_only(), Operation::Remove)
+    }
+
+    fn set_permissions(&self, _permissions: Permissions) -> Self::SetPermissions {
+        self.fail(read_only(), Operation::SetPermissions)
+    }
+
+    fn set_times(&self, _times: FileTimes) -> Self::SetTimes {
+        self.fail(read_only(), Operation::SetTimes)
+    }
+
+    fn set_owner(&self, _uid: Option<u32>, _gid: Option<u32>) -> Self::SetOwner {
+        self.fail(read_only(), Operation::SetOwner)
+    }
+
+    fn supports_xattr(&self) -> bool {
+        false
+    }
+
+    fn get_xattr(&self, _name: &str) -> Self::GetXattr {
+        self.fail(no_xattr(), Operation::GetXattr)
+    }
+
+    fn set_xattr(&self, _name: &str, _value: &[u8]) -> Self::SetXattr {
+        self.fail(no_xattr(), Operation::SetXattr)
+    }
+
+    fn list_xattr(&self) -> Self::ListXattr {
+        self.fail(no_xattr(), Operation::ListXattr)
+    }
+
+    fn remove_xattr(&self, _name: &str) -> Self::RemoveXattr {
+        self.fail(no_xattr(), Operation::RemoveXattr)
+    }
+
+    /// The image cannot change, so the watcher never yields any events
+    fn watch(&self, _recursive: bool) -> Self::Watch {
+        Box::pin(async move { Ok(Box::pin(futures::stream::pending()) as Self::Watcher) })
+    }
+}
+
+pub(crate) const fn read_only() -> Error {
+    Error::new_const(
+        ErrorKind::ReadOnlyFilesystem,
+        "ISO 9660 images are read only",
+    )
+}
+
+const fn no_xattr() -> Error {
+    Error::new_const(
+        ErrorKind::Unsupported,
+        "ISO 9660 has no extended attributes",
+    )
+}
+
+/// Split a normalized path into its components
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|part| !part.is_empty())
+}
+
+/// Resolve `path` against the path `base`. `..` is kept for the lookup,
+/// which steps out of the target of a symbolic link like the host would.
+/// A `..` with nothing before it stays at the root and is dropped
+fn join(base: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = components(base).collect();
+
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if parts.is_empty() => {}
+            part => parts.push(part),
+        }
+    }
+
+    parts.join("/")
+}
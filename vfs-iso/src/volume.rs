@@ -0,0 +1,541 @@
+use alloc::{string::String, vec, vec::Vec};
+use core::time::Duration;
+
+use async_lock::Mutex;
+use vfs::{
+    Error, ErrorKind, FileType, Metadata, Permissions, SeekFrom, Timestamp, VFile, VFileExt,
+};
+
+/// Volume descriptors start at this sector
+const DESCRIPTOR_START: u64 = 16;
+const SECTOR_SIZE: u64 = 2048;
+
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+
+/// A file or directory as recorded in a directory of the image
+#[derive(Debug, Clone)]
+pub(crate) struct Record {
+    pub name: String,
+    /// Byte offsets and lengths of the data
+    pub extents: Vec<(u64, u64)>,
+    pub kind: FileType,
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub recorded: Option<Timestamp>,
+    pub modified: Option<Timestamp>,
+    pub accessed: Option<Timestamp>,
+    pub created: Option<Timestamp>,
+    /// Target of a Rock Ridge symbolic link
+    pub target: Option<String>,
+    /// Location of a directory moved away by Rock Ridge deep directory relocation
+    child_link: Option<u64>,
+    /// A relocated directory, which is listed at its original location instead
+    relocated: bool,
+    multi_extent: bool,
+}
+
+impl Record {
+    pub fn size(&self) -> u64 {
+        self.extents.iter().map(|(_, len)| len).sum()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileType::Dir
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        let mode = match self.kind {
+            FileType::Dir => 0o555,
+            FileType::Symlink => 0o777,
+            FileType::File => 0o444,
+        };
+
+        Metadata {
+            size: self.size(),
+            kind: self.kind,
+            permissions: Some(Permissions::from_mode(
+                self.mode.map(|mode| mode & 0o7777).unwrap_or(mode),
+            )),
+            accessed: self.accessed,
+            modified: self.modified.or(self.recorded),
+            created: self.created,
+            uid: self.uid,
+            gid: self.gid,
+            hash: None,
+        }
+    }
+}
+
+/// Which directory hierarchy of the image is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Names {
+    /// Plain ISO 9660 names
+    Iso,
+    /// UCS-2 names from the Joliet supplementary volume descriptor
+    Joliet,
+    /// POSIX names and attributes from Rock Ridge. Holds the number of bytes
+    /// to skip at the start of each system use area
+    RockRidge(usize),
+}
+
+/// The device holding the image, along with the parsed volume descriptors
+pub(crate) struct Volume<F> {
+    device: Mutex<F>,
+    pub names: Names,
+    pub root: Record,
+    pub label: String,
+    /// Size of the volume in bytes
+    pub size: u64,
+    /// End of the data which can be read, the size of the volume unless the
+    /// device is shorter
+    end: u64,
+}
+
+const fn invalid(message: &'static str) -> Error {
+    Error::new_const(ErrorKind::InvalidData, message)
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+impl<F> Volume<F>
+where
+    F: VFile + Unpin,
+{
+    pub async fn open(mut device: F) -> Result<Volume<F>, Error> {
+        let end = device.seek(SeekFrom::End(0)).await?;
+        let device = Mutex::new(device);
+        let mut primary = None;
+        let mut joliet = None;
+
+        for sector in DESCRIPTOR_START.. {
+            let desc = read_at(&device, sector * SECTOR_SIZE, SECTOR_SIZE as usize).await?;
+            if &desc[1..6] != b"CD001" {
+                return Err(invalid("missing ISO 9660 volume descriptor"));
+            }
+
+            match desc[0] {
+                1 if primary.is_none() => primary = Some(desc),
+                2 if matches!(&desc[88..91], b"%/@" | b"%/C" | b"%/E") => joliet = Some(desc),
+                255 => break,
+                _ => {}
+            }
+        }
+
+        let primary = primary.ok_or_else(|| invalid("missing primary volume descriptor"))?;
+        if u16_at(&primary, 128) as u64 != SECTOR_SIZE {
+            return Err(Error::new_const(
+                ErrorKind::Unsupported,
+                "only images with 2048 byte blocks are supported",
+            ));
+        }
+
+        let size = u32_at(&primary, 80) as u64 * SECTOR_SIZE;
+        let mut volume = Volume {
+            device,
+            names: Names::Iso,
+            root: parse_record(&primary[156..190], Names::Iso)?,
+            label: latin1(&primary[40..72]).trim_end().into(),
+            size,
+            end: end.min(size),
+        };
+
+        // Rock Ridge is announced by the SP entry in the first record of the root directory
+        let data = volume.read_extents(&volume.root.extents).await?;
+        let len = *data
+            .first()
+            .ok_or_else(|| invalid("empty root directory"))? as usize;
+        if len >= 34 && len <= data.len() {
+            let area = &data[34..len];
+            if area.len() >= 7 && &area[..2] == b"SP" && area[4..6] == [0xBE, 0xEF] {
+                volume.names = Names::RockRidge(area[6] as usize);
+
+                // The skip does not apply to the record holding the SP entry
+                let mut root = volume.root.clone();
+                volume.rock_ridge(&mut root, &data[..len], 0).await?;
+                volume.root = root;
+            }
+        }
+
+        if volume.names == Names::Iso
+            && let Some(joliet) = joliet
+        {
+            volume.names = Names::Joliet;
+            volume.root = parse_record(&joliet[156..190], Names::Joliet)?;
+            volume.label = ucs2(&joliet[40..72]).trim_end().into();
+        }
+
+        volume.root.name = String::new();
+
+        Ok(volume)
+    }
+
+    /// Read `len` bytes at `offset`, which the image places within the volume
+    pub async fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>, Error> {
+        if offset
+            .checked_add(len as u64)
+            .is_none_or(|end| end > self.end)
+        {
+            return Err(invalid("data beyond the end of the volume"));
+        }
+        read_at(&self.device, offset, len).await
+    }
+
+    async fn read_extents(&self, extents: &[(u64, u64)]) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        for &(offset, len) in extents {
+            data.extend(self.read_at(offset, len as usize).await?);
+        }
+        Ok(data)
+    }
+
+    /// All records of a directory, excluding `.` and `..`
+    pub async fn read_dir(&self, dir: &Record) -> Result<Vec<Record>, Error> {
+        let data = self.read_extents(&dir.extents).await?;
+        let mut records: Vec<Record> = Vec::new();
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let len = data[pos] as usize;
+            if len == 0 {
+                // Records do not cross sector boundaries, the rest of the sector is padding
+                pos = (pos / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+                continue;
+            }
+
+            if len < 34 || pos + len > data.len() {
+                return Err(invalid("malformed directory record"));
+            }
+
+            let raw = &data[pos..pos + len];
+            pos += len;
+
+            if raw[32] == 1 && (raw[33] == 0 || raw[33] == 1) {
+                continue;
+            }
+
+            let mut record = parse_record(raw, self.names)?;
+            if let Names::RockRidge(skip) = self.names {
+                self.rock_ridge(&mut record, raw, skip).await?;
+            }
+
+            if record.relocated {
+                continue;
+            }
+
+            if let Some(offset) = record.child_link {
+                // The first record of a directory describes the directory itself
+                let this = self.read_at(offset, 34).await?;
+                record.extents = Vec::from([(offset, u32_at(&this, 10) as u64)]);
+                record.kind = FileType::Dir;
+            }
+
+            // Files larger than an extent are split over consecutive records
+            match records.last_mut() {
+                Some(prev) if prev.multi_extent && prev.name == record.name => {
+                    prev.extents.extend(record.extents);
+                    prev.multi_extent = record.multi_extent;
+                }
+                _ => records.push(record),
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Apply the Rock Ridge entries in the system use area of a record
+    async fn rock_ridge(&self, record: &mut Record, raw: &[u8], skip: usize) -> Result<(), Error> {
+        let name_len = raw[32] as usize;
+        let start = 33 + name_len + (1 - name_len % 2) + skip;
+
+        let mut area = raw.get(start..).unwrap_or_default().to_vec();
+        let mut name: Option<String> = None;
+        let mut target: Option<(String, bool)> = None;
+
+        // Continuation areas may chain, but not indefinitely
+        for _ in 0..16 {
+            let mut continuation = None;
+            let mut pos = 0;
+
+            while pos + 4 <= area.len() {
+                let len = area[pos + 2] as usize;
+                if len < 4 || pos + len > area.len() {
+                    break;
+                }
+                let entry = &area[pos..pos + len];
+                pos += len;
+
+                match &entry[..2] {
+                    b"ST" => break,
+                    b"CE" if len >= 28 => {
+                        let block = u32_at(entry, 4) as u64;
+                        let offset = u32_at(entry, 12) as u64;
+                        let size = u32_at(entry, 20) as usize;
+                        continuation = Some((block * SECTOR_SIZE + offset, size));
+                    }
+                    b"PX" if len >= 36 => {
+                        let mode = u32_at(entry, 4);
+                        record.mode = Some(mode);
+                        record.uid = Some(u32_at(entry, 20));
+                        record.gid = Some(u32_at(entry, 28));
+                        record.kind = match mode & S_IFMT {
+                            S_IFDIR => FileType::Dir,
+                            S_IFLNK => FileType::Symlink,
+                            _ => FileType::File,
+                        };
+                    }
+                    b"NM" if len >= 5 => {
+                        let flags = entry[4];
+                        // The current and parent flags only occur on `.` and `..`
+                        if flags & 0x06 == 0 {
+                            name.get_or_insert_default()
+                                .push_str(&latin1_or_utf8(&entry[5..]));
+                        }
+                    }
+                    b"SL" if len >= 5 => {
+                        let (target, separate) = target.get_or_insert_default();
+                        symlink_components(&entry[5..], target, separate);
+                        record.kind = FileType::Symlink;
+                    }
+                    b"TF" if len >= 5 => timestamps(record, entry),
+                    b"CL" if len >= 12 => {
+                        record.child_link = Some(u32_at(entry, 4) as u64 * SECTOR_SIZE);
+                    }
+                    b"RE" => record.relocated = true,
+                    _ => {}
+                }
+            }
+
+            let Some((offset, size)) = continuation else {
+                break;
+            };
+            area = self.read_at(offset, size).await?;
+        }
+
+        if let Some(name) = name {
+            record.name = name;
+        }
+
+        record.target = target.map(|(target, _)| target);
+
+        Ok(())
+    }
+}
+
+async fn read_at<F: VFile + Unpin>(
+    device: &Mutex<F>,
+    offset: u64,
+    len: usize,
+) -> Result<Vec<u8>, Error> {
+    let mut device = device.lock().await;
+    let mut buf = vec![0; len];
+    device.seek(SeekFrom::Start(offset)).await?;
+    device.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// Parse the fixed part of a directory record
+fn parse_record(raw: &[u8], names: Names) -> Result<Record, Error> {
+    let name_len = raw[32] as usize;
+    let ident = raw
+        .get(33..33 + name_len)
+        .ok_or_else(|| invalid("malformed directory record"))?;
+
+    let name = match names {
+        Names::Joliet => ucs2(ident),
+        _ => latin1(ident),
+    };
+
+    // Strip the version number and the dot of names without an extension
+    let name = match name.rsplit_once(';') {
+        Some((name, _)) => name,
+        None => &name,
+    };
+    let name = name.strip_suffix('.').unwrap_or(name);
+
+    let flags = raw[25];
+    let offset = u32_at(raw, 2) as u64 * SECTOR_SIZE;
+    let len = u32_at(raw, 10) as u64;
+
+    Ok(Record {
+        name: name.into(),
+        extents: Vec::from([(offset, len)]),
+        kind: if flags & FLAG_DIRECTORY != 0 {
+            FileType::Dir
+        } else {
+            FileType::File
+        },
+        mode: None,
+        uid: None,
+        gid: None,
+        recorded: short_timestamp(&raw[18..25]),
+        modified: None,
+        accessed: None,
+        created: None,
+        target: None,
+        child_link: None,
+        relocated: false,
+        multi_extent: flags & FLAG_MULTI_EXTENT != 0,
+    })
+}
+
+/// Append the components of an SL entry to a symlink target
+fn symlink_components(mut data: &[u8], target: &mut String, separate: &mut bool) {
+    while data.len() >= 2 {
+        let flags = data[0];
+        let len = (data[1] as usize).min(data.len() - 2);
+        let content = &data[2..2 + len];
+        data = &data[2 + len..];
+
+        if flags & 0x08 != 0 {
+            target.clear();
+            target.push('/');
+            *separate = false;
+            continue;
+        }
+
+        if *separate {
+            target.push('/');
+        }
+
+        if flags & 0x02 != 0 {
+            target.push('.');
+        } else if flags & 0x04 != 0 {
+            target.push_str("..");
+        } else {
+            target.push_str(&latin1_or_utf8(content));
+        }
+
+        // The component continues in the next component record
+        *separate = flags & 0x01 == 0;
+    }
+}
+
+/// Apply the timestamps of a TF entry
+fn timestamps(record: &mut Record, entry: &[u8]) {
+    let flags = entry[4];
+    let long = flags & 0x80 != 0;
+    let size = if long { 17 } else { 7 };
+    let mut pos = 5;
+
+    for bit in 0..7 {
+        if flags & (1 << bit) == 0 {
+            continue;
+        }
+
+        let Some(raw) = entry.get(pos..pos + size) else {
+            return;
+        };
+        pos += size;
+
+        let timestamp = if long {
+            long_timestamp(raw)
+        } else {
+            short_timestamp(raw)
+        };
+
+        match bit {
+            0 => record.created = timestamp,
+            1 => record.modified = timestamp,
+            2 => record.accessed = timestamp,
+            _ => {}
+        }
+    }
+}
+
+/// Days since the unix epoch of a proleptic gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Seconds since the epoch of a date given in local time with an offset
+/// from GMT in 15 minute intervals
+fn timestamp(date: [i64; 6], hundredths: i64, offset: i8) -> Option<Timestamp> {
+    let [year, month, day, hour, minute, second] = date;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+        - offset as i64 * 900;
+    let secs = u64::try_from(secs).ok()?;
+
+    Some(Timestamp::from_unix(
+        Duration::from_secs(secs) + Duration::from_millis(hundredths as u64 * 10),
+    ))
+}
+
+/// The 7 byte date format of directory records
+fn short_timestamp(raw: &[u8]) -> Option<Timestamp> {
+    let date = [
+        1900 + raw[0] as i64,
+        raw[1] as i64,
+        raw[2] as i64,
+        raw[3] as i64,
+        raw[4] as i64,
+        raw[5] as i64,
+    ];
+    timestamp(date, 0, raw[6] as i8)
+}
+
+/// The 17 byte date format of volume descriptors, made of ascii digits
+fn long_timestamp(raw: &[u8]) -> Option<Timestamp> {
+    let digits = |range: core::ops::Range<usize>| {
+        core::str::from_utf8(&raw[range])
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+    };
+
+    let date = [
+        digits(0..4)?,
+        digits(4..6)?,
+        digits(6..8)?,
+        digits(8..10)?,
+        digits(10..12)?,
+        digits(12..14)?,
+    ];
+
+    timestamp(date, digits(14..16)?, raw[16] as i8)
+}
+
+fn latin1(raw: &[u8]) -> String {
+    raw.iter().map(|&b| b as char).collect()
+}
+
+/// Rock Ridge does not specify an encoding, but names are usually UTF-8
+fn latin1_or_utf8(raw: &[u8]) -> String {
+    match core::str::from_utf8(raw) {
+        Ok(s) => s.into(),
+        Err(_) => latin1(raw),
+    }
+}
+
+fn ucs2(raw: &[u8]) -> String {
+    let units = raw
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
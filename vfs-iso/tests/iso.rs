@@ -0,0 +1,192 @@
+use futures::executor::block_on;
+use vfs::{ErrorKind, OpenOptions, SeekFrom, VFS, VFileExt, VPath};
+use vfs_iso::IsoFS;
+use vfs_test::{Device, names, pattern, read_file, try_names, try_read_file};
+
+const SECTOR: usize = 2048;
+
+/// Sector of the root directory, followed by `SUB` and the file contents
+const ROOT: usize = 18;
+
+/// A directory record
+fn record(name: &[u8], sector: usize, len: u32, dir: bool) -> Vec<u8> {
+    // Records have an even length
+    let size = 33 + name.len() + (1 - name.len() % 2);
+    let mut raw = vec![0; size];
+    raw[0] = size as u8;
+    raw[2..6].copy_from_slice(&(sector as u32).to_le_bytes());
+    raw[6..10].copy_from_slice(&(sector as u32).to_be_bytes());
+    raw[10..14].copy_from_slice(&len.to_le_bytes());
+    raw[14..18].copy_from_slice(&len.to_be_bytes());
+    // 2024-03-05 06:07:08 GMT
+    raw[18..25].copy_from_slice(&[124, 3, 5, 6, 7, 8, 0]);
+    raw[25] = if dir { 0x02 } else { 0 };
+    raw[28] = 1;
+    raw[32] = name.len() as u8;
+    raw[33..33 + name.len()].copy_from_slice(name);
+    raw
+}
+
+/// The records of a directory, starting with `.` and `..`
+fn directory(this: usize, parent: usize, records: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = record(&[0], this, SECTOR as u32, true);
+    data.extend(record(&[1], parent, SECTOR as u32, true));
+    for raw in records {
+        data.extend(raw);
+    }
+    data
+}
+
+/// A plain ISO 9660 image holding `/HELLO.TXT` and `/SUB/INNER.TXT`
+struct Image {
+    data: Vec<u8>,
+    hello: Vec<u8>,
+    /// Byte offset of the record of `HELLO.TXT`
+    hello_record: usize,
+}
+
+fn build() -> Image {
+    let hello = pattern(3000);
+    let sectors = ROOT + 5;
+    let mut data = vec![0; sectors * SECTOR];
+
+    let primary = &mut data[16 * SECTOR..17 * SECTOR];
+    primary[0] = 1;
+    primary[1..6].copy_from_slice(b"CD001");
+    primary[6] = 1;
+    primary[40..72].fill(b' ');
+    primary[40..47].copy_from_slice(b"TESTVOL");
+    primary[80..84].copy_from_slice(&(sectors as u32).to_le_bytes());
+    primary[84..88].copy_from_slice(&(sectors as u32).to_be_bytes());
+    primary[128..130].copy_from_slice(&(SECTOR as u16).to_le_bytes());
+    primary[130..132].copy_from_slice(&(SECTOR as u16).to_be_bytes());
+    primary[156..190].copy_from_slice(&record(&[0], ROOT, SECTOR as u32, true));
+
+    let terminator = &mut data[17 * SECTOR..18 * SECTOR];
+    terminator[0] = 255;
+    terminator[1..6].copy_from_slice(b"CD001");
+    terminator[6] = 1;
+
+    let hello_record = record(b"HELLO.TXT;1", ROOT + 2, hello.len() as u32, false);
+    let root = directory(
+        ROOT,
+        ROOT,
+        &[
+            hello_record.clone(),
+            record(b"SUB", ROOT + 1, SECTOR as u32, true),
+        ],
+    );
+    // After the records of `.` and `..`
+    let hello_offset = ROOT * SECTOR + 2 * 34;
+    data[ROOT * SECTOR..][..root.len()].copy_from_slice(&root);
+
+    let sub = directory(
+        ROOT + 1,
+        ROOT,
+        &[record(b"INNER.TXT;1", ROOT + 4, 5, false)],
+    );
+    data[(ROOT + 1) * SECTOR..][..sub.len()].copy_from_slice(&sub);
+
+    data[(ROOT + 2) * SECTOR..][..hello.len()].copy_from_slice(&hello);
+    data[(ROOT + 4) * SECTOR..][..5].copy_from_slice(b"inner");
+
+    Image {
+        data,
+        hello,
+        hello_record: hello_offset,
+    }
+}
+
+#[test]
+fn reads_generated_image() {
+    block_on(async {
+        let image = build();
+        let fs = IsoFS::new(Device::new(image.data)).await.unwrap();
+        assert_eq!(fs.label(), "TESTVOL");
+        assert!(!fs.rock_ridge());
+
+        assert_eq!(names(&fs.path("/").unwrap()).await, ["HELLO.TXT", "SUB"]);
+        assert_eq!(names(&fs.path("/sub").unwrap()).await, ["INNER.TXT"]);
+
+        // Plain ISO 9660 names are matched without regard to case
+        let path = fs.path("/hello.txt").unwrap();
+        assert_eq!(read_file(&path).await, image.hello);
+        let metadata = path.metadata().await.unwrap();
+        assert_eq!(metadata.size, 3000);
+        assert!(metadata.modified.is_some());
+        assert!(fs.path("/SUB").unwrap().metadata().await.unwrap().is_dir());
+        assert_eq!(
+            read_file(&fs.path("/SUB/INNER.TXT").unwrap()).await,
+            b"inner"
+        );
+
+        // The parent of a path ending in `..` steps out of it
+        let parent = fs.path("/SUB/..").unwrap().parent().unwrap();
+        assert_eq!(parent.virtual_path(), "/SUB/../..");
+        assert_eq!(names(&parent).await, ["HELLO.TXT", "SUB"]);
+
+        let mut file = path.open(OpenOptions::new().read(true)).await.unwrap();
+        file.seek(SeekFrom::Start(2048 - 4)).await.unwrap();
+        let mut buf = [0; 8];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, image.hello[2044..2052]);
+
+        let err = path
+            .open(OpenOptions::new().write(true))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::ReadOnlyFilesystem);
+    })
+}
+
+#[test]
+fn truncated_images() {
+    block_on(async {
+        let image = build();
+
+        // Without the volume descriptors
+        for len in [0, 100, 16 * SECTOR + 10, 17 * SECTOR] {
+            let device = Device::new(image.data[..len].to_vec());
+            assert!(IsoFS::new(device).await.is_err(), "{len} bytes");
+        }
+
+        // Without the file contents
+        let device = Device::new(image.data[..(ROOT + 2) * SECTOR].to_vec());
+        let fs = IsoFS::new(device).await.unwrap();
+        assert_eq!(names(&fs.path("/").unwrap()).await, ["HELLO.TXT", "SUB"]);
+        let err = try_read_file(&fs.path("/HELLO.TXT").unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    })
+}
+
+#[test]
+fn malformed_images() {
+    block_on(async {
+        // A root directory without any data
+        let mut image = build();
+        let root = 16 * SECTOR + 156;
+        image.data[root + 10..root + 14].fill(0);
+        let err = IsoFS::new(Device::new(image.data)).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // A file far larger than the volume is refused before reading it
+        let mut image = build();
+        let record = image.hello_record;
+        image.data[record + 10..record + 14].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        let fs = IsoFS::new(Device::new(image.data)).await.unwrap();
+        let path = fs.path("/HELLO.TXT").unwrap();
+        assert_eq!(path.metadata().await.unwrap().size, 0xFFFF_FFF0);
+        let err = try_read_file(&path).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // A directory record shorter than its fixed part
+        let mut image = build();
+        image.data[image.hello_record] = 20;
+        let fs = IsoFS::new(Device::new(image.data)).await.unwrap();
+        let err = try_names(&fs.path("/").unwrap()).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    })
+}
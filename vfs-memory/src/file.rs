@@ -0,0 +1,171 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use vfs::{Error, ErrorKind, OpenOptions, Operation, SeekFrom, VFile, WatchEvent};
+
+use crate::{
+    MemoryPath,
+    node::{Kind, Node, get, get_mut},
+    now,
+};
+
+/// A file of a [`MemoryFS`](crate::MemoryFS).
+///
+/// Reads and writes go straight to the tree of the filesystem it was opened
+/// in, so they are visible to other handles at once, but not to forks.
+/// Once the file is removed, they fail with [`ErrorKind::NotFound`], also
+/// if a new file was created at its path since.
+#[derive(Debug)]
+pub struct MemoryFile {
+    path: MemoryPath,
+    /// The node of the file
+    id: u64,
+    pos: u64,
+    read: bool,
+    write: bool,
+    append: bool,
+    /// Written since the last flush
+    dirty: bool,
+}
+
+impl MemoryFile {
+    pub(crate) fn new(path: MemoryPath, id: u64, options: OpenOptions) -> MemoryFile {
+        MemoryFile {
+            path,
+            id,
+            pos: 0,
+            read: options.read,
+            write: options.write || options.append,
+            append: options.append,
+            dirty: false,
+        }
+    }
+
+    /// The file was truncated when opened
+    pub(crate) fn truncated(mut self) -> MemoryFile {
+        self.dirty = true;
+        self
+    }
+
+    fn context(&self, err: Error, operation: Operation) -> Error {
+        self.path.context(err, operation)
+    }
+
+    /// The node of the file below `root`, unless the file was removed
+    fn node<'a>(&self, root: &'a Node) -> Result<&'a Node, Error> {
+        match get(root, &self.path.components()) {
+            Ok(node) if node.id == self.id => Ok(node),
+            Ok(_) | Err(_) => Err(Error::new_const(
+                ErrorKind::NotFound,
+                "the file was removed",
+            )),
+        }
+    }
+}
+
+impl VFile for MemoryFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        if !this.read {
+            let err = Error::new_const(ErrorKind::PermissionDenied, "file not opened for reading");
+            return Poll::Ready(Err(this.context(err, Operation::Read)));
+        }
+
+        let root = this.path.fs.root();
+        let n = this
+            .node(&root)
+            .and_then(|node| match &node.kind {
+                Kind::File(data) => Ok(data.read_at(this.pos, buf)),
+                Kind::Dir(_) => Err(ErrorKind::IsADirectory.into()),
+            })
+            .map_err(|err| this.context(err, Operation::Read))?;
+
+        this.pos += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        let this = self.get_mut();
+        let target = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(offset) => this.pos.checked_add_signed(offset),
+            SeekFrom::End(offset) => {
+                let root = this.path.fs.root();
+                let size = this
+                    .node(&root)
+                    .map_err(|err| this.context(err, Operation::Seek))?
+                    .metadata()
+                    .size;
+                size.checked_add_signed(offset)
+            }
+        };
+
+        let Some(target) = target else {
+            let err = Error::new_const(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            );
+            return Poll::Ready(Err(this.context(err, Operation::Seek)));
+        };
+
+        this.pos = target;
+        Poll::Ready(Ok(target))
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        if !this.write {
+            let err = Error::new_const(ErrorKind::PermissionDenied, "file not opened for writing");
+            return Poll::Ready(Err(this.context(err, Operation::Write)));
+        }
+
+        let components = this.path.components();
+        let mut root = this.path.fs.inner.root.lock().unwrap();
+        let result = this.node(&root).map(drop).and_then(|()| {
+            let node = get_mut(&mut root, &components)?;
+            let data = node.data_mut()?;
+            let pos = if this.append { data.len() } else { this.pos };
+            data.write_at(pos, buf);
+            node.modified = now();
+            Ok(pos)
+        });
+        drop(root);
+
+        let pos = result.map_err(|err| this.context(err, Operation::Write))?;
+        this.pos = pos + buf.len() as u64;
+        this.dirty |= !buf.is_empty();
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    /// Writes are applied at once, so flushing only reports the change to watchers
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        if this.dirty {
+            this.dirty = false;
+            this.path
+                .fs
+                .inner
+                .hub
+                .emit(WatchEvent::Modify(this.path.clone()));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.poll_flush(cx)
+    }
+}
@@ -0,0 +1,679 @@
+//! An in-memory [`VFS`] which can be forked cheaply.
+//!
+//! The tree is made of reference counted nodes, and file contents of reference
+//! counted blocks. [`MemoryFS::fork`] copies only the reference to the root, and a
+//! change after a fork copies just the nodes and blocks on the way to it, so the
+//! forks share everything else. [`MemoryFS::diff`] compares two forks by skipping
+//! the parts they still share.
+mod file;
+mod node;
+
+pub use self::file::MemoryFile;
+
+use core::future::{Ready, ready};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use vfs::{
+    Capabilities, DirEntry, Error, ErrorKind, FileTimes, FsStats, HubWatcher, Metadata,
+    OpenOptions, Operation, Permissions, Timestamp, VFS, VPath, VPathStr, WatchEvent, WatchHub,
+};
+
+use self::node::{BLOCK_SIZE, Change, Kind, Node, diff, get, get_mut};
+
+fn now() -> Timestamp {
+    std::time::SystemTime::now().into()
+}
+
+struct Inner {
+    root: Mutex<Arc<Node>>,
+    hub: WatchHub<MemoryPath>,
+}
+
+/// A filesystem keeping its files in memory.
+///
+/// Clones share the same tree, while [`fork`](MemoryFS::fork) creates a copy which
+/// is changed independently. Access times are not updated by reads, so reading
+/// never copies shared nodes.
+#[derive(Clone)]
+pub struct MemoryFS {
+    inner: Arc<Inner>,
+}
+
+impl core::fmt::Debug for MemoryFS {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MemoryFS").finish_non_exhaustive()
+    }
+}
+
+impl Default for MemoryFS {
+    fn default() -> Self {
+        MemoryFS::new()
+    }
+}
+
+impl MemoryFS {
+    /// Create an empty filesystem
+    pub fn new() -> MemoryFS {
+        MemoryFS::from_root(Arc::new(Node::new(Kind::Dir(BTreeMap::new()), now())))
+    }
+
+    fn from_root(root: Arc<Node>) -> MemoryFS {
+        MemoryFS {
+            inner: Arc::new(Inner {
+                root: Mutex::new(root),
+                hub: WatchHub::new(),
+            }),
+        }
+    }
+
+    /// Create a copy of the filesystem in constant time. The copy shares all data
+    /// with this filesystem until either of them changes it, and changes made to
+    /// one are not visible in the other. Files opened before the fork stay with
+    /// this filesystem
+    pub fn fork(&self) -> MemoryFS {
+        MemoryFS::from_root(self.root())
+    }
+
+    /// The changes turning this filesystem into `other`, with the paths of `other`.
+    ///
+    /// A created or removed directory is reported as a single change. The cost depends
+    /// on the size of the changes when `other` is a fork of this filesystem, or the
+    /// other way around, as the parts both still share are skipped.
+    pub fn diff(&self, other: &MemoryFS) -> Vec<WatchEvent<MemoryPath>> {
+        let mut changes = Vec::new();
+        diff("", &self.root(), &other.root(), &mut changes);
+
+        let path = |path| MemoryPath::new(other.clone(), path);
+        changes
+            .into_iter()
+            .map(|change| match change {
+                Change::Create(name) => WatchEvent::Create(path(name)),
+                Change::Modify(name) => WatchEvent::Modify(path(name)),
+                Change::Remove(name) => WatchEvent::Remove(path(name)),
+            })
+            .collect()
+    }
+
+    /// The current tree, which stays unchanged while it is held
+    fn root(&self) -> Arc<Node> {
+        self.inner.root.lock().unwrap().clone()
+    }
+}
+
+impl VFS for MemoryFS {
+    type Path = MemoryPath;
+
+    type Stats = Ready<Result<FsStats, Error>>;
+
+    fn path(&self, path: impl AsRef<VPathStr>) -> Result<Self::Path, Error> {
+        Ok(MemoryPath::new(
+            self.clone(),
+            VPathStr::new("").resolve(path).into_string(),
+        ))
+    }
+
+    /// The space is only limited by the memory of the process, so it is reported as unlimited
+    fn stats(&self) -> Self::Stats {
+        ready(Ok(FsStats {
+            block_size: BLOCK_SIZE as u64,
+            total: u64::MAX,
+            free: u64::MAX,
+            available: u64::MAX,
+        }))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            symlinks: false,
+            atomic_rename: false,
+            xattr: true,
+            seek: true,
+            write: true,
+            case_sensitive: true,
+            max_name_len: None,
+        }
+    }
+}
+
+/// A path of a [`MemoryFS`]
+#[derive(Clone)]
+pub struct MemoryPath {
+    fs: MemoryFS,
+    /// Normalized path without leading slash, empty for the root directory
+    path: String,
+}
+
+impl core::fmt::Debug for MemoryPath {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("MemoryPath").field(&self.path).finish()
+    }
+}
+
+/// Paths are equal when they point into the same filesystem, so paths of forks differ
+impl PartialEq for MemoryPath {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.fs.inner, &other.fs.inner) && self.path == other.path
+    }
+}
+
+impl Eq for MemoryPath {}
+
+impl core::hash::Hash for MemoryPath {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.path.hash(state)
+    }
+}
+
+impl MemoryPath {
+    fn new(fs: MemoryFS, path: String) -> MemoryPath {
+        MemoryPath { fs, path }
+    }
+
+    fn child(&self, name: &str) -> MemoryPath {
+        let path = VPathStr::new(&self.path).join(name).into_string();
+        MemoryPath::new(self.fs.clone(), path)
+    }
+
+    fn components(&self) -> Vec<&str> {
+        let path = VPathStr::new(&self.path);
+        path.components().map(|name| name.as_str()).collect()
+    }
+
+    fn context(&self, err: Error, operation: Operation) -> Error {
+        err.with_operation(operation)
+            .with_path(format!("/{}", self.path))
+    }
+
+    fn emit(&self, event: WatchEvent<MemoryPath>) {
+        self.fs.inner.hub.emit(event);
+    }
+
+    fn metadata_now(&self) -> Result<Metadata, Error> {
+        let root = self.fs.root();
+        Ok(get(&root, &self.components())?.metadata())
+    }
+
+    /// Apply `change` to the node of this path and report it to watchers
+    fn change<T>(
+        &self,
+        operation: Operation,
+        change: impl FnOnce(&mut Node) -> T,
+    ) -> Result<T, Error> {
+        let mut root = self.fs.inner.root.lock().unwrap();
+        let result = get_mut(&mut root, &self.components())
+            .map(change)
+            .map_err(|err| self.context(err, operation))?;
+        drop(root);
+
+        self.emit(WatchEvent::Modify(self.clone()));
+        Ok(result)
+    }
+
+    fn open_now(&self, options: OpenOptions) -> Result<MemoryFile, Error> {
+        let write = options.write || options.append;
+        let components = self.components();
+        let mut root = self.fs.inner.root.lock().unwrap();
+
+        match get(&root, &components) {
+            Ok(node) if matches!(node.kind, Kind::Dir(_)) => Err(ErrorKind::IsADirectory.into()),
+            Ok(_) if write && options.truncate => {
+                let node = get_mut(&mut root, &components)?;
+                node.data_mut()?.set_len(0);
+                node.modified = now();
+                Ok(MemoryFile::new(self.clone(), node.id, options).truncated())
+            }
+            Ok(node) => Ok(MemoryFile::new(self.clone(), node.id, options)),
+            Err(err) if err.kind() == ErrorKind::NotFound && write && options.create => {
+                let (name, parent) = components.split_last().ok_or(ErrorKind::IsADirectory)?;
+                let parent = get_mut(&mut root, parent)?;
+                let now = now();
+                let node = Node::new(Kind::File(Default::default()), now);
+                let id = node.id;
+                parent
+                    .entries_mut()?
+                    .insert(name.to_string(), Arc::new(node));
+                parent.modified = now;
+                drop(root);
+
+                self.emit(WatchEvent::Create(self.clone()));
+                Ok(MemoryFile::new(self.clone(), id, options))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn create_dir_now(&self) -> Result<(), Error> {
+        let components = self.components();
+        let mut root = self.fs.inner.root.lock().unwrap();
+
+        // Existing directories are left alone, so they stay shared with forks
+        match get(&root, &components) {
+            Ok(node) if matches!(node.kind, Kind::Dir(_)) => return Ok(()),
+            Ok(_) => {
+                return Err(Error::new_const(
+                    ErrorKind::AlreadyExists,
+                    "a file with the same name exists",
+                ));
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        let now = now();
+        let mut created = Vec::new();
+        let mut node: &mut Arc<Node> = &mut root;
+        for (depth, name) in components.iter().enumerate() {
+            let dir = Arc::make_mut(node);
+            let entries = dir.entries_mut()?;
+            if !entries.contains_key(*name) {
+                let child = Node::new(Kind::Dir(BTreeMap::new()), now);
+                entries.insert(name.to_string(), Arc::new(child));
+                dir.modified = now;
+                created.push(components[..=depth].join("/"));
+            }
+            node = dir.entries_mut()?.get_mut(*name).unwrap();
+        }
+        if !matches!(node.kind, Kind::Dir(_)) {
+            return Err(ErrorKind::NotADirectory.into());
+        }
+        drop(root);
+
+        for path in created {
+            self.emit(WatchEvent::Create(MemoryPath::new(self.fs.clone(), path)));
+        }
+        Ok(())
+    }
+
+    fn remove_now(&self) -> Result<(), Error> {
+        let components = self.components();
+        let mut root = self.fs.inner.root.lock().unwrap();
+        get(&root, &components)?;
+
+        let now = now();
+        match components.split_last() {
+            Some((name, parent)) => {
+                let parent = get_mut(&mut root, parent)?;
+                parent.entries_mut()?.remove(*name);
+                parent.modified = now;
+            }
+            // The root itself stays, emptied
+            None => {
+                let root = Arc::make_mut(&mut root);
+                root.entries_mut()?.clear();
+                root.modified = now;
+            }
+        }
+        drop(root);
+
+        self.emit(WatchEvent::Remove(self.clone()));
+        Ok(())
+    }
+}
+
+impl VPath for MemoryPath {
+    type FS = MemoryFS;
+
+    type File = MemoryFile;
+
+    type ListDir = futures::stream::Iter<std::vec::IntoIter<Result<DirEntry<Self>, Error>>>;
+
+    type Metadata = Ready<Result<Metadata, Error>>;
+
+    type Open = Ready<Result<MemoryFile, Error>>;
+
+    type CreateDir = Ready<Result<(), Error>>;
+
+    type Remove = Ready<Result<(), Error>>;
+
+    type ReadDir = Ready<Result<Self::ListDir, Error>>;
+
+    type SetPermissions = Ready<Result<(), Error>>;
+
+    type SetTimes = Ready<Result<(), Error>>;
+
+    type SetOwner = Ready<Result<(), Error>>;
+
+    type GetXattr = Ready<Result<Option<Vec<u8>>, Error>>;
+
+    type SetXattr = Ready<Result<(), Error>>;
+
+    type ListXattr = Ready<Result<Vec<String>, Error>>;
+
+    type RemoveXattr = Ready<Result<(), Error>>;
+
+    type Watcher = HubWatcher<Self>;
+
+    type Watch = Ready<Result<HubWatcher<Self>, Error>>;
+
+    type Canonicalize = Ready<Result<Self, Error>>;
+
+    fn fs(&self) -> Self::FS {
+        self.fs.clone()
+    }
+
+    fn virtual_path(&self) -> String {
+        format!("/{}", self.path)
+    }
+
+    fn to_string(&self) -> String {
+        self.virtual_path()
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        VPathStr::new(&self.path).file_name()
+    }
+
+    fn extension(&self) -> Option<&str> {
+        VPathStr::new(&self.path).extension()
+    }
+
+    fn resolve(&self, path: &str) -> Result<Self, Error> {
+        Ok(MemoryPath::new(
+            self.fs.clone(),
+            VPathStr::new(&self.path).resolve(path).into_string(),
+        ))
+    }
+
+    fn parent(&self) -> Option<Self> {
+        let parent = VPathStr::new(&self.path).parent()?;
+        Some(MemoryPath::new(self.fs.clone(), parent.as_str().into()))
+    }
+
+    /// There are no symbolic links, so this only checks that the path exists
+    fn canonicalize(&self) -> Self::Canonicalize {
+        ready(
+            self.metadata_now()
+                .map(|_| self.clone())
+                .map_err(|err| self.context(err, Operation::Canonicalize)),
+        )
+    }
+
+    fn metadata(&self) -> Self::Metadata {
+        ready(
+            self.metadata_now()
+                .map_err(|err| self.context(err, Operation::Metadata)),
+        )
+    }
+
+    fn open(&self, options: OpenOptions) -> Self::Open {
+        ready(
+            self.open_now(options)
+                .map_err(|err| self.context(err, Operation::Open)),
+        )
+    }
+
+    fn read_dir(&self) -> Self::ReadDir {
+        let root = self.fs.root();
+        let entries = get(&root, &self.components())
+            .and_then(|node| node.entries())
+            .map(|entries| {
+                let entries = entries
+                    .iter()
+                    .map(|(name, node)| {
+                        Ok(DirEntry::with_metadata(self.child(name), node.metadata()))
+                    })
+                    .collect::<Vec<_>>();
+                futures::stream::iter(entries)
+            });
+
+        ready(entries.map_err(|err| self.context(err, Operation::ReadDir)))
+    }
+
+    fn create_dir(&self) -> Self::CreateDir {
+        ready(
+            self.create_dir_now()
+                .map_err(|err| self.context(err, Operation::CreateDir)),
+        )
+    }
+
+    fn rm(&self) -> Self::Remove {
+        ready(
+            self.remove_now()
+                .map_err(|err| self.context(err, Operation::Remove)),
+        )
+    }
+
+    fn set_permissions(&self, permissions: Permissions) -> Self::SetPermissions {
+        ready(self.change(Operation::SetPermissions, |node| {
+            node.permissions = permissions;
+        }))
+    }
+
+    fn set_times(&self, times: FileTimes) -> Self::SetTimes {
+        ready(self.change(Operation::SetTimes, |node| {
+            if let Some(accessed) = times.accessed {
+                node.accessed = accessed;
+            }
+            if let Some(modified) = times.modified {
+                node.modified = modified;
+            }
+        }))
+    }
+
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> Self::SetOwner {
+        ready(self.change(Operation::SetOwner, |node| {
+            node.uid = uid.or(node.uid);
+            node.gid = gid.or(node.gid);
+        }))
+    }
+
+    fn supports_xattr(&self) -> bool {
+        true
+    }
+
+    fn get_xattr(&self, name: &str) -> Self::GetXattr {
+        let root = self.fs.root();
+        ready(
+            get(&root, &self.components())
+                .map(|node| node.xattrs.get(name).cloned())
+                .map_err(|err| self.context(err, Operation::GetXattr)),
+        )
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8]) -> Self::SetXattr {
+        ready(self.change(Operation::SetXattr, |node| {
+            node.xattrs.insert(name.to_string(), value.to_vec());
+        }))
+    }
+
+    fn list_xattr(&self) -> Self::ListXattr {
+        let root = self.fs.root();
+        ready(
+            get(&root, &self.components())
+                .map(|node| node.xattrs.keys().cloned().collect())
+                .map_err(|err| self.context(err, Operation::ListXattr)),
+        )
+    }
+
+    fn remove_xattr(&self, name: &str) -> Self::RemoveXattr {
+        ready(self.change(Operation::RemoveXattr, |node| {
+            node.xattrs.remove(name);
+        }))
+    }
+
+    fn watch(&self, recursive: bool) -> Self::Watch {
+        ready(Ok(self.fs.inner.hub.subscribe(self, recursive)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use vfs::{SeekFrom, VFileExt};
+
+    use super::*;
+
+    fn write(fs: &MemoryFS, path: &str, data: &[u8]) {
+        let options = OpenOptions::new().write(true).create(true).truncate(true);
+        let mut file = block_on(fs.path(path).unwrap().open(options)).unwrap();
+        block_on(file.write_all(data)).unwrap();
+        block_on(file.close()).unwrap();
+    }
+
+    fn read(fs: &MemoryFS, path: &str) -> Result<Vec<u8>, Error> {
+        let mut file = block_on(fs.path(path)?.open(OpenOptions::new().read(true)))?;
+        let mut data = Vec::new();
+        block_on(file.read_to_end(&mut data))?;
+        Ok(data)
+    }
+
+    fn exists(fs: &MemoryFS, path: &str) -> bool {
+        block_on(fs.path(path).unwrap().metadata()).is_ok()
+    }
+
+    /// The node at `path` below `root`
+    fn node<'a>(root: &'a Arc<Node>, path: &str) -> &'a Arc<Node> {
+        path.split('/').fold(root, |node, name| {
+            node.entries().unwrap().get(name).unwrap()
+        })
+    }
+
+    /// The changes from `old` to `new` as the kind of event and the path
+    fn changes(old: &MemoryFS, new: &MemoryFS) -> Vec<(&'static str, String)> {
+        let mut changes: Vec<_> = old
+            .diff(new)
+            .into_iter()
+            .map(|event| match event {
+                WatchEvent::Create(path) => ("create", path.virtual_path()),
+                WatchEvent::Modify(path) => ("modify", path.virtual_path()),
+                WatchEvent::Remove(path) => ("remove", path.virtual_path()),
+                event => panic!("unexpected change {event:?}"),
+            })
+            .collect();
+        changes.sort();
+        changes
+    }
+
+    #[test]
+    fn fork_is_isolated() {
+        let fs = MemoryFS::new();
+        write(&fs, "/a", b"one");
+        block_on(fs.path("/dir").unwrap().create_dir()).unwrap();
+        write(&fs, "/dir/b", b"b");
+
+        let fork = fs.fork();
+
+        // Changes to the fork stay in the fork
+        write(&fork, "/a", b"two");
+        write(&fork, "/new", b"new");
+        block_on(fork.path("/dir/b").unwrap().rm()).unwrap();
+        assert_eq!(read(&fs, "/a").unwrap(), b"one");
+        assert!(!exists(&fs, "/new"));
+        assert_eq!(read(&fs, "/dir/b").unwrap(), b"b");
+
+        // And changes to the original stay in the original
+        write(&fs, "/dir/c", b"c");
+        block_on(fs.path("/a").unwrap().rm()).unwrap();
+        assert!(!exists(&fork, "/dir/c"));
+        assert_eq!(read(&fork, "/a").unwrap(), b"two");
+
+        // Files opened before the fork write to the original
+        let options = OpenOptions::new().write(true).create(true);
+        let mut file = block_on(fs.path("/open").unwrap().open(options)).unwrap();
+        let fork = fs.fork();
+        block_on(file.write_all(b"late")).unwrap();
+        assert_eq!(read(&fs, "/open").unwrap(), b"late");
+        assert_eq!(read(&fork, "/open").unwrap(), b"");
+    }
+
+    #[test]
+    fn handle_of_removed_file() {
+        let fs = MemoryFS::new();
+        write(&fs, "/a", b"old");
+        let options = OpenOptions::new().read(true).write(true);
+        let mut file = block_on(fs.path("/a").unwrap().open(options)).unwrap();
+
+        block_on(fs.path("/a").unwrap().rm()).unwrap();
+        write(&fs, "/a", b"new");
+
+        // The handle does not reach the file created at the same path
+        let err = block_on(file.write_all(b"lost")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        let err = block_on(file.read(&mut [0; 4])).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        let err = block_on(file.seek(SeekFrom::End(0))).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(read(&fs, "/a").unwrap(), b"new");
+
+        // Copies made for forks are the same file
+        let mut file = block_on(fs.path("/a").unwrap().open(options)).unwrap();
+        let _fork = fs.fork();
+        block_on(file.write_all(b"NEW")).unwrap();
+        assert_eq!(read(&fs, "/a").unwrap(), b"NEW");
+    }
+
+    #[test]
+    fn fork_shares_unchanged_nodes() {
+        let fs = MemoryFS::new();
+        block_on(fs.path("/x/y").unwrap().create_dir()).unwrap();
+        block_on(fs.path("/z").unwrap().create_dir()).unwrap();
+        write(&fs, "/x/y/file", b"one");
+        write(&fs, "/x/other", b"other");
+        write(&fs, "/z/file", b"z");
+
+        let fork = fs.fork();
+        assert!(Arc::ptr_eq(&fs.root(), &fork.root()));
+
+        write(&fork, "/x/y/file", b"two");
+        let (old, new) = (fs.root(), fork.root());
+
+        // The path to the change is copied, everything beside it is shared
+        assert!(!Arc::ptr_eq(&old, &new));
+        for path in ["x", "x/y", "x/y/file"] {
+            assert!(!Arc::ptr_eq(node(&old, path), node(&new, path)), "{path}");
+        }
+        for path in ["z", "z/file", "x/other"] {
+            assert!(Arc::ptr_eq(node(&old, path), node(&new, path)), "{path}");
+        }
+
+        // Reading does not copy anything
+        read(&fork, "/z/file").unwrap();
+        assert!(Arc::ptr_eq(node(&old, "z"), node(&fork.root(), "z")));
+    }
+
+    #[test]
+    fn diff_reports_changes() {
+        let fs = MemoryFS::new();
+        write(&fs, "/same", b"same");
+        write(&fs, "/changed", b"old");
+        block_on(fs.path("/dir/sub").unwrap().create_dir()).unwrap();
+        write(&fs, "/dir/sub/file", b"file");
+
+        let fork = fs.fork();
+        assert!(fs.diff(&fork).is_empty());
+
+        write(&fork, "/changed", b"new");
+        write(&fork, "/created", b"created");
+        block_on(fork.path("/dir").unwrap().rm()).unwrap();
+        let mode = Permissions::from_mode(0o600);
+        block_on(fork.path("/same").unwrap().set_permissions(mode)).unwrap();
+
+        // A removed directory is a single change
+        assert_eq!(
+            changes(&fs, &fork),
+            [
+                ("create", String::from("/created")),
+                ("modify", String::from("/changed")),
+                ("modify", String::from("/same")),
+                ("remove", String::from("/dir")),
+            ]
+        );
+
+        // The other way around, with the paths of the original
+        assert_eq!(
+            changes(&fork, &fs),
+            [
+                ("create", String::from("/dir")),
+                ("modify", String::from("/changed")),
+                ("modify", String::from("/same")),
+                ("remove", String::from("/created")),
+            ]
+        );
+        // Paths are those of the filesystem compared against
+        let fork_path = fork.path("/created").unwrap();
+        assert!(fs.diff(&fork).contains(&WatchEvent::Create(fork_path)));
+    }
+}
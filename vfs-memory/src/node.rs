@@ -0,0 +1,308 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use vfs::{Error, ErrorKind, FileType, Metadata, Permissions, Timestamp, VPathStr};
+
+/// File contents are split into blocks of this size, so a fork copies
+/// only the blocks written to
+pub(crate) const BLOCK_SIZE: usize = 64 * 1024;
+
+/// The content of a file. Every block but the last holds [`BLOCK_SIZE`] bytes
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Data {
+    blocks: Vec<Arc<Vec<u8>>>,
+    len: u64,
+}
+
+impl Data {
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Read from `pos` into `buf`, returning the number of bytes read
+    pub fn read_at(&self, pos: u64, buf: &mut [u8]) -> usize {
+        let available = self.len.saturating_sub(pos);
+        let len = buf.len().min(available as usize);
+
+        let mut done = 0;
+        while done < len {
+            let pos = pos as usize + done;
+            let block = &self.blocks[pos / BLOCK_SIZE];
+            let offset = pos % BLOCK_SIZE;
+            let n = (len - done).min(block.len() - offset);
+            buf[done..done + n].copy_from_slice(&block[offset..offset + n]);
+            done += n;
+        }
+        len
+    }
+
+    /// Write `data` at `pos`, filling a gap after the end with zeros
+    pub fn write_at(&mut self, pos: u64, data: &[u8]) {
+        if pos > self.len {
+            self.set_len(pos);
+        }
+
+        let mut done = 0;
+        while done < data.len() {
+            let pos = pos as usize + done;
+            let (index, offset) = (pos / BLOCK_SIZE, pos % BLOCK_SIZE);
+            if index == self.blocks.len() {
+                self.blocks.push(Arc::default());
+            }
+
+            let block = Arc::make_mut(&mut self.blocks[index]);
+            let n = (data.len() - done).min(BLOCK_SIZE - offset);
+            if block.len() < offset + n {
+                block.resize(offset + n, 0);
+            }
+            block[offset..offset + n].copy_from_slice(&data[done..done + n]);
+            done += n;
+        }
+
+        self.len = self.len.max(pos + data.len() as u64);
+    }
+
+    /// Truncate or extend with zeros to `len` bytes
+    pub fn set_len(&mut self, len: u64) {
+        if len <= self.len {
+            let blocks = (len as usize).div_ceil(BLOCK_SIZE);
+            self.blocks.truncate(blocks);
+            if let Some(last) = self.blocks.last_mut() {
+                let last_len = len as usize - (blocks - 1) * BLOCK_SIZE;
+                if last.len() != last_len {
+                    Arc::make_mut(last).truncate(last_len);
+                }
+            }
+        } else {
+            while self.len < len {
+                let (index, offset) = (
+                    self.len as usize / BLOCK_SIZE,
+                    self.len as usize % BLOCK_SIZE,
+                );
+                if index == self.blocks.len() {
+                    self.blocks.push(Arc::default());
+                }
+                let n = (len - self.len).min((BLOCK_SIZE - offset) as u64);
+                Arc::make_mut(&mut self.blocks[index]).resize(offset + n as usize, 0);
+                self.len += n;
+            }
+        }
+        self.len = len;
+    }
+
+    fn same_content(&self, other: &Data) -> bool {
+        self.len == other.len
+            && self
+                .blocks
+                .iter()
+                .zip(&other.blocks)
+                .all(|(a, b)| Arc::ptr_eq(a, b) || a == b)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Kind {
+    File(Data),
+    Dir(BTreeMap<String, Arc<Node>>),
+}
+
+/// A file or directory. Nodes are shared between forks until one of them changes
+#[derive(Debug, Clone)]
+pub(crate) struct Node {
+    /// Unique among the nodes created, and kept by the copies made for forks
+    pub id: u64,
+    pub kind: Kind,
+    pub permissions: Permissions,
+    pub accessed: Timestamp,
+    pub modified: Timestamp,
+    pub created: Timestamp,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+impl Node {
+    pub fn new(kind: Kind, now: Timestamp) -> Node {
+        let mode = match kind {
+            Kind::File(_) => 0o644,
+            Kind::Dir(_) => 0o755,
+        };
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Node {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            kind,
+            permissions: Permissions::from_mode(mode),
+            accessed: now,
+            modified: now,
+            created: now,
+            uid: None,
+            gid: None,
+            xattrs: BTreeMap::new(),
+        }
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        let (kind, size) = match &self.kind {
+            Kind::File(data) => (FileType::File, data.len()),
+            Kind::Dir(_) => (FileType::Dir, 0),
+        };
+        Metadata {
+            size,
+            kind,
+            permissions: Some(self.permissions),
+            accessed: Some(self.accessed),
+            modified: Some(self.modified),
+            created: Some(self.created),
+            uid: self.uid,
+            gid: self.gid,
+            hash: None,
+        }
+    }
+
+    pub fn entries(&self) -> Result<&BTreeMap<String, Arc<Node>>, Error> {
+        match &self.kind {
+            Kind::Dir(entries) => Ok(entries),
+            Kind::File(_) => Err(ErrorKind::NotADirectory.into()),
+        }
+    }
+
+    pub fn entries_mut(&mut self) -> Result<&mut BTreeMap<String, Arc<Node>>, Error> {
+        match &mut self.kind {
+            Kind::Dir(entries) => Ok(entries),
+            Kind::File(_) => Err(ErrorKind::NotADirectory.into()),
+        }
+    }
+
+    pub fn data_mut(&mut self) -> Result<&mut Data, Error> {
+        match &mut self.kind {
+            Kind::File(data) => Ok(data),
+            Kind::Dir(_) => Err(ErrorKind::IsADirectory.into()),
+        }
+    }
+
+    /// Returns true if the properties other than the content and times differ
+    fn attributes_differ(&self, other: &Node) -> bool {
+        self.permissions != other.permissions
+            || self.uid != other.uid
+            || self.gid != other.gid
+            || self.xattrs != other.xattrs
+    }
+}
+
+/// Find the node at `components` below `root`
+pub(crate) fn get<'a>(mut node: &'a Node, components: &[&str]) -> Result<&'a Node, Error> {
+    for name in components {
+        node = node.entries()?.get(*name).ok_or(ErrorKind::NotFound)?;
+    }
+    Ok(node)
+}
+
+/// Find the node at `components` below `root` to change it, copying it
+/// and its parents if they are shared with a fork
+pub(crate) fn get_mut<'a>(
+    mut node: &'a mut Arc<Node>,
+    components: &[&str],
+) -> Result<&'a mut Node, Error> {
+    // Look up first, so a failed lookup copies nothing
+    get(node, components)?;
+    for name in components {
+        node = Arc::make_mut(node)
+            .entries_mut()?
+            .get_mut(*name)
+            .ok_or(ErrorKind::NotFound)?;
+    }
+    Ok(Arc::make_mut(node))
+}
+
+/// A change found by [`diff`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Change {
+    Create(String),
+    Modify(String),
+    Remove(String),
+}
+
+/// Collect the changes turning `old` into `new` into `changes`. Nodes shared
+/// by both trees are equal, so only the parts changed since a fork are compared
+pub(crate) fn diff(path: &str, old: &Arc<Node>, new: &Arc<Node>, changes: &mut Vec<Change>) {
+    if Arc::ptr_eq(old, new) {
+        return;
+    }
+
+    match (&old.kind, &new.kind) {
+        (Kind::File(a), Kind::File(b)) => {
+            if !a.same_content(b) || old.modified != new.modified || old.attributes_differ(new) {
+                changes.push(Change::Modify(path.to_string()));
+            }
+        }
+        (Kind::Dir(a), Kind::Dir(b)) => {
+            // Changed entries change the modification time, and are reported themselves
+            if old.attributes_differ(new) {
+                changes.push(Change::Modify(path.to_string()));
+            }
+
+            for (name, node) in a {
+                let child = VPathStr::new(path).join(name).into_string();
+                match b.get(name) {
+                    Some(other) => diff(&child, node, other, changes),
+                    None => changes.push(Change::Remove(child)),
+                }
+            }
+            for name in b.keys().filter(|name| !a.contains_key(*name)) {
+                changes.push(Change::Create(VPathStr::new(path).join(name).into_string()));
+            }
+        }
+        _ => {
+            changes.push(Change::Remove(path.to_string()));
+            changes.push(Change::Create(path.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn content(data: &Data) -> Vec<u8> {
+        let mut buf = vec![0; data.len() as usize];
+        assert_eq!(data.read_at(0, &mut buf), buf.len());
+        buf
+    }
+
+    #[test]
+    fn copies_share_unchanged_blocks() {
+        let mut data = Data::default();
+        data.write_at(0, &pattern(BLOCK_SIZE * 3 + 10));
+        let original = content(&data);
+
+        let mut copy = data.clone();
+        copy.write_at(BLOCK_SIZE as u64 + 5, b"changed");
+
+        let shared: Vec<_> = data
+            .blocks
+            .iter()
+            .zip(&copy.blocks)
+            .map(|(a, b)| Arc::ptr_eq(a, b))
+            .collect();
+        assert_eq!(shared, [true, false, true, true]);
+        assert_eq!(content(&data), original);
+        assert!(!data.same_content(&copy));
+
+        // Cutting the last block short copies only that block
+        let mut copy = data.clone();
+        copy.set_len(BLOCK_SIZE as u64 * 3 + 2);
+        assert!(Arc::ptr_eq(&data.blocks[2], &copy.blocks[2]));
+        assert!(!Arc::ptr_eq(&data.blocks[3], &copy.blocks[3]));
+        assert_eq!(content(&data), original);
+        assert_eq!(content(&copy), original[..BLOCK_SIZE * 3 + 2]);
+    }
+}
@@ -0,0 +1,113 @@
+use core::fmt;
+
+use vfs::{FileTimes, Permissions};
+
+/// A change a [`RecordingFS`](crate::RecordingFS) left undone. Paths are the
+/// normalized virtual paths.
+///
+/// The [`Display`](fmt::Display) implementation describes the change in a line,
+/// for listing what would be done.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    CreateDir {
+        path: String,
+    },
+    /// Creating an empty file, before any writes to it
+    CreateFile {
+        path: String,
+    },
+    /// Removing a file, or a directory with everything in it
+    Remove {
+        path: String,
+    },
+    /// Cutting a file opened with `truncate` to zero bytes
+    Truncate {
+        path: String,
+    },
+    /// Writing `len` bytes at `offset`. Consecutive writes through the same file
+    /// are recorded as one
+    Write {
+        path: String,
+        offset: u64,
+        len: u64,
+    },
+    SetPermissions {
+        path: String,
+        permissions: Permissions,
+    },
+    SetTimes {
+        path: String,
+        times: FileTimes,
+    },
+    SetOwner {
+        path: String,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    },
+    SetXattr {
+        path: String,
+        name: String,
+        value: Vec<u8>,
+    },
+    RemoveXattr {
+        path: String,
+        name: String,
+    },
+}
+
+impl Change {
+    /// The path changed
+    pub fn path(&self) -> &str {
+        match self {
+            Change::CreateDir { path }
+            | Change::CreateFile { path }
+            | Change::Remove { path }
+            | Change::Truncate { path }
+            | Change::Write { path, .. }
+            | Change::SetPermissions { path, .. }
+            | Change::SetTimes { path, .. }
+            | Change::SetOwner { path, .. }
+            | Change::SetXattr { path, .. }
+            | Change::RemoveXattr { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::CreateDir { path } => write!(f, "create directory {path}"),
+            Change::CreateFile { path } => write!(f, "create file {path}"),
+            Change::Remove { path } => write!(f, "remove {path}"),
+            Change::Truncate { path } => write!(f, "truncate {path}"),
+            Change::Write { path, offset, len } => {
+                write!(f, "write {} at offset {offset} to {path}", bytes(*len))
+            }
+            Change::SetPermissions { path, permissions } => {
+                write!(f, "set permissions of {path} to {:o}", permissions.mode())
+            }
+            Change::SetTimes { path, .. } => write!(f, "set times of {path}"),
+            Change::SetOwner { path, uid, gid } => {
+                write!(f, "set owner of {path} to")?;
+                if let Some(uid) = uid {
+                    write!(f, " uid {uid}")?;
+                }
+                if let Some(gid) = gid {
+                    write!(f, " gid {gid}")?;
+                }
+                Ok(())
+            }
+            Change::SetXattr { path, name, value } => {
+                write!(f, "set {name} of {path} to {}", bytes(value.len() as u64))
+            }
+            Change::RemoveXattr { path, name } => write!(f, "remove {name} of {path}"),
+        }
+    }
+}
+
+fn bytes(len: u64) -> String {
+    match len {
+        1 => String::from("1 byte"),
+        len => format!("{len} bytes"),
+    }
+}
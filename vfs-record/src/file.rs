@@ -0,0 +1,248 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use vfs::{Error, ErrorKind, Operation, SeekFrom, SendVFS, VFS, VFile};
+
+use crate::{Change, InnerFile, RecordingPath, overlay::Buffer};
+
+pub(crate) enum Content<F: VFS> {
+    /// Opened for reading only, straight from the inner filesystem.
+    /// Writes are refused before they reach it
+    Inner(Pin<Box<InnerFile<F>>>),
+    /// Writes are recorded and dropped, while reads see the inner file as it is.
+    /// `size` is the size the file would have, and `seek` is set when the inner
+    /// file has to be moved to the position before reading
+    Discard {
+        inner: Option<Pin<Box<InnerFile<F>>>>,
+        size: u64,
+        seek: bool,
+    },
+    Simulated(Buffer),
+}
+
+/// A file of a [`RecordingFS`](crate::RecordingFS).
+///
+/// Files opened for reading only are read from the inner filesystem. Writes are
+/// recorded when the file is flushed, closed or dropped, and applied to the
+/// simulated content if the filesystem simulates its changes.
+pub struct RecordingFile<F: VFS> {
+    path: RecordingPath<F>,
+    content: Content<F>,
+    pos: u64,
+    read: bool,
+    write: bool,
+    append: bool,
+    /// The offset and length of the writes not recorded yet
+    pending: Option<(u64, u64)>,
+}
+
+// The inner files are boxed, and the path is never pinned
+impl<F: VFS> Unpin for RecordingFile<F> {}
+
+impl<F: VFS> core::fmt::Debug for RecordingFile<F>
+where
+    F::Path: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RecordingFile")
+            .field("path", &self.path)
+            .field("pos", &self.pos)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: SendVFS> RecordingFile<F> {
+    pub(crate) fn new(
+        path: RecordingPath<F>,
+        content: Content<F>,
+        read: bool,
+        write: bool,
+        append: bool,
+    ) -> RecordingFile<F> {
+        RecordingFile {
+            path,
+            content,
+            pos: 0,
+            read,
+            write: write || append,
+            append,
+            pending: None,
+        }
+    }
+
+    fn context(&self, err: Error, operation: Operation) -> Error {
+        self.path.context(err, operation)
+    }
+}
+
+impl<F: VFS> RecordingFile<F> {
+    /// Add a write to the pending one, recording that first unless the write continues it
+    fn wrote(&mut self, offset: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        if let Some((start, pending)) = &mut self.pending
+            && *start + *pending == offset
+        {
+            *pending += len;
+            return;
+        }
+        self.record_pending();
+        self.pending = Some((offset, len));
+    }
+
+    fn record_pending(&mut self) {
+        if let Some((offset, len)) = self.pending.take() {
+            self.path.record(Change::Write {
+                path: self.path.key(),
+                offset,
+                len,
+            });
+        }
+    }
+}
+
+/// Records the writes of a file dropped without flushing or closing it
+impl<F: VFS> Drop for RecordingFile<F> {
+    fn drop(&mut self) {
+        self.record_pending();
+    }
+}
+
+impl<F: SendVFS> VFile for RecordingFile<F> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        if !this.read {
+            let err = Error::new_const(ErrorKind::PermissionDenied, "file not opened for reading");
+            return Poll::Ready(Err(this.context(err, Operation::Read)));
+        }
+
+        let n = match &mut this.content {
+            Content::Inner(inner) => return inner.as_mut().poll_read(cx, buf),
+            Content::Discard { inner: None, .. } => 0,
+            Content::Discard {
+                inner: Some(inner),
+                seek,
+                ..
+            } => {
+                if *seek {
+                    ready!(inner.as_mut().poll_seek(cx, SeekFrom::Start(this.pos)))?;
+                    *seek = false;
+                }
+                ready!(inner.as_mut().poll_read(cx, buf))?
+            }
+            Content::Simulated(buffer) => {
+                let buffer = buffer.lock().unwrap();
+                let start = this.pos.min(buffer.len() as u64) as usize;
+                let n = buf.len().min(buffer.len() - start);
+                buf[..n].copy_from_slice(&buffer[start..start + n]);
+                n
+            }
+        };
+
+        this.pos += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        let this = self.get_mut();
+        if let Content::Inner(inner) = &mut this.content {
+            return inner.as_mut().poll_seek(cx, pos);
+        }
+
+        let target = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(offset) => this.pos.checked_add_signed(offset),
+            SeekFrom::End(offset) => {
+                let size = match &mut this.content {
+                    Content::Inner(_) => unreachable!(),
+                    Content::Discard { size, .. } => *size,
+                    Content::Simulated(buffer) => buffer.lock().unwrap().len() as u64,
+                };
+                size.checked_add_signed(offset)
+            }
+        };
+
+        let Some(target) = target else {
+            let err = Error::new_const(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            );
+            return Poll::Ready(Err(this.context(err, Operation::Seek)));
+        };
+
+        if let Content::Discard { seek, .. } = &mut this.content {
+            *seek = true;
+        }
+        this.pos = target;
+        Poll::Ready(Ok(target))
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        // Files with inner content are never opened for writing
+        if !this.write {
+            let err = Error::new_const(ErrorKind::PermissionDenied, "file not opened for writing");
+            return Poll::Ready(Err(this.context(err, Operation::Write)));
+        }
+
+        let offset = match &mut this.content {
+            Content::Inner(_) => unreachable!(),
+            Content::Discard { size, seek, .. } => {
+                let offset = if this.append { *size } else { this.pos };
+                *size = (*size).max(offset + buf.len() as u64);
+                *seek = true;
+                offset
+            }
+            Content::Simulated(buffer) => {
+                let mut buffer = buffer.lock().unwrap();
+                let offset = match this.append {
+                    true => buffer.len(),
+                    false => this.pos as usize,
+                };
+                let end = offset + buf.len();
+                if buffer.len() < end {
+                    buffer.resize(end, 0);
+                }
+                buffer[offset..end].copy_from_slice(buf);
+                offset as u64
+            }
+        };
+
+        this.wrote(offset, buf.len() as u64);
+        this.pos = offset + buf.len() as u64;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    /// Records the writes made since the last flush
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.get_mut().record_pending();
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        this.record_pending();
+        match &mut this.content {
+            Content::Inner(inner)
+            | Content::Discard {
+                inner: Some(inner), ..
+            } => inner.as_mut().poll_close(cx),
+            _ => Poll::Ready(Ok(())),
+        }
+    }
+}
@@ -0,0 +1,672 @@
+//! A [`VFS`] which records the changes made through it instead of making them.
+//!
+//! Reads pass through to the inner filesystem, while creating directories,
+//! removing, writing files and setting their properties only adds a [`Change`]
+//! to a list, for a dry run to show what would be done.
+//!
+//! Created and removed paths are always tracked, so a file can be created in a
+//! directory made before, and nothing is recorded twice. Changes can also be
+//! simulated, so later reads see the tree and file contents as if the changes
+//! were made. The simulation is kept in memory, including the
+//! whole content of every file written to. Permissions, times, owners and
+//! extended attributes are recorded, but read back as they are on the inner
+//! filesystem.
+mod change;
+mod file;
+mod overlay;
+
+pub use self::{change::Change, file::RecordingFile};
+
+use std::sync::{Arc, Mutex};
+
+use futures::{StreamExt, TryStreamExt};
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use vfs::{
+    Capabilities, DirEntry, Error, ErrorKind, FileTimes, FileType, Metadata, OpenOptions,
+    Operation, Permissions, SendVFS, VFS, VFileExt, VPath, VPathStr, WatchEvent,
+};
+
+use self::{
+    file::Content,
+    overlay::{Buffer, Lookup, Overlay},
+};
+
+pub(crate) type InnerFile<F> = <<F as VFS>::Path as VPath>::File;
+
+#[derive(Debug, Default)]
+struct Shared {
+    changes: Mutex<Vec<Change>>,
+    overlay: Mutex<Overlay>,
+}
+
+/// A filesystem which records its changes in a list instead of making them.
+///
+/// The recorded changes are listed with [`changes`](RecordingFS::changes). Clones
+/// share the list, and so do paths and files.
+pub struct RecordingFS<F: VFS> {
+    fs: F,
+    simulate: bool,
+    shared: Arc<Shared>,
+}
+
+impl<F: VFS + Clone> Clone for RecordingFS<F> {
+    fn clone(&self) -> Self {
+        RecordingFS {
+            fs: self.fs.clone(),
+            simulate: self.simulate,
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<F: VFS + core::fmt::Debug> core::fmt::Debug for RecordingFS<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RecordingFS")
+            .field("fs", &self.fs)
+            .field("simulate", &self.simulate)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: SendVFS> RecordingFS<F> {
+    /// Record the changes to `fs` without simulating them
+    pub fn new(fs: F) -> RecordingFS<F> {
+        RecordingFS {
+            fs,
+            simulate: false,
+            shared: Arc::default(),
+        }
+    }
+
+    /// Simulate the recorded changes, so reads see them
+    pub fn simulate(mut self, simulate: bool) -> Self {
+        self.simulate = simulate;
+        self
+    }
+
+    /// The wrapped filesystem
+    pub fn get_ref(&self) -> &F {
+        &self.fs
+    }
+
+    /// The changes recorded so far, in the order they were made
+    pub fn changes(&self) -> Vec<Change> {
+        self.shared.changes.lock().unwrap().clone()
+    }
+
+    /// Take the changes recorded so far, leaving the list empty. The simulation is kept
+    pub fn take_changes(&self) -> Vec<Change> {
+        core::mem::take(&mut *self.shared.changes.lock().unwrap())
+    }
+}
+
+impl<F: SendVFS> VFS for RecordingFS<F> {
+    type Path = RecordingPath<F>;
+
+    type Stats = F::Stats;
+
+    fn path(&self, path: impl AsRef<VPathStr>) -> Result<Self::Path, Error> {
+        Ok(RecordingPath {
+            simulate: self.simulate,
+            shared: self.shared.clone(),
+            inner: self.fs.path(path)?,
+        })
+    }
+
+    /// The space of the inner filesystem, which the changes do not use
+    fn stats(&self) -> Self::Stats {
+        self.fs.stats()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.fs.capabilities()
+    }
+}
+
+/// A path of a [`RecordingFS`]
+pub struct RecordingPath<F: VFS> {
+    simulate: bool,
+    shared: Arc<Shared>,
+    inner: F::Path,
+}
+
+impl<F: VFS> Clone for RecordingPath<F>
+where
+    F::Path: Clone,
+{
+    fn clone(&self) -> Self {
+        RecordingPath {
+            simulate: self.simulate,
+            shared: self.shared.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<F: VFS> core::fmt::Debug for RecordingPath<F>
+where
+    F::Path: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("RecordingPath").field(&self.inner).finish()
+    }
+}
+
+impl<F: VFS> PartialEq for RecordingPath<F>
+where
+    F::Path: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<F: VFS> Eq for RecordingPath<F> where F::Path: Eq {}
+
+impl<F: VFS> core::hash::Hash for RecordingPath<F>
+where
+    F::Path: core::hash::Hash,
+{
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.inner.hash(state)
+    }
+}
+
+impl<F: VFS> RecordingPath<F> {
+    /// The normalized path changes are recorded under
+    pub(crate) fn key(&self) -> String {
+        VPathStr::new(&self.inner.virtual_path())
+            .normalize()
+            .into_string()
+    }
+
+    pub(crate) fn context(&self, err: Error, operation: Operation) -> Error {
+        err.with_operation(operation)
+            .with_path(self.inner.virtual_path())
+    }
+
+    pub(crate) fn record(&self, change: Change) {
+        self.shared.changes.lock().unwrap().push(change);
+    }
+}
+
+impl<F: SendVFS> RecordingPath<F> {
+    /// The path of the inner filesystem
+    pub fn get_ref(&self) -> &F::Path {
+        &self.inner
+    }
+
+    fn wrap(&self, inner: F::Path) -> RecordingPath<F> {
+        RecordingPath {
+            simulate: self.simulate,
+            shared: self.shared.clone(),
+            inner,
+        }
+    }
+
+    /// The state of the path as reads see it, which is the inner filesystem
+    /// unless the changes are simulated
+    fn lookup(&self) -> Lookup {
+        match self.simulate {
+            true => self.tracked(),
+            false => Lookup::Inner,
+        }
+    }
+
+    /// The state of the path after the changes recorded so far. Files created
+    /// without simulating have an empty buffer
+    fn tracked(&self) -> Lookup {
+        self.shared.overlay.lock().unwrap().lookup(&self.key())
+    }
+
+    /// The type and size of the file at this path, or `None` if there is none
+    async fn state(&self) -> Result<Option<(FileType, u64)>, Error> {
+        match self.tracked() {
+            Lookup::Inner => match self.inner.metadata().await {
+                Ok(metadata) => Ok(Some((metadata.kind, metadata.size))),
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err),
+            },
+            Lookup::Missing => Ok(None),
+            Lookup::Dir => Ok(Some((FileType::Dir, 0))),
+            Lookup::File(buffer) => Ok(Some((FileType::File, buffer.lock().unwrap().len() as u64))),
+        }
+    }
+
+    /// Fail unless there is a file or directory at this path
+    async fn exists(&self) -> Result<(), Error> {
+        match self.state().await? {
+            Some(_) => Ok(()),
+            None => Err(ErrorKind::NotFound.into()),
+        }
+    }
+
+    async fn metadata_simulated(&self) -> Result<Metadata, Error> {
+        let (kind, size) = match self.lookup() {
+            Lookup::Inner => return self.inner.metadata().await,
+            Lookup::Missing => return Err(ErrorKind::NotFound.into()),
+            Lookup::Dir => (FileType::Dir, 0),
+            Lookup::File(buffer) => {
+                let size = buffer.lock().unwrap().len() as u64;
+                // A file written to keeps the other properties of the inner file
+                match self.inner.metadata().await {
+                    Ok(metadata) if metadata.is_file() => {
+                        return Ok(Metadata {
+                            size,
+                            hash: None,
+                            ..metadata
+                        });
+                    }
+                    _ => (FileType::File, size),
+                }
+            }
+        };
+
+        Ok(Metadata {
+            size,
+            kind,
+            permissions: None,
+            accessed: None,
+            modified: None,
+            created: None,
+            uid: None,
+            gid: None,
+            hash: None,
+        })
+    }
+
+    async fn open_recorded(&self, options: OpenOptions) -> Result<RecordingFile<F>, Error> {
+        if !(options.write || options.append) {
+            let content = match self.lookup() {
+                Lookup::Inner => {
+                    let file = self.inner.open(OpenOptions::new().read(true)).await?;
+                    Content::Inner(Box::pin(file))
+                }
+                Lookup::Missing => return Err(ErrorKind::NotFound.into()),
+                Lookup::Dir => return Err(ErrorKind::IsADirectory.into()),
+                Lookup::File(buffer) => Content::Simulated(buffer),
+            };
+            return Ok(RecordingFile::new(
+                self.clone(),
+                content,
+                options.read,
+                false,
+                false,
+            ));
+        }
+
+        let tracked = self.tracked();
+        let state = self.state().await?;
+        match state {
+            Some((FileType::Dir, _)) => return Err(ErrorKind::IsADirectory.into()),
+            None if !options.create => return Err(ErrorKind::NotFound.into()),
+            None => {
+                if let Some(parent) = self.parent() {
+                    match parent.state().await? {
+                        Some((FileType::Dir, _)) => {}
+                        Some(_) => return Err(ErrorKind::NotADirectory.into()),
+                        None => return Err(ErrorKind::NotFound.into()),
+                    }
+                }
+                self.record(Change::CreateFile { path: self.key() });
+            }
+            Some(_) if options.truncate => self.record(Change::Truncate { path: self.key() }),
+            Some(_) => {}
+        }
+
+        let keep = state.is_some() && !options.truncate;
+        let content = if self.simulate {
+            let buffer = match self.lookup() {
+                Lookup::File(buffer) => buffer,
+                lookup => {
+                    let mut data = Vec::new();
+                    if keep && matches!(lookup, Lookup::Inner) {
+                        let file = self.inner.open(OpenOptions::new().read(true)).await?;
+                        let mut file = core::pin::pin!(file);
+                        file.read_to_end(&mut data).await?;
+                    }
+                    let buffer = Arc::new(Mutex::new(data));
+                    let mut overlay = self.shared.overlay.lock().unwrap();
+                    overlay.create_file(&self.key(), buffer.clone());
+                    buffer
+                }
+            };
+            if options.truncate {
+                buffer.lock().unwrap().clear();
+            }
+            Content::Simulated(buffer)
+        } else {
+            if state.is_none() {
+                let mut overlay = self.shared.overlay.lock().unwrap();
+                overlay.create_file(&self.key(), Buffer::default());
+            }
+            // A file created before has nothing on the inner filesystem to read
+            let inner = match keep && options.read && matches!(tracked, Lookup::Inner) {
+                true => {
+                    let file = self.inner.open(OpenOptions::new().read(true)).await?;
+                    Some(Box::pin(file))
+                }
+                false => None,
+            };
+            let size = match (state, keep) {
+                (Some((_, size)), true) => size,
+                _ => 0,
+            };
+            Content::Discard {
+                inner,
+                size,
+                seek: false,
+            }
+        };
+
+        Ok(RecordingFile::new(
+            self.clone(),
+            content,
+            options.read,
+            options.write,
+            options.append,
+        ))
+    }
+
+    async fn list_simulated(&self) -> Result<Vec<DirEntry<Self>>, Error> {
+        let mut entries = Vec::new();
+        match self.lookup() {
+            Lookup::Inner => {
+                let list = self.inner.read_dir().await?;
+                let mut list = core::pin::pin!(list);
+                while let Some(entry) = list.try_next().await? {
+                    let entry = entry.map(|inner| self.wrap(inner));
+                    match entry.path().lookup() {
+                        Lookup::Inner => entries.push(entry),
+                        Lookup::Missing => {}
+                        Lookup::Dir => {
+                            entries.push(DirEntry::new(entry.into_path(), FileType::Dir))
+                        }
+                        Lookup::File(_) => {
+                            entries.push(DirEntry::new(entry.into_path(), FileType::File))
+                        }
+                    }
+                }
+            }
+            Lookup::Missing => return Err(ErrorKind::NotFound.into()),
+            Lookup::Dir => {}
+            Lookup::File(_) => return Err(ErrorKind::NotADirectory.into()),
+        }
+
+        let created = self.shared.overlay.lock().unwrap().created(&self.key());
+        for (name, dir) in created {
+            if entries
+                .iter()
+                .any(|entry| entry.path().file_name() == Some(name.as_str()))
+            {
+                continue;
+            }
+            let kind = if dir { FileType::Dir } else { FileType::File };
+            entries.push(DirEntry::new(self.resolve(&name)?, kind));
+        }
+        Ok(entries)
+    }
+
+    async fn create_dir_recorded(&self) -> Result<(), Error> {
+        // The path and its parents up to the first which exists
+        let mut missing = Vec::new();
+        let mut path = self.clone();
+        loop {
+            match path.state().await? {
+                Some((FileType::Dir, _)) => break,
+                Some(_) if missing.is_empty() => {
+                    return Err(Error::new_const(
+                        ErrorKind::AlreadyExists,
+                        "a file exists at the path",
+                    ));
+                }
+                Some(_) => return Err(ErrorKind::NotADirectory.into()),
+                None => {
+                    let parent = path.parent();
+                    missing.push(path);
+                    match parent {
+                        Some(parent) => path = parent,
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        for path in missing.iter().rev() {
+            let key = path.key();
+            self.shared.overlay.lock().unwrap().create_dir(&key);
+            self.record(Change::CreateDir { path: key });
+        }
+        Ok(())
+    }
+
+    async fn remove_recorded(&self) -> Result<(), Error> {
+        self.exists().await?;
+        let key = self.key();
+        self.shared.overlay.lock().unwrap().remove(&key);
+        self.record(Change::Remove { path: key });
+        Ok(())
+    }
+
+    /// Record `change` if the path exists
+    fn record_existing(
+        &self,
+        change: Change,
+        operation: Operation,
+    ) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+        Box::pin(async move {
+            this.exists()
+                .await
+                .map_err(|err| this.context(err, operation))?;
+            this.record(change);
+            Ok(())
+        })
+    }
+}
+
+impl<F: SendVFS> VPath for RecordingPath<F> {
+    type FS = RecordingFS<F>;
+
+    type File = RecordingFile<F>;
+
+    type ListDir = BoxStream<'static, Result<DirEntry<Self>, Error>>;
+
+    type Metadata = BoxFuture<'static, Result<Metadata, Error>>;
+
+    type Open = BoxFuture<'static, Result<Self::File, Error>>;
+
+    type CreateDir = BoxFuture<'static, Result<(), Error>>;
+
+    type Remove = BoxFuture<'static, Result<(), Error>>;
+
+    type ReadDir = BoxFuture<'static, Result<Self::ListDir, Error>>;
+
+    type SetPermissions = BoxFuture<'static, Result<(), Error>>;
+
+    type SetTimes = BoxFuture<'static, Result<(), Error>>;
+
+    type SetOwner = BoxFuture<'static, Result<(), Error>>;
+
+    type GetXattr = <F::Path as VPath>::GetXattr;
+
+    type SetXattr = BoxFuture<'static, Result<(), Error>>;
+
+    type ListXattr = <F::Path as VPath>::ListXattr;
+
+    type RemoveXattr = BoxFuture<'static, Result<(), Error>>;
+
+    type Watcher = BoxStream<'static, Result<WatchEvent<Self>, Error>>;
+
+    type Watch = BoxFuture<'static, Result<Self::Watcher, Error>>;
+
+    type Canonicalize = BoxFuture<'static, Result<Self, Error>>;
+
+    fn fs(&self) -> Self::FS {
+        RecordingFS {
+            fs: self.inner.fs(),
+            simulate: self.simulate,
+            shared: self.shared.clone(),
+        }
+    }
+
+    fn virtual_path(&self) -> String {
+        self.inner.virtual_path()
+    }
+
+    fn to_string(&self) -> String {
+        self.inner.to_string()
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        self.inner.file_name()
+    }
+
+    fn extension(&self) -> Option<&str> {
+        self.inner.extension()
+    }
+
+    fn resolve(&self, path: &str) -> Result<Self, Error> {
+        Ok(self.wrap(self.inner.resolve(path)?))
+    }
+
+    fn parent(&self) -> Option<Self> {
+        Some(self.wrap(self.inner.parent()?))
+    }
+
+    /// Paths created by the simulation are returned as they are
+    fn canonicalize(&self) -> Self::Canonicalize {
+        let this = self.clone();
+        Box::pin(async move {
+            match this.lookup() {
+                Lookup::Dir | Lookup::File(_) => Ok(this),
+                _ => Ok(this.wrap(this.inner.canonicalize().await?)),
+            }
+        })
+    }
+
+    fn metadata(&self) -> Self::Metadata {
+        let this = self.clone();
+        Box::pin(async move {
+            this.metadata_simulated()
+                .await
+                .map_err(|err| this.context(err, Operation::Metadata))
+        })
+    }
+
+    /// Files opened for writing record the writes to them, and their creation
+    /// or truncation when opened
+    fn open(&self, options: OpenOptions) -> Self::Open {
+        let this = self.clone();
+        Box::pin(async move {
+            this.open_recorded(options)
+                .await
+                .map_err(|err| this.context(err, Operation::Open))
+        })
+    }
+
+    fn read_dir(&self) -> Self::ReadDir {
+        let this = self.clone();
+        Box::pin(async move {
+            if !this.simulate {
+                let wrap = this.clone();
+                let entries = this.inner.read_dir().await?;
+                return Ok(entries
+                    .map_ok(move |entry| entry.map(|inner| wrap.wrap(inner)))
+                    .boxed());
+            }
+
+            let entries = this
+                .list_simulated()
+                .await
+                .map_err(|err| this.context(err, Operation::ReadDir))?;
+            Ok(futures::stream::iter(entries.into_iter().map(Ok)).boxed())
+        })
+    }
+
+    /// Records the creation of the path and every missing parent
+    fn create_dir(&self) -> Self::CreateDir {
+        let this = self.clone();
+        Box::pin(async move {
+            this.create_dir_recorded()
+                .await
+                .map_err(|err| this.context(err, Operation::CreateDir))
+        })
+    }
+
+    /// Records the removal as one change, whether the path is a file or a directory
+    fn rm(&self) -> Self::Remove {
+        let this = self.clone();
+        Box::pin(async move {
+            this.remove_recorded()
+                .await
+                .map_err(|err| this.context(err, Operation::Remove))
+        })
+    }
+
+    fn set_permissions(&self, permissions: Permissions) -> Self::SetPermissions {
+        let change = Change::SetPermissions {
+            path: self.key(),
+            permissions,
+        };
+        self.record_existing(change, Operation::SetPermissions)
+    }
+
+    fn set_times(&self, times: FileTimes) -> Self::SetTimes {
+        let change = Change::SetTimes {
+            path: self.key(),
+            times,
+        };
+        self.record_existing(change, Operation::SetTimes)
+    }
+
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> Self::SetOwner {
+        let change = Change::SetOwner {
+            path: self.key(),
+            uid,
+            gid,
+        };
+        self.record_existing(change, Operation::SetOwner)
+    }
+
+    fn supports_xattr(&self) -> bool {
+        self.inner.supports_xattr()
+    }
+
+    fn get_xattr(&self, name: &str) -> Self::GetXattr {
+        self.inner.get_xattr(name)
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8]) -> Self::SetXattr {
+        let change = Change::SetXattr {
+            path: self.key(),
+            name: name.to_string(),
+            value: value.to_vec(),
+        };
+        self.record_existing(change, Operation::SetXattr)
+    }
+
+    fn list_xattr(&self) -> Self::ListXattr {
+        self.inner.list_xattr()
+    }
+
+    fn remove_xattr(&self, name: &str) -> Self::RemoveXattr {
+        let change = Change::RemoveXattr {
+            path: self.key(),
+            name: name.to_string(),
+        };
+        self.record_existing(change, Operation::RemoveXattr)
+    }
+
+    /// Reports the changes of the inner filesystem, which the recorded ones are not
+    fn watch(&self, recursive: bool) -> Self::Watch {
+        let this = self.clone();
+        let future = self.inner.watch(recursive);
+        Box::pin(async move {
+            let watcher = future.await?;
+            Ok(watcher
+                .map_ok(move |event| event.map(|inner| this.wrap(inner)))
+                .boxed())
+        })
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use vfs::VPathStr;
+
+/// The content of a simulated file, shared by its open handles
+pub(crate) type Buffer = Arc<Mutex<Vec<u8>>>;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Removed,
+    /// A directory created by the simulation. The inner filesystem has nothing
+    /// below it, or had it removed before, so only entries of the overlay are in it
+    Dir,
+    File(Buffer),
+}
+
+/// The state of a path in the simulated tree
+#[derive(Debug, Clone)]
+pub(crate) enum Lookup {
+    /// Unchanged, as it is on the inner filesystem
+    Inner,
+    Missing,
+    Dir,
+    File(Buffer),
+}
+
+/// The created and removed paths, with the content of simulated files, laid over
+/// the inner filesystem and keyed by the normalized virtual path
+#[derive(Debug, Default)]
+pub(crate) struct Overlay {
+    nodes: BTreeMap<String, Node>,
+}
+
+impl Overlay {
+    pub fn lookup(&self, key: &str) -> Lookup {
+        if let Some(node) = self.nodes.get(key) {
+            return match node {
+                Node::Removed => Lookup::Missing,
+                Node::Dir => Lookup::Dir,
+                Node::File(buffer) => Lookup::File(buffer.clone()),
+            };
+        }
+
+        // The nearest changed parent decides whether the inner path is visible
+        let mut parent = key;
+        while let Some(next) = parent_key(parent) {
+            if self.nodes.contains_key(next) {
+                return Lookup::Missing;
+            }
+            parent = next;
+        }
+        Lookup::Inner
+    }
+
+    pub fn create_dir(&mut self, key: &str) {
+        self.nodes.insert(key.to_string(), Node::Dir);
+    }
+
+    pub fn create_file(&mut self, key: &str, buffer: Buffer) {
+        self.nodes.insert(key.to_string(), Node::File(buffer));
+    }
+
+    /// Remove `key` and everything below it. The root is left as an empty directory
+    pub fn remove(&mut self, key: &str) {
+        let prefix = child_prefix(key);
+        self.nodes.retain(|other, _| !other.starts_with(&prefix));
+        let node = match parent_key(key) {
+            Some(_) => Node::Removed,
+            None => Node::Dir,
+        };
+        self.nodes.insert(key.to_string(), node);
+    }
+
+    /// The names of the entries created directly below `key`, and whether they are directories
+    pub fn created(&self, key: &str) -> Vec<(String, bool)> {
+        let prefix = child_prefix(key);
+        self.nodes
+            .range(prefix.clone()..)
+            .take_while(|(other, _)| other.starts_with(&prefix))
+            .filter_map(|(other, node)| {
+                let name = &other[prefix.len()..];
+                match node {
+                    _ if name.contains('/') => None,
+                    Node::Removed => None,
+                    Node::Dir => Some((name.to_string(), true)),
+                    Node::File(_) => Some((name.to_string(), false)),
+                }
+            })
+            .collect()
+    }
+}
+
+fn parent_key(key: &str) -> Option<&str> {
+    VPathStr::new(key).parent().map(VPathStr::as_str)
+}
+
+fn child_prefix(key: &str) -> String {
+    match key {
+        "/" => String::from("/"),
+        key => format!("{key}/"),
+    }
+}
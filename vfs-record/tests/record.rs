@@ -0,0 +1,156 @@
+use futures::executor::block_on;
+use vfs::{ErrorKind, OpenOptions, SeekFrom, VFS, VFileExt, VPath};
+use vfs_memory::MemoryFS;
+use vfs_record::{Change, RecordingFS};
+use vfs_test::{names, read_file, write_file};
+
+fn path(path: &str) -> String {
+    path.to_string()
+}
+
+#[test]
+fn inner_is_left_alone() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        write_file(&inner.path("/file").unwrap(), b"inner").await;
+        inner.path("/dir").unwrap().create_dir().await.unwrap();
+
+        for simulate in [false, true] {
+            let fs = RecordingFS::new(inner.clone()).simulate(simulate);
+            fs.path("/new/sub").unwrap().create_dir().await.unwrap();
+            write_file(&fs.path("/new/sub/file").unwrap(), b"data").await;
+            write_file(&fs.path("/file").unwrap(), b"changed").await;
+            fs.path("/dir").unwrap().rm().await.unwrap();
+            assert!(!fs.changes().is_empty());
+
+            let err = inner.path("/new").unwrap().metadata().await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::NotFound);
+            assert!(
+                inner
+                    .path("/dir")
+                    .unwrap()
+                    .metadata()
+                    .await
+                    .unwrap()
+                    .is_dir()
+            );
+            assert_eq!(read_file(&inner.path("/file").unwrap()).await, b"inner");
+        }
+    })
+}
+
+#[test]
+fn scripted_changes() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        write_file(&inner.path("/file").unwrap(), b"0123456789").await;
+        let fs = RecordingFS::new(inner);
+
+        // Files can be created in directories the recording made
+        fs.path("/a/b").unwrap().create_dir().await.unwrap();
+        fs.path("/a").unwrap().create_dir().await.unwrap();
+        write_file(&fs.path("/a/b/new").unwrap(), b"hello").await;
+
+        // Writes of a file dropped without closing it are kept
+        let options = OpenOptions::new().write(true);
+        let mut file = fs.path("/file").unwrap().open(options).await.unwrap();
+        file.seek(SeekFrom::Start(4)).await.unwrap();
+        file.write_all(b"ab").await.unwrap();
+        file.write_all(b"cd").await.unwrap();
+        drop(file);
+
+        let options = OpenOptions::new().write(true).create(true);
+        let mut file = fs.path("/a/b/new").unwrap().open(options).await.unwrap();
+        file.close().await.unwrap();
+
+        fs.path("/a").unwrap().rm().await.unwrap();
+        let err = fs.path("/a/b/new").unwrap().rm().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        assert_eq!(
+            fs.take_changes(),
+            [
+                Change::CreateDir { path: path("/a") },
+                Change::CreateDir { path: path("/a/b") },
+                Change::CreateFile {
+                    path: path("/a/b/new")
+                },
+                Change::Write {
+                    path: path("/a/b/new"),
+                    offset: 0,
+                    len: 5
+                },
+                Change::Write {
+                    path: path("/file"),
+                    offset: 4,
+                    len: 4
+                },
+                Change::Remove { path: path("/a") },
+            ]
+        );
+        assert!(fs.changes().is_empty());
+    })
+}
+
+#[test]
+fn simulated_reads() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        inner.path("/dir").unwrap().create_dir().await.unwrap();
+        write_file(&inner.path("/dir/file").unwrap(), b"0123456789").await;
+        write_file(&inner.path("/dir/gone").unwrap(), b"gone").await;
+        let fs = RecordingFS::new(inner).simulate(true);
+
+        let options = OpenOptions::new().write(true);
+        let mut file = fs.path("/dir/file").unwrap().open(options).await.unwrap();
+        file.seek(SeekFrom::Start(8)).await.unwrap();
+        file.write_all(b"abcd").await.unwrap();
+        file.close().await.unwrap();
+        assert_eq!(
+            read_file(&fs.path("/dir/file").unwrap()).await,
+            b"01234567abcd"
+        );
+        let metadata = fs.path("/dir/file").unwrap().metadata().await.unwrap();
+        assert_eq!(metadata.size, 12);
+
+        fs.path("/dir/gone").unwrap().rm().await.unwrap();
+        fs.path("/dir/sub").unwrap().create_dir().await.unwrap();
+        write_file(&fs.path("/dir/sub/new").unwrap(), b"new").await;
+        assert_eq!(names(&fs.path("/dir").unwrap()).await, ["file", "sub"]);
+        assert_eq!(names(&fs.path("/dir/sub").unwrap()).await, ["new"]);
+        assert_eq!(read_file(&fs.path("/dir/sub/new").unwrap()).await, b"new");
+
+        let err = fs.path("/dir/gone").unwrap().metadata().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        // Removing a directory hides what the inner filesystem has below it
+        fs.path("/dir").unwrap().rm().await.unwrap();
+        fs.path("/dir").unwrap().create_dir().await.unwrap();
+        assert!(names(&fs.path("/dir").unwrap()).await.is_empty());
+    })
+}
+
+#[test]
+fn read_only_inner_file() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        write_file(&inner.path("/file").unwrap(), b"inner").await;
+
+        for simulate in [false, true] {
+            let fs = RecordingFS::new(inner.clone()).simulate(simulate);
+
+            // Only reading is passed on, whatever else the options ask for
+            let options = OpenOptions::new().read(true).truncate(true).create(true);
+            let mut file = fs.path("/file").unwrap().open(options).await.unwrap();
+            let err = file.write_all(b"changed").await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).await.unwrap();
+            assert_eq!(data, b"inner");
+            file.close().await.unwrap();
+
+            assert!(fs.changes().is_empty());
+            assert_eq!(read_file(&inner.path("/file").unwrap()).await, b"inner");
+        }
+    })
+}
@@ -0,0 +1,105 @@
+use alloc::{format, vec::Vec};
+
+use vfs::{Error, ErrorKind};
+
+/// The compression algorithm used for all blocks of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Lzma,
+    Lzo,
+    Xz,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    pub(crate) fn from_id(id: u16) -> Result<Compression, Error> {
+        Ok(match id {
+            1 => Compression::Gzip,
+            2 => Compression::Lzma,
+            3 => Compression::Lzo,
+            4 => Compression::Xz,
+            5 => Compression::Lz4,
+            6 => Compression::Zstd,
+            _ => {
+                return Err(Error::new_const(
+                    ErrorKind::InvalidData,
+                    "unknown squashfs compression",
+                ));
+            }
+        })
+    }
+
+    /// Returns true if blocks compressed with this algorithm can be read
+    pub fn is_supported(self) -> bool {
+        match self {
+            Compression::Gzip => cfg!(feature = "gzip"),
+            Compression::Xz => cfg!(feature = "xz"),
+            Compression::Lz4 => cfg!(feature = "lz4"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+            Compression::Lzma | Compression::Lzo => false,
+        }
+    }
+
+    /// Decompress a block, which expands to at most `max` bytes
+    pub(crate) fn decompress(self, data: &[u8], max: usize) -> Result<Vec<u8>, Error> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(data, max)
+                .map_err(|err| corrupt(format!("{:?}", err.status))),
+            #[cfg(feature = "xz")]
+            Compression::Xz => {
+                use lzma_rust2::Read;
+
+                let mut reader = lzma_rust2::XzReader::new(data, false);
+                let mut out = alloc::vec![0; max];
+                let mut len = 0;
+                while len < max {
+                    match reader.read(&mut out[len..]) {
+                        Ok(0) => break,
+                        Ok(n) => len += n,
+                        Err(err) => return Err(corrupt(format!("{err:?}"))),
+                    }
+                }
+                out.truncate(len);
+                Ok(out)
+            }
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                let mut out = alloc::vec![0; max];
+                let len = lz4_flex::block::decompress_into(data, &mut out)
+                    .map_err(|err| corrupt(format!("{err}")))?;
+                out.truncate(len);
+                Ok(out)
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let mut out = Vec::with_capacity(max);
+                ruzstd::decoding::FrameDecoder::new()
+                    .decode_all_to_vec(data, &mut out)
+                    .map_err(|err| corrupt(format!("{err}")))?;
+                Ok(out)
+            }
+            _ => {
+                let _ = (data, max);
+                Err(unsupported(self))
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "xz", feature = "lz4", feature = "zstd"))]
+fn corrupt(reason: alloc::string::String) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("failed to decompress block: {reason}"),
+    )
+}
+
+pub(crate) fn unsupported(compression: Compression) -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!("{compression:?} compressed images are not supported"),
+    )
+}
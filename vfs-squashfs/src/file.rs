@@ -0,0 +1,197 @@
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use core::{
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use vfs::{Error, ErrorKind, Operation, SeekFrom, VFile};
+
+use crate::image::Image;
+
+type BlockFuture = Pin<Box<dyn Future<Output = Result<(usize, Vec<u8>), Error>> + Send + Sync>>;
+
+/// Where the data of a block of the file is stored
+#[derive(Debug, Clone, Copy)]
+enum Block {
+    /// Offset and on disk size of a data block
+    Data(u64, u32),
+    /// Fragment index and offset of the tail of the file
+    Fragment(u32, u32),
+}
+
+/// A regular file in a squashfs image
+pub struct SquashFile<F> {
+    image: Arc<Image<F>>,
+    path: String,
+    blocks: Vec<Block>,
+    size: u64,
+    pos: u64,
+    /// The last block read, decompressed
+    cache: Option<(usize, Vec<u8>)>,
+    read: Option<BlockFuture>,
+}
+
+impl<F> SquashFile<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    pub(crate) fn new(
+        image: Arc<Image<F>>,
+        path: String,
+        blocks_start: u64,
+        sizes: &[u32],
+        fragment: Option<(u32, u32)>,
+        size: u64,
+    ) -> SquashFile<F> {
+        let mut offset = blocks_start;
+        let mut blocks = Vec::with_capacity(sizes.len() + 1);
+        for &disk_size in sizes {
+            blocks.push(Block::Data(offset, disk_size));
+            offset += Image::<F>::disk_size(disk_size);
+        }
+
+        if let Some((idx, offset)) = fragment {
+            blocks.push(Block::Fragment(idx, offset));
+        }
+
+        SquashFile {
+            image,
+            path,
+            blocks,
+            size,
+            pos: 0,
+            cache: None,
+            read: None,
+        }
+    }
+
+    fn context(&self, err: Error, operation: Operation) -> Error {
+        err.with_operation(operation)
+            .with_path(format!("/{}", self.path))
+    }
+
+    fn block_size(&self) -> u64 {
+        self.image.superblock.block_size as u64
+    }
+
+    /// Load and decompress the block at `idx`
+    fn load(&self, idx: usize) -> BlockFuture {
+        let image = self.image.clone();
+        let block = self.blocks[idx];
+        let start = idx as u64 * self.block_size();
+        let len = (self.size - start).min(self.block_size()) as usize;
+
+        Box::pin(async move {
+            let mut data = match block {
+                Block::Data(offset, disk_size) => image.data_block(offset, disk_size, len).await?,
+                Block::Fragment(fragment, offset) => {
+                    let data = image.fragment(fragment).await?;
+                    let offset = offset as usize;
+                    data.get(offset..offset + len)
+                        .ok_or(Error::new_const(
+                            ErrorKind::InvalidData,
+                            "fragment is smaller than the tail of the file",
+                        ))?
+                        .to_vec()
+                }
+            };
+
+            if data.len() < len {
+                return Err(Error::new_const(
+                    ErrorKind::InvalidData,
+                    "data block is smaller than expected",
+                ));
+            }
+
+            data.truncate(len);
+            Ok((idx, data))
+        })
+    }
+}
+
+impl<F> VFile for SquashFile<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+
+        if buf.is_empty() || this.pos >= this.size {
+            return Poll::Ready(Ok(0));
+        }
+
+        let idx = (this.pos / this.block_size()) as usize;
+
+        if !matches!(this.cache, Some((cached, _)) if cached == idx) {
+            if this.read.is_none() {
+                this.read = Some(this.load(idx));
+            }
+
+            let ret = ready!(this.read.as_mut().unwrap().as_mut().poll(cx));
+            this.read = None;
+
+            match ret {
+                Ok(block) => this.cache = Some(block),
+                Err(err) => return Poll::Ready(Err(this.context(err, Operation::Read))),
+            }
+        }
+
+        let (_, data) = this.cache.as_ref().unwrap();
+        let within = (this.pos % this.block_size()) as usize;
+        let n = (data.len() - within).min(buf.len());
+        buf[..n].copy_from_slice(&data[within..within + n]);
+        this.pos += n as u64;
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        let this = self.get_mut();
+        // A pending read has no side effects and can be abandoned
+        this.read = None;
+
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => this.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => this.pos.checked_add_signed(offset),
+        };
+
+        Poll::Ready(match pos {
+            Some(pos) => {
+                this.pos = pos;
+                Ok(pos)
+            }
+            None => {
+                let err = Error::new_const(
+                    ErrorKind::InvalidInput,
+                    "invalid seek to a negative or overflowing position",
+                );
+                Err(this.context(err, Operation::Seek))
+            }
+        })
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Poll::Ready(Err(self.context(crate::read_only(), Operation::Write)))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
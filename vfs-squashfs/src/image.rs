@@ -0,0 +1,514 @@
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec, vec::Vec};
+use core::time::Duration;
+
+use async_lock::Mutex;
+use vfs::{
+    Error, ErrorKind, FileType, Metadata, Permissions, SeekFrom, Timestamp, VFile, VFileExt,
+};
+
+use crate::compression::{Compression, unsupported};
+
+const MAGIC: u32 = 0x7371_7368;
+
+/// Uncompressed size of a metadata block
+const METADATA_SIZE: usize = 8192;
+
+/// Set in the header of metadata blocks stored without compression
+const METADATA_UNCOMPRESSED: u16 = 0x8000;
+
+/// Set in the size of data blocks and fragments stored without compression
+const DATA_UNCOMPRESSED: u32 = 1 << 24;
+
+const NO_FRAGMENT: u32 = 0xFFFF_FFFF;
+
+/// Decompressed metadata blocks kept in memory
+const METADATA_CACHE: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Superblock {
+    pub block_size: u32,
+    pub compression: Compression,
+    pub id_count: u16,
+    pub root_inode: u64,
+    pub bytes_used: u64,
+    pub id_table: u64,
+    pub inode_table: u64,
+    pub dir_table: u64,
+    pub frag_table: u64,
+}
+
+/// Position in a metadata table
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Cursor {
+    /// Absolute offset of the metadata block
+    block: u64,
+    /// Offset within the uncompressed block
+    offset: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum InodeData {
+    Dir {
+        start: u32,
+        offset: u16,
+        size: u32,
+    },
+    File {
+        blocks_start: u64,
+        /// On disk sizes of the data blocks
+        blocks: Vec<u32>,
+        /// Fragment index and offset of the tail of the file
+        fragment: Option<(u32, u32)>,
+        size: u64,
+    },
+    Symlink(String),
+    /// Devices, fifos and sockets
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Inode {
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: u32,
+    pub data: InodeData,
+}
+
+impl Inode {
+    pub fn kind(&self) -> FileType {
+        match self.data {
+            InodeData::Dir { .. } => FileType::Dir,
+            InodeData::Symlink(_) => FileType::Symlink,
+            InodeData::File { .. } | InodeData::Other => FileType::File,
+        }
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        let size = match &self.data {
+            InodeData::Dir { size, .. } => *size as u64,
+            InodeData::File { size, .. } => *size,
+            InodeData::Symlink(target) => target.len() as u64,
+            InodeData::Other => 0,
+        };
+
+        Metadata {
+            size,
+            kind: self.kind(),
+            permissions: Some(Permissions::from_mode(self.mode as u32 & 0o7777)),
+            accessed: None,
+            modified: Some(Timestamp::from_unix(Duration::from_secs(self.mtime as u64))),
+            created: None,
+            uid: Some(self.uid),
+            gid: Some(self.gid),
+            hash: None,
+        }
+    }
+}
+
+/// An entry of a directory listing
+#[derive(Debug, Clone)]
+pub(crate) struct DirItem {
+    pub name: String,
+    pub kind: FileType,
+    inode: Cursor,
+}
+
+struct State<F> {
+    device: F,
+    metadata: BTreeMap<u64, Arc<(Vec<u8>, u64)>>,
+}
+
+/// The device holding the image, along with the tables needed to navigate it
+pub(crate) struct Image<F> {
+    state: Mutex<State<F>>,
+    pub superblock: Superblock,
+    ids: Vec<u32>,
+    pub root: Inode,
+    /// End of the data which can be read, the bytes used by the image unless
+    /// the device is shorter
+    end: u64,
+}
+
+const fn invalid(message: &'static str) -> Error {
+    Error::new_const(ErrorKind::InvalidData, message)
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+impl Superblock {
+    fn parse(raw: &[u8]) -> Result<Superblock, Error> {
+        if u32_at(raw, 0) != MAGIC {
+            return Err(invalid("missing squashfs magic"));
+        }
+
+        if u16_at(raw, 28) != 4 {
+            return Err(Error::new_const(
+                ErrorKind::Unsupported,
+                "only squashfs 4.0 images are supported",
+            ));
+        }
+
+        let block_size = u32_at(raw, 12);
+        if !block_size.is_power_of_two() || !(4096..=1 << 20).contains(&block_size) {
+            return Err(invalid("invalid squashfs block size"));
+        }
+
+        Ok(Superblock {
+            block_size,
+            compression: Compression::from_id(u16_at(raw, 20))?,
+            id_count: u16_at(raw, 26),
+            root_inode: u64_at(raw, 32),
+            bytes_used: u64_at(raw, 40),
+            id_table: u64_at(raw, 48),
+            inode_table: u64_at(raw, 64),
+            dir_table: u64_at(raw, 72),
+            frag_table: u64_at(raw, 80),
+        })
+    }
+}
+
+impl<F> Image<F>
+where
+    F: VFile + Unpin,
+{
+    pub async fn open(mut device: F) -> Result<Image<F>, Error> {
+        let mut raw = [0; 96];
+        device.seek(SeekFrom::Start(0)).await?;
+        device.read_exact(&mut raw).await?;
+
+        let superblock = Superblock::parse(&raw)?;
+        if !superblock.compression.is_supported() {
+            return Err(unsupported(superblock.compression));
+        }
+        let end = device.seek(SeekFrom::End(0)).await?;
+
+        let mut image = Image {
+            state: Mutex::new(State {
+                device,
+                metadata: BTreeMap::new(),
+            }),
+            ids: Vec::new(),
+            root: Inode {
+                mode: 0,
+                uid: 0,
+                gid: 0,
+                mtime: 0,
+                data: InodeData::Other,
+            },
+            end: end.min(superblock.bytes_used),
+            superblock,
+        };
+
+        let mut ids = Vec::with_capacity(image.superblock.id_count as usize);
+        for idx in 0..image.superblock.id_count as u32 {
+            let raw = image.table_entry(image.superblock.id_table, idx, 4).await?;
+            ids.push(u32_at(&raw, 0));
+        }
+        image.ids = ids;
+
+        let root = image.superblock.root_inode;
+        image.root = image
+            .inode(image.table_cursor(image.superblock.inode_table, root))
+            .await?;
+
+        if image.root.kind() != FileType::Dir {
+            return Err(invalid("root inode is not a directory"));
+        }
+
+        Ok(image)
+    }
+
+    /// Read `len` bytes at `offset`, which the image places within the bytes it uses
+    pub async fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>, Error> {
+        if offset
+            .checked_add(len as u64)
+            .is_none_or(|end| end > self.end)
+        {
+            return Err(invalid("data beyond the end of the image"));
+        }
+
+        let mut state = self.state.lock().await;
+        let mut buf = vec![0; len];
+        state.device.seek(SeekFrom::Start(offset)).await?;
+        state.device.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+
+    /// A decompressed metadata block and the offset of the block following it
+    async fn metadata_block(&self, offset: u64) -> Result<Arc<(Vec<u8>, u64)>, Error> {
+        if let Some(block) = self.state.lock().await.metadata.get(&offset) {
+            return Ok(block.clone());
+        }
+
+        let header = self.read_at(offset, 2).await?;
+        let header = u16_at(&header, 0);
+        let size = (header & !METADATA_UNCOMPRESSED) as usize;
+        let raw = self.read_at(offset + 2, size).await?;
+
+        let data = if header & METADATA_UNCOMPRESSED != 0 {
+            raw
+        } else {
+            self.superblock
+                .compression
+                .decompress(&raw, METADATA_SIZE)?
+        };
+
+        let block = Arc::new((data, offset + 2 + size as u64));
+
+        let mut state = self.state.lock().await;
+        if state.metadata.len() >= METADATA_CACHE {
+            state.metadata.pop_first();
+        }
+        state.metadata.insert(offset, block.clone());
+
+        Ok(block)
+    }
+
+    /// Read `len` bytes from a metadata table, advancing the cursor
+    async fn read_metadata(&self, cursor: &mut Cursor, len: usize) -> Result<Vec<u8>, Error> {
+        // The length comes from the image, so the buffer only grows with the data read
+        let mut out = Vec::with_capacity(len.min(METADATA_SIZE));
+
+        while out.len() < len {
+            let block = self.metadata_block(cursor.block).await?;
+            let (data, next) = &*block;
+
+            if cursor.offset >= data.len() {
+                if data.is_empty() {
+                    return Err(invalid("empty metadata block"));
+                }
+                cursor.block = *next;
+                cursor.offset -= data.len();
+                continue;
+            }
+
+            let n = (len - out.len()).min(data.len() - cursor.offset);
+            out.extend_from_slice(&data[cursor.offset..cursor.offset + n]);
+            cursor.offset += n;
+        }
+
+        Ok(out)
+    }
+
+    /// Cursor of a reference made of a block offset in the upper bits and
+    /// an offset in the uncompressed block in the lower 16 bits
+    fn table_cursor(&self, table: u64, reference: u64) -> Cursor {
+        Cursor {
+            block: table + (reference >> 16),
+            offset: (reference & 0xFFFF) as usize,
+        }
+    }
+
+    /// An entry of a table stored as metadata blocks, found through an index of block offsets
+    async fn table_entry(&self, table: u64, idx: u32, size: usize) -> Result<Vec<u8>, Error> {
+        let per_block = (METADATA_SIZE / size) as u64;
+        let idx = idx as u64;
+
+        let pointer = self.read_at(table + idx / per_block * 8, 8).await?;
+        let mut cursor = Cursor {
+            block: u64_at(&pointer, 0),
+            offset: (idx % per_block) as usize * size,
+        };
+
+        self.read_metadata(&mut cursor, size).await
+    }
+
+    fn id(&self, idx: u16) -> Result<u32, Error> {
+        self.ids
+            .get(idx as usize)
+            .copied()
+            .ok_or_else(|| invalid("inode refers to a missing id"))
+    }
+
+    pub async fn inode(&self, mut cursor: Cursor) -> Result<Inode, Error> {
+        let header = self.read_metadata(&mut cursor, 16).await?;
+        let kind = u16_at(&header, 0);
+
+        let data = match kind {
+            1 => {
+                let raw = self.read_metadata(&mut cursor, 16).await?;
+                InodeData::Dir {
+                    start: u32_at(&raw, 0),
+                    size: u16_at(&raw, 8) as u32,
+                    offset: u16_at(&raw, 10),
+                }
+            }
+            8 => {
+                let raw = self.read_metadata(&mut cursor, 24).await?;
+                InodeData::Dir {
+                    size: u32_at(&raw, 4),
+                    start: u32_at(&raw, 8),
+                    offset: u16_at(&raw, 18),
+                }
+            }
+            2 | 9 => {
+                let (blocks_start, size, fragment, frag_offset) = if kind == 2 {
+                    let raw = self.read_metadata(&mut cursor, 16).await?;
+                    (
+                        u32_at(&raw, 0) as u64,
+                        u32_at(&raw, 12) as u64,
+                        u32_at(&raw, 4),
+                        u32_at(&raw, 8),
+                    )
+                } else {
+                    let raw = self.read_metadata(&mut cursor, 40).await?;
+                    (
+                        u64_at(&raw, 0),
+                        u64_at(&raw, 8),
+                        u32_at(&raw, 28),
+                        u32_at(&raw, 32),
+                    )
+                };
+
+                let block_size = self.superblock.block_size as u64;
+                let fragment = (fragment != NO_FRAGMENT).then_some((fragment, frag_offset));
+                let count = match fragment {
+                    Some(_) => size / block_size,
+                    None => size.div_ceil(block_size),
+                };
+
+                let raw = self.read_metadata(&mut cursor, count as usize * 4).await?;
+                let blocks = raw.chunks_exact(4).map(|b| u32_at(b, 0)).collect();
+
+                InodeData::File {
+                    blocks_start,
+                    blocks,
+                    fragment,
+                    size,
+                }
+            }
+            3 | 10 => {
+                let raw = self.read_metadata(&mut cursor, 8).await?;
+                let target = self
+                    .read_metadata(&mut cursor, u32_at(&raw, 4) as usize)
+                    .await?;
+                InodeData::Symlink(String::from_utf8_lossy(&target).into())
+            }
+            4..=7 | 11..=14 => InodeData::Other,
+            _ => return Err(invalid("unknown inode type")),
+        };
+
+        Ok(Inode {
+            mode: u16_at(&header, 2),
+            uid: self.id(u16_at(&header, 4))?,
+            gid: self.id(u16_at(&header, 6))?,
+            mtime: u32_at(&header, 8),
+            data,
+        })
+    }
+
+    pub async fn item_inode(&self, item: &DirItem) -> Result<Inode, Error> {
+        self.inode(item.inode).await
+    }
+
+    pub async fn read_dir(&self, dir: &Inode) -> Result<Vec<DirItem>, Error> {
+        let InodeData::Dir {
+            start,
+            offset,
+            size,
+        } = dir.data
+        else {
+            return Err(Error::new_const(
+                ErrorKind::NotADirectory,
+                "not a directory",
+            ));
+        };
+
+        let mut items = Vec::new();
+
+        // The size accounts for the implicit `.` and `..` entries
+        let Some(len) = (size as usize).checked_sub(3).filter(|len| *len > 0) else {
+            return Ok(items);
+        };
+
+        let mut cursor = Cursor {
+            block: self.superblock.dir_table + start as u64,
+            offset: offset as usize,
+        };
+        let data = self.read_metadata(&mut cursor, len).await?;
+
+        let mut pos = 0;
+        while pos + 12 <= data.len() {
+            let count = u32_at(&data, pos) as usize + 1;
+            let inode_block = u32_at(&data, pos + 4) as u64;
+            pos += 12;
+
+            for _ in 0..count {
+                let raw = data
+                    .get(pos..pos + 8)
+                    .ok_or_else(|| invalid("truncated directory entry"))?;
+                let name_len = u16_at(raw, 6) as usize + 1;
+                let name = data
+                    .get(pos + 8..pos + 8 + name_len)
+                    .ok_or_else(|| invalid("truncated directory entry"))?;
+
+                items.push(DirItem {
+                    name: String::from_utf8_lossy(name).into(),
+                    kind: match u16_at(raw, 4) {
+                        1 => FileType::Dir,
+                        3 => FileType::Symlink,
+                        _ => FileType::File,
+                    },
+                    inode: Cursor {
+                        block: self.superblock.inode_table + inode_block,
+                        offset: u16_at(raw, 0) as usize,
+                    },
+                });
+
+                pos += 8 + name_len;
+            }
+        }
+
+        Ok(items)
+    }
+
+    /// Bytes taken on disk by a data block, given its size field
+    pub fn disk_size(size: u32) -> u64 {
+        (size & !DATA_UNCOMPRESSED) as u64
+    }
+
+    /// Read a data block or fragment block with its on disk size field.
+    /// `len` is the uncompressed length, used for blocks that are not stored
+    pub async fn data_block(&self, offset: u64, size: u32, len: usize) -> Result<Vec<u8>, Error> {
+        let disk_size = Self::disk_size(size) as usize;
+
+        // Blocks of zeros are not stored at all
+        if disk_size == 0 {
+            return Ok(vec![0; len]);
+        }
+
+        // Blocks which do not compress are stored as they are
+        if disk_size > self.superblock.block_size as usize {
+            return Err(invalid("data block larger than the block size"));
+        }
+
+        let raw = self.read_at(offset, disk_size).await?;
+        if size & DATA_UNCOMPRESSED != 0 {
+            Ok(raw)
+        } else {
+            self.superblock
+                .compression
+                .decompress(&raw, self.superblock.block_size as usize)
+        }
+    }
+
+    /// The decompressed fragment block at `idx`
+    pub async fn fragment(&self, idx: u32) -> Result<Vec<u8>, Error> {
+        let entry = self
+            .table_entry(self.superblock.frag_table, idx, 16)
+            .await?;
+        self.data_block(u64_at(&entry, 0), u32_at(&entry, 8), 0)
+            .await
+    }
+}
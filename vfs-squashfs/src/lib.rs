@@ -0,0 +1,511 @@
+//! A read-only [`VFS`] backed by a squashfs image, read from any [`VFile`].
+//!
+//! Images compressed with gzip, xz, lz4 or zstd can be read, each behind the
+//! cargo feature of the same name.
+#![no_std]
+
+extern crate alloc;
+
+mod compression;
+mod file;
+mod image;
+
+pub use self::{compression::Compression, file::SquashFile};
+
+use alloc::{boxed::Box, collections::VecDeque, format, string::String, sync::Arc, vec::Vec};
+
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use vfs::{
+    Capabilities, DirEntry, Error, ErrorKind, FileTimes, FileType, FsStats, Metadata, OpenOptions,
+    Operation, Permissions, VFS, VFile, VPath, VPathStr, WatchEvent,
+};
+
+use self::image::{Image, Inode, InodeData};
+
+/// Symbolic links followed while resolving a single path
+const MAX_SYMLINKS: usize = 40;
+
+/// A mounted squashfs image
+pub struct SquashFS<F> {
+    image: Arc<Image<F>>,
+}
+
+impl<F> Clone for SquashFS<F> {
+    fn clone(&self) -> Self {
+        SquashFS {
+            image: self.image.clone(),
+        }
+    }
+}
+
+impl<F> core::fmt::Debug for SquashFS<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SquashFS")
+            .field("compression", &self.image.superblock.compression)
+            .field("block_size", &self.image.superblock.block_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F> SquashFS<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    /// Mount the image starting at the beginning of `device`
+    pub async fn new(device: F) -> Result<SquashFS<F>, Error> {
+        Ok(SquashFS {
+            image: Arc::new(Image::open(device).await?),
+        })
+    }
+
+    /// The algorithm the image is compressed with
+    pub fn compression(&self) -> Compression {
+        self.image.superblock.compression
+    }
+}
+
+impl<F> VFS for SquashFS<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    type Path = SquashPath<F>;
+
+    type Stats = BoxFuture<'static, Result<FsStats, Error>>;
+
+    fn path(&self, path: impl AsRef<VPathStr>) -> Result<Self::Path, Error> {
+        Ok(SquashPath::new(
+            self.clone(),
+            join("", path.as_ref().as_str()),
+        ))
+    }
+
+    fn stats(&self) -> Self::Stats {
+        let superblock = &self.image.superblock;
+        let (block_size, total) = (superblock.block_size as u64, superblock.bytes_used);
+        Box::pin(async move {
+            Ok(FsStats {
+                block_size,
+                total,
+                free: 0,
+                available: 0,
+            })
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            symlinks: true,
+            atomic_rename: false,
+            xattr: false,
+            seek: true,
+            write: false,
+            case_sensitive: true,
+            max_name_len: Some(256),
+        }
+    }
+}
+
+impl<F> Image<F>
+where
+    F: VFile + Unpin,
+{
+    /// Find the inode of a path, following symbolic links
+    async fn lookup(&self, path: &str) -> Result<Inode, Error> {
+        Ok(self.walk(path).await?.1)
+    }
+
+    /// Find the inode of a path, following symbolic links, along with the
+    /// canonical path leading to it
+    async fn walk(&self, path: &str) -> Result<(String, Inode), Error> {
+        let mut stack = Vec::from([self.root.clone()]);
+        let mut names: Vec<String> = Vec::new();
+        let mut queue: VecDeque<String> = components(path).map(String::from).collect();
+        let mut links = 0;
+
+        while let Some(name) = queue.pop_front() {
+            match name.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    if stack.last().unwrap().kind() != FileType::Dir {
+                        return Err(Error::new_const(
+                            ErrorKind::NotADirectory,
+                            "not a directory",
+                        ));
+                    }
+                    if stack.len() > 1 {
+                        stack.pop();
+                        names.pop();
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            let item = self
+                .read_dir(stack.last().unwrap())
+                .await?
+                .into_iter()
+                .find(|item| item.name == name)
+                .ok_or(Error::new_const(
+                    ErrorKind::NotFound,
+                    "no such file or directory",
+                ))?;
+            let inode = self.item_inode(&item).await?;
+
+            match inode.data {
+                InodeData::Symlink(target) => {
+                    links += 1;
+                    if links > MAX_SYMLINKS {
+                        return Err(Error::new_const(
+                            ErrorKind::FilesystemLoop,
+                            "too many levels of symbolic links",
+                        ));
+                    }
+
+                    if target.starts_with('/') {
+                        stack.truncate(1);
+                        names.clear();
+                    }
+
+                    for part in target.split('/').rev() {
+                        queue.push_front(part.into());
+                    }
+                }
+                _ => {
+                    names.push(item.name);
+                    stack.push(inode);
+                }
+            }
+        }
+
+        Ok((names.join("/"), stack.pop().unwrap()))
+    }
+}
+
+/// A path in a squashfs image
+pub struct SquashPath<F> {
+    fs: SquashFS<F>,
+    /// Path without leading slash, empty for the root directory. Any `..`
+    /// left in it is resolved by the lookup
+    path: String,
+}
+
+impl<F> Clone for SquashPath<F> {
+    fn clone(&self) -> Self {
+        SquashPath {
+            fs: self.fs.clone(),
+            path: self.path.clone(),
+        }
+    }
+}
+
+impl<F> core::fmt::Debug for SquashPath<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("SquashPath").field(&self.path).finish()
+    }
+}
+
+/// Paths are equal when they point into the same mounted image
+impl<F> PartialEq for SquashPath<F> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.fs.image, &other.fs.image) && self.path == other.path
+    }
+}
+
+impl<F> Eq for SquashPath<F> {}
+
+impl<F> core::hash::Hash for SquashPath<F> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.path.hash(state)
+    }
+}
+
+impl<F> SquashPath<F> {
+    fn new(fs: SquashFS<F>, path: String) -> SquashPath<F> {
+        SquashPath { fs, path }
+    }
+
+    fn child(&self, name: &str) -> SquashPath<F> {
+        let path = if self.path.is_empty() {
+            String::from(name)
+        } else {
+            format!("{}/{}", self.path, name)
+        };
+        SquashPath::new(self.fs.clone(), path)
+    }
+
+    fn context(&self, err: Error, operation: Operation) -> Error {
+        err.with_operation(operation)
+            .with_path(format!("/{}", self.path))
+    }
+
+    fn fail<T: Send + 'static>(
+        &self,
+        err: Error,
+        operation: Operation,
+    ) -> BoxFuture<'static, Result<T, Error>> {
+        let err = self.context(err, operation);
+        Box::pin(async move { Err(err) })
+    }
+}
+
+impl<F> VPath for SquashPath<F>
+where
+    F: VFile + Unpin + 'static,
+{
+    type FS = SquashFS<F>;
+
+    type File = SquashFile<F>;
+
+    type ListDir = BoxStream<'static, Result<DirEntry<Self>, Error>>;
+
+    type Metadata = BoxFuture<'static, Result<Metadata, Error>>;
+
+    type Open = BoxFuture<'static, Result<SquashFile<F>, Error>>;
+
+    type CreateDir = BoxFuture<'static, Result<(), Error>>;
+
+    type Remove = BoxFuture<'static, Result<(), Error>>;
+
+    type ReadDir = BoxFuture<'static, Result<Self::ListDir, Error>>;
+
+    type SetPermissions = BoxFuture<'static, Result<(), Error>>;
+
+    type SetTimes = BoxFuture<'static, Result<(), Error>>;
+
+    type SetOwner = BoxFuture<'static, Result<(), Error>>;
+
+    type GetXattr = BoxFuture<'static, Result<Option<Vec<u8>>, Error>>;
+
+    type SetXattr = BoxFuture<'static, Result<(), Error>>;
+
+    type ListXattr = BoxFuture<'static, Result<Vec<String>, Error>>;
+
+    type RemoveXattr = BoxFuture<'static, Result<(), Error>>;
+
+    type Watcher = BoxStream<'static, Result<WatchEvent<Self>, Error>>;
+
+    type Watch = BoxFuture<'static, Result<Self::Watcher, Error>>;
+
+    type Canonicalize = BoxFuture<'static, Result<Self, Error>>;
+
+    fn fs(&self) -> Self::FS {
+        self.fs.clone()
+    }
+
+    fn virtual_path(&self) -> String {
+        format!("/{}", self.path)
+    }
+
+    fn to_string(&self) -> String {
+        self.virtual_path()
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        self.path
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty() && *name != "..")
+    }
+
+    fn extension(&self) -> Option<&str> {
+        let name = self.file_name()?;
+        match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => Some(ext),
+            _ => None,
+        }
+    }
+
+    fn resolve(&self, path: &str) -> Result<Self, Error> {
+        Ok(SquashPath::new(self.fs.clone(), join(&self.path, path)))
+    }
+
+    fn parent(&self) -> Option<Self> {
+        if self.path.is_empty() {
+            return None;
+        }
+
+        let parent = match self.path.rsplit_once('/') {
+            // Only the lookup knows where `..` leads, so step out of it too
+            _ if self.file_name().is_none() => join(&self.path, ".."),
+            Some((parent, _)) => String::from(parent),
+            None => String::new(),
+        };
+
+        Some(SquashPath::new(self.fs.clone(), parent))
+    }
+
+    fn canonicalize(&self) -> Self::Canonicalize {
+        let this = self.clone();
+        Box::pin(async move {
+            let (path, _) = this
+                .fs
+                .image
+                .walk(&this.path)
+                .await
+                .map_err(|err| this.context(err, Operation::Canonicalize))?;
+            Ok(SquashPath::new(this.fs, path))
+        })
+    }
+
+    fn metadata(&self) -> Self::Metadata {
+        let this = self.clone();
+        Box::pin(async move {
+            let inode = this
+                .fs
+                .image
+                .lookup(&this.path)
+                .await
+                .map_err(|err| this.context(err, Operation::Metadata))?;
+            Ok(inode.metadata())
+        })
+    }
+
+    fn open(&self, options: OpenOptions) -> Self::Open {
+        if options.write || options.append || options.create || options.truncate {
+            return self.fail(read_only(), Operation::Open);
+        }
+
+        let this = self.clone();
+        Box::pin(async move {
+            let inode = this
+                .fs
+                .image
+                .lookup(&this.path)
+                .await
+                .map_err(|err| this.context(err, Operation::Open))?;
+
+            match inode.data {
+                InodeData::File {
+                    blocks_start,
+                    blocks,
+                    fragment,
+                    size,
+                } => Ok(SquashFile::new(
+                    this.fs.image,
+                    this.path,
+                    blocks_start,
+                    &blocks,
+                    fragment,
+                    size,
+                )),
+                InodeData::Dir { .. } => {
+                    let err = Error::new_const(ErrorKind::IsADirectory, "is a directory");
+                    Err(this.context(err, Operation::Open))
+                }
+                _ => {
+                    let err =
+                        Error::new_const(ErrorKind::Unsupported, "special files cannot be opened");
+                    Err(this.context(err, Operation::Open))
+                }
+            }
+        })
+    }
+
+    fn read_dir(&self) -> Self::ReadDir {
+        let this = self.clone();
+        Box::pin(async move {
+            let image = &this.fs.image;
+            let entries = async {
+                let dir = image.lookup(&this.path).await?;
+                let mut entries = Vec::new();
+                for item in image.read_dir(&dir).await? {
+                    let path = this.child(&item.name);
+                    // Links are followed when asking for the metadata of the entry
+                    entries.push(Ok(match item.kind {
+                        FileType::Symlink => DirEntry::new(path, FileType::Symlink),
+                        _ => {
+                            DirEntry::with_metadata(path, image.item_inode(&item).await?.metadata())
+                        }
+                    }));
+                }
+                Ok(entries)
+            }
+            .await
+            .map_err(|err| this.context(err, Operation::ReadDir))?;
+
+            Ok(Box::pin(futures::stream::iter(entries)) as Self::ListDir)
+        })
+    }
+
+    fn create_dir(&self) -> Self::CreateDir {
+        self.fail(read_only(), Operation::CreateDir)
+    }
+
+    fn rm(&self) -> Self::Remove {
+        self.fail(read_only(), Operation::Remove)
+    }
+
+    fn set_permissions(&self, _permissions: Permissions) -> Self::SetPermissions {
+        self.fail(read_only(), Operation::SetPermissions)
+    }
+
+    fn set_times(&self, _times: FileTimes) -> Self::SetTimes {
+        self.fail(read_only(), Operation::SetTimes)
+    }
+
+    fn set_owner(&self, _uid: Option<u32>, _gid: Option<u32>) -> Self::SetOwner {
+        self.fail(read_only(), Operation::SetOwner)
+    }
+
+    fn supports_xattr(&self) -> bool {
+        false
+    }
+
+    fn get_xattr(&self, _name: &str) -> Self::GetXattr {
+        self.fail(no_xattr(), Operation::GetXattr)
+    }
+
+    fn set_xattr(&self, _name: &str, _value: &[u8]) -> Self::SetXattr {
+        self.fail(no_xattr(), Operation::SetXattr)
+    }
+
+    fn list_xattr(&self) -> Self::ListXattr {
+        self.fail(no_xattr(), Operation::ListXattr)
+    }
+
+    fn remove_xattr(&self, _name: &str) -> Self::RemoveXattr {
+        self.fail(no_xattr(), Operation::RemoveXattr)
+    }
+
+    /// The image cannot change, so the watcher never yields any events
+    fn watch(&self, _recursive: bool) -> Self::Watch {
+        Box::pin(async move { Ok(Box::pin(futures::stream::pending()) as Self::Watcher) })
+    }
+}
+
+pub(crate) const fn read_only() -> Error {
+    Error::new_const(
+        ErrorKind::ReadOnlyFilesystem,
+        "squashfs images are read only",
+    )
+}
+
+const fn no_xattr() -> Error {
+    Error::new_const(
+        ErrorKind::Unsupported,
+        "extended attributes of squashfs images are not supported",
+    )
+}
+
+/// Split a normalized path into its components
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|part| !part.is_empty())
+}
+
+/// Resolve `path` against the path `base`. `..` is kept for the lookup,
+/// which steps out of the target of a symbolic link like the host would.
+/// A `..` with nothing before it stays at the root and is dropped
+fn join(base: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = components(base).collect();
+
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if parts.is_empty() => {}
+            part => parts.push(part),
+        }
+    }
+
+    parts.join("/")
+}
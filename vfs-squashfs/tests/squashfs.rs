@@ -0,0 +1,239 @@
+use futures::{TryStreamExt, executor::block_on};
+use vfs::{ErrorKind, FileType, OpenOptions, SeekFrom, VFS, VFileExt, VPath};
+use vfs_squashfs::SquashFS;
+use vfs_test::{Device, names, pattern, read_file, try_read_file};
+
+const BLOCK: usize = 4096;
+
+/// Set in the size of data blocks stored without compression
+const UNCOMPRESSED: u32 = 1 << 24;
+
+const NO_FRAGMENT: u32 = 0xFFFF_FFFF;
+
+const MTIME: u32 = 1_700_000_000;
+
+/// The fields every inode starts with
+fn inode(kind: u16, mode: u16, number: u32) -> Vec<u8> {
+    let mut raw = Vec::new();
+    raw.extend(kind.to_le_bytes());
+    raw.extend(mode.to_le_bytes());
+    // Both owners are the first entry of the id table
+    raw.extend([0; 4]);
+    raw.extend(MTIME.to_le_bytes());
+    raw.extend(number.to_le_bytes());
+    raw
+}
+
+/// A file inode with its data stored uncompressed at `start`
+fn file_inode(number: u32, start: usize, len: usize) -> Vec<u8> {
+    let mut raw = inode(2, 0o644, number);
+    raw.extend((start as u32).to_le_bytes());
+    raw.extend(NO_FRAGMENT.to_le_bytes());
+    raw.extend(0u32.to_le_bytes());
+    raw.extend((len as u32).to_le_bytes());
+    for start in (0..len).step_by(BLOCK) {
+        let size = (len - start).min(BLOCK) as u32;
+        raw.extend((size | UNCOMPRESSED).to_le_bytes());
+    }
+    raw
+}
+
+fn dir_inode(number: u32, listing: usize, len: usize) -> Vec<u8> {
+    let mut raw = inode(1, 0o755, number);
+    raw.extend(0u32.to_le_bytes());
+    raw.extend(2u32.to_le_bytes());
+    // The size counts the implicit `.` and `..` entries
+    raw.extend(((len + 3) as u16).to_le_bytes());
+    raw.extend((listing as u16).to_le_bytes());
+    raw.extend(1u32.to_le_bytes());
+    raw
+}
+
+/// A directory listing of entries with their inode offset and type, all
+/// inodes being in the first metadata block
+fn listing(entries: &[(&str, usize, u16)]) -> Vec<u8> {
+    let mut raw = Vec::new();
+    raw.extend((entries.len() as u32 - 1).to_le_bytes());
+    raw.extend(0u32.to_le_bytes());
+    raw.extend(1u32.to_le_bytes());
+    for (name, inode, kind) in entries {
+        raw.extend((*inode as u16).to_le_bytes());
+        raw.extend(0u16.to_le_bytes());
+        raw.extend(kind.to_le_bytes());
+        raw.extend((name.len() as u16 - 1).to_le_bytes());
+        raw.extend(name.as_bytes());
+    }
+    raw
+}
+
+/// A metadata block stored without compression
+fn metadata_block(data: &[u8]) -> Vec<u8> {
+    let mut raw = (data.len() as u16 | 0x8000).to_le_bytes().to_vec();
+    raw.extend(data);
+    raw
+}
+
+/// An image holding `/hello.txt`, `/link` pointing to it and `/sub/inner`,
+/// without compression. The tables follow the file contents
+struct Image {
+    data: Vec<u8>,
+    hello: Vec<u8>,
+    /// Byte offsets of the inodes of `hello.txt` and `link`
+    hello_inode: usize,
+    link_inode: usize,
+}
+
+fn build() -> Image {
+    let hello = pattern(BLOCK + 904);
+    let mut data = vec![0; 96];
+    let hello_start = data.len();
+    data.extend(&hello);
+    let inner_start = data.len();
+    data.extend(b"inner");
+
+    let mut inodes = Vec::new();
+    let hello_ref = inodes.len();
+    inodes.extend(file_inode(2, hello_start, hello.len()));
+    let inner_ref = inodes.len();
+    inodes.extend(file_inode(3, inner_start, 5));
+    let link_ref = inodes.len();
+    inodes.extend(inode(3, 0o777, 4));
+    inodes.extend(1u32.to_le_bytes());
+    inodes.extend(9u32.to_le_bytes());
+    inodes.extend(b"hello.txt");
+
+    let sub_ref = inodes.len();
+    let sub = listing(&[("inner", inner_ref, 2)]);
+    let root = listing(&[
+        ("hello.txt", hello_ref, 2),
+        ("link", link_ref, 3),
+        ("sub", sub_ref, 1),
+    ]);
+    inodes.extend(dir_inode(5, 0, sub.len()));
+    let root_ref = inodes.len();
+    inodes.extend(dir_inode(1, sub.len(), root.len()));
+
+    let inode_table = data.len();
+    data.extend(metadata_block(&inodes));
+    let dir_table = data.len();
+    data.extend(metadata_block(&[sub, root].concat()));
+    let ids = data.len();
+    data.extend(metadata_block(&1000u32.to_le_bytes()));
+    let id_table = data.len();
+    data.extend((ids as u64).to_le_bytes());
+
+    let len = data.len() as u64;
+    let superblock = &mut data[..96];
+    superblock[0..4].copy_from_slice(&0x7371_7368u32.to_le_bytes());
+    superblock[4..8].copy_from_slice(&5u32.to_le_bytes());
+    superblock[8..12].copy_from_slice(&MTIME.to_le_bytes());
+    superblock[12..16].copy_from_slice(&(BLOCK as u32).to_le_bytes());
+    // gzip, although nothing is compressed
+    superblock[20..22].copy_from_slice(&1u16.to_le_bytes());
+    superblock[22..24].copy_from_slice(&12u16.to_le_bytes());
+    superblock[26..28].copy_from_slice(&1u16.to_le_bytes());
+    superblock[28..30].copy_from_slice(&4u16.to_le_bytes());
+    superblock[32..40].copy_from_slice(&(root_ref as u64).to_le_bytes());
+    superblock[40..48].copy_from_slice(&len.to_le_bytes());
+    superblock[48..56].copy_from_slice(&(id_table as u64).to_le_bytes());
+    superblock[56..64].fill(0xFF);
+    superblock[64..72].copy_from_slice(&(inode_table as u64).to_le_bytes());
+    superblock[72..80].copy_from_slice(&(dir_table as u64).to_le_bytes());
+    superblock[80..96].fill(0xFF);
+
+    Image {
+        data,
+        hello,
+        hello_inode: inode_table + 2 + hello_ref,
+        link_inode: inode_table + 2 + link_ref,
+    }
+}
+
+#[test]
+fn reads_generated_image() {
+    block_on(async {
+        let image = build();
+        let fs = SquashFS::new(Device::new(image.data)).await.unwrap();
+
+        assert_eq!(
+            names(&fs.path("/").unwrap()).await,
+            ["hello.txt", "link", "sub"]
+        );
+        assert_eq!(names(&fs.path("/sub").unwrap()).await, ["inner"]);
+
+        let path = fs.path("/hello.txt").unwrap();
+        assert_eq!(read_file(&path).await, image.hello);
+        let metadata = path.metadata().await.unwrap();
+        assert_eq!(metadata.size, image.hello.len() as u64);
+        assert_eq!(metadata.uid, Some(1000));
+        assert_eq!(metadata.permissions.unwrap().mode(), 0o644);
+        assert_eq!(read_file(&fs.path("/sub/inner").unwrap()).await, b"inner");
+
+        // The parent of a path ending in `..` steps out of it
+        let parent = fs.path("/sub/..").unwrap().parent().unwrap();
+        assert_eq!(parent.virtual_path(), "/sub/../..");
+        assert_eq!(parent.metadata().await.unwrap().kind, FileType::Dir);
+
+        // Links are followed
+        assert_eq!(read_file(&fs.path("/link").unwrap()).await, image.hello);
+        let entries = fs.path("/").unwrap().read_dir().await.unwrap();
+        let entries: Vec<_> = entries.try_collect().await.unwrap();
+        assert_eq!(entries[1].file_type(), FileType::Symlink);
+
+        // Reads across the end of the first block
+        let mut file = path.open(OpenOptions::new().read(true)).await.unwrap();
+        file.seek(SeekFrom::Start(BLOCK as u64 - 4)).await.unwrap();
+        let mut buf = [0; 8];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, image.hello[BLOCK - 4..BLOCK + 4]);
+    })
+}
+
+#[test]
+fn truncated_images() {
+    block_on(async {
+        let image = build();
+        let len = image.data.len();
+        for len in [0, 50, 96, 2000, image.hello_inode, len - 4] {
+            let device = Device::new(image.data[..len].to_vec());
+            assert!(SquashFS::new(device).await.is_err(), "{len} bytes");
+        }
+    })
+}
+
+#[test]
+fn malformed_images() {
+    block_on(async {
+        let mut image = build();
+        image.data[0] ^= 1;
+        let err = SquashFS::new(Device::new(image.data)).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // A data block larger than the block size
+        let mut image = build();
+        let block = image.hello_inode + 36;
+        let size = 0x00F0_0000 | UNCOMPRESSED;
+        image.data[block..block + 4].copy_from_slice(&size.to_le_bytes());
+        let fs = SquashFS::new(Device::new(image.data)).await.unwrap();
+        let err = try_read_file(&fs.path("/hello.txt").unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // A file claiming a block list larger than the image
+        let mut image = build();
+        let size = image.hello_inode + 28;
+        image.data[size..size + 4].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        let fs = SquashFS::new(Device::new(image.data)).await.unwrap();
+        let err = fs.path("/hello.txt").unwrap().metadata().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // A link target longer than the image
+        let mut image = build();
+        let len = image.link_inode + 20;
+        image.data[len..len + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let fs = SquashFS::new(Device::new(image.data)).await.unwrap();
+        let err = try_read_file(&fs.path("/link").unwrap()).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    })
+}
@@ -0,0 +1,192 @@
+//! Fixtures shared by the tests of the filesystems in this workspace.
+//!
+//! The helpers panic on errors, apart from the `try_` variants for tests
+//! which expect one.
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll},
+};
+
+use futures::TryStreamExt;
+use vfs::{Error, ErrorKind, OpenOptions, SeekFrom, VFile, VFileExt, VPath};
+
+/// Bytes which compress, but not to nothing
+pub fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 / 5 % 251) as u8).collect()
+}
+
+/// Bytes which neither repeat nor compress, the same for the same `seed`
+pub fn noise(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+/// Create or truncate the file at `path` and write `data` to it
+pub async fn write_file<P: VPath<File: Unpin>>(path: &P, data: &[u8]) {
+    try_write_file(path, data).await.unwrap()
+}
+
+pub async fn try_write_file<P: VPath<File: Unpin>>(path: &P, data: &[u8]) -> Result<(), Error> {
+    let options = OpenOptions::new().write(true).create(true).truncate(true);
+    let mut file = path.open(options).await?;
+    file.write_all(data).await?;
+    file.close().await
+}
+
+/// The whole content of the file at `path`
+pub async fn read_file<P: VPath<File: Unpin>>(path: &P) -> Vec<u8> {
+    try_read_file(path).await.unwrap()
+}
+
+pub async fn try_read_file<P: VPath<File: Unpin>>(path: &P) -> Result<Vec<u8>, Error> {
+    let mut file = path.open(OpenOptions::new().read(true)).await?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).await?;
+    Ok(data)
+}
+
+/// The sorted names of the entries of the directory at `path`
+pub async fn names<P: VPath>(path: &P) -> Vec<String> {
+    try_names(path).await.unwrap()
+}
+
+pub async fn try_names<P: VPath>(path: &P) -> Result<Vec<String>, Error> {
+    let entries: Vec<_> = path.read_dir().await?.try_collect().await?;
+    let mut names: Vec<_> = entries
+        .iter()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// A scratch directory removed again when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// A new empty directory, named after the test and the process
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("vfs-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A disk image in memory. Clones share the image, so a volume can be
+/// mounted again after the previous mount is dropped
+#[derive(Clone)]
+pub struct Device {
+    data: Arc<Mutex<Vec<u8>>>,
+    pos: u64,
+}
+
+impl Device {
+    pub fn new(data: Vec<u8>) -> Device {
+        Device {
+            data: Arc::new(Mutex::new(data)),
+            pos: 0,
+        }
+    }
+
+    /// The same image, positioned at its start
+    pub fn reopen(&self) -> Device {
+        Device {
+            data: self.data.clone(),
+            pos: 0,
+        }
+    }
+
+    /// The image, to inspect or change it behind the back of a mount
+    pub fn data(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.data.lock().unwrap()
+    }
+}
+
+impl VFile for Device {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        let data = this.data.lock().unwrap();
+        let start = (this.pos as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        this.pos += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        let this = self.get_mut();
+        let len = this.data.lock().unwrap().len() as u64;
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => this.pos.checked_add_signed(offset),
+        };
+
+        Poll::Ready(match pos {
+            Some(pos) => {
+                this.pos = pos;
+                Ok(pos)
+            }
+            None => Err(Error::new_const(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        })
+    }
+
+    /// Writes stay within the image, which does not grow
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        let mut data = this.data.lock().unwrap();
+        let start = this.pos as usize;
+        if start + buf.len() > data.len() {
+            return Poll::Ready(Err(Error::new_const(
+                ErrorKind::StorageFull,
+                "write past the end of the image",
+            )));
+        }
+        data[start..start + buf.len()].copy_from_slice(buf);
+        this.pos += buf.len() as u64;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -11,8 +11,7 @@ async fn main() -> vfs::Result<()> {
     let mut stream = path.read_dir().await?;
 
     while let Some(next) = stream.try_next().await? {
-        let metadata = next.metadata().await?;
-        println!("Next {:?} {}", metadata, next.to_string());
+        println!("Next {:?} {}", next.file_type(), next.path().to_string());
     }
 
     Ok(())
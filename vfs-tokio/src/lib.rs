@@ -1,5 +1,9 @@
+mod watch;
+
+pub use self::watch::Watcher;
+
 use std::{
-    os::unix::fs::MetadataExt,
+    os::unix::fs::{MetadataExt, PermissionsExt},
     path::PathBuf,
     task::{Poll, ready},
 };
@@ -8,11 +12,48 @@ use async_compat::Compat;
 use futures_core::{Stream, future::BoxFuture};
 use futures_io::{AsyncRead, AsyncSeek, AsyncWrite};
 use pin_project_lite::pin_project;
-use relative_path::RelativePath;
-use vfs::{Error, ErrorKind, FileType, Metadata, VFS, VFile, VPath};
+use relative_path::{Component, RelativePath};
+use vfs::{
+    Capabilities, DirEntry, Error, ErrorKind, FileTimes, FileType, FsStats, Metadata, Operation,
+    Permissions, Timestamp, VFS, VFile, VPath, VPathStr,
+};
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct FS(PathBuf);
+/// A directory of the local filesystem. The capabilities are queried from the
+/// directory once, when it is opened
+#[derive(Clone)]
+pub struct FS(PathBuf, Capabilities);
+
+impl core::fmt::Debug for FS {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("FS").field(&self.0).finish()
+    }
+}
+
+impl PartialEq for FS {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for FS {}
+
+impl PartialOrd for FS {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FS {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl core::hash::Hash for FS {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
 
 impl FS {
     pub async fn new(path: PathBuf) -> Result<FS, Error> {
@@ -23,32 +64,210 @@ impl FS {
         }
 
         let path = tokio::fs::canonicalize(path).await?;
+        let capabilities = PathWork::spawn(Operation::Stats, path.clone(), probe).await?;
 
-        Ok(FS(path))
+        Ok(FS(path, capabilities))
+    }
+}
+
+/// Query the capabilities of the filesystem `root` is on
+fn probe(root: &std::path::Path) -> std::io::Result<Capabilities> {
+    let stat = rustix::fs::statvfs(root)?;
+    let kind = FsKind::of(root);
+    let case_sensitive = match probe_case(root)? {
+        Some(case_sensitive) => case_sensitive,
+        None => kind.case_sensitive(),
+    };
+
+    Ok(Capabilities {
+        symlinks: kind.symlinks(),
+        atomic_rename: true,
+        xattr: xattr::SUPPORTED_PLATFORM,
+        seek: true,
+        write: !stat.f_flag.contains(rustix::fs::StatVfsMountFlags::RDONLY),
+        case_sensitive,
+        max_name_len: (stat.f_namemax > 0).then_some(stat.f_namemax as usize),
+    })
+}
+
+/// Look up an entry of `root` with the case of its name swapped. Returns `None`
+/// if there is no entry with letters in its name to try
+fn probe_case(root: &std::path::Path) -> std::io::Result<Option<bool>> {
+    for entry in std::fs::read_dir(root)? {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(swap_case) else {
+            continue;
+        };
+        if entry.file_name() == name.as_str() {
+            continue;
+        }
+
+        let (Ok(found), Ok(swapped)) =
+            (entry.metadata(), std::fs::symlink_metadata(root.join(name)))
+        else {
+            return Ok(Some(true));
+        };
+        // A different file with the swapped name means names are case sensitive as well
+        let same = found.dev() == swapped.dev() && found.ino() == swapped.ino();
+        return Ok(Some(!same));
+    }
+    Ok(None)
+}
+
+fn swap_case(name: &str) -> String {
+    name.chars()
+        .map(|c| match c.is_ascii_lowercase() {
+            true => c.to_ascii_uppercase(),
+            false => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+/// The filesystems which differ from the unix defaults
+enum FsKind {
+    /// FAT and exFAT, without symbolic links or case sensitive names
+    Fat,
+    /// SMB shares, usually without case sensitive names
+    Smb,
+    Other,
+}
+
+impl FsKind {
+    #[cfg(target_os = "linux")]
+    fn of(root: &std::path::Path) -> FsKind {
+        const MSDOS: u32 = 0x4d44;
+        const EXFAT: u32 = 0x2011_bab0;
+        const SMB: u32 = 0x517b;
+        const SMB2: u32 = 0xfe53_4d42;
+        const CIFS: u32 = 0xff53_4d42;
+
+        match rustix::fs::statfs(root).map(|stat| stat.f_type as u32) {
+            Ok(MSDOS | EXFAT) => FsKind::Fat,
+            Ok(SMB | SMB2 | CIFS) => FsKind::Smb,
+            _ => FsKind::Other,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn of(_root: &std::path::Path) -> FsKind {
+        FsKind::Other
+    }
+
+    fn symlinks(&self) -> bool {
+        !matches!(self, FsKind::Fat)
+    }
+
+    fn case_sensitive(&self) -> bool {
+        match self {
+            FsKind::Fat | FsKind::Smb => false,
+            // APFS and HFS+ are case insensitive unless formatted otherwise
+            FsKind::Other => !cfg!(target_os = "macos"),
+        }
     }
 }
 
 impl VFS for FS {
     type Path = Path;
 
-    fn path(&self, path: &str) -> Result<Self::Path, vfs::Error> {
-        Ok(Path(RelativePath::new(path).to_logical_path(&self.0)))
+    type Stats = PathWork<FsStats>;
+
+    fn path(&self, path: impl AsRef<VPathStr>) -> Result<Self::Path, vfs::Error> {
+        Ok(Path {
+            path: join(&self.0, &self.0, path.as_ref().as_str())?,
+            fs: self.clone(),
+        })
+    }
+
+    fn stats(&self) -> Self::Stats {
+        PathWork::spawn(Operation::Stats, self.0.clone(), |path| {
+            let stat = rustix::fs::statvfs(path)?;
+            Ok(FsStats {
+                block_size: stat.f_bsize,
+                total: stat.f_blocks * stat.f_frsize,
+                free: stat.f_bfree * stat.f_frsize,
+                available: stat.f_bavail * stat.f_frsize,
+            })
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.1
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct Path(PathBuf);
+pub struct Path {
+    fs: FS,
+    /// Location on the host, always below the root of `fs`
+    path: PathBuf,
+}
 
 impl Path {
-    pub const fn new(path: PathBuf) -> Path {
-        Path(path)
+    pub fn real_path(&self) -> &std::path::Path {
+        &self.path
     }
 
-    pub fn real_path(&self) -> &std::path::Path {
-        &self.0
+    /// A path of the same filesystem at the host location `path`
+    fn with_path(&self, path: PathBuf) -> Path {
+        Path {
+            fs: self.fs.clone(),
+            path,
+        }
     }
 }
 
+/// A time for `utimensat`, leaving the time unchanged if `None`
+fn timespec(time: Option<Timestamp>) -> rustix::fs::Timespec {
+    match time {
+        Some(time) => rustix::fs::Timespec {
+            tv_sec: time.as_unix().as_secs() as rustix::fs::Secs,
+            tv_nsec: time.as_unix().subsec_nanos() as rustix::fs::Nsecs,
+        },
+        None => rustix::fs::Timespec {
+            tv_sec: 0,
+            tv_nsec: rustix::fs::UTIME_OMIT,
+        },
+    }
+}
+
+/// Resolve `path` against `base`. Where `..` leads depends on the symbolic
+/// links before it, so the host resolves the path up to there, which reads
+/// them on the calling thread. `..` never climbs above `root`
+fn join(root: &std::path::Path, base: &std::path::Path, path: &str) -> Result<PathBuf, Error> {
+    let mut out = base.to_path_buf();
+
+    for component in RelativePath::new(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if out == root => {}
+            Component::ParentDir => {
+                let context = |err| context(err, Operation::Canonicalize, &out);
+                let real = std::fs::canonicalize(&out).map_err(context)?;
+                if !real.starts_with(root) {
+                    return Err(outside_root());
+                }
+                if !std::fs::metadata(&real).map_err(context)?.is_dir() {
+                    return Err(context(std::io::ErrorKind::NotADirectory.into()));
+                }
+                out = match real.parent() {
+                    Some(parent) if real != root => parent.to_path_buf(),
+                    _ => real,
+                };
+            }
+            Component::Normal(name) => out.push(name),
+        }
+    }
+
+    Ok(out)
+}
+
+const fn outside_root() -> Error {
+    Error::new_const(
+        ErrorKind::PermissionDenied,
+        "path resolves outside of the filesystem root",
+    )
+}
+
 impl VPath for Path {
     type FS = FS;
 
@@ -66,49 +285,103 @@ impl VPath for Path {
 
     type ReadDir = BoxFuture<'static, Result<ListDir, Error>>;
 
+    type SetPermissions = PathWork<()>;
+
+    type SetTimes = PathWork<()>;
+
+    type SetOwner = PathWork<()>;
+
+    type GetXattr = PathWork<Option<Vec<u8>>>;
+
+    type SetXattr = PathWork<()>;
+
+    type ListXattr = PathWork<Vec<String>>;
+
+    type RemoveXattr = PathWork<()>;
+
+    type Watcher = Watcher;
+
+    type Watch = BoxFuture<'static, Result<Watcher, Error>>;
+
+    type Canonicalize = PathWork<Path>;
+
+    fn fs(&self) -> Self::FS {
+        self.fs.clone()
+    }
+
+    fn virtual_path(&self) -> String {
+        let rest = self.path.strip_prefix(&self.fs.0).unwrap_or(&self.path);
+        let mut out = String::new();
+        for component in rest.components() {
+            out.push('/');
+            out.push_str(&component.as_os_str().to_string_lossy());
+        }
+
+        if out.is_empty() {
+            out.push('/');
+        }
+
+        out
+    }
+
     fn file_name(&self) -> Option<&str> {
-        self.0.file_name().and_then(|m| m.to_str())
+        self.path.file_name().and_then(|m| m.to_str())
     }
 
+    /// The location on the host
     fn to_string(&self) -> String {
-        self.0.display().to_string()
+        self.path.display().to_string()
     }
 
     fn extension(&self) -> Option<&str> {
-        self.0.extension().and_then(|m| m.to_str())
+        self.path.extension().and_then(|m| m.to_str())
     }
 
     fn resolve(&self, path: &str) -> Result<Self, vfs::Error> {
-        let path = RelativePath::new(path).to_logical_path(&self.0);
-        Ok(Self(path))
+        Ok(self.with_path(join(&self.fs.0, &self.path, path)?))
     }
 
     fn parent(&self) -> Option<Self> {
-        self.0.parent().map(|m| Self(m.to_path_buf()))
-    }
+        if self.path == self.fs.0 {
+            return None;
+        }
 
-    fn metadata(&self) -> Self::Metadata {
-        let path = self.0.clone();
-        PathWork {
-            inner: tokio::task::spawn_blocking(move || {
-                let metadata = std::fs::metadata(path)?;
+        self.path.parent().map(|m| self.with_path(m.to_path_buf()))
+    }
 
-                let ty = if metadata.is_dir() {
-                    FileType::Dir
-                } else {
-                    FileType::File
-                };
+    /// Resolved by the host, so links pointing outside of the root
+    /// of the filesystem are refused
+    fn canonicalize(&self) -> Self::Canonicalize {
+        let fs = self.fs.clone();
+        PathWork::spawn(Operation::Canonicalize, self.path.clone(), move |path| {
+            let path = std::fs::canonicalize(path)?;
+            if !path.starts_with(&fs.0) {
+                return Err(outside_root().into());
+            }
+            Ok(Path { fs, path })
+        })
+    }
 
-                vfs::Result::Ok(vfs::Metadata {
-                    size: metadata.size(),
-                    kind: ty,
-                })
-            }),
-        }
+    fn metadata(&self) -> Self::Metadata {
+        PathWork::spawn(Operation::Metadata, self.path.clone(), |path| {
+            let metadata = std::fs::metadata(path)?;
+
+            Ok(vfs::Metadata {
+                size: metadata.size(),
+                kind: file_type(metadata.file_type()),
+                permissions: Some(Permissions::from_mode(metadata.mode() & 0o7777)),
+                accessed: metadata.accessed().ok().map(Into::into),
+                modified: metadata.modified().ok().map(Into::into),
+                created: metadata.created().ok().map(Into::into),
+                uid: Some(metadata.uid()),
+                gid: Some(metadata.gid()),
+                hash: None,
+            })
+        })
     }
 
     fn open(&self, options: vfs::OpenOptions) -> Self::Open {
-        let path = self.0.clone();
+        let path = self.path.clone();
         Box::pin(async move {
             let mut ops = tokio::fs::OpenOptions::new();
 
@@ -118,40 +391,110 @@ impl VPath for Path {
                 .truncate(options.truncate)
                 .create(options.create);
 
-            let file = ops.open(path).await?;
+            let file = ops
+                .open(&path)
+                .await
+                .map_err(|err| context(err, Operation::Open, &path))?;
 
             Ok(File {
                 file: Compat::new(file),
+                path,
+                seeking: false,
             })
         })
     }
 
     fn read_dir(&self) -> Self::ReadDir {
-        let path = self.0.clone();
+        let this = self.clone();
         Box::pin(async move {
-            let readdir = tokio::fs::read_dir(path).await?;
-            Ok(ListDir { inner: readdir })
+            let readdir = tokio::fs::read_dir(&this.path)
+                .await
+                .map_err(|err| context(err, Operation::ReadDir, &this.path))?;
+            Ok(ListDir {
+                inner: readdir,
+                file_type: None,
+                dir: this,
+            })
         })
     }
 
     fn create_dir(&self) -> Self::CreateDir {
-        let path = self.0.clone();
-        PathWork {
-            inner: tokio::task::spawn_blocking(move || {
-                std::fs::create_dir_all(path)?;
-                vfs::Result::Ok(())
-            }),
-        }
+        PathWork::spawn(Operation::CreateDir, self.path.clone(), |path| {
+            std::fs::create_dir_all(path)
+        })
     }
 
     fn rm(&self) -> Self::Remove {
-        let path = self.0.clone();
-        PathWork {
-            inner: tokio::task::spawn_blocking(move || {
-                std::fs::remove_dir_all(path)?;
-                vfs::Result::Ok(())
-            }),
-        }
+        PathWork::spawn(Operation::Remove, self.path.clone(), |path| {
+            if std::fs::symlink_metadata(path)?.is_dir() {
+                std::fs::remove_dir_all(path)
+            } else {
+                std::fs::remove_file(path)
+            }
+        })
+    }
+
+    fn set_permissions(&self, permissions: Permissions) -> Self::SetPermissions {
+        PathWork::spawn(Operation::SetPermissions, self.path.clone(), move |path| {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(permissions.mode()))
+        })
+    }
+
+    fn set_times(&self, times: FileTimes) -> Self::SetTimes {
+        PathWork::spawn(Operation::SetTimes, self.path.clone(), move |path| {
+            // Set by path, so files which cannot be opened can be updated as well
+            let times = rustix::fs::Timestamps {
+                last_access: timespec(times.accessed),
+                last_modification: timespec(times.modified),
+            };
+            rustix::fs::utimensat(rustix::fs::CWD, path, &times, rustix::fs::AtFlags::empty())?;
+            Ok(())
+        })
+    }
+
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> Self::SetOwner {
+        PathWork::spawn(Operation::SetOwner, self.path.clone(), move |path| {
+            std::os::unix::fs::chown(path, uid, gid)
+        })
+    }
+
+    fn supports_xattr(&self) -> bool {
+        xattr::SUPPORTED_PLATFORM
+    }
+
+    fn get_xattr(&self, name: &str) -> Self::GetXattr {
+        let name = name.to_string();
+        PathWork::spawn(Operation::GetXattr, self.path.clone(), move |path| {
+            xattr::get(path, name)
+        })
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8]) -> Self::SetXattr {
+        let name = name.to_string();
+        let value = value.to_vec();
+        PathWork::spawn(Operation::SetXattr, self.path.clone(), move |path| {
+            xattr::set(path, name, &value)
+        })
+    }
+
+    fn list_xattr(&self) -> Self::ListXattr {
+        PathWork::spawn(Operation::ListXattr, self.path.clone(), |path| {
+            let names = xattr::list(path)?
+                .filter_map(|name| name.into_string().ok())
+                .collect();
+            Ok(names)
+        })
+    }
+
+    fn remove_xattr(&self, name: &str) -> Self::RemoveXattr {
+        let name = name.to_string();
+        PathWork::spawn(Operation::RemoveXattr, self.path.clone(), move |path| {
+            xattr::remove(path, name)
+        })
+    }
+
+    fn watch(&self, recursive: bool) -> Self::Watch {
+        Box::pin(watch::watch(self.clone(), recursive))
     }
 }
 
@@ -162,6 +505,19 @@ pin_project! {
     }
 }
 
+impl<T: Send + 'static> PathWork<T> {
+    fn spawn<F>(operation: Operation, path: PathBuf, work: F) -> PathWork<T>
+    where
+        F: FnOnce(&std::path::Path) -> std::io::Result<T> + Send + 'static,
+    {
+        PathWork {
+            inner: tokio::task::spawn_blocking(move || {
+                work(&path).map_err(|err| context(err, operation, &path))
+            }),
+        }
+    }
+}
+
 impl<T> Future for PathWork<T> {
     type Output = Result<T, Error>;
 
@@ -171,32 +527,91 @@ impl<T> Future for PathWork<T> {
     ) -> std::task::Poll<Self::Output> {
         match ready!(self.project().inner.poll(cx)) {
             Ok(ret) => std::task::Poll::Ready(ret),
-            Err(err) => Poll::Ready(Err(vfs::Error::new(vfs::ErrorKind::Other, err))),
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(err) => Poll::Ready(Err(Error::new_const(
+                ErrorKind::Other,
+                "background task was cancelled",
+            )
+            .with_source(err))),
         }
     }
 }
 
+fn context(err: std::io::Error, operation: Operation, path: &std::path::Path) -> Error {
+    let error = match err.raw_os_error().and_then(errno_kind) {
+        Some(kind) => Error::from(kind).with_source(err),
+        None => Error::from(err),
+    };
+
+    error.with_operation(operation).with_path(path.display())
+}
+
+/// Error kinds for os errors which std does not categorize yet
+fn errno_kind(code: i32) -> Option<ErrorKind> {
+    match rustix::io::Errno::from_raw_os_error(code) {
+        rustix::io::Errno::LOOP => Some(ErrorKind::FilesystemLoop),
+        _ => None,
+    }
+}
+
+fn file_type(ty: std::fs::FileType) -> FileType {
+    if ty.is_dir() {
+        FileType::Dir
+    } else if ty.is_symlink() {
+        FileType::Symlink
+    } else {
+        FileType::File
+    }
+}
+
+type EntryFileType = BoxFuture<'static, (PathBuf, std::io::Result<std::fs::FileType>)>;
+
 pin_project! {
     pub struct ListDir {
         #[pin]
-        inner: tokio::fs::ReadDir
+        inner: tokio::fs::ReadDir,
+        file_type: Option<EntryFileType>,
+        dir: Path,
     }
 }
 
 impl Stream for ListDir {
-    type Item = Result<Path, Error>;
+    type Item = Result<DirEntry<Path>, Error>;
 
     fn poll_next(
         self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
-        match ready!(self.project().inner.poll_next_entry(cx)) {
-            Ok(Some(ret)) => {
-                let path = ret.path();
-                Poll::Ready(Some(Ok(Path(path))))
+        let mut this = self.project();
+        loop {
+            // The file type is cached by the directory listing on most platforms,
+            // so this usually resolves on the first poll
+            if let Some(future) = this.file_type {
+                let (path, ty) = ready!(future.as_mut().poll(cx));
+                *this.file_type = None;
+                let entry = match ty {
+                    Ok(ty) => Ok(DirEntry::new(this.dir.with_path(path), file_type(ty))),
+                    Err(err) => Err(context(err, Operation::ReadDir, &path)),
+                };
+                return Poll::Ready(Some(entry));
+            }
+
+            match ready!(this.inner.as_mut().poll_next_entry(cx)) {
+                Ok(Some(ret)) => {
+                    *this.file_type = Some(Box::pin(async move {
+                        let ty = ret.file_type().await;
+                        (ret.path(), ty)
+                    }));
+                }
+                Ok(None) => return Poll::Ready(None),
+                Err(err) => {
+                    return Poll::Ready(Some(Err(context(
+                        err,
+                        Operation::ReadDir,
+                        &this.dir.path,
+                    ))));
+                }
             }
-            Ok(None) => Poll::Ready(None),
-            Err(err) => Poll::Ready(Some(Err(err.into()))),
         }
     }
 }
@@ -204,7 +619,10 @@ impl Stream for ListDir {
 pin_project! {
     pub struct File {
         #[pin]
-        file: Compat<tokio::fs::File>
+        file: Compat<tokio::fs::File>,
+        path: PathBuf,
+        // A seek was started and has not completed yet
+        seeking: bool,
     }
 }
 
@@ -214,10 +632,10 @@ impl VFile for File {
         cx: &mut std::task::Context<'_>,
         buf: &mut [u8],
     ) -> std::task::Poll<Result<usize, vfs::Error>> {
-        self.project()
-            .file
+        let this = self.project();
+        this.file
             .poll_read(cx, buf)
-            .map_err(|err| err.into())
+            .map_err(|err| context(err, Operation::Read, this.path))
     }
 
     fn poll_seek(
@@ -225,10 +643,17 @@ impl VFile for File {
         cx: &mut std::task::Context<'_>,
         pos: vfs::SeekFrom,
     ) -> std::task::Poll<Result<u64, vfs::Error>> {
-        self.project()
-            .file
-            .poll_seek(cx, pos.into())
-            .map_err(|err| err.into())
+        let mut this = self.project();
+        // Tokio refuses to seek while a write is still in flight. Once the seek
+        // is started, flushing would complete it and lose the new position
+        if !*this.seeking {
+            ready!(this.file.as_mut().poll_flush(cx))
+                .map_err(|err| context(err, Operation::Seek, this.path))?;
+            *this.seeking = true;
+        }
+        let ret = ready!(this.file.poll_seek(cx, pos.into()));
+        *this.seeking = false;
+        std::task::Poll::Ready(ret.map_err(|err| context(err, Operation::Seek, this.path)))
     }
 
     fn poll_write(
@@ -236,23 +661,29 @@ impl VFile for File {
         cx: &mut std::task::Context<'_>,
         buf: &[u8],
     ) -> std::task::Poll<Result<usize, vfs::Error>> {
-        self.project()
-            .file
+        let this = self.project();
+        this.file
             .poll_write(cx, buf)
-            .map_err(|err| err.into())
+            .map_err(|err| context(err, Operation::Write, this.path))
     }
 
     fn poll_flush(
         self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Result<(), vfs::Error>> {
-        self.project().file.poll_flush(cx).map_err(|err| err.into())
+        let this = self.project();
+        this.file
+            .poll_flush(cx)
+            .map_err(|err| context(err, Operation::Flush, this.path))
     }
 
     fn poll_close(
         self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Result<(), vfs::Error>> {
-        self.project().file.poll_close(cx).map_err(|err| err.into())
+        let this = self.project();
+        this.file
+            .poll_close(cx)
+            .map_err(|err| context(err, Operation::Close, this.path))
     }
 }
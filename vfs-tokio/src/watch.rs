@@ -0,0 +1,331 @@
+use vfs::Error;
+#[cfg(not(target_os = "linux"))]
+use vfs::WatchEvent;
+
+use crate::Path;
+
+#[cfg(target_os = "linux")]
+pub use self::inotify::Watcher;
+
+#[cfg(not(target_os = "linux"))]
+pub type Watcher = futures_core::stream::BoxStream<'static, Result<WatchEvent<Path>, Error>>;
+
+#[cfg(target_os = "linux")]
+pub(crate) async fn watch(path: Path, recursive: bool) -> Result<Watcher, Error> {
+    inotify::Watcher::new(path, recursive).await
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) async fn watch(path: Path, recursive: bool) -> Result<Watcher, Error> {
+    Ok(vfs::poll_watch(path, recursive, Ticks::new()))
+}
+
+/// Drives the polling fallback on platforms without a native watcher
+#[cfg(not(target_os = "linux"))]
+struct Ticks(tokio::time::Interval);
+
+#[cfg(not(target_os = "linux"))]
+impl Ticks {
+    fn new() -> Ticks {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        Ticks(interval)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl futures_core::Stream for Ticks {
+    type Item = ();
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.0.poll_tick(cx).map(|_| Some(()))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::{
+        collections::{HashMap, VecDeque},
+        path::PathBuf,
+        pin::Pin,
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use futures_core::Stream;
+    use inotify::{EventMask, EventStream, Inotify, WatchDescriptor, WatchMask, Watches};
+    use tokio::time::{Instant, Sleep};
+    use vfs::{Error, ErrorKind, Operation, WatchEvent};
+
+    use crate::{Path, PathWork, context};
+
+    const MASK: WatchMask = WatchMask::CREATE
+        .union(WatchMask::DELETE)
+        .union(WatchMask::DELETE_SELF)
+        .union(WatchMask::MODIFY)
+        .union(WatchMask::ATTRIB)
+        .union(WatchMask::MOVED_FROM)
+        .union(WatchMask::MOVED_TO);
+
+    /// How long the source of a rename waits for its destination. The kernel
+    /// queues both halves together, but they can be split across two reads
+    const MOVE_WINDOW: Duration = Duration::from_millis(50);
+
+    /// Stream of changes backed by inotify
+    pub struct Watcher {
+        stream: EventStream<Vec<u8>>,
+        watches: Watches,
+        root: Path,
+        recursive: bool,
+        dirs: HashMap<WatchDescriptor, PathBuf>,
+        /// Sources of renames waiting for their destination, by cookie, oldest first
+        moved_from: VecDeque<(u32, PathBuf, Instant)>,
+        /// Wakes the stream when the oldest source has waited long enough
+        timer: Option<Pin<Box<Sleep>>>,
+        queue: VecDeque<WatchEvent<Path>>,
+    }
+
+    impl Watcher {
+        pub(crate) async fn new(path: Path, recursive: bool) -> Result<Watcher, Error> {
+            let (inotify, dirs) =
+                PathWork::spawn(Operation::Watch, path.path.clone(), move |root| {
+                    let inotify = Inotify::init()?;
+                    let mut watches = inotify.watches();
+                    let mut dirs = HashMap::new();
+                    add_watches(&mut watches, &mut dirs, root, recursive, &mut |_| {})?;
+                    Ok((inotify, dirs))
+                })
+                .await?;
+
+            let stream = inotify
+                .into_event_stream(vec![0; 4096])
+                .map_err(|err| context(err, Operation::Watch, &path.path))?;
+
+            Ok(Watcher {
+                watches: stream.watches(),
+                stream,
+                root: path,
+                recursive,
+                dirs,
+                moved_from: VecDeque::new(),
+                timer: None,
+                queue: VecDeque::new(),
+            })
+        }
+
+        fn handle(&mut self, event: inotify::EventOwned) -> Result<(), Error> {
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                return Err(
+                    Error::new_const(ErrorKind::Other, "inotify event queue overflowed")
+                        .with_operation(Operation::Watch)
+                        .with_path(self.root.path.display()),
+                );
+            }
+
+            if event.mask.contains(EventMask::IGNORED) {
+                self.dirs.remove(&event.wd);
+                return Ok(());
+            }
+
+            let Some(dir) = self.dirs.get(&event.wd) else {
+                return Ok(());
+            };
+
+            let Some(name) = event.name else {
+                // The event concerns the watched path itself
+                if event.mask.contains(EventMask::DELETE_SELF) && *dir == self.root.path {
+                    self.queue
+                        .push_back(WatchEvent::Remove(self.root.with_path(dir.clone())));
+                } else if event.mask.intersects(EventMask::MODIFY | EventMask::ATTRIB)
+                    && !event.mask.contains(EventMask::ISDIR)
+                {
+                    self.queue
+                        .push_back(WatchEvent::Modify(self.root.with_path(dir.clone())));
+                }
+                return Ok(());
+            };
+
+            let path = dir.join(name);
+            let is_dir = event.mask.contains(EventMask::ISDIR);
+
+            if event.mask.contains(EventMask::MOVED_TO) {
+                let source = self
+                    .moved_from
+                    .iter()
+                    .position(|(cookie, ..)| *cookie == event.cookie);
+                match source.and_then(|index| self.moved_from.remove(index)) {
+                    Some((_, from, _)) => {
+                        if is_dir {
+                            self.rename_dirs(&from, &path);
+                        }
+                        self.queue.push_back(WatchEvent::Rename {
+                            from: self.root.with_path(from),
+                            to: self.root.with_path(path),
+                        });
+                    }
+                    None => self.created(path, is_dir)?,
+                }
+                return Ok(());
+            }
+
+            if event.mask.contains(EventMask::MOVED_FROM) {
+                self.moved_from
+                    .push_back((event.cookie, path, Instant::now() + MOVE_WINDOW));
+            } else if event.mask.contains(EventMask::CREATE) {
+                self.created(path, is_dir)?;
+            } else if event.mask.contains(EventMask::DELETE) {
+                self.queue
+                    .push_back(WatchEvent::Remove(self.root.with_path(path)));
+            } else if event.mask.intersects(EventMask::MODIFY | EventMask::ATTRIB) {
+                self.queue
+                    .push_back(WatchEvent::Modify(self.root.with_path(path)));
+            }
+
+            Ok(())
+        }
+
+        fn created(&mut self, path: PathBuf, is_dir: bool) -> Result<(), Error> {
+            self.queue
+                .push_back(WatchEvent::Create(self.root.with_path(path.clone())));
+
+            if is_dir && self.recursive {
+                // Entries created before the watch was in place would go unnoticed,
+                // so they are reported while walking the new directory
+                let (root, queue) = (&self.root, &mut self.queue);
+                let ret = add_watches(
+                    &mut self.watches,
+                    &mut self.dirs,
+                    &path,
+                    true,
+                    &mut |path| queue.push_back(WatchEvent::Create(root.with_path(path))),
+                );
+
+                match ret {
+                    // Already removed again, which will be reported by its own event
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(context(err, Operation::Watch, &path)),
+                    Ok(()) => {}
+                }
+            }
+
+            Ok(())
+        }
+
+        fn rename_dirs(&mut self, from: &std::path::Path, to: &std::path::Path) {
+            for dir in self.dirs.values_mut() {
+                if let Ok(rest) = dir.strip_prefix(from) {
+                    *dir = to.join(rest);
+                }
+            }
+        }
+
+        /// Moves without a matching destination by `deadline` left the watched tree
+        fn flush_moved(&mut self, deadline: Option<Instant>) {
+            while let Some((_, _, expires)) = self.moved_from.front()
+                && deadline.is_none_or(|deadline| *expires <= deadline)
+            {
+                let (_, from, _) = self.moved_from.pop_front().unwrap();
+                self.queue
+                    .push_back(WatchEvent::Remove(self.root.with_path(from)));
+            }
+        }
+
+        /// Wait for the oldest source of a rename to expire. Returns true once
+        /// it has, and there may be more to report
+        fn poll_timer(&mut self, cx: &mut Context<'_>) -> bool {
+            let Some((_, _, expires)) = self.moved_from.front() else {
+                self.timer = None;
+                return false;
+            };
+
+            let timer = self
+                .timer
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(*expires)));
+            if timer.deadline() != *expires {
+                timer.as_mut().reset(*expires);
+            }
+            if timer.as_mut().poll(cx).is_pending() {
+                return false;
+            }
+
+            self.flush_moved(Some(Instant::now()));
+            true
+        }
+    }
+
+    fn add_watches(
+        watches: &mut Watches,
+        dirs: &mut HashMap<WatchDescriptor, PathBuf>,
+        root: &std::path::Path,
+        recursive: bool,
+        found: &mut dyn FnMut(PathBuf),
+    ) -> std::io::Result<()> {
+        let wd = watches.add(root, MASK)?;
+        dirs.insert(wd, root.to_path_buf());
+
+        if !recursive || !root.is_dir() {
+            return Ok(());
+        }
+
+        let mut queue = vec![root.to_path_buf()];
+
+        while let Some(dir) = queue.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_dir() {
+                    let wd = watches.add(&path, MASK)?;
+                    dirs.insert(wd, path.clone());
+                    queue.push(path.clone());
+                }
+                found(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    impl Stream for Watcher {
+        type Item = Result<WatchEvent<Path>, Error>;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let this = self.get_mut();
+            loop {
+                if let Some(event) = this.queue.pop_front() {
+                    return Poll::Ready(Some(Ok(event)));
+                }
+
+                match Pin::new(&mut this.stream).poll_next(cx) {
+                    Poll::Ready(Some(Ok(event))) => {
+                        if let Err(err) = this.handle(event) {
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                    }
+                    Poll::Ready(Some(Err(err))) => {
+                        return Poll::Ready(Some(Err(context(
+                            err,
+                            Operation::Watch,
+                            &this.root.path,
+                        ))));
+                    }
+                    Poll::Ready(None) => {
+                        this.flush_moved(None);
+                        if this.queue.is_empty() {
+                            return Poll::Ready(None);
+                        }
+                    }
+                    Poll::Pending => {
+                        // A source without a destination within the window was
+                        // moved out of the tree
+                        if !this.poll_timer(cx) && this.queue.is_empty() {
+                            return Poll::Pending;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
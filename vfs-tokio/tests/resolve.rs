@@ -0,0 +1,59 @@
+use vfs::{ErrorKind, VFS, VPath};
+use vfs_test::TempDir;
+use vfs_tokio::FS;
+
+#[tokio::test]
+async fn parent_of_symlinked_dir() {
+    let dir = TempDir::new("tokio-resolve");
+    std::fs::create_dir_all(dir.path().join("real/sub")).unwrap();
+    std::fs::write(dir.path().join("real/file"), b"real").unwrap();
+    std::fs::write(dir.path().join("file"), b"top").unwrap();
+    std::os::unix::fs::symlink("real/sub", dir.path().join("link")).unwrap();
+
+    let fs = FS::new(dir.path().to_path_buf()).await.unwrap();
+
+    // `..` leaves the target of the link, not the directory holding it
+    let path = fs.path("/link/../file").unwrap();
+    assert_eq!(path.virtual_path(), "/real/file");
+    assert_eq!(path.metadata().await.unwrap().size, 4);
+    let canonical = path.canonicalize().await.unwrap();
+    assert_eq!(canonical, path);
+
+    let path = fs.path("/link").unwrap().resolve("../file").unwrap();
+    assert_eq!(path, canonical);
+
+    // The parent and name agree with where the path leads
+    let path = fs.path("/link/..").unwrap();
+    assert_eq!(path.virtual_path(), "/real");
+    assert_eq!(path.file_name(), Some("real"));
+    assert_eq!(path.parent().unwrap().virtual_path(), "/");
+    let path = fs.path("/real/..").unwrap();
+    assert_eq!(path.virtual_path(), "/");
+    assert!(path.parent().is_none());
+
+    // Paths without links behave as before
+    let path = fs.path("/real/sub/../../file").unwrap();
+    assert_eq!(path.virtual_path(), "/file");
+    assert_eq!(path.metadata().await.unwrap().size, 3);
+
+    // Never above the root
+    let path = fs.path("/../../file").unwrap();
+    assert_eq!(path.virtual_path(), "/file");
+
+    // `..` after a file is not a directory, and after nothing is not found
+    let err = fs.path("/file/../real").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotADirectory);
+    let err = fs.path("/missing/../file").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+#[tokio::test]
+async fn link_out_of_root() {
+    let dir = TempDir::new("tokio-outside");
+    std::fs::create_dir_all(dir.path().join("root")).unwrap();
+    std::os::unix::fs::symlink("..", dir.path().join("root/up")).unwrap();
+
+    let fs = FS::new(dir.path().join("root")).await.unwrap();
+    let err = fs.path("/up/../file").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+}
@@ -0,0 +1,27 @@
+use vfs::{OpenOptions, SeekFrom, VFS, VFileExt, VPath};
+use vfs_test::TempDir;
+use vfs_tokio::FS;
+
+#[tokio::test]
+async fn seek_returns_new_position() {
+    let dir = TempDir::new("tokio-seek");
+    std::fs::write(dir.path().join("file"), vec![1; 5000]).unwrap();
+    let fs = FS::new(dir.path().to_path_buf()).await.unwrap();
+    let path = fs.path("/file").unwrap();
+
+    // The seek runs on the blocking pool, so it is not always done when first polled
+    for _ in 0..200 {
+        let mut file = path.open(OpenOptions::new().read(true)).await.unwrap();
+        assert_eq!(file.seek(SeekFrom::End(0)).await.unwrap(), 5000);
+        assert_eq!(file.seek(SeekFrom::Current(-10)).await.unwrap(), 4990);
+    }
+
+    // A write in flight completes before seeking
+    let options = OpenOptions::new().write(true).read(true);
+    let mut file = path.open(options).await.unwrap();
+    file.write_all(b"data").await.unwrap();
+    assert_eq!(file.seek(SeekFrom::Start(0)).await.unwrap(), 0);
+    let mut buf = [0; 5];
+    file.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"data\x01");
+}
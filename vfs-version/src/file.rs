@@ -0,0 +1,97 @@
+use std::{
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll, ready},
+};
+
+use futures_core::future::BoxFuture;
+use vfs::{Error, ErrorKind, SeekFrom, SendVFS, VFS, VFile, VPath};
+
+type InnerFile<F> = <<F as VFS>::Path as VPath>::File;
+
+type Preserve = BoxFuture<'static, Result<(), Error>>;
+
+enum State {
+    /// The content has not been changed yet. The future preserves it, and is
+    /// only polled once the first write is about to change it
+    Unchanged(Mutex<Preserve>),
+    Preserved,
+    /// Writing would lose the previous content
+    Failed,
+}
+
+/// A file of a [`VersionedFS`](crate::VersionedFS). An existing file opened
+/// for writing is preserved when it is written to first, so opening it
+/// without writing leaves no version behind
+pub struct VersionedFile<F: VFS> {
+    inner: Pin<Box<InnerFile<F>>>,
+    state: State,
+}
+
+impl<F: SendVFS> VersionedFile<F> {
+    /// Wrap `inner`, running `preserve` before its first write if given
+    pub(crate) fn new(inner: InnerFile<F>, preserve: Option<Preserve>) -> VersionedFile<F> {
+        VersionedFile {
+            inner: Box::pin(inner),
+            state: match preserve {
+                Some(preserve) => State::Unchanged(Mutex::new(preserve)),
+                None => State::Preserved,
+            },
+        }
+    }
+
+    fn poll_preserve(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let result = match &mut self.state {
+            State::Unchanged(preserve) => ready!(preserve.get_mut().unwrap().as_mut().poll(cx)),
+            State::Preserved => return Poll::Ready(Ok(())),
+            State::Failed => {
+                return Poll::Ready(Err(Error::new_const(
+                    ErrorKind::Other,
+                    "the previous version could not be preserved",
+                )));
+            }
+        };
+
+        self.state = match &result {
+            Ok(()) => State::Preserved,
+            Err(_) => State::Failed,
+        };
+        Poll::Ready(result)
+    }
+}
+
+impl<F: SendVFS> VFile for VersionedFile<F> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.get_mut().inner.as_mut().poll_read(cx, buf)
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        self.get_mut().inner.as_mut().poll_seek(cx, pos)
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_preserve(cx))?;
+        this.inner.as_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.get_mut().inner.as_mut().poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.get_mut().inner.as_mut().poll_close(cx)
+    }
+}
@@ -0,0 +1,331 @@
+use std::sync::Mutex;
+
+use async_lock::Mutex as AsyncMutex;
+use vfs::{
+    ContentHash, Error, ErrorKind, FileType, OpenOptions, SeekFrom, SendVFS, Timestamp, VFS,
+    VFileExt, VPath,
+};
+use vfs_cas::{Objects, hash_file, read_all};
+
+/// The directory of the history in the inner filesystem, hidden from the versioned tree
+pub(crate) const HISTORY: &str = ".versions";
+
+/// The log of all versions and snapshots, one per line
+const LOG: &str = "log";
+
+/// The directory of the preserved contents, stored once by their hash
+const OBJECTS: &str = "objects";
+
+/// A previous state of a path, preserved before the path was changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    /// Identifies the version among all versions of the filesystem, counting up from 0
+    pub id: u64,
+    /// The path the version belongs to
+    pub path: String,
+    /// When the path was changed from this version
+    pub time: Timestamp,
+    /// The hash of the content, or `None` if the path did not exist, because it was
+    /// created by the change
+    pub hash: Option<ContentHash>,
+    pub size: u64,
+    /// The number of snapshots taken before the version was replaced
+    generation: u64,
+}
+
+/// A named state of the whole tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub name: String,
+    pub time: Timestamp,
+    /// Versions of a later generation were replaced after the snapshot was taken
+    generation: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    versions: Vec<Version>,
+    snapshots: Vec<Snapshot>,
+    generation: u64,
+}
+
+impl State {
+    fn parse(data: &[u8]) -> Result<State, Error> {
+        let data = core::str::from_utf8(data).map_err(|_| invalid())?;
+        let mut state = State::default();
+        // Names and paths can contain carriage returns, so lines only end in line feeds
+        for line in data.split_terminator('\n') {
+            // Left by clearing a line cut short
+            if line.bytes().all(|byte| byte == b' ') {
+                continue;
+            }
+
+            let (kind, rest) = line.split_once(' ').ok_or_else(invalid)?;
+            match kind {
+                "version" => {
+                    let mut parts = rest.splitn(5, ' ');
+                    let (Some(generation), Some(time), Some(hash), Some(size), Some(path)) = (
+                        parts.next(),
+                        parts.next(),
+                        parts.next(),
+                        parts.next(),
+                        parts.next(),
+                    ) else {
+                        return Err(invalid());
+                    };
+
+                    let hash = match hash {
+                        "-" => None,
+                        hash => Some(hash.parse().map_err(|_| invalid())?),
+                    };
+                    state.versions.push(Version {
+                        id: state.versions.len() as u64,
+                        path: path.to_string(),
+                        time: parse_time(time)?,
+                        hash,
+                        size: size.parse().map_err(|_| invalid())?,
+                        generation: generation.parse().map_err(|_| invalid())?,
+                    });
+                }
+                "snapshot" => {
+                    let mut parts = rest.splitn(3, ' ');
+                    let (Some(generation), Some(time), Some(name)) =
+                        (parts.next(), parts.next(), parts.next())
+                    else {
+                        return Err(invalid());
+                    };
+
+                    let generation: u64 = generation.parse().map_err(|_| invalid())?;
+                    state.snapshots.push(Snapshot {
+                        name: name.to_string(),
+                        time: parse_time(time)?,
+                        generation,
+                    });
+                    state.generation = generation + 1;
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(state)
+    }
+}
+
+/// The versions and snapshots of a filesystem, kept in its history directory
+pub(crate) struct History<F: VFS> {
+    dir: F::Path,
+    objects: Objects<F>,
+    /// Held while appending to the log, so lines are written one at a time
+    log: AsyncMutex<()>,
+    state: Mutex<State>,
+}
+
+impl<F: SendVFS> History<F> {
+    pub async fn open(fs: &F) -> Result<History<F>, Error> {
+        let dir = fs.path(HISTORY)?;
+        let objects = Objects::new(fs.clone(), format!("{HISTORY}/{OBJECTS}")).await?;
+
+        let log = dir.resolve(LOG)?;
+        let state = match read_all(&log).await {
+            Ok(data) => {
+                // The last line has no line feed if a crash cut it short while
+                // it was appended, so it is left out and cleared
+                let end = data.iter().rposition(|&byte| byte == b'\n');
+                let end = end.map_or(0, |end| end + 1);
+                if end < data.len() {
+                    clear_line(&log, end as u64, data.len() - end).await?;
+                }
+                State::parse(&data[..end])?
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => State::default(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(History {
+            dir,
+            objects,
+            log: AsyncMutex::new(()),
+            state: Mutex::new(state),
+        })
+    }
+
+    pub fn versions(&self, path: &str) -> Vec<Version> {
+        let state = self.state.lock().unwrap();
+        state
+            .versions
+            .iter()
+            .filter(|version| version.path == path)
+            .cloned()
+            .collect()
+    }
+
+    pub fn snapshots(&self) -> Vec<Snapshot> {
+        self.state.lock().unwrap().snapshots.clone()
+    }
+
+    pub fn snapshot_named(&self, name: &str) -> Result<Snapshot, Error> {
+        let state = self.state.lock().unwrap();
+        state
+            .snapshots
+            .iter()
+            .find(|snapshot| snapshot.name == name)
+            .cloned()
+            .ok_or(Error::new_const(ErrorKind::NotFound, "no such snapshot"))
+    }
+
+    /// The first versions replaced after `snapshot`, which are the states
+    /// of the paths changed since, when the snapshot was taken
+    pub fn changed_since(&self, snapshot: &Snapshot) -> Vec<Version> {
+        let state = self.state.lock().unwrap();
+        let mut changed: Vec<Version> = Vec::new();
+        for version in &state.versions {
+            if version.generation > snapshot.generation
+                && !changed.iter().any(|other| other.path == version.path)
+            {
+                changed.push(version.clone());
+            }
+        }
+        changed
+    }
+
+    /// The state of `path` when `snapshot` was taken, or `None` if the path
+    /// has not been changed since
+    pub fn version_at(&self, path: &str, snapshot: &Snapshot) -> Option<Version> {
+        let state = self.state.lock().unwrap();
+        state
+            .versions
+            .iter()
+            .find(|version| version.path == path && version.generation > snapshot.generation)
+            .cloned()
+    }
+
+    pub async fn snapshot(&self, name: &str) -> Result<Snapshot, Error> {
+        check_name(name)?;
+        let _log = self.log.lock().await;
+        if self.snapshot_named(name).is_ok() {
+            return Err(Error::new_const(
+                ErrorKind::AlreadyExists,
+                "a snapshot with the same name exists",
+            ));
+        }
+
+        let snapshot = Snapshot {
+            name: name.to_string(),
+            time: now(),
+            generation: self.state.lock().unwrap().generation,
+        };
+        self.append(format!(
+            "snapshot {} {} {}\n",
+            snapshot.generation,
+            format_time(snapshot.time),
+            snapshot.name
+        ))
+        .await?;
+
+        let mut state = self.state.lock().unwrap();
+        state.snapshots.push(snapshot.clone());
+        state.generation = snapshot.generation + 1;
+        Ok(snapshot)
+    }
+
+    /// Preserve the current state of the file at `inner`, known as `path`, before it
+    /// is changed. Paths which do not exist are recorded as well if `create` is true,
+    /// while directories are left to the caller
+    pub async fn preserve(&self, path: &str, inner: &F::Path, create: bool) -> Result<(), Error> {
+        let (hash, size) = match inner.metadata().await {
+            Ok(metadata) if metadata.kind == FileType::File => {
+                let (hash, size) = hash_file(inner).await?;
+                self.objects.put_file(inner, hash, size).await?;
+                (Some(hash), size)
+            }
+            Ok(_) => return Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound && create => (None, 0),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        check_name(path)?;
+        let time = now();
+        let _log = self.log.lock().await;
+        let generation = self.state.lock().unwrap().generation;
+        let stored = match hash {
+            Some(hash) => hash.to_string(),
+            None => String::from("-"),
+        };
+        self.append(format!(
+            "version {generation} {} {stored} {size} {path}\n",
+            format_time(time)
+        ))
+        .await?;
+
+        let mut state = self.state.lock().unwrap();
+        let id = state.versions.len() as u64;
+        state.versions.push(Version {
+            id,
+            path: path.to_string(),
+            time,
+            hash,
+            size,
+            generation,
+        });
+        Ok(())
+    }
+
+    /// Append `line` to the log. The log lock has to be held
+    async fn append(&self, line: String) -> Result<(), Error> {
+        let file = self
+            .dir
+            .resolve(LOG)?
+            .open(OpenOptions::new().append(true).create(true))
+            .await?;
+        let mut file = core::pin::pin!(file);
+        file.write_all(line.as_bytes()).await?;
+        file.close().await
+    }
+
+    pub async fn open_object(&self, hash: ContentHash) -> Result<<F::Path as VPath>::File, Error> {
+        self.objects.open(hash).await
+    }
+}
+
+/// Overwrite the `len` bytes at `start` of the log with a blank line, so the
+/// next line appended starts on a line of its own
+async fn clear_line<P: VPath>(log: &P, start: u64, len: usize) -> Result<(), Error> {
+    let file = log.open(OpenOptions::new().write(true)).await?;
+    let mut file = core::pin::pin!(file);
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut blank = vec![b' '; len];
+    blank[len - 1] = b'\n';
+    file.write_all(&blank).await?;
+    file.close().await
+}
+
+fn now() -> Timestamp {
+    std::time::SystemTime::now().into()
+}
+
+/// Times are stored as nanoseconds since the unix epoch
+fn format_time(time: Timestamp) -> u64 {
+    time.as_unix().as_nanos() as u64
+}
+
+fn parse_time(time: &str) -> Result<Timestamp, Error> {
+    let nanos = time.parse().map_err(|_| invalid())?;
+    Ok(Timestamp::from_unix(core::time::Duration::from_nanos(
+        nanos,
+    )))
+}
+
+/// Names and paths end their line in the log
+fn check_name(name: &str) -> Result<(), Error> {
+    if name.contains('\n') {
+        return Err(Error::new_const(
+            ErrorKind::InvalidFilename,
+            "names cannot contain line breaks",
+        ));
+    }
+    Ok(())
+}
+
+fn invalid() -> Error {
+    Error::new_const(ErrorKind::InvalidData, "invalid version log")
+}
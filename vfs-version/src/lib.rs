@@ -0,0 +1,504 @@
+//! A [`VFS`] which keeps the previous versions of its files.
+//!
+//! Before a file is written to, truncated or removed, its content is preserved in a
+//! hidden `.versions` directory of the inner filesystem, where every content is
+//! stored once by its BLAKE3 hash. Named snapshots of the whole tree cost nothing
+//! when taken: a file changed after a snapshot has its content at the snapshot
+//! preserved as the first version replaced after it.
+//!
+//! Only files are versioned. Directories, permissions, times and other properties
+//! are those of the inner filesystem, and versions are never removed.
+mod file;
+mod history;
+
+pub use self::{
+    file::VersionedFile,
+    history::{Snapshot, Version},
+};
+
+use std::sync::Arc;
+
+use futures::{StreamExt, TryStreamExt};
+use futures_core::{future::BoxFuture, stream::BoxStream};
+use vfs::{
+    Capabilities, DirEntry, Error, ErrorKind, FileTimes, FileType, OpenOptions, Operation,
+    Permissions, SendVFS, VFS, VFileExt, VPath, VPathStr, WatchEvent,
+};
+use vfs_cas::copy;
+
+use self::history::{HISTORY, History};
+
+type InnerFile<F> = <<F as VFS>::Path as VPath>::File;
+
+/// A filesystem which preserves every file before it is overwritten or removed.
+///
+/// Previous versions of a path are listed with [`versions`](VersionedFS::versions),
+/// read with [`open_version`](VersionedFS::open_version) and brought back with
+/// [`restore`](VersionedFS::restore). Snapshots of the whole tree are taken with
+/// [`snapshot`](VersionedFS::snapshot).
+pub struct VersionedFS<F: VFS> {
+    fs: F,
+    history: Arc<History<F>>,
+}
+
+impl<F: VFS + Clone> Clone for VersionedFS<F> {
+    fn clone(&self) -> Self {
+        VersionedFS {
+            fs: self.fs.clone(),
+            history: self.history.clone(),
+        }
+    }
+}
+
+impl<F: VFS + core::fmt::Debug> core::fmt::Debug for VersionedFS<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("VersionedFS")
+            .field("fs", &self.fs)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: SendVFS> VersionedFS<F> {
+    /// Version the files of `fs`, continuing the history kept in it if there is one
+    pub async fn new(fs: F) -> Result<VersionedFS<F>, Error> {
+        let history = History::open(&fs).await?;
+        Ok(VersionedFS {
+            fs,
+            history: Arc::new(history),
+        })
+    }
+
+    /// The wrapped filesystem
+    pub fn get_ref(&self) -> &F {
+        &self.fs
+    }
+
+    /// The previous versions of `path`, oldest first
+    pub fn versions(&self, path: impl AsRef<VPathStr>) -> Result<Vec<Version>, Error> {
+        let path = self.path(path)?;
+        Ok(self.history.versions(&path.key()))
+    }
+
+    /// Open a previous version of a file for reading
+    pub async fn open_version(&self, version: &Version) -> Result<InnerFile<F>, Error> {
+        let Some(hash) = version.hash else {
+            let err = Error::new_const(ErrorKind::NotFound, "the file did not exist");
+            return Err(err
+                .with_operation(Operation::Open)
+                .with_path(version.path.clone()));
+        };
+
+        self.history
+            .open_object(hash)
+            .await
+            .map_err(|err| err.with_path(version.path.clone()))
+    }
+
+    /// Bring a file back to a previous version, removing it if it did not exist.
+    /// The current state is preserved as a version first, so restoring can be undone
+    pub async fn restore(&self, version: &Version) -> Result<(), Error> {
+        let path = self.path(&version.path)?;
+        let Some(hash) = version.hash else {
+            return match path.rm().await {
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+                result => result,
+            };
+        };
+
+        if let Some(parent) = path.parent() {
+            parent.create_dir().await?;
+        }
+        let from = self.history.open_object(hash).await?;
+        let from = core::pin::pin!(from);
+        let to = path
+            .open(OpenOptions::new().write(true).create(true).truncate(true))
+            .await?;
+        let mut to = core::pin::pin!(to);
+        copy(from, to.as_mut()).await?;
+        to.close().await
+    }
+
+    /// Take a snapshot of the whole tree named `name`, which has to be unique
+    pub async fn snapshot(&self, name: &str) -> Result<Snapshot, Error> {
+        self.history.snapshot(name).await
+    }
+
+    /// All snapshots, oldest first
+    pub fn snapshots(&self) -> Vec<Snapshot> {
+        self.history.snapshots()
+    }
+
+    /// Open a file as it was when the snapshot `name` was taken
+    pub async fn open_at(
+        &self,
+        path: impl AsRef<VPathStr>,
+        snapshot: &str,
+    ) -> Result<InnerFile<F>, Error> {
+        let snapshot = self.history.snapshot_named(snapshot)?;
+        let path = self.path(path)?;
+        match self.history.version_at(&path.key(), &snapshot) {
+            Some(version) => self.open_version(&version).await,
+            // Unchanged since
+            None => path.inner.open(OpenOptions::new().read(true)).await,
+        }
+    }
+
+    /// Bring every file changed since the snapshot `name` back to its state at the
+    /// snapshot. Directories created since are kept
+    pub async fn restore_snapshot(&self, name: &str) -> Result<(), Error> {
+        let snapshot = self.history.snapshot_named(name)?;
+        for version in self.history.changed_since(&snapshot) {
+            self.restore(&version).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<F: SendVFS> VFS for VersionedFS<F> {
+    type Path = VersionedPath<F>;
+
+    type Stats = F::Stats;
+
+    fn path(&self, path: impl AsRef<VPathStr>) -> Result<Self::Path, Error> {
+        VersionedPath::new(self.history.clone(), self.fs.path(path)?)
+    }
+
+    /// The space used on the inner filesystem, including the history
+    fn stats(&self) -> Self::Stats {
+        self.fs.stats()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.fs.capabilities()
+    }
+}
+
+/// A path of a [`VersionedFS`]
+pub struct VersionedPath<F: VFS> {
+    history: Arc<History<F>>,
+    inner: F::Path,
+}
+
+impl<F: VFS> Clone for VersionedPath<F>
+where
+    F::Path: Clone,
+{
+    fn clone(&self) -> Self {
+        VersionedPath {
+            history: self.history.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<F: VFS> core::fmt::Debug for VersionedPath<F>
+where
+    F::Path: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("VersionedPath").field(&self.inner).finish()
+    }
+}
+
+impl<F: VFS> PartialEq for VersionedPath<F>
+where
+    F::Path: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<F: VFS> Eq for VersionedPath<F> where F::Path: Eq {}
+
+impl<F: VFS> core::hash::Hash for VersionedPath<F>
+where
+    F::Path: core::hash::Hash,
+{
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.inner.hash(state)
+    }
+}
+
+impl<F: SendVFS> VersionedPath<F> {
+    /// Wrap `inner`, which must not be part of the history
+    fn new(history: Arc<History<F>>, inner: F::Path) -> Result<VersionedPath<F>, Error> {
+        if is_history(&key(&inner)) {
+            let err = Error::new_const(
+                ErrorKind::PermissionDenied,
+                "the version history cannot be accessed directly",
+            );
+            return Err(err.with_path(inner.virtual_path()));
+        }
+        Ok(VersionedPath { history, inner })
+    }
+
+    /// The path of the inner filesystem
+    pub fn get_ref(&self) -> &F::Path {
+        &self.inner
+    }
+
+    /// The normalized path versions are recorded under
+    fn key(&self) -> String {
+        key(&self.inner)
+    }
+
+    fn context(&self, err: Error, operation: Operation) -> Error {
+        err.with_operation(operation)
+            .with_path(self.inner.virtual_path())
+    }
+
+    /// Preserve every file below this path, or this path if it is a file
+    async fn preserve_tree(&self) -> Result<(), Error> {
+        let mut dirs = match self.inner.metadata().await?.kind {
+            FileType::Dir => vec![self.inner.clone()],
+            _ => return self.history.preserve(&self.key(), &self.inner, false).await,
+        };
+
+        while let Some(dir) = dirs.pop() {
+            let entries = dir.read_dir().await?;
+            let mut entries = core::pin::pin!(entries);
+            while let Some(entry) = entries.try_next().await? {
+                if is_history(&key(entry.path())) {
+                    continue;
+                }
+                match entry.file_type() {
+                    FileType::Dir => dirs.push(entry.into_path()),
+                    FileType::File => {
+                        let path = entry.into_path();
+                        self.history.preserve(&key(&path), &path, false).await?;
+                    }
+                    FileType::Symlink => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn remove(&self) -> Result<(), Error> {
+        if self.key() != "/" {
+            self.preserve_tree().await?;
+            return self.inner.rm().await;
+        }
+
+        // The history lives in the root, so its siblings are removed one by one
+        let entries = self.inner.read_dir().await?;
+        let mut entries = core::pin::pin!(entries);
+        while let Some(entry) = entries.try_next().await? {
+            let path = entry.into_path();
+            if !is_history(&key(&path)) {
+                let path = VersionedPath::new(self.history.clone(), path)?;
+                path.preserve_tree().await?;
+                path.inner.rm().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn key<P: VPath>(path: &P) -> String {
+    VPathStr::new(&path.virtual_path())
+        .normalize()
+        .into_string()
+}
+
+fn is_history(key: &str) -> bool {
+    key.strip_prefix('/')
+        .unwrap_or(key)
+        .split('/')
+        .next()
+        .is_some_and(|name| name == HISTORY)
+}
+
+impl<F: SendVFS> VPath for VersionedPath<F> {
+    type FS = VersionedFS<F>;
+
+    type File = VersionedFile<F>;
+
+    type ListDir = BoxStream<'static, Result<DirEntry<Self>, Error>>;
+
+    type Metadata = <F::Path as VPath>::Metadata;
+
+    type Open = BoxFuture<'static, Result<Self::File, Error>>;
+
+    type CreateDir = <F::Path as VPath>::CreateDir;
+
+    type Remove = BoxFuture<'static, Result<(), Error>>;
+
+    type ReadDir = BoxFuture<'static, Result<Self::ListDir, Error>>;
+
+    type SetPermissions = <F::Path as VPath>::SetPermissions;
+
+    type SetTimes = <F::Path as VPath>::SetTimes;
+
+    type SetOwner = <F::Path as VPath>::SetOwner;
+
+    type GetXattr = <F::Path as VPath>::GetXattr;
+
+    type SetXattr = <F::Path as VPath>::SetXattr;
+
+    type ListXattr = <F::Path as VPath>::ListXattr;
+
+    type RemoveXattr = <F::Path as VPath>::RemoveXattr;
+
+    type Watcher = BoxStream<'static, Result<WatchEvent<Self>, Error>>;
+
+    type Watch = BoxFuture<'static, Result<Self::Watcher, Error>>;
+
+    type Canonicalize = BoxFuture<'static, Result<Self, Error>>;
+
+    fn fs(&self) -> Self::FS {
+        VersionedFS {
+            fs: self.inner.fs(),
+            history: self.history.clone(),
+        }
+    }
+
+    fn virtual_path(&self) -> String {
+        self.inner.virtual_path()
+    }
+
+    fn to_string(&self) -> String {
+        self.inner.to_string()
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        self.inner.file_name()
+    }
+
+    fn extension(&self) -> Option<&str> {
+        self.inner.extension()
+    }
+
+    fn resolve(&self, path: &str) -> Result<Self, Error> {
+        VersionedPath::new(self.history.clone(), self.inner.resolve(path)?)
+    }
+
+    fn parent(&self) -> Option<Self> {
+        Some(VersionedPath {
+            history: self.history.clone(),
+            inner: self.inner.parent()?,
+        })
+    }
+
+    fn canonicalize(&self) -> Self::Canonicalize {
+        let history = self.history.clone();
+        let future = self.inner.canonicalize();
+        Box::pin(async move { VersionedPath::new(history, future.await?) })
+    }
+
+    fn metadata(&self) -> Self::Metadata {
+        self.inner.metadata()
+    }
+
+    /// Files are preserved before they are truncated or first written to,
+    /// and the absence of files created by opening them is preserved as well
+    fn open(&self, options: OpenOptions) -> Self::Open {
+        let this = self.clone();
+        Box::pin(async move {
+            let mut preserve = None;
+            if options.write || options.append {
+                if options.truncate || this.inner.metadata().await.is_err() {
+                    this.history
+                        .preserve(&this.key(), &this.inner, options.create)
+                        .await
+                        .map_err(|err| this.context(err, Operation::Open))?;
+                } else {
+                    let history = this.history.clone();
+                    let (key, inner) = (this.key(), this.inner.clone());
+                    preserve = Some(Box::pin(async move {
+                        history.preserve(&key, &inner, false).await.map_err(|err| {
+                            err.with_operation(Operation::Write)
+                                .with_path(inner.virtual_path())
+                        })
+                    }) as BoxFuture<'static, _>);
+                }
+            }
+            let file = this.inner.open(options).await?;
+            Ok(VersionedFile::new(file, preserve))
+        })
+    }
+
+    fn read_dir(&self) -> Self::ReadDir {
+        let history = self.history.clone();
+        let future = self.inner.read_dir();
+        Box::pin(async move {
+            let entries = future.await?.try_filter_map(move |entry| {
+                let entry = match is_history(&key(entry.path())) {
+                    true => None,
+                    false => Some(entry.map(|inner| VersionedPath {
+                        history: history.clone(),
+                        inner,
+                    })),
+                };
+                futures::future::ready(Ok(entry))
+            });
+            Ok(entries.boxed())
+        })
+    }
+
+    fn create_dir(&self) -> Self::CreateDir {
+        self.inner.create_dir()
+    }
+
+    /// Every file removed is preserved first
+    fn rm(&self) -> Self::Remove {
+        let this = self.clone();
+        Box::pin(async move {
+            this.remove()
+                .await
+                .map_err(|err| this.context(err, Operation::Remove))
+        })
+    }
+
+    fn set_permissions(&self, permissions: Permissions) -> Self::SetPermissions {
+        self.inner.set_permissions(permissions)
+    }
+
+    fn set_times(&self, times: FileTimes) -> Self::SetTimes {
+        self.inner.set_times(times)
+    }
+
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> Self::SetOwner {
+        self.inner.set_owner(uid, gid)
+    }
+
+    fn supports_xattr(&self) -> bool {
+        self.inner.supports_xattr()
+    }
+
+    fn get_xattr(&self, name: &str) -> Self::GetXattr {
+        self.inner.get_xattr(name)
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8]) -> Self::SetXattr {
+        self.inner.set_xattr(name, value)
+    }
+
+    fn list_xattr(&self) -> Self::ListXattr {
+        self.inner.list_xattr()
+    }
+
+    fn remove_xattr(&self, name: &str) -> Self::RemoveXattr {
+        self.inner.remove_xattr(name)
+    }
+
+    /// Changes to the history are left out
+    fn watch(&self, recursive: bool) -> Self::Watch {
+        let history = self.history.clone();
+        let future = self.inner.watch(recursive);
+        Box::pin(async move {
+            let watcher = future.await?.try_filter_map(move |event| {
+                let event = match is_history(&key(event.path())) {
+                    true => None,
+                    false => Some(event.map(|inner| VersionedPath {
+                        history: history.clone(),
+                        inner,
+                    })),
+                };
+                futures::future::ready(Ok(event))
+            });
+            Ok(watcher.boxed())
+        })
+    }
+}
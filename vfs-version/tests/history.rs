@@ -0,0 +1,259 @@
+use futures::{FutureExt, StreamExt, executor::block_on};
+use vfs::{ErrorKind, OpenOptions, VFS, VFileExt, VPath};
+use vfs_memory::MemoryFS;
+use vfs_test::{names, read_file, write_file};
+use vfs_version::VersionedFS;
+
+#[test]
+fn carriage_return_in_names() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        let fs = VersionedFS::new(inner.clone()).await.unwrap();
+        let path = fs.path("/file\r").unwrap();
+        write_file(&path, b"first").await;
+        fs.snapshot("snap\r").await.unwrap();
+        write_file(&path, b"second").await;
+
+        // The history is read back from the log
+        let fs = VersionedFS::new(inner).await.unwrap();
+        let names: Vec<_> = fs.snapshots().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, ["snap\r"]);
+
+        let versions = fs.versions("/file\r").unwrap();
+        assert_eq!(versions.len(), 2);
+        assert!(versions.iter().all(|version| version.path.ends_with('\r')));
+
+        let mut file = fs.open_version(&versions[1]).await.unwrap();
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"first");
+
+        let path = fs.path("/file\r").unwrap();
+        assert_eq!(read_file(&path).await, b"second");
+
+        let err = fs.snapshot("snap\nshot").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidFilename);
+    })
+}
+
+#[test]
+fn remove_root() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        let fs = VersionedFS::new(inner.clone()).await.unwrap();
+        fs.path("/dir").unwrap().create_dir().await.unwrap();
+        write_file(&fs.path("/dir/file").unwrap(), b"nested").await;
+        write_file(&fs.path("/top").unwrap(), b"top").await;
+
+        // Spelled so its parent is not the root, which it is
+        fs.path("/dir/..").unwrap().rm().await.unwrap();
+        assert!(names(&fs.path("/").unwrap()).await.is_empty());
+        assert_eq!(names(&inner.path("/").unwrap()).await, [".versions"]);
+
+        let fs = VersionedFS::new(inner).await.unwrap();
+        for (path, data) in [("/dir/file", b"nested" as &[u8]), ("/top", b"top")] {
+            let versions = fs.versions(path).unwrap();
+            let removed = versions.last().unwrap();
+            let mut file = fs.open_version(removed).await.unwrap();
+            let mut read = Vec::new();
+            file.read_to_end(&mut read).await.unwrap();
+            assert_eq!(read, data);
+        }
+    })
+}
+
+#[test]
+fn log_cut_short() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        let fs = VersionedFS::new(inner.clone()).await.unwrap();
+        let path = fs.path("/file").unwrap();
+        write_file(&path, b"first").await;
+        write_file(&path, b"second").await;
+        assert_eq!(fs.versions("/file").unwrap().len(), 2);
+
+        // A crash while a line is appended
+        let log = inner.path("/.versions/log").unwrap();
+        let mut file = log.open(OpenOptions::new().append(true)).await.unwrap();
+        file.write_all("version 0 12 \u{e9}".as_bytes())
+            .await
+            .unwrap();
+        file.close().await.unwrap();
+        let cut = read_file(&log).await;
+        let cut = &cut[..cut.len() - 1];
+        write_file(&log, cut).await;
+
+        let fs = VersionedFS::new(inner.clone()).await.unwrap();
+        assert_eq!(fs.versions("/file").unwrap().len(), 2);
+
+        // Lines appended later are read back
+        let path = fs.path("/file").unwrap();
+        write_file(&path, b"third").await;
+        fs.snapshot("snap").await.unwrap();
+        let fs = VersionedFS::new(inner).await.unwrap();
+        assert_eq!(fs.versions("/file").unwrap().len(), 3);
+        assert_eq!(fs.snapshots().len(), 1);
+    })
+}
+
+#[test]
+fn preserved_on_first_write() {
+    block_on(async {
+        let fs = VersionedFS::new(MemoryFS::new()).await.unwrap();
+        let path = fs.path("/file").unwrap();
+
+        // Creating the file preserves its absence
+        write_file(&path, b"first").await;
+        let versions = fs.versions("/file").unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].hash, None);
+
+        // Opening without writing changes nothing
+        let options = OpenOptions::new().read(true).write(true);
+        let mut file = path.open(options).await.unwrap();
+        file.close().await.unwrap();
+        assert_eq!(fs.versions("/file").unwrap().len(), 1);
+
+        // Only the first write preserves the content
+        let mut file = path.open(OpenOptions::new().append(true)).await.unwrap();
+        file.write_all(b", second").await.unwrap();
+        file.write_all(b", third").await.unwrap();
+        file.close().await.unwrap();
+        let versions = fs.versions("/file").unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[1].size, 5);
+        assert_eq!(read_file(&path).await, b"first, second, third");
+
+        // Truncating changes the file when it is opened
+        let options = OpenOptions::new().write(true).truncate(true);
+        let mut file = path.open(options).await.unwrap();
+        assert_eq!(fs.versions("/file").unwrap().len(), 3);
+        file.close().await.unwrap();
+        let versions = fs.versions("/file").unwrap();
+        let mut file = fs.open_version(&versions[2]).await.unwrap();
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"first, second, third");
+    })
+}
+
+#[test]
+fn restore() {
+    block_on(async {
+        let fs = VersionedFS::new(MemoryFS::new()).await.unwrap();
+        let path = fs.path("/file").unwrap();
+        write_file(&path, b"first").await;
+        write_file(&path, b"second").await;
+
+        let versions = fs.versions("/file").unwrap();
+        fs.restore(&versions[1]).await.unwrap();
+        assert_eq!(read_file(&path).await, b"first");
+
+        // Restoring is undone like any other change
+        let versions = fs.versions("/file").unwrap();
+        assert_eq!(versions.len(), 3);
+        fs.restore(&versions[2]).await.unwrap();
+        assert_eq!(read_file(&path).await, b"second");
+
+        // Back to before the file was created
+        fs.restore(&versions[0]).await.unwrap();
+        let err = path.metadata().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    })
+}
+
+#[test]
+fn snapshots() {
+    block_on(async {
+        let fs = VersionedFS::new(MemoryFS::new()).await.unwrap();
+        fs.path("/dir").unwrap().create_dir().await.unwrap();
+        write_file(&fs.path("/dir/kept").unwrap(), b"kept").await;
+        write_file(&fs.path("/changed").unwrap(), b"before").await;
+        write_file(&fs.path("/removed").unwrap(), b"removed").await;
+        fs.snapshot("snap").await.unwrap();
+
+        write_file(&fs.path("/changed").unwrap(), b"after").await;
+        write_file(&fs.path("/changed").unwrap(), b"again").await;
+        fs.path("/removed").unwrap().rm().await.unwrap();
+        write_file(&fs.path("/created").unwrap(), b"created").await;
+
+        let mut data = Vec::new();
+        let mut file = fs.open_at("/changed", "snap").await.unwrap();
+        file.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"before");
+        let mut data = Vec::new();
+        let mut file = fs.open_at("/dir/kept", "snap").await.unwrap();
+        file.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"kept");
+        let err = fs.open_at("/created", "snap").await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        let err = fs.open_at("/changed", "missing").await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        fs.restore_snapshot("snap").await.unwrap();
+        let root = fs.path("/").unwrap();
+        assert_eq!(names(&root).await, ["changed", "dir", "removed"]);
+        assert_eq!(read_file(&fs.path("/changed").unwrap()).await, b"before");
+        assert_eq!(read_file(&fs.path("/removed").unwrap()).await, b"removed");
+        assert_eq!(read_file(&fs.path("/dir/kept").unwrap()).await, b"kept");
+
+        let err = fs.snapshot("snap").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    })
+}
+
+#[test]
+fn remove_dir() {
+    block_on(async {
+        let fs = VersionedFS::new(MemoryFS::new()).await.unwrap();
+        fs.path("/dir/sub").unwrap().create_dir().await.unwrap();
+        write_file(&fs.path("/dir/a").unwrap(), b"a").await;
+        write_file(&fs.path("/dir/sub/b").unwrap(), b"b").await;
+
+        fs.path("/dir").unwrap().rm().await.unwrap();
+        assert!(names(&fs.path("/").unwrap()).await.is_empty());
+
+        for (path, data) in [("/dir/a", b"a"), ("/dir/sub/b", b"b")] {
+            let versions = fs.versions(path).unwrap();
+            assert_eq!(versions.len(), 2);
+            fs.restore(&versions[1]).await.unwrap();
+            assert_eq!(read_file(&fs.path(path).unwrap()).await, data);
+        }
+    })
+}
+
+#[test]
+fn history_hidden() {
+    block_on(async {
+        let inner = MemoryFS::new();
+        let fs = VersionedFS::new(inner.clone()).await.unwrap();
+        let mut watcher = fs.path("/").unwrap().watch(true).await.unwrap();
+        write_file(&fs.path("/file").unwrap(), b"first").await;
+        write_file(&fs.path("/file").unwrap(), b"second").await;
+
+        assert_eq!(names(&fs.path("/").unwrap()).await, ["file"]);
+        assert_eq!(
+            names(&inner.path("/").unwrap()).await,
+            [".versions", "file"]
+        );
+
+        let mut events = Vec::new();
+        while let Some(event) = watcher.next().now_or_never().flatten() {
+            events.push(event.unwrap().path().virtual_path());
+        }
+        assert!(events.contains(&String::from("/file")));
+        assert!(events.iter().all(|path| path == "/file"), "{events:?}");
+
+        for path in ["/.versions", "/.versions/log", "/dir/../.versions/objects"] {
+            let err = fs.path(path).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        }
+        let root = fs.path("/").unwrap();
+        let err = root.resolve(".versions/log").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(
+            fs.path("/dir/.versions").unwrap().virtual_path(),
+            "/dir/.versions"
+        );
+    })
+}
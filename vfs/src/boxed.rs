@@ -1,40 +1,59 @@
-use core::pin::Pin;
-
+use core::{
+    any::Any,
+    fmt,
+    hash::{Hash, Hasher},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use alloc::{boxed::Box, string::String, vec::Vec};
 use dyn_clone::DynClone;
 use futures::{StreamExt, TryStreamExt};
 use futures_core::{future::BoxFuture, stream::BoxStream};
-use std::{boxed::Box, string::String};
+use pin_project_lite::pin_project;
 
-use crate::{Error, Metadata, OpenOptions, VFS, VFile, VPath};
+use crate::{
+    Capabilities, DirEntry, Error, FileTimes, FsStats, Metadata, OpenOptions, Permissions,
+    SeekFrom, SendVFS, SendVPath, VFS, VFile, VPath, VPathStr, WatchEvent,
+};
 
 pub type BoxVPath = Box<dyn VPathBox + Send + Sync>;
 
-pub type BoxVFile = Pin<Box<dyn VFile + Send + Sync>>;
+pub type BoxVFile = Pin<Box<dyn VFileBox + Send + Sync>>;
 
 pub type BoxVFS = Box<dyn VFSBox + Send + Sync>;
 
-pub fn path_box<T>(path: T) -> BoxVPath
-where
-    T: Clone + 'static,
-    T: VPath + Send + Sync,
-    T::File: Send + Sync + 'static,
-    T::Metadata: Send + 'static,
-    T::Open: Send + 'static,
-    T::CreateDir: Send + 'static,
-    T::Remove: Send + 'static,
-    T::ReadDir: Send + 'static,
-    T::ListDir: Send + 'static,
-{
+pub type BoxListDir = BoxStream<'static, Result<DirEntry<BoxVPath>, Error>>;
+
+pub type BoxWatcher = BoxStream<'static, Result<WatchEvent<BoxVPath>, Error>>;
+
+pub fn path_box<T: SendVPath>(path: T) -> BoxVPath {
     Box::new(BoxedVPath(path))
 }
 
+pub fn fs_box<T: SendVFS>(fs: T) -> BoxVFS {
+    Box::new(BoxedVFS(fs))
+}
+
 pub trait VFSBox: DynClone {
     fn path(&self, path: &str) -> Result<BoxVPath, Error>;
+
+    /// Query the total and free space of the filesystem
+    fn stats(&self) -> BoxFuture<'static, Result<FsStats, Error>>;
+
+    /// The capabilities of the filesystem
+    fn capabilities(&self) -> Capabilities;
 }
 
 dyn_clone::clone_trait_object!(VFSBox);
 
 pub trait VPathBox: DynClone {
+    /// The filesystem this path belongs to
+    fn fs(&self) -> BoxVFS;
+
+    /// The path relative to the root of its filesystem
+    fn virtual_path(&self) -> String;
+
     fn file_name(&self) -> Option<&str>;
 
     fn to_string(&self) -> String;
@@ -48,38 +67,95 @@ pub trait VPathBox: DynClone {
     /// Get the parent path
     fn parent(&self) -> Option<BoxVPath>;
 
+    /// Resolve `.`, `..` and symbolic links
+    fn canonicalize(&self) -> BoxFuture<'static, Result<BoxVPath, Error>>;
+
     /// Get the file's metadata
     fn metadata(&self) -> BoxFuture<'static, Result<Metadata, Error>>;
 
     fn open(&self, options: OpenOptions) -> BoxFuture<'static, Result<BoxVFile, Error>>;
-    fn read_dir(
-        &self,
-    ) -> BoxFuture<'static, Result<BoxStream<'static, Result<BoxVPath, Error>>, Error>>;
+    fn read_dir(&self) -> BoxFuture<'static, Result<BoxListDir, Error>>;
 
     /// Create a directory at the location by this path
     fn create_dir(&self) -> BoxFuture<'static, Result<(), Error>>;
 
     /// Remove a file or directory and all its contents
     fn rm(&self) -> BoxFuture<'static, Result<(), Error>>;
+
+    /// Change the permissions of the file or directory
+    fn set_permissions(&self, permissions: Permissions) -> BoxFuture<'static, Result<(), Error>>;
+
+    /// Change the access and modification times of the file or directory
+    fn set_times(&self, times: FileTimes) -> BoxFuture<'static, Result<(), Error>>;
+
+    /// Change the owner and group of the file or directory
+    fn set_owner(
+        &self,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> BoxFuture<'static, Result<(), Error>>;
+
+    /// Returns true if extended attributes can be used on this path
+    fn supports_xattr(&self) -> bool;
+
+    /// Get the value of an extended attribute
+    fn get_xattr(&self, name: &str) -> BoxFuture<'static, Result<Option<Vec<u8>>, Error>>;
+
+    /// Set the value of an extended attribute
+    fn set_xattr(&self, name: &str, value: &[u8]) -> BoxFuture<'static, Result<(), Error>>;
+
+    /// List the names of all extended attributes
+    fn list_xattr(&self) -> BoxFuture<'static, Result<Vec<String>, Error>>;
+
+    /// Remove an extended attribute
+    fn remove_xattr(&self, name: &str) -> BoxFuture<'static, Result<(), Error>>;
+
+    /// Watch the path for changes
+    fn watch(&self, recursive: bool) -> BoxFuture<'static, Result<BoxWatcher, Error>>;
+
+    /// The wrapped path, for downcasting to the concrete type
+    fn as_any(&self) -> &dyn Any;
+
+    /// Compare with the wrapped path of another boxed path
+    fn dyn_eq(&self, other: &dyn Any) -> bool;
+
+    fn dyn_hash(&self, state: &mut dyn Hasher);
+
+    fn dyn_fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
 }
 
 dyn_clone::clone_trait_object!(VPathBox);
 
+#[derive(Clone)]
+struct BoxedVFS<T>(T);
+
+impl<T: SendVFS> VFSBox for BoxedVFS<T> {
+    fn path(&self, path: &str) -> Result<BoxVPath, Error> {
+        self.0.path(path).map(path_box)
+    }
+
+    fn stats(&self) -> BoxFuture<'static, Result<FsStats, Error>> {
+        let future = self.0.stats();
+        Box::pin(future)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.0.capabilities()
+    }
+}
+
 #[derive(Clone)]
 struct BoxedVPath<T>(T);
 
-impl<T> VPathBox for BoxedVPath<T>
-where
-    T: Clone + 'static,
-    T: VPath + Send + Sync,
-    T::File: Send + Sync + 'static,
-    T::Metadata: Send + 'static,
-    T::Open: Send + 'static,
-    T::CreateDir: Send + 'static,
-    T::Remove: Send + 'static,
-    T::ReadDir: Send + 'static,
-    T::ListDir: Send + 'static,
-{
+impl<T: SendVPath> VPathBox for BoxedVPath<T> {
+    fn fs(&self) -> BoxVFS {
+        fs_box(self.0.fs())
+    }
+
+    fn virtual_path(&self) -> String {
+        self.0.virtual_path()
+    }
+
     fn file_name(&self) -> Option<&str> {
         self.0.file_name()
     }
@@ -102,6 +178,14 @@ where
         self.0.parent().map(|m| Box::new(BoxedVPath(m)) as BoxVPath)
     }
 
+    fn canonicalize(&self) -> BoxFuture<'static, Result<BoxVPath, Error>> {
+        let future = self.0.canonicalize();
+        Box::pin(async move {
+            let path = future.await?;
+            Ok(Box::new(BoxedVPath(path)) as BoxVPath)
+        })
+    }
+
     fn metadata(&self) -> BoxFuture<'static, Result<Metadata, Error>> {
         let future = self.0.metadata();
         Box::pin(future)
@@ -111,18 +195,16 @@ where
         let future = self.0.open(options);
         Box::pin(async move {
             let ret = future.await?;
-            Ok(Box::pin(ret) as BoxVFile)
+            Ok(file_box(ret))
         })
     }
 
-    fn read_dir(
-        &self,
-    ) -> BoxFuture<'static, Result<BoxStream<'static, Result<BoxVPath, Error>>, Error>> {
+    fn read_dir(&self) -> BoxFuture<'static, Result<BoxListDir, Error>> {
         let future = self.0.read_dir();
         Box::pin(async move {
             let read_dir = future.await?;
             let stream = read_dir
-                .map_ok(|m| Box::new(BoxedVPath(m)) as BoxVPath)
+                .map_ok(|m| m.map(|m| Box::new(BoxedVPath(m)) as BoxVPath))
                 .boxed();
             Ok(stream)
         })
@@ -137,13 +219,234 @@ where
         let future = self.0.rm();
         Box::pin(future)
     }
+
+    fn set_permissions(&self, permissions: Permissions) -> BoxFuture<'static, Result<(), Error>> {
+        let future = self.0.set_permissions(permissions);
+        Box::pin(future)
+    }
+
+    fn set_times(&self, times: FileTimes) -> BoxFuture<'static, Result<(), Error>> {
+        let future = self.0.set_times(times);
+        Box::pin(future)
+    }
+
+    fn set_owner(
+        &self,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> BoxFuture<'static, Result<(), Error>> {
+        let future = self.0.set_owner(uid, gid);
+        Box::pin(future)
+    }
+
+    fn supports_xattr(&self) -> bool {
+        self.0.supports_xattr()
+    }
+
+    fn get_xattr(&self, name: &str) -> BoxFuture<'static, Result<Option<Vec<u8>>, Error>> {
+        let future = self.0.get_xattr(name);
+        Box::pin(future)
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8]) -> BoxFuture<'static, Result<(), Error>> {
+        let future = self.0.set_xattr(name, value);
+        Box::pin(future)
+    }
+
+    fn list_xattr(&self) -> BoxFuture<'static, Result<Vec<String>, Error>> {
+        let future = self.0.list_xattr();
+        Box::pin(future)
+    }
+
+    fn remove_xattr(&self, name: &str) -> BoxFuture<'static, Result<(), Error>> {
+        let future = self.0.remove_xattr(name);
+        Box::pin(future)
+    }
+
+    fn watch(&self, recursive: bool) -> BoxFuture<'static, Result<BoxWatcher, Error>> {
+        let future = self.0.watch(recursive);
+        Box::pin(async move {
+            let watcher = future.await?;
+            let stream = watcher
+                .map_ok(|m| m.map(|m| Box::new(BoxedVPath(m)) as BoxVPath))
+                .boxed();
+            Ok(stream)
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        &self.0
+    }
+
+    fn dyn_eq(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<T>() == Some(&self.0)
+    }
+
+    fn dyn_hash(&self, mut state: &mut dyn Hasher) {
+        self.0.hash(&mut state)
+    }
+
+    fn dyn_fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl dyn VPathBox + Send + Sync {
+    /// Returns true if the boxed path is a `T`
+    pub fn is<T: VPath + 'static>(&self) -> bool {
+        self.as_any().is::<T>()
+    }
+
+    /// The boxed path, if it is a `T`
+    pub fn downcast_ref<T: VPath + 'static>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+}
+
+impl fmt::Debug for dyn VPathBox + Send + Sync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.dyn_fmt(f)
+    }
+}
+
+impl PartialEq for dyn VPathBox + Send + Sync {
+    fn eq(&self, other: &Self) -> bool {
+        self.dyn_eq(other.as_any())
+    }
+}
+
+impl Eq for dyn VPathBox + Send + Sync {}
+
+impl Hash for dyn VPathBox + Send + Sync {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.dyn_hash(state)
+    }
+}
+
+pub trait VFileBox: VFile {
+    /// The wrapped file, for downcasting to the concrete type
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// The name of the wrapped file type
+    fn type_name(&self) -> &'static str;
+}
+
+pin_project! {
+    struct BoxedVFile<T> {
+        #[pin]
+        file: T,
+    }
+}
+
+impl<T> VFile for BoxedVFile<T>
+where
+    T: VFile,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.project().file.poll_read(cx, buf)
+    }
+
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        self.project().file.poll_seek(cx, pos)
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        self.project().file.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.project().file.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.project().file.poll_close(cx)
+    }
+}
+
+impl<T> VFileBox for BoxedVFile<T>
+where
+    T: VFile + 'static,
+{
+    fn as_any(&self) -> &dyn Any {
+        &self.file
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        &mut self.file
+    }
+
+    fn type_name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+}
+
+/// Box a file, keeping its concrete type available for downcasting
+pub fn file_box<T>(file: T) -> BoxVFile
+where
+    T: VFile + Send + Sync + 'static,
+{
+    Box::pin(BoxedVFile { file })
+}
+
+impl dyn VFileBox + Send + Sync {
+    /// Returns true if the boxed file is a `T`
+    pub fn is<T: VFile + 'static>(&self) -> bool {
+        self.as_any().is::<T>()
+    }
+
+    /// The boxed file, if it is a `T`
+    pub fn downcast_ref<T: VFile + 'static>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+
+    /// The boxed file, if it is a `T`.
+    /// `T` must be [`Unpin`] as the boxed file is pinned
+    pub fn downcast_mut<T: VFile + Unpin + 'static>(self: Pin<&mut Self>) -> Option<&mut T> {
+        // SAFETY: a mutable reference is only handed out when the file is a `T`,
+        // which is `Unpin` and so has no pinning guarantees to uphold
+        unsafe { self.get_unchecked_mut() }
+            .as_any_mut()
+            .downcast_mut()
+    }
+}
+
+impl fmt::Debug for dyn VFileBox + Send + Sync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BoxVFile")
+            .field(&format_args!("{}", self.type_name()))
+            .finish()
+    }
 }
 
 impl VFS for BoxVFS {
     type Path = BoxVPath;
 
-    fn path(&self, path: &str) -> Result<Self::Path, Error> {
-        (**self).path(path)
+    type Stats = BoxFuture<'static, Result<FsStats, Error>>;
+
+    fn path(&self, path: impl AsRef<VPathStr>) -> Result<Self::Path, Error> {
+        (**self).path(path.as_ref().as_str())
+    }
+
+    fn stats(&self) -> Self::Stats {
+        (**self).stats()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        (**self).capabilities()
     }
 }
 
@@ -152,7 +455,7 @@ impl VPath for BoxVPath {
 
     type File = BoxVFile;
 
-    type ListDir = BoxStream<'static, Result<BoxVPath, Error>>;
+    type ListDir = BoxListDir;
 
     type Metadata = BoxFuture<'static, Result<Metadata, Error>>;
 
@@ -164,6 +467,34 @@ impl VPath for BoxVPath {
 
     type ReadDir = BoxFuture<'static, Result<Self::ListDir, Error>>;
 
+    type SetPermissions = BoxFuture<'static, Result<(), Error>>;
+
+    type SetTimes = BoxFuture<'static, Result<(), Error>>;
+
+    type SetOwner = BoxFuture<'static, Result<(), Error>>;
+
+    type GetXattr = BoxFuture<'static, Result<Option<Vec<u8>>, Error>>;
+
+    type SetXattr = BoxFuture<'static, Result<(), Error>>;
+
+    type ListXattr = BoxFuture<'static, Result<Vec<String>, Error>>;
+
+    type RemoveXattr = BoxFuture<'static, Result<(), Error>>;
+
+    type Watcher = BoxWatcher;
+
+    type Watch = BoxFuture<'static, Result<BoxWatcher, Error>>;
+
+    type Canonicalize = BoxFuture<'static, Result<BoxVPath, Error>>;
+
+    fn fs(&self) -> Self::FS {
+        (**self).fs()
+    }
+
+    fn virtual_path(&self) -> String {
+        (**self).virtual_path()
+    }
+
     fn file_name(&self) -> Option<&str> {
         (**self).file_name()
     }
@@ -184,6 +515,10 @@ impl VPath for BoxVPath {
         (**self).parent()
     }
 
+    fn canonicalize(&self) -> Self::Canonicalize {
+        (**self).canonicalize()
+    }
+
     fn metadata(&self) -> Self::Metadata {
         (**self).metadata()
     }
@@ -203,4 +538,40 @@ impl VPath for BoxVPath {
     fn rm(&self) -> Self::Remove {
         (**self).rm()
     }
+
+    fn set_permissions(&self, permissions: Permissions) -> Self::SetPermissions {
+        (**self).set_permissions(permissions)
+    }
+
+    fn set_times(&self, times: FileTimes) -> Self::SetTimes {
+        (**self).set_times(times)
+    }
+
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> Self::SetOwner {
+        (**self).set_owner(uid, gid)
+    }
+
+    fn supports_xattr(&self) -> bool {
+        (**self).supports_xattr()
+    }
+
+    fn get_xattr(&self, name: &str) -> Self::GetXattr {
+        (**self).get_xattr(name)
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8]) -> Self::SetXattr {
+        (**self).set_xattr(name, value)
+    }
+
+    fn list_xattr(&self) -> Self::ListXattr {
+        (**self).list_xattr()
+    }
+
+    fn remove_xattr(&self, name: &str) -> Self::RemoveXattr {
+        (**self).remove_xattr(name)
+    }
+
+    fn watch(&self, recursive: bool) -> Self::Watch {
+        (**self).watch(recursive)
+    }
 }
@@ -0,0 +1,763 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use futures_core::Stream;
+use pin_project_lite::pin_project;
+
+use crate::{Error, ErrorKind, SeekFrom, VFile};
+
+const DEFAULT_BUF_SIZE: usize = 8 * 1024;
+
+/// A file with an internal buffer, which can be read without copying
+pub trait BufVFile: VFile {
+    /// Get the buffered data, filling the buffer from the file when it is empty.
+    /// An empty slice is returned at the end of the file
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<&[u8], Error>>;
+
+    /// Mark `amt` bytes of the buffer as read
+    fn consume(self: Pin<&mut Self>, amt: usize);
+}
+
+impl<T> BufVFile for &mut T
+where
+    T: BufVFile + ?Sized + Unpin,
+{
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<&[u8], Error>> {
+        Pin::new(&mut **self.get_mut()).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut **self).consume(amt)
+    }
+}
+
+impl<T> BufVFile for Box<T>
+where
+    T: BufVFile + ?Sized + Unpin,
+{
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<&[u8], Error>> {
+        Pin::new(&mut **self.get_mut()).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut **self).consume(amt)
+    }
+}
+
+pin_project! {
+    /// Buffers the reads of a file, which makes many small reads efficient.
+    ///
+    /// Seeking discards the buffer, taking the buffered bytes into account for
+    /// [`SeekFrom::Current`]. Writes go to the file at the position seen by the reader.
+    #[derive(Debug)]
+    pub struct BufReader<R> {
+        #[pin]
+        inner: R,
+        buf: Box<[u8]>,
+        pos: usize,
+        cap: usize,
+    }
+}
+
+impl<R: VFile> BufReader<R> {
+    /// Create a reader with a buffer of 8 KiB
+    pub fn new(inner: R) -> BufReader<R> {
+        BufReader::with_capacity(DEFAULT_BUF_SIZE, inner)
+    }
+
+    /// Create a reader with a buffer of `capacity` bytes
+    pub fn with_capacity(capacity: usize, inner: R) -> BufReader<R> {
+        BufReader {
+            inner,
+            buf: vec![0; capacity].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+        }
+    }
+}
+
+impl<R> BufReader<R> {
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reading from the file directly leaves the buffer out of sync
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut R> {
+        self.project().inner
+    }
+
+    /// The buffered data is lost
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// The data which has been read into the buffer, but not yet consumed
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..self.cap]
+    }
+
+    fn discard_buffer(self: Pin<&mut Self>) {
+        let this = self.project();
+        *this.pos = 0;
+        *this.cap = 0;
+    }
+}
+
+impl<R: VFile> BufReader<R> {
+    /// Move the file back to the position seen by the reader and drop the buffer
+    fn poll_unread(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let remainder = (self.cap - self.pos) as i64;
+        if remainder > 0 {
+            ready!(
+                self.as_mut()
+                    .project()
+                    .inner
+                    .poll_seek(cx, SeekFrom::Current(-remainder))
+            )?;
+        }
+        self.discard_buffer();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: VFile> VFile for BufReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        // Large reads bypass the buffer when it is empty
+        if self.pos == self.cap && buf.len() >= self.buf.len() {
+            let ret = ready!(self.as_mut().get_pin_mut().poll_read(cx, buf));
+            self.discard_buffer();
+            return Poll::Ready(ret);
+        }
+
+        let data = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        self.consume(len);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        let pos = match pos {
+            // The file is ahead of the reader by the buffered bytes
+            SeekFrom::Current(offset) => {
+                let remainder = (self.cap - self.pos) as i64;
+                match offset.checked_sub(remainder) {
+                    Some(offset) => SeekFrom::Current(offset),
+                    None => {
+                        // Too far back to express at once, so rewind the buffer first
+                        ready!(self.as_mut().poll_unread(cx))?;
+                        SeekFrom::Current(offset)
+                    }
+                }
+            }
+            pos => pos,
+        };
+
+        let ret = ready!(self.as_mut().get_pin_mut().poll_seek(cx, pos))?;
+        self.discard_buffer();
+        Poll::Ready(Ok(ret))
+    }
+
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        ready!(self.as_mut().poll_unread(cx))?;
+        self.get_pin_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.get_pin_mut().poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.get_pin_mut().poll_close(cx)
+    }
+}
+
+impl<R: VFile> BufVFile for BufReader<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<&[u8], Error>> {
+        let this = self.project();
+        if *this.pos >= *this.cap {
+            *this.cap = ready!(this.inner.poll_read(cx, this.buf))?;
+            *this.pos = 0;
+        }
+        Poll::Ready(Ok(&this.buf[*this.pos..*this.cap]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.project();
+        *this.pos = (*this.pos + amt).min(*this.cap);
+    }
+}
+
+pin_project! {
+    /// Buffers the writes to a file, which makes many small writes efficient.
+    ///
+    /// The buffer is written to the file when it is full, and before the file is
+    /// read, seeked, flushed or closed. Buffered data is lost when the writer
+    /// is dropped without flushing or closing it.
+    #[derive(Debug)]
+    pub struct BufWriter<W> {
+        #[pin]
+        inner: W,
+        buf: Vec<u8>,
+        written: usize,
+    }
+}
+
+impl<W: VFile> BufWriter<W> {
+    /// Create a writer with a buffer of 8 KiB
+    pub fn new(inner: W) -> BufWriter<W> {
+        BufWriter::with_capacity(DEFAULT_BUF_SIZE, inner)
+    }
+
+    /// Create a writer with a buffer of `capacity` bytes
+    pub fn with_capacity(capacity: usize, inner: W) -> BufWriter<W> {
+        BufWriter {
+            inner,
+            buf: Vec::with_capacity(capacity),
+            written: 0,
+        }
+    }
+
+    /// Write the buffered data to the file
+    fn poll_flush_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let mut this = self.project();
+        let mut ret = Ok(());
+        while *this.written < this.buf.len() {
+            match ready!(
+                this.inner
+                    .as_mut()
+                    .poll_write(cx, &this.buf[*this.written..])
+            ) {
+                Ok(0) => {
+                    ret = Err(Error::new_const(
+                        ErrorKind::WriteZero,
+                        "failed to write the buffered data",
+                    ));
+                    break;
+                }
+                Ok(n) => *this.written += n,
+                Err(err) => {
+                    ret = Err(err);
+                    break;
+                }
+            }
+        }
+
+        // Keep what could not be written, so it can be retried
+        this.buf.drain(..*this.written);
+        *this.written = 0;
+        Poll::Ready(ret)
+    }
+}
+
+impl<W> BufWriter<W> {
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Writing to the file directly bypasses the buffered data
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut W> {
+        self.project().inner
+    }
+
+    /// The buffered data is lost
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// The data which has not been written to the file yet
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.written..]
+    }
+}
+
+impl<W: VFile> VFile for BufWriter<W> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        ready!(self.as_mut().poll_flush_buf(cx))?;
+        self.get_pin_mut().poll_read(cx, buf)
+    }
+
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<Result<u64, Error>> {
+        ready!(self.as_mut().poll_flush_buf(cx))?;
+        self.get_pin_mut().poll_seek(cx, pos)
+    }
+
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        if self.buf.len() + buf.len() > self.buf.capacity() {
+            ready!(self.as_mut().poll_flush_buf(cx))?;
+        }
+
+        // Writes which do not fit the buffer go straight to the file
+        if buf.len() >= self.buf.capacity() {
+            self.get_pin_mut().poll_write(cx, buf)
+        } else {
+            self.project().buf.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(self.as_mut().poll_flush_buf(cx))?;
+        self.get_pin_mut().poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(self.as_mut().poll_flush_buf(cx))?;
+        self.get_pin_mut().poll_close(cx)
+    }
+}
+
+pub trait BufVFileExt: BufVFile {
+    /// Read until `byte` or the end of the file, appending the data including
+    /// `byte` to `buf`. Returns the number of bytes read
+    fn read_until<'a>(&'a mut self, byte: u8, buf: &'a mut Vec<u8>) -> ReadUntil<'a, Self>
+    where
+        Self: Sized + Unpin,
+    {
+        ReadUntil {
+            reader: self,
+            byte,
+            buf,
+            read: 0,
+        }
+    }
+
+    /// Read a line including its `\n` and append it to `buf`. Returns the number of
+    /// bytes read, which is 0 at the end of the file. Fails with
+    /// [`ErrorKind::InvalidData`] and leaves `buf` untouched when the line is not valid UTF-8
+    fn read_line<'a>(&'a mut self, buf: &'a mut String) -> ReadLine<'a, Self>
+    where
+        Self: Sized + Unpin,
+    {
+        ReadLine {
+            reader: self,
+            buf,
+            bytes: Vec::new(),
+        }
+    }
+
+    /// A stream of the lines of the file, without their `\n` or `\r\n`
+    fn lines(self) -> Lines<Self>
+    where
+        Self: Sized,
+    {
+        Lines {
+            reader: self,
+            bytes: Vec::new(),
+        }
+    }
+}
+
+impl<T> BufVFileExt for T where T: BufVFile {}
+
+/// Move bytes up to and including `byte` from the buffer of `reader` to `buf`.
+/// Returns the number of bytes moved once `byte` or the end of the file is reached
+fn poll_read_until<R: BufVFile + ?Sized>(
+    mut reader: Pin<&mut R>,
+    cx: &mut Context<'_>,
+    byte: u8,
+    buf: &mut Vec<u8>,
+    read: &mut usize,
+) -> Poll<Result<usize, Error>> {
+    loop {
+        let (done, used) = {
+            let available = ready!(reader.as_mut().poll_fill_buf(cx))?;
+            match available.iter().position(|b| *b == byte) {
+                Some(idx) => {
+                    buf.extend_from_slice(&available[..=idx]);
+                    (true, idx + 1)
+                }
+                None => {
+                    buf.extend_from_slice(available);
+                    (available.is_empty(), available.len())
+                }
+            }
+        };
+        reader.as_mut().consume(used);
+        *read += used;
+        if done {
+            return Poll::Ready(Ok(core::mem::take(read)));
+        }
+    }
+}
+
+fn invalid_utf8() -> Error {
+    Error::new_const(ErrorKind::InvalidData, "stream did not contain valid UTF-8")
+}
+
+/// Future for the [`BufVFileExt::read_until`] method.
+#[derive(Debug)]
+pub struct ReadUntil<'a, R: ?Sized> {
+    reader: &'a mut R,
+    byte: u8,
+    buf: &'a mut Vec<u8>,
+    read: usize,
+}
+
+impl<R: BufVFile + ?Sized + Unpin> Future for ReadUntil<'_, R> {
+    type Output = Result<usize, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        poll_read_until(
+            Pin::new(&mut *this.reader),
+            cx,
+            this.byte,
+            this.buf,
+            &mut this.read,
+        )
+    }
+}
+
+/// Future for the [`BufVFileExt::read_line`] method.
+#[derive(Debug)]
+pub struct ReadLine<'a, R: ?Sized> {
+    reader: &'a mut R,
+    buf: &'a mut String,
+    bytes: Vec<u8>,
+}
+
+impl<R: BufVFile + ?Sized + Unpin> Future for ReadLine<'_, R> {
+    type Output = Result<usize, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut read = 0;
+        ready!(poll_read_until(
+            Pin::new(&mut *this.reader),
+            cx,
+            b'\n',
+            &mut this.bytes,
+            &mut read
+        ))?;
+
+        let bytes = core::mem::take(&mut this.bytes);
+        let line = core::str::from_utf8(&bytes).map_err(|_| invalid_utf8())?;
+        this.buf.push_str(line);
+        Poll::Ready(Ok(bytes.len()))
+    }
+}
+
+pin_project! {
+    /// Stream for the [`BufVFileExt::lines`] method.
+    #[derive(Debug)]
+    pub struct Lines<R> {
+        #[pin]
+        reader: R,
+        bytes: Vec<u8>,
+    }
+}
+
+impl<R: BufVFile> Stream for Lines<R> {
+    type Item = Result<String, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let mut read = 0;
+        ready!(poll_read_until(
+            this.reader,
+            cx,
+            b'\n',
+            this.bytes,
+            &mut read
+        ))?;
+
+        let mut bytes = core::mem::take(this.bytes);
+        if bytes.is_empty() {
+            return Poll::Ready(None);
+        }
+
+        if bytes.last() == Some(&b'\n') {
+            bytes.pop();
+            if bytes.last() == Some(&b'\r') {
+                bytes.pop();
+            }
+        }
+
+        Poll::Ready(Some(String::from_utf8(bytes).map_err(|_| invalid_utf8())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VFileExt;
+    use futures::{StreamExt, executor::block_on};
+
+    /// A file in memory, which reads at most `max_read` bytes at once
+    #[derive(Debug, Default)]
+    struct Cursor {
+        data: Vec<u8>,
+        pos: usize,
+        max_read: usize,
+        reads: usize,
+        writes: usize,
+    }
+
+    impl Cursor {
+        fn new(data: &[u8]) -> Cursor {
+            Cursor {
+                data: data.to_vec(),
+                max_read: usize::MAX,
+                ..Cursor::default()
+            }
+        }
+    }
+
+    impl VFile for Cursor {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<Result<usize, Error>> {
+            let this = &mut *self;
+            this.reads += 1;
+            let data = this.data.get(this.pos..).unwrap_or_default();
+            let len = data.len().min(buf.len()).min(this.max_read);
+            buf[..len].copy_from_slice(&data[..len]);
+            this.pos += len;
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_seek(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            pos: SeekFrom,
+        ) -> Poll<Result<u64, Error>> {
+            let target = match pos {
+                SeekFrom::Start(pos) => Some(pos),
+                SeekFrom::Current(offset) => (self.pos as u64).checked_add_signed(offset),
+                SeekFrom::End(offset) => (self.data.len() as u64).checked_add_signed(offset),
+            };
+            let target = target.ok_or(ErrorKind::InvalidInput)?;
+            self.pos = target as usize;
+            Poll::Ready(Ok(target))
+        }
+
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize, Error>> {
+            let this = &mut *self;
+            this.writes += 1;
+            let end = this.pos + buf.len();
+            if this.data.len() < end {
+                this.data.resize(end, 0);
+            }
+            this.data[this.pos..end].copy_from_slice(buf);
+            this.pos = end;
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn lines(data: &[u8], capacity: usize) -> Vec<String> {
+        let reader = BufReader::with_capacity(capacity, Cursor::new(data));
+        block_on(reader.lines().map(Result::unwrap).collect())
+    }
+
+    #[test]
+    fn lines_across_fills() {
+        let data = b"first line\nsecond\r\n\nlast without newline";
+        for capacity in [1, 3, 7, 64] {
+            assert_eq!(
+                lines(data, capacity),
+                ["first line", "second", "", "last without newline"]
+            );
+        }
+
+        assert_eq!(lines(b"one\ntwo\n", 3), ["one", "two"]);
+        assert_eq!(lines(b"\r\n", 1), [""]);
+        assert!(lines(b"", 4).is_empty());
+
+        let reader = BufReader::with_capacity(4, Cursor::new(b"ok\n\xff\xfe\nafter"));
+        let lines: Vec<_> = block_on(reader.lines().collect());
+        assert_eq!(lines[0].as_ref().unwrap(), "ok");
+        assert_eq!(
+            lines[1].as_ref().unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(lines[2].as_ref().unwrap(), "after");
+    }
+
+    #[test]
+    fn read_until_and_read_line() {
+        block_on(async {
+            let mut file = Cursor::new(b"a,bc,,defgh");
+            file.max_read = 2;
+            let mut reader = BufReader::with_capacity(3, file);
+
+            let mut buf = Vec::new();
+            assert_eq!(reader.read_until(b',', &mut buf).await.unwrap(), 2);
+            assert_eq!(reader.read_until(b',', &mut buf).await.unwrap(), 3);
+            assert_eq!(reader.read_until(b',', &mut buf).await.unwrap(), 1);
+            assert_eq!(buf, b"a,bc,,");
+
+            // The last part has no delimiter
+            buf.clear();
+            assert_eq!(reader.read_until(b',', &mut buf).await.unwrap(), 5);
+            assert_eq!(buf, b"defgh");
+            assert_eq!(reader.read_until(b',', &mut buf).await.unwrap(), 0);
+
+            let mut reader = BufReader::with_capacity(4, Cursor::new(b"line one\n\xffbad\nend"));
+            let mut line = String::new();
+            assert_eq!(reader.read_line(&mut line).await.unwrap(), 9);
+            assert_eq!(line, "line one\n");
+
+            let err = reader.read_line(&mut line).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert_eq!(line, "line one\n");
+
+            assert_eq!(reader.read_line(&mut line).await.unwrap(), 3);
+            assert_eq!(line, "line one\nend");
+            assert_eq!(reader.read_line(&mut line).await.unwrap(), 0);
+        })
+    }
+
+    #[test]
+    fn reader_seeks_while_buffered() {
+        block_on(async {
+            let data: Vec<u8> = (0..100).collect();
+            let mut reader = BufReader::with_capacity(16, Cursor::new(&data));
+            let mut buf = [0; 4];
+
+            reader.read_exact(&mut buf).await.unwrap();
+            assert_eq!(reader.buffer().len(), 12);
+
+            // Relative seeks are relative to the position of the reader
+            assert_eq!(reader.seek(SeekFrom::Current(6)).await.unwrap(), 10);
+            assert!(reader.buffer().is_empty());
+            reader.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, [10, 11, 12, 13]);
+
+            assert_eq!(reader.seek(SeekFrom::Current(-8)).await.unwrap(), 6);
+            reader.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, [6, 7, 8, 9]);
+
+            // Too far back to subtract the buffered bytes from
+            assert_eq!(reader.seek(SeekFrom::Current(i64::MIN)).await.ok(), None);
+            assert_eq!(reader.seek(SeekFrom::Current(0)).await.unwrap(), 10);
+
+            assert_eq!(reader.seek(SeekFrom::End(-2)).await.unwrap(), 98);
+            let mut rest = Vec::new();
+            reader.read_to_end(&mut rest).await.unwrap();
+            assert_eq!(rest, [98, 99]);
+
+            // Writes land at the position of the reader, not of the file
+            reader.seek(SeekFrom::Start(20)).await.unwrap();
+            reader.read_exact(&mut buf).await.unwrap();
+            reader.write_all(b"xy").await.unwrap();
+            reader.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, [26, 27, 28, 29]);
+            assert_eq!(&reader.get_ref().data[22..26], b"\x16\x17xy");
+        })
+    }
+
+    #[test]
+    fn large_reads_bypass_the_reader() {
+        block_on(async {
+            let data: Vec<u8> = (0..64).collect();
+            let mut reader = BufReader::with_capacity(8, Cursor::new(&data));
+            let mut buf = [0; 16];
+            reader.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf[..], data[..16]);
+            assert!(reader.buffer().is_empty());
+            assert_eq!(reader.get_ref().reads, 1);
+
+            // A partly consumed buffer is used up first
+            let mut small = [0; 2];
+            reader.read_exact(&mut small).await.unwrap();
+            reader.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf[..], data[18..34]);
+        })
+    }
+
+    #[test]
+    fn writer_buffers_small_writes() {
+        block_on(async {
+            let mut writer = BufWriter::with_capacity(8, Cursor::new(b""));
+            writer.write_all(b"abc").await.unwrap();
+            writer.write_all(b"def").await.unwrap();
+            assert_eq!(writer.buffer(), b"abcdef");
+            assert_eq!(writer.get_ref().writes, 0);
+
+            // The buffer is written before a write which does not fit
+            writer.write_all(b"ghi").await.unwrap();
+            assert_eq!(writer.get_ref().data, b"abcdef");
+            assert_eq!(writer.buffer(), b"ghi");
+
+            // Large writes go straight to the file, after the buffer
+            writer.write_all(&[b'x'; 10]).await.unwrap();
+            assert!(writer.buffer().is_empty());
+            assert_eq!(writer.get_ref().data, b"abcdefghixxxxxxxxxx");
+
+            writer.write_all(b"end").await.unwrap();
+            writer.close().await.unwrap();
+            assert!(writer.buffer().is_empty());
+            assert_eq!(writer.get_ref().data.len(), 22);
+        })
+    }
+
+    #[test]
+    fn writer_flushes_before_seeking_and_reading() {
+        block_on(async {
+            let mut writer = BufWriter::with_capacity(16, Cursor::new(b"0123456789"));
+            writer.write_all(b"ab").await.unwrap();
+            assert_eq!(writer.seek(SeekFrom::Start(6)).await.unwrap(), 6);
+            assert_eq!(writer.get_ref().data, b"ab23456789");
+
+            writer.write_all(b"cd").await.unwrap();
+            let mut rest = Vec::new();
+            writer.read_to_end(&mut rest).await.unwrap();
+            assert_eq!(rest, b"89");
+            assert_eq!(writer.into_inner().data, b"ab2345cd89");
+
+            let mut writer = BufWriter::new(Cursor::new(b""));
+            writer.write_all(b"unflushed").await.unwrap();
+            assert!(writer.into_inner().data.is_empty());
+        })
+    }
+}
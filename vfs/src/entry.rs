@@ -0,0 +1,101 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use pin_project_lite::pin_project;
+
+use crate::{Error, FileType, Metadata, VPath};
+
+/// An entry yielded by [`VPath::read_dir`].
+///
+/// The file type is known up front, while the full metadata is only
+/// fetched from the backend when asked for, unless the backend already had it at hand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry<P> {
+    path: P,
+    kind: FileType,
+    metadata: Option<Metadata>,
+}
+
+impl<P> DirEntry<P> {
+    pub fn new(path: P, kind: FileType) -> DirEntry<P> {
+        DirEntry {
+            path,
+            kind,
+            metadata: None,
+        }
+    }
+
+    /// Create an entry for a backend which already knows the full metadata
+    pub fn with_metadata(path: P, metadata: Metadata) -> DirEntry<P> {
+        DirEntry {
+            path,
+            kind: metadata.kind,
+            metadata: Some(metadata),
+        }
+    }
+
+    pub fn path(&self) -> &P {
+        &self.path
+    }
+
+    pub fn into_path(self) -> P {
+        self.path
+    }
+
+    /// The file type of the entry. Symlinks are not followed
+    pub fn file_type(&self) -> FileType {
+        self.kind
+    }
+
+    /// Convert the path of the entry, keeping the file type and any cached metadata
+    pub fn map<T>(self, func: impl FnOnce(P) -> T) -> DirEntry<T> {
+        DirEntry {
+            path: func(self.path),
+            kind: self.kind,
+            metadata: self.metadata,
+        }
+    }
+}
+
+impl<P: VPath> DirEntry<P> {
+    /// The file name of the entry
+    pub fn name(&self) -> &str {
+        self.path.file_name().unwrap_or_default()
+    }
+
+    /// Get the entry's metadata, querying the backend if it is not cached
+    pub fn metadata(&self) -> EntryMetadata<P> {
+        match self.metadata {
+            Some(metadata) => EntryMetadata::Ready {
+                metadata: Some(metadata),
+            },
+            None => EntryMetadata::Pending {
+                future: self.path.metadata(),
+            },
+        }
+    }
+}
+
+pin_project! {
+    /// Future for the [`DirEntry::metadata`] method.
+    #[project = EntryMetadataProj]
+    pub enum EntryMetadata<P: VPath> {
+        Ready { metadata: Option<Metadata> },
+        Pending { #[pin] future: P::Metadata },
+    }
+}
+
+impl<P: VPath> Future for EntryMetadata<P> {
+    type Output = Result<Metadata, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            EntryMetadataProj::Ready { metadata } => Poll::Ready(Ok(metadata
+                .take()
+                .expect("EntryMetadata polled after completion"))),
+            EntryMetadataProj::Pending { future } => future.poll(cx),
+        }
+    }
+}
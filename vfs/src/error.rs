@@ -1,13 +1,15 @@
 use core::fmt;
 
-#[cfg(all(feature = "alloc", not(feature = "std")))]
-use alloc::string::{String, ToString};
-#[cfg(all(feature = "std"))]
-use std::string::{String, ToString};
+#[cfg(feature = "alloc")]
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+};
 
 pub type Result<T> = core::result::Result<T, Error>;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum ErrorKind {
     Interrupted,
     WriteZero,
@@ -30,6 +32,24 @@ pub enum ErrorKind {
     Unsupported,
     UnexpectedEof,
     OutOfMemory,
+    HostUnreachable,
+    NetworkUnreachable,
+    NetworkDown,
+    IsADirectory,
+    DirectoryNotEmpty,
+    ReadOnlyFilesystem,
+    FilesystemLoop,
+    StaleNetworkFileHandle,
+    StorageFull,
+    NotSeekable,
+    QuotaExceeded,
+    FileTooLarge,
+    ResourceBusy,
+    ExecutableFileBusy,
+    Deadlock,
+    CrossesDevices,
+    TooManyLinks,
+    ArgumentListTooLong,
     Other,
 }
 
@@ -41,39 +61,39 @@ impl ErrorKind {
             AddrInUse => "address in use",
             AddrNotAvailable => "address not available",
             AlreadyExists => "entity already exists",
-            // ArgumentListTooLong => "argument list too long",
+            ArgumentListTooLong => "argument list too long",
             BrokenPipe => "broken pipe",
             ConnectionAborted => "connection aborted",
             ConnectionRefused => "connection refused",
             ConnectionReset => "connection reset",
-            // CrossesDevices => "cross-device link or rename",
-            // Deadlock => "deadlock",
-            // DirectoryNotEmpty => "directory not empty",
-            // ExecutableFileBusy => "executable file busy",
-            // FileTooLarge => "file too large",
-            // FilesystemLoop => "filesystem loop or indirection limit (e.g. symlink loop)",
-            // FilesystemQuotaExceeded => "filesystem quota exceeded",
-            // HostUnreachable => "host unreachable",
+            CrossesDevices => "cross-device link or rename",
+            Deadlock => "deadlock",
+            DirectoryNotEmpty => "directory not empty",
+            ExecutableFileBusy => "executable file busy",
+            FileTooLarge => "file too large",
+            FilesystemLoop => "filesystem loop or indirection limit (e.g. symlink loop)",
+            HostUnreachable => "host unreachable",
             Interrupted => "operation interrupted",
             InvalidData => "invalid data",
             InvalidFilename => "invalid filename",
             InvalidInput => "invalid input parameter",
-            // IsADirectory => "is a directory",
-            // NetworkDown => "network down",
-            // NetworkUnreachable => "network unreachable",
+            IsADirectory => "is a directory",
+            NetworkDown => "network down",
+            NetworkUnreachable => "network unreachable",
             NotADirectory => "not a directory",
             NotConnected => "not connected",
             NotFound => "entity not found",
-            // NotSeekable => "seek on unseekable file",
+            NotSeekable => "seek on unseekable file",
             Other => "other error",
             OutOfMemory => "out of memory",
             PermissionDenied => "permission denied",
-            // ReadOnlyFilesystem => "read-only filesystem or storage medium",
-            // ResourceBusy => "resource busy",
-            // StaleNetworkFileHandle => "stale network file handle",
-            // StorageFull => "no storage space",
+            QuotaExceeded => "filesystem quota exceeded",
+            ReadOnlyFilesystem => "read-only filesystem or storage medium",
+            ResourceBusy => "resource busy",
+            StaleNetworkFileHandle => "stale network file handle",
+            StorageFull => "no storage space",
             TimedOut => "timed out",
-            // TooManyLinks => "too many links",
+            TooManyLinks => "too many links",
             // Uncategorized => "uncategorized error",
             UnexpectedEof => "unexpected end of file",
             Unsupported => "unsupported",
@@ -89,61 +109,206 @@ impl fmt::Display for ErrorKind {
     }
 }
 
+/// The filesystem operation during which an error occurred
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Operation {
+    Metadata,
+    Open,
+    ReadDir,
+    CreateDir,
+    Remove,
+    SetPermissions,
+    SetTimes,
+    SetOwner,
+    GetXattr,
+    SetXattr,
+    ListXattr,
+    RemoveXattr,
+    Watch,
+    Canonicalize,
+    Stats,
+    Read,
+    Write,
+    Seek,
+    Flush,
+    Close,
+}
+
+impl Operation {
+    fn as_str(&self) -> &'static str {
+        use Operation::*;
+        match *self {
+            Metadata => "metadata",
+            Open => "open",
+            ReadDir => "read_dir",
+            CreateDir => "create_dir",
+            Remove => "rm",
+            SetPermissions => "set_permissions",
+            SetTimes => "set_times",
+            SetOwner => "set_owner",
+            GetXattr => "get_xattr",
+            SetXattr => "set_xattr",
+            ListXattr => "list_xattr",
+            RemoveXattr => "remove_xattr",
+            Watch => "watch",
+            Canonicalize => "canonicalize",
+            Stats => "stats",
+            Read => "read",
+            Write => "write",
+            Seek => "seek",
+            Flush => "flush",
+            Close => "close",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(feature = "alloc")]
+type Source = Box<dyn core::error::Error + Send + Sync>;
+
 #[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
     message: Option<Message>,
+    operation: Option<Operation>,
+    #[cfg(feature = "alloc")]
+    path: Option<String>,
+    #[cfg(feature = "alloc")]
+    source: Option<Source>,
 }
 
 #[derive(Debug)]
 enum Message {
     Static(&'static str),
-    #[cfg(any(feature = "alloc", feature = "std"))]
+    #[cfg(feature = "alloc")]
     Owned(String),
 }
 
 impl Message {
     fn as_str(&self) -> &str {
         match self {
-            #[cfg(any(feature = "alloc", feature = "std"))]
+            #[cfg(feature = "alloc")]
             Message::Owned(s) => s,
-            Message::Static(s) => &s,
+            Message::Static(s) => s,
         }
     }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(operation) = self.operation {
+            write!(f, "{operation}")?;
+            if let Some(path) = self.path() {
+                write!(f, " '{path}'")?;
+            }
+            write!(f, ": ")?;
+        } else if let Some(path) = self.path() {
+            write!(f, "'{path}': ")?;
+        }
+
         write!(f, "{}", self.kind)?;
         if let Some(msg) = &self.message {
             write!(f, ": {}", msg.as_str())?;
         }
 
+        #[cfg(feature = "std")]
+        if let Some(code) = self.raw_os_error() {
+            write!(f, " (os error {code})")?;
+        }
+
         Ok(())
     }
 }
 
-impl core::error::Error for Error {}
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        #[cfg(feature = "alloc")]
+        if let Some(source) = &self.source {
+            return Some(&**source);
+        }
+
+        None
+    }
+}
 
 impl Error {
     pub const fn new_const(kind: ErrorKind, message: &'static str) -> Error {
         Error {
             kind,
             message: Some(Message::Static(message)),
+            operation: None,
+            #[cfg(feature = "alloc")]
+            path: None,
+            #[cfg(feature = "alloc")]
+            source: None,
         }
     }
 
-    #[cfg(any(feature = "alloc", feature = "std"))]
+    #[cfg(feature = "alloc")]
     pub fn new(kind: ErrorKind, message: impl ToString) -> Error {
         Error {
-            kind,
             message: Some(Message::Owned(message.to_string())),
+            ..Error::from(kind)
         }
     }
 
     pub fn kind(&self) -> ErrorKind {
         self.kind
     }
+
+    /// The operation which failed, if known
+    pub fn operation(&self) -> Option<Operation> {
+        self.operation
+    }
+
+    /// The path the failed operation was performed on, if known
+    pub fn path(&self) -> Option<&str> {
+        #[cfg(feature = "alloc")]
+        return self.path.as_deref();
+        #[cfg(not(feature = "alloc"))]
+        return None;
+    }
+
+    /// The underlying OS error code, if this error originated from the OS
+    #[cfg(feature = "std")]
+    pub fn raw_os_error(&self) -> Option<i32> {
+        self.source
+            .as_ref()?
+            .downcast_ref::<std::io::Error>()?
+            .raw_os_error()
+    }
+
+    /// Record the operation which failed
+    pub fn with_operation(mut self, operation: Operation) -> Error {
+        self.operation = Some(operation);
+        self
+    }
+
+    /// Record the path the failed operation was performed on
+    #[cfg(feature = "alloc")]
+    pub fn with_path(mut self, path: impl ToString) -> Error {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    /// Record the underlying cause of the error
+    #[cfg(feature = "alloc")]
+    pub fn with_source(mut self, source: impl Into<Source>) -> Error {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Consume the error, returning its underlying cause
+    #[cfg(feature = "alloc")]
+    pub fn into_source(self) -> Option<Source> {
+        self.source
+    }
 }
 
 impl From<ErrorKind> for Error {
@@ -151,6 +316,11 @@ impl From<ErrorKind> for Error {
         Error {
             kind: value,
             message: None,
+            operation: None,
+            #[cfg(feature = "alloc")]
+            path: None,
+            #[cfg(feature = "alloc")]
+            source: None,
         }
     }
 }
@@ -158,6 +328,12 @@ impl From<ErrorKind> for Error {
 #[cfg(feature = "std")]
 impl From<std::io::Error> for Error {
     fn from(value: std::io::Error) -> Self {
+        // An error which was converted into an io error before, so give back the original
+        if value.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let inner = value.into_inner().expect("inner error");
+            return *inner.downcast::<Error>().expect("vfs error");
+        }
+
         let kind = match value.kind() {
             std::io::ErrorKind::NotFound => ErrorKind::NotFound,
             std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
@@ -180,17 +356,97 @@ impl From<std::io::Error> for Error {
             std::io::ErrorKind::OutOfMemory => ErrorKind::OutOfMemory,
             std::io::ErrorKind::Other => ErrorKind::Other,
             std::io::ErrorKind::NotADirectory => ErrorKind::NotADirectory,
+            std::io::ErrorKind::InvalidFilename => ErrorKind::InvalidFilename,
+            std::io::ErrorKind::HostUnreachable => ErrorKind::HostUnreachable,
+            std::io::ErrorKind::NetworkUnreachable => ErrorKind::NetworkUnreachable,
+            std::io::ErrorKind::NetworkDown => ErrorKind::NetworkDown,
+            std::io::ErrorKind::IsADirectory => ErrorKind::IsADirectory,
+            std::io::ErrorKind::DirectoryNotEmpty => ErrorKind::DirectoryNotEmpty,
+            std::io::ErrorKind::ReadOnlyFilesystem => ErrorKind::ReadOnlyFilesystem,
+            std::io::ErrorKind::StaleNetworkFileHandle => ErrorKind::StaleNetworkFileHandle,
+            std::io::ErrorKind::StorageFull => ErrorKind::StorageFull,
+            std::io::ErrorKind::NotSeekable => ErrorKind::NotSeekable,
+            std::io::ErrorKind::QuotaExceeded => ErrorKind::QuotaExceeded,
+            std::io::ErrorKind::FileTooLarge => ErrorKind::FileTooLarge,
+            std::io::ErrorKind::ResourceBusy => ErrorKind::ResourceBusy,
+            std::io::ErrorKind::ExecutableFileBusy => ErrorKind::ExecutableFileBusy,
+            std::io::ErrorKind::Deadlock => ErrorKind::Deadlock,
+            std::io::ErrorKind::CrossesDevices => ErrorKind::CrossesDevices,
+            std::io::ErrorKind::TooManyLinks => ErrorKind::TooManyLinks,
+            std::io::ErrorKind::ArgumentListTooLong => ErrorKind::ArgumentListTooLong,
             e => {
-                return Error {
-                    kind: ErrorKind::Other,
-                    message: Some(Message::Owned(e.to_string())),
-                };
+                return Error::new(ErrorKind::Other, e).with_source(value);
             }
         };
 
-        Error {
-            kind,
-            message: None,
+        Error::from(kind).with_source(value)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<ErrorKind> for std::io::ErrorKind {
+    fn from(value: ErrorKind) -> Self {
+        match value {
+            ErrorKind::NotFound => std::io::ErrorKind::NotFound,
+            ErrorKind::PermissionDenied => std::io::ErrorKind::PermissionDenied,
+            ErrorKind::ConnectionRefused => std::io::ErrorKind::ConnectionRefused,
+            ErrorKind::ConnectionReset => std::io::ErrorKind::ConnectionReset,
+            ErrorKind::ConnectionAborted => std::io::ErrorKind::ConnectionAborted,
+            ErrorKind::NotConnected => std::io::ErrorKind::NotConnected,
+            ErrorKind::AddrInUse => std::io::ErrorKind::AddrInUse,
+            ErrorKind::AddrNotAvailable => std::io::ErrorKind::AddrNotAvailable,
+            ErrorKind::BrokenPipe => std::io::ErrorKind::BrokenPipe,
+            ErrorKind::AlreadyExists => std::io::ErrorKind::AlreadyExists,
+            ErrorKind::WouldBlock => std::io::ErrorKind::WouldBlock,
+            ErrorKind::InvalidInput => std::io::ErrorKind::InvalidInput,
+            ErrorKind::InvalidFilename => std::io::ErrorKind::InvalidFilename,
+            ErrorKind::InvalidData => std::io::ErrorKind::InvalidData,
+            ErrorKind::TimedOut => std::io::ErrorKind::TimedOut,
+            ErrorKind::WriteZero => std::io::ErrorKind::WriteZero,
+            ErrorKind::Interrupted => std::io::ErrorKind::Interrupted,
+            ErrorKind::Unsupported => std::io::ErrorKind::Unsupported,
+            ErrorKind::UnexpectedEof => std::io::ErrorKind::UnexpectedEof,
+            ErrorKind::OutOfMemory => std::io::ErrorKind::OutOfMemory,
+            ErrorKind::Other => std::io::ErrorKind::Other,
+            ErrorKind::NotADirectory => std::io::ErrorKind::NotADirectory,
+            ErrorKind::HostUnreachable => std::io::ErrorKind::HostUnreachable,
+            ErrorKind::NetworkUnreachable => std::io::ErrorKind::NetworkUnreachable,
+            ErrorKind::NetworkDown => std::io::ErrorKind::NetworkDown,
+            ErrorKind::IsADirectory => std::io::ErrorKind::IsADirectory,
+            ErrorKind::DirectoryNotEmpty => std::io::ErrorKind::DirectoryNotEmpty,
+            ErrorKind::ReadOnlyFilesystem => std::io::ErrorKind::ReadOnlyFilesystem,
+            ErrorKind::StaleNetworkFileHandle => std::io::ErrorKind::StaleNetworkFileHandle,
+            ErrorKind::StorageFull => std::io::ErrorKind::StorageFull,
+            ErrorKind::NotSeekable => std::io::ErrorKind::NotSeekable,
+            ErrorKind::QuotaExceeded => std::io::ErrorKind::QuotaExceeded,
+            ErrorKind::FileTooLarge => std::io::ErrorKind::FileTooLarge,
+            ErrorKind::ResourceBusy => std::io::ErrorKind::ResourceBusy,
+            ErrorKind::ExecutableFileBusy => std::io::ErrorKind::ExecutableFileBusy,
+            ErrorKind::Deadlock => std::io::ErrorKind::Deadlock,
+            ErrorKind::CrossesDevices => std::io::ErrorKind::CrossesDevices,
+            ErrorKind::TooManyLinks => std::io::ErrorKind::TooManyLinks,
+            ErrorKind::ArgumentListTooLong => std::io::ErrorKind::ArgumentListTooLong,
+            // Not yet stable in std
+            ErrorKind::FilesystemLoop => std::io::ErrorKind::Other,
         }
     }
 }
+
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    fn from(value: Error) -> Self {
+        // Without added context the original io error can be handed back as is
+        let plain = value.message.is_none() && value.operation.is_none() && value.path.is_none();
+        if plain
+            && value
+                .source
+                .as_ref()
+                .is_some_and(|s| s.is::<std::io::Error>())
+        {
+            let source = value.source.expect("source");
+            return *source.downcast::<std::io::Error>().expect("io error");
+        }
+
+        std::io::Error::new(value.kind.into(), value)
+    }
+}
@@ -1,33 +1,40 @@
-#[cfg(all(feature = "alloc", not(feature = "std")))]
+#[cfg(feature = "alloc")]
 use alloc::vec::Vec;
 use core::{
     pin::Pin,
     task::{Context, Poll, ready},
 };
 use pin_project_lite::pin_project;
-#[cfg(all(feature = "std", not(feature = "alloc")))]
-use std::vec::Vec;
 
-use crate::{Error, ErrorKind, SeekFrom, VFile, VPath};
+use crate::{Error, ErrorKind, SeekFrom, VFile};
+#[cfg(feature = "alloc")]
+use crate::{VFS, VPath};
 
+#[cfg(feature = "alloc")]
+pub trait VFSExt: VFS {
+    fn boxed(self) -> crate::boxed::BoxVFS
+    where
+        Self: crate::SendVFS,
+    {
+        crate::boxed::fs_box(self)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> VFSExt for T where T: VFS {}
+
+#[cfg(feature = "alloc")]
 pub trait VPathExt: VPath {
-    #[cfg(any(feature = "std", feature = "alloc"))]
+    #[cfg(feature = "alloc")]
     fn boxed(self) -> crate::boxed::BoxVPath
     where
-        Self: Clone + 'static,
-        Self: VPath + Send + Sync,
-        Self::File: Send + Sync + 'static,
-        Self::Metadata: Send + 'static,
-        Self::Open: Send + 'static,
-        Self::CreateDir: Send + 'static,
-        Self::Remove: Send + 'static,
-        Self::ReadDir: Send + 'static,
-        Self::ListDir: Send + 'static,
+        Self: crate::SendVPath,
     {
         crate::boxed::path_box(self)
     }
 }
 
+#[cfg(feature = "alloc")]
 impl<T> VPathExt for T where T: VPath {}
 
 pub trait VFileExt: VFile {
@@ -38,7 +45,24 @@ pub trait VFileExt: VFile {
         Read { reader: self, buf }
     }
 
-    #[cfg(any(feature = "std", feature = "alloc"))]
+    /// Read exactly enough bytes to fill `buf`. Fails with [`ErrorKind::UnexpectedEof`]
+    /// when the file ends first, in which case the content of `buf` is unspecified
+    fn read_exact<'a>(&'a mut self, buf: &'a mut [u8]) -> ReadExact<'a, Self>
+    where
+        Self: Sized + Unpin,
+    {
+        ReadExact { reader: self, buf }
+    }
+
+    /// Flush the file and close it, reporting the errors which dropping it would lose
+    fn close(&mut self) -> Close<'_, Self>
+    where
+        Self: Sized + Unpin,
+    {
+        Close { file: self }
+    }
+
+    #[cfg(feature = "alloc")]
     fn read_to_end<'a>(&'a mut self, buf: &'a mut Vec<u8>) -> ReadToEnd<'a, Self>
     where
         Self: Sized + Unpin,
@@ -83,13 +107,58 @@ where
     }
 }
 
-#[cfg(any(feature = "std", feature = "alloc"))]
+/// Future for the [`VFileExt::read_exact`] method.
+#[derive(Debug)]
+pub struct ReadExact<'a, R: ?Sized> {
+    reader: &'a mut R,
+    buf: &'a mut [u8],
+}
+
+impl<R> Future for ReadExact<'_, R>
+where
+    R: VFile + ?Sized + Unpin,
+{
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        while !this.buf.is_empty() {
+            let n = ready!(Pin::new(&mut *this.reader).poll_read(cx, this.buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(ErrorKind::UnexpectedEof.into()));
+            }
+            let (_, rest) = core::mem::take(&mut this.buf).split_at_mut(n);
+            this.buf = rest;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Future for the [`VFileExt::close`] method.
+#[derive(Debug)]
+pub struct Close<'a, F: ?Sized> {
+    file: &'a mut F,
+}
+
+impl<F> Future for Close<'_, F>
+where
+    F: VFile + ?Sized + Unpin,
+{
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.file).poll_close(cx)
+    }
+}
+
+#[cfg(feature = "alloc")]
 pub(crate) struct Guard<'a> {
     pub buf: &'a mut Vec<u8>,
     pub len: usize,
 }
 
-#[cfg(any(feature = "std", feature = "alloc"))]
+#[cfg(feature = "alloc")]
 impl Drop for Guard<'_> {
     fn drop(&mut self) {
         unsafe {
@@ -107,7 +176,7 @@ impl Drop for Guard<'_> {
 //
 // Because we're extending the buffer with uninitialized data for trusted
 // readers, we need to make sure to truncate that if any of this panics.
-#[cfg(any(feature = "std", feature = "alloc"))]
+#[cfg(feature = "alloc")]
 fn read_to_end_internal<R: VFile + ?Sized>(
     mut rd: core::pin::Pin<&mut R>,
     cx: &mut core::task::Context<'_>,
@@ -145,24 +214,24 @@ fn read_to_end_internal<R: VFile + ?Sized>(
     ret
 }
 
-#[cfg(any(feature = "std", feature = "alloc"))]
+#[cfg(feature = "alloc")]
 #[derive(Debug)]
 pub struct ReadToEnd<'a, R: ?Sized + Unpin> {
     reader: &'a mut R,
     buf: &'a mut Vec<u8>,
 }
 
-#[cfg(any(feature = "std", feature = "alloc"))]
+#[cfg(feature = "alloc")]
 impl<R: ?Sized + Unpin> Unpin for ReadToEnd<'_, R> {}
 
-#[cfg(any(feature = "std", feature = "alloc"))]
+#[cfg(feature = "alloc")]
 impl<'a, R: VFile + ?Sized + Unpin> ReadToEnd<'a, R> {
     pub(super) fn new(reader: &'a mut R, buf: &'a mut Vec<u8>) -> Self {
         ReadToEnd { reader, buf }
     }
 }
 
-#[cfg(any(feature = "std", feature = "alloc"))]
+#[cfg(feature = "alloc")]
 impl<A> Future for ReadToEnd<'_, A>
 where
     A: VFile + ?Sized + Unpin,
@@ -198,7 +267,7 @@ impl<W: VFile + ?Sized + Unpin> Future for WriteAll<'_, W> {
         while !this.buf.is_empty() {
             let n = ready!(Pin::new(&mut this.writer).poll_write(cx, this.buf))?;
             {
-                let (_, rest) = core::mem::replace(&mut this.buf, &[]).split_at(n);
+                let (_, rest) = core::mem::take(&mut this.buf).split_at(n);
                 this.buf = rest;
             }
             if n == 0 {
@@ -3,10 +3,8 @@ use core::{
     task::{Context, Poll},
 };
 
-#[cfg(all(feature = "alloc", not(feature = "std")))]
+#[cfg(feature = "alloc")]
 use alloc::boxed::Box;
-#[cfg(all(feature = "std"))]
-use std::boxed::Box;
 
 use crate::error::Error;
 
@@ -137,7 +135,7 @@ pub trait VFile: Send + Sync {
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>>;
 }
 
-impl<'a, T> VFile for &'a mut T
+impl<T> VFile for &mut T
 where
     T: VFile + ?Sized + Unpin,
 {
@@ -174,7 +172,7 @@ where
     }
 }
 
-#[cfg(any(feature = "alloc", feature = "std"))]
+#[cfg(feature = "alloc")]
 impl<T: ?Sized + VFile + Unpin> VFile for Box<T> {
     fn poll_read(
         mut self: Pin<&mut Self>,
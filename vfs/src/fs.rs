@@ -1,7 +1,59 @@
-use crate::{Error, VPath};
+use crate::{Error, SendVPath, VPath, VPathStr};
+
+/// Space usage of a filesystem, as reported by statvfs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FsStats {
+    /// The preferred block size in bytes
+    pub block_size: u64,
+    /// Total size of the filesystem in bytes
+    pub total: u64,
+    /// Free space in bytes
+    pub free: u64,
+    /// Free space in bytes available to unprivileged users
+    pub available: u64,
+}
+
+/// Describes which operations and semantics a filesystem supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    /// The filesystem can contain symbolic links
+    pub symlinks: bool,
+    /// Renames are atomic
+    pub atomic_rename: bool,
+    /// Extended attributes are supported
+    pub xattr: bool,
+    /// Files can be seeked
+    pub seek: bool,
+    /// Files and directories can be created, modified and removed
+    pub write: bool,
+    /// File names are case sensitive
+    pub case_sensitive: bool,
+    /// The maximum length of a single file name in bytes, if limited
+    pub max_name_len: Option<usize>,
+}
 
 pub trait VFS: Sized {
     type Path: VPath<FS = Self>;
+    type Stats: Future<Output = Result<FsStats, Error>>;
+
+    /// Get a path of the filesystem, relative to its root
+    fn path(&self, path: impl AsRef<VPathStr>) -> Result<Self::Path, Error>;
+
+    /// Query the total and free space of the filesystem
+    fn stats(&self) -> Self::Stats;
+
+    /// The capabilities of the filesystem
+    fn capabilities(&self) -> Capabilities;
+}
+
+/// A [`VFS`] whose paths are [`SendVPath`], so it can be boxed or wrapped
+/// in another filesystem. Implemented for every filesystem meeting the bounds
+pub trait SendVFS:
+    VFS<Path: SendVPath, Stats: Send + 'static> + Clone + Send + Sync + 'static
+{
+}
 
-    fn path(&self, path: &str) -> Result<Self::Path, Error>;
+impl<T> SendVFS for T where
+    T: VFS<Path: SendVPath, Stats: Send + 'static> + Clone + Send + Sync + 'static
+{
 }
@@ -1,25 +1,37 @@
 #![no_std]
 
-#[cfg(all(feature = "alloc", not(feature = "std")))]
+#[cfg(feature = "alloc")]
 extern crate alloc;
 
-#[cfg(all(feature = "std", not(feature = "alloc")))]
+#[cfg(feature = "std")]
 extern crate std;
 
-#[cfg(any(feature = "alloc", feature = "std"))]
+#[cfg(feature = "alloc")]
 pub mod boxed;
+#[cfg(feature = "alloc")]
+mod buf;
+#[cfg(feature = "alloc")]
+mod entry;
 mod error;
 mod ext;
 mod file;
+#[cfg(feature = "alloc")]
 mod fs;
 mod metadata;
+#[cfg(feature = "alloc")]
 mod path;
+#[cfg(feature = "alloc")]
+mod path_buf;
+mod watch;
 
-pub use self::{error::*, ext::*, file::*, fs::*, metadata::*, path::*};
+pub use self::{error::*, ext::*, file::*, metadata::*, watch::*};
+
+#[cfg(feature = "alloc")]
+pub use self::{buf::*, entry::*, fs::*, path::*, path_buf::*};
 
 pub mod prelude {
-    pub use super::{
-        VFS, VFile, VPath,
-        ext::{VFileExt, VPathExt},
-    };
+    pub use super::{VFile, ext::VFileExt};
+
+    #[cfg(feature = "alloc")]
+    pub use super::{BufVFile, VFS, VPath, buf::BufVFileExt, ext::VFSExt, ext::VPathExt};
 }
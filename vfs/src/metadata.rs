@@ -1,13 +1,168 @@
+use core::time::Duration;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum FileType {
     Dir,
     File,
+    Symlink,
+}
+
+/// Unix style permission bits of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Permissions(u32);
+
+impl Permissions {
+    /// Create permissions from unix mode bits
+    pub const fn from_mode(mode: u32) -> Permissions {
+        Permissions(mode)
+    }
+
+    /// The unix mode bits
+    pub const fn mode(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns true if no one is allowed to write
+    pub const fn readonly(&self) -> bool {
+        self.0 & 0o222 == 0
+    }
+
+    /// Clear or set all write bits
+    pub fn set_readonly(&mut self, readonly: bool) {
+        if readonly {
+            self.0 &= !0o222;
+        } else {
+            self.0 |= 0o222;
+        }
+    }
+}
+
+/// A point in time, stored as the duration since the unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Timestamp(Duration);
+
+impl Timestamp {
+    pub const UNIX_EPOCH: Timestamp = Timestamp(Duration::ZERO);
+
+    /// Create a timestamp from a duration since the unix epoch
+    pub const fn from_unix(duration: Duration) -> Timestamp {
+        Timestamp(duration)
+    }
+
+    /// The duration since the unix epoch
+    pub const fn as_unix(&self) -> Duration {
+        self.0
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::time::SystemTime> for Timestamp {
+    fn from(value: std::time::SystemTime) -> Self {
+        // Times before the epoch are clamped to the epoch
+        Timestamp(
+            value
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default(),
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Timestamp> for std::time::SystemTime {
+    fn from(value: Timestamp) -> Self {
+        std::time::UNIX_EPOCH + value.0
+    }
+}
+
+/// Access and modification times to apply with [`VPath::set_times`](crate::VPath::set_times).
+/// Times left as `None` are not changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FileTimes {
+    pub accessed: Option<Timestamp>,
+    pub modified: Option<Timestamp>,
+}
+
+impl FileTimes {
+    /// Create a new instance
+    pub fn new() -> FileTimes {
+        Default::default()
+    }
+
+    /// Set the last access time
+    pub fn set_accessed(mut self, time: impl Into<Timestamp>) -> Self {
+        self.accessed = Some(time.into());
+        self
+    }
+
+    /// Set the last modification time
+    pub fn set_modified(mut self, time: impl Into<Timestamp>) -> Self {
+        self.modified = Some(time.into());
+        self
+    }
+}
+
+/// A 256 bit hash identifying the content of a file or directory
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ContentHash([u8; 32]);
+
+impl ContentHash {
+    pub const fn from_bytes(bytes: [u8; 32]) -> ContentHash {
+        ContentHash(bytes)
+    }
+
+    pub const fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl core::fmt::Debug for ContentHash {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "ContentHash({self})")
+    }
+}
+
+/// Formats the hash as lowercase hex
+impl core::fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the lowercase or uppercase hex written by [`Display`](core::fmt::Display)
+impl core::str::FromStr for ContentHash {
+    type Err = crate::Error;
+
+    fn from_str(hex: &str) -> Result<ContentHash, crate::Error> {
+        let invalid = || crate::Error::new_const(crate::ErrorKind::InvalidData, "invalid hash");
+        if hex.len() != 64 {
+            return Err(invalid());
+        }
+
+        let digit = |c: u8| char::from(c).to_digit(16).ok_or_else(invalid);
+        let mut bytes = [0; 32];
+        for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *byte = (digit(pair[0])? << 4 | digit(pair[1])?) as u8;
+        }
+        Ok(ContentHash(bytes))
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Metadata {
     pub size: u64,
     pub kind: FileType,
+    pub permissions: Option<Permissions>,
+    pub accessed: Option<Timestamp>,
+    pub modified: Option<Timestamp>,
+    pub created: Option<Timestamp>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// The hash of the content, known by backends which address data by
+    /// its content. The hash function is up to the backend
+    pub hash: Option<ContentHash>,
 }
 
 impl Metadata {
@@ -18,4 +173,8 @@ impl Metadata {
     pub fn is_dir(&self) -> bool {
         matches!(self.kind, FileType::Dir)
     }
+
+    pub fn is_symlink(&self) -> bool {
+        matches!(self.kind, FileType::Symlink)
+    }
 }
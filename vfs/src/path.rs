@@ -1,25 +1,48 @@
-use std::string::String;
+use alloc::{string::String, vec::Vec};
 
 use futures_core::Stream;
 
 use crate::{
+    entry::DirEntry,
     error::Error,
     file::{OpenOptions, VFile},
     fs::VFS,
-    metadata::Metadata,
+    metadata::{FileTimes, Metadata, Permissions},
+    watch::WatchEvent,
 };
 
 pub trait VPath: Sized {
     type FS: VFS<Path = Self>;
     type File: VFile;
-    type ListDir: Stream<Item = Result<Self, Error>>;
+    type ListDir: Stream<Item = Result<DirEntry<Self>, Error>>;
 
     type Metadata: Future<Output = Result<Metadata, Error>>;
     type Open: Future<Output = Result<Self::File, Error>>;
     type CreateDir: Future<Output = Result<(), Error>>;
     type Remove: Future<Output = Result<(), Error>>;
     type ReadDir: Future<Output = Result<Self::ListDir, Error>>;
+    type SetPermissions: Future<Output = Result<(), Error>>;
+    type SetTimes: Future<Output = Result<(), Error>>;
+    type SetOwner: Future<Output = Result<(), Error>>;
+    type GetXattr: Future<Output = Result<Option<Vec<u8>>, Error>>;
+    type SetXattr: Future<Output = Result<(), Error>>;
+    type ListXattr: Future<Output = Result<Vec<String>, Error>>;
+    type RemoveXattr: Future<Output = Result<(), Error>>;
+    type Watcher: Stream<Item = Result<WatchEvent<Self>, Error>>;
+    type Watch: Future<Output = Result<Self::Watcher, Error>>;
+    type Canonicalize: Future<Output = Result<Self, Error>>;
 
+    /// The filesystem this path belongs to
+    fn fs(&self) -> Self::FS;
+
+    /// The path relative to the root of its filesystem, as a normalized string
+    /// using `/` separators and starting with `/`. Passing it to [`VFS::path`]
+    /// on any backend resolves the same location within that backend.
+    fn virtual_path(&self) -> String;
+
+    /// A human readable form of the path, meant for display and logging.
+    /// Backends may include details such as the location on the host,
+    /// so use [`virtual_path`](VPath::virtual_path) to serialize a path.
     fn to_string(&self) -> String;
 
     fn file_name(&self) -> Option<&str>;
@@ -27,12 +50,21 @@ pub trait VPath: Sized {
     /// The extension of this filename
     fn extension(&self) -> Option<&str>;
 
-    /// append a segment to this path
+    /// append a segment to this path.
+    /// `..` steps out of wherever the path before it leads, so after a
+    /// symbolic link it leaves the link's target, and backends with links
+    /// may read them here. It never climbs above the root.
+    /// Use [`canonicalize`](VPath::canonicalize) to compare paths
     fn resolve(&self, path: &str) -> Result<Self, Error>;
 
     /// Get the parent path
     fn parent(&self) -> Option<Self>;
 
+    /// Resolve `.`, `..` and symbolic links to the canonical path of an existing
+    /// file or directory. Fails with [`ErrorKind::FilesystemLoop`](crate::ErrorKind::FilesystemLoop)
+    /// when symbolic links form a cycle
+    fn canonicalize(&self) -> Self::Canonicalize;
+
     /// Get the file's metadata
     fn metadata(&self) -> Self::Metadata;
 
@@ -44,4 +76,96 @@ pub trait VPath: Sized {
 
     /// Remove a file or directory and all its contents
     fn rm(&self) -> Self::Remove;
+
+    /// Change the permissions of the file or directory
+    fn set_permissions(&self, permissions: Permissions) -> Self::SetPermissions;
+
+    /// Change the access and modification times of the file or directory
+    fn set_times(&self, times: FileTimes) -> Self::SetTimes;
+
+    /// Change the owner and group of the file or directory. `None` leaves the id unchanged
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> Self::SetOwner;
+
+    /// Returns true if extended attributes can be used on this path.
+    /// When false, the xattr operations fail with [`ErrorKind::Unsupported`](crate::ErrorKind::Unsupported)
+    fn supports_xattr(&self) -> bool;
+
+    /// Get the value of an extended attribute, or `None` if it is not set
+    fn get_xattr(&self, name: &str) -> Self::GetXattr;
+
+    /// Set the value of an extended attribute
+    fn set_xattr(&self, name: &str, value: &[u8]) -> Self::SetXattr;
+
+    /// List the names of all extended attributes
+    fn list_xattr(&self) -> Self::ListXattr;
+
+    /// Remove an extended attribute
+    fn remove_xattr(&self, name: &str) -> Self::RemoveXattr;
+
+    /// Watch the path for changes. When `recursive` is true, changes anywhere
+    /// below the path are reported as well
+    fn watch(&self, recursive: bool) -> Self::Watch;
+}
+
+/// A [`VPath`] whose futures, streams and files can be moved across threads,
+/// as needed to box it or to wrap it in another filesystem.
+/// Implemented for every path meeting the bounds
+pub trait SendVPath:
+    VPath<
+        FS: VFS<Stats: Send + 'static> + Clone + Send + Sync + 'static,
+        File: Send + Sync + 'static,
+        ListDir: Send + 'static,
+        Metadata: Send + 'static,
+        Open: Send + 'static,
+        CreateDir: Send + 'static,
+        Remove: Send + 'static,
+        ReadDir: Send + 'static,
+        SetPermissions: Send + 'static,
+        SetTimes: Send + 'static,
+        SetOwner: Send + 'static,
+        GetXattr: Send + 'static,
+        SetXattr: Send + 'static,
+        ListXattr: Send + 'static,
+        RemoveXattr: Send + 'static,
+        Watcher: Send + 'static,
+        Watch: Send + 'static,
+        Canonicalize: Send + 'static,
+    > + Clone
+    + core::fmt::Debug
+    + Eq
+    + core::hash::Hash
+    + Send
+    + Sync
+    + 'static
+{
+}
+
+impl<T> SendVPath for T where
+    T: VPath<
+            FS: VFS<Stats: Send + 'static> + Clone + Send + Sync + 'static,
+            File: Send + Sync + 'static,
+            ListDir: Send + 'static,
+            Metadata: Send + 'static,
+            Open: Send + 'static,
+            CreateDir: Send + 'static,
+            Remove: Send + 'static,
+            ReadDir: Send + 'static,
+            SetPermissions: Send + 'static,
+            SetTimes: Send + 'static,
+            SetOwner: Send + 'static,
+            GetXattr: Send + 'static,
+            SetXattr: Send + 'static,
+            ListXattr: Send + 'static,
+            RemoveXattr: Send + 'static,
+            Watcher: Send + 'static,
+            Watch: Send + 'static,
+            Canonicalize: Send + 'static,
+        > + Clone
+        + core::fmt::Debug
+        + Eq
+        + core::hash::Hash
+        + Send
+        + Sync
+        + 'static
+{
 }
@@ -0,0 +1,743 @@
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+use core::{
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+
+use crate::{Error, ErrorKind};
+
+/// A single segment of a [`VPathStr`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Component<'a> {
+    /// The leading `/` of an absolute path
+    RootDir,
+    /// `.`
+    CurDir,
+    /// `..`
+    ParentDir,
+    /// A file or directory name
+    Normal(&'a str),
+}
+
+impl<'a> Component<'a> {
+    pub fn as_str(&self) -> &'a str {
+        match self {
+            Component::RootDir => "/",
+            Component::CurDir => ".",
+            Component::ParentDir => "..",
+            Component::Normal(name) => name,
+        }
+    }
+}
+
+/// Iterator over the [`Component`]s of a path. Repeated separators are skipped
+#[derive(Debug, Clone)]
+pub struct Components<'a> {
+    root: bool,
+    rest: &'a str,
+}
+
+impl<'a> Components<'a> {
+    fn new(path: &'a str) -> Components<'a> {
+        Components {
+            root: path.starts_with('/'),
+            rest: path,
+        }
+    }
+
+    /// The part of the path not yet yielded
+    pub fn as_path(&self) -> &'a VPathStr {
+        if self.root {
+            VPathStr::new(self.rest)
+        } else {
+            VPathStr::new(self.rest.trim_start_matches('/'))
+        }
+    }
+
+    fn parse(segment: &'a str) -> Component<'a> {
+        match segment {
+            "." => Component::CurDir,
+            ".." => Component::ParentDir,
+            name => Component::Normal(name),
+        }
+    }
+}
+
+impl<'a> Iterator for Components<'a> {
+    type Item = Component<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.root {
+            self.root = false;
+            return Some(Component::RootDir);
+        }
+
+        let rest = self.rest.trim_start_matches('/');
+        if rest.is_empty() {
+            self.rest = rest;
+            return None;
+        }
+
+        let (segment, rest) = rest.split_once('/').unwrap_or((rest, ""));
+        self.rest = rest;
+        Some(Self::parse(segment))
+    }
+}
+
+impl DoubleEndedIterator for Components<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let rest = self.rest.trim_end_matches('/');
+        if rest.is_empty() {
+            self.rest = rest;
+            return self.root.then(|| {
+                self.root = false;
+                Component::RootDir
+            });
+        }
+
+        let (rest, segment) = match rest.rfind('/') {
+            Some(idx) => (&rest[..idx + 1], &rest[idx + 1..]),
+            None => ("", rest),
+        };
+        self.rest = rest;
+        Some(Self::parse(segment))
+    }
+}
+
+/// A borrowed, backend independent path using `/` as separator.
+///
+/// All operations are purely lexical and never touch a filesystem.
+#[repr(transparent)]
+pub struct VPathStr(str);
+
+impl VPathStr {
+    pub fn new<S: AsRef<str> + ?Sized>(path: &S) -> &VPathStr {
+        // SAFETY: VPathStr is a transparent wrapper around str
+        unsafe { &*(path.as_ref() as *const str as *const VPathStr) }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn to_path_buf(&self) -> VPathBuf {
+        VPathBuf(self.0.into())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns true if the path starts at the root
+    pub fn is_absolute(&self) -> bool {
+        self.0.starts_with('/')
+    }
+
+    pub fn components(&self) -> Components<'_> {
+        Components::new(&self.0)
+    }
+
+    /// The last component, unless it is `.`, `..` or the root
+    pub fn file_name(&self) -> Option<&str> {
+        match self.components().next_back()? {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// The file name without its extension
+    pub fn file_stem(&self) -> Option<&str> {
+        let name = self.file_name()?;
+        match name.rsplit_once('.') {
+            Some((stem, _)) if !stem.is_empty() => Some(stem),
+            _ => Some(name),
+        }
+    }
+
+    /// The extension of the file name
+    pub fn extension(&self) -> Option<&str> {
+        let name = self.file_name()?;
+        match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => Some(ext),
+            _ => None,
+        }
+    }
+
+    /// The path without its last component, or `None` for the root and the empty path
+    pub fn parent(&self) -> Option<&VPathStr> {
+        let mut components = self.components();
+        match components.next_back()? {
+            Component::RootDir => None,
+            _ => {
+                let parent = components.as_path().as_str();
+                let trimmed = parent.trim_end_matches('/');
+                Some(VPathStr::new(if trimmed.is_empty() && self.is_absolute() {
+                    "/"
+                } else {
+                    trimmed
+                }))
+            }
+        }
+    }
+
+    /// Append `path`. An absolute `path` replaces this one
+    pub fn join<P: AsRef<VPathStr>>(&self, path: P) -> VPathBuf {
+        let mut buf = self.to_path_buf();
+        buf.push(path);
+        buf
+    }
+
+    /// Resolve `path` against this path as if both started at the root and
+    /// normalize the result, leaving out the leading `/` so the root is the
+    /// empty path. Backends without symbolic links keep their paths this way
+    pub fn resolve<P: AsRef<VPathStr>>(&self, path: P) -> VPathBuf {
+        let mut joined = VPathBuf(alloc::format!("/{}", &self.0));
+        joined.push(path);
+        let normalized = joined.normalize();
+        VPathBuf(String::from(&normalized.0[1..]))
+    }
+
+    /// Returns true if the leading components of this path are those of `base`
+    pub fn starts_with<P: AsRef<VPathStr>>(&self, base: P) -> bool {
+        self.strip_prefix(base).is_some()
+    }
+
+    /// The remainder of this path after the components of `base`
+    pub fn strip_prefix<P: AsRef<VPathStr>>(&self, base: P) -> Option<&VPathStr> {
+        let mut components = self.components();
+        for component in base.as_ref().components() {
+            if components.next() != Some(component) {
+                return None;
+            }
+        }
+        Some(components.as_path())
+    }
+
+    /// The path leading from `base` to this path, using `..` where needed.
+    /// Both paths are normalized first. Returns `None` if one path is absolute
+    /// and the other is not, or if `base` climbs above its starting point
+    pub fn relative_to<P: AsRef<VPathStr>>(&self, base: P) -> Option<VPathBuf> {
+        let base = base.as_ref();
+        if self.is_absolute() != base.is_absolute() {
+            return None;
+        }
+
+        let path = self.normalize();
+        let base = base.normalize();
+        let mut path = path.components().peekable();
+        let mut base = base.components().peekable();
+
+        while path.peek().is_some() && path.peek() == base.peek() {
+            path.next();
+            base.next();
+        }
+
+        let mut parts = Vec::new();
+        for component in base {
+            match component {
+                Component::Normal(_) => parts.push(".."),
+                _ => return None,
+            }
+        }
+        parts.extend(path.map(|component| component.as_str()));
+
+        Some(VPathBuf(parts.join("/")))
+    }
+
+    /// A copy of this path with the extension of the file name replaced.
+    /// An empty `extension` removes it
+    pub fn with_extension(&self, extension: &str) -> VPathBuf {
+        let mut buf = self.to_path_buf();
+        buf.set_extension(extension);
+        buf
+    }
+
+    /// Lexically resolve `.` and `..` and remove repeated separators.
+    /// `..` never climbs above the root of an absolute path, while leading `..`
+    /// of a relative path are kept
+    pub fn normalize(&self) -> VPathBuf {
+        let mut parts: Vec<&str> = Vec::new();
+
+        for component in self.components() {
+            match component {
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir => match parts.last() {
+                    Some(&last) if last != ".." => {
+                        parts.pop();
+                    }
+                    _ if self.is_absolute() => {}
+                    _ => parts.push(".."),
+                },
+                Component::Normal(name) => parts.push(name),
+            }
+        }
+
+        let path = parts.join("/");
+        VPathBuf(if self.is_absolute() {
+            alloc::format!("/{path}")
+        } else {
+            path
+        })
+    }
+
+    /// Check that every name is usable as a file name and that the path
+    /// does not climb above its starting point with `..`
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut depth = 0usize;
+
+        for component in self.components() {
+            match component {
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir => {
+                    depth = depth.checked_sub(1).ok_or_else(|| {
+                        Error::new_const(ErrorKind::InvalidInput, "path escapes the root")
+                            .with_path(&self.0)
+                    })?;
+                }
+                Component::Normal(name) => {
+                    if name.contains('\0') {
+                        return Err(Error::new_const(
+                            ErrorKind::InvalidFilename,
+                            "file names cannot contain NUL characters",
+                        )
+                        .with_path(&self.0));
+                    }
+                    depth += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for VPathStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for VPathStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Paths are compared by their components, so `a//b/` equals `a/b`
+impl PartialEq for VPathStr {
+    fn eq(&self, other: &Self) -> bool {
+        self.components().eq(other.components())
+    }
+}
+
+impl Eq for VPathStr {}
+
+impl PartialOrd for VPathStr {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for VPathStr {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.components().cmp(other.components())
+    }
+}
+
+impl Hash for VPathStr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for component in self.components() {
+            component.hash(state);
+        }
+    }
+}
+
+impl ToOwned for VPathStr {
+    type Owned = VPathBuf;
+
+    fn to_owned(&self) -> VPathBuf {
+        self.to_path_buf()
+    }
+}
+
+impl AsRef<VPathStr> for VPathStr {
+    fn as_ref(&self) -> &VPathStr {
+        self
+    }
+}
+
+impl AsRef<VPathStr> for str {
+    fn as_ref(&self) -> &VPathStr {
+        VPathStr::new(self)
+    }
+}
+
+impl AsRef<VPathStr> for String {
+    fn as_ref(&self) -> &VPathStr {
+        VPathStr::new(self)
+    }
+}
+
+impl AsRef<str> for VPathStr {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// An owned, backend independent path. See [`VPathStr`]
+#[derive(Clone, Default)]
+pub struct VPathBuf(String);
+
+impl VPathBuf {
+    pub fn new() -> VPathBuf {
+        VPathBuf(String::new())
+    }
+
+    pub fn as_path(&self) -> &VPathStr {
+        VPathStr::new(&self.0)
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+
+    /// Append `path`. An absolute `path` replaces this one
+    pub fn push<P: AsRef<VPathStr>>(&mut self, path: P) {
+        let path = path.as_ref().as_str();
+
+        if path.starts_with('/') {
+            self.0.clear();
+        } else if !self.0.is_empty() && !self.0.ends_with('/') && !path.is_empty() {
+            self.0.push('/');
+        }
+
+        self.0.push_str(path);
+    }
+
+    /// Remove the last component. Returns false if there was no parent
+    pub fn pop(&mut self) -> bool {
+        match self.parent().map(|parent| parent.as_str().len()) {
+            Some(len) => {
+                self.0.truncate(len);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replace the extension of the file name, or remove it if `extension` is empty.
+    /// Returns false if the path has no file name
+    pub fn set_extension(&mut self, extension: &str) -> bool {
+        let Some(stem) = self.file_stem() else {
+            return false;
+        };
+
+        // The file name always ends the path, apart from trailing separators
+        let end = self.0.trim_end_matches('/').len();
+        let start = end - self.file_name().unwrap().len() + stem.len();
+        self.0.truncate(start);
+
+        if !extension.is_empty() {
+            self.0.push('.');
+            self.0.push_str(extension);
+        }
+
+        true
+    }
+}
+
+impl Deref for VPathBuf {
+    type Target = VPathStr;
+
+    fn deref(&self) -> &VPathStr {
+        self.as_path()
+    }
+}
+
+impl Borrow<VPathStr> for VPathBuf {
+    fn borrow(&self) -> &VPathStr {
+        self.as_path()
+    }
+}
+
+impl AsRef<VPathStr> for VPathBuf {
+    fn as_ref(&self) -> &VPathStr {
+        self.as_path()
+    }
+}
+
+impl AsRef<str> for VPathBuf {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for VPathBuf {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_path() == other.as_path()
+    }
+}
+
+impl Eq for VPathBuf {}
+
+impl PartialOrd for VPathBuf {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for VPathBuf {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_path().cmp(other.as_path())
+    }
+}
+
+impl Hash for VPathBuf {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_path().hash(state)
+    }
+}
+
+impl fmt::Debug for VPathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for VPathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for VPathBuf {
+    fn from(value: String) -> Self {
+        VPathBuf(value)
+    }
+}
+
+impl From<&str> for VPathBuf {
+    fn from(value: &str) -> Self {
+        VPathBuf(value.into())
+    }
+}
+
+impl From<&VPathStr> for VPathBuf {
+    fn from(value: &VPathStr) -> Self {
+        value.to_path_buf()
+    }
+}
+
+impl From<VPathBuf> for String {
+    fn from(value: VPathBuf) -> Self {
+        value.0
+    }
+}
+
+impl<P: AsRef<VPathStr>> FromIterator<P> for VPathBuf {
+    fn from_iter<T: IntoIterator<Item = P>>(iter: T) -> Self {
+        let mut buf = VPathBuf::new();
+        for path in iter {
+            buf.push(path);
+        }
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn components(path: &str) -> Vec<Component<'_>> {
+        VPathStr::new(path).components().collect()
+    }
+
+    fn components_rev(path: &str) -> Vec<Component<'_>> {
+        VPathStr::new(path).components().rev().collect()
+    }
+
+    fn parent_of(path: &str) -> Option<&str> {
+        VPathStr::new(path).parent().map(VPathStr::as_str)
+    }
+
+    fn relative_of(path: &str, base: &str) -> Option<String> {
+        VPathStr::new(path)
+            .relative_to(base)
+            .map(VPathBuf::into_string)
+    }
+
+    fn normalized(path: &str) -> String {
+        VPathStr::new(path).normalize().into_string()
+    }
+
+    fn with_extension(path: &str, extension: &str) -> (bool, String) {
+        let mut buf = VPathBuf::from(path);
+        let changed = buf.set_extension(extension);
+        (changed, buf.into_string())
+    }
+
+    #[test]
+    fn components_forward() {
+        use Component::*;
+
+        assert_eq!(components("/"), [RootDir]);
+        assert_eq!(components(""), []);
+        assert_eq!(components("/a//b/"), [RootDir, Normal("a"), Normal("b")]);
+        assert_eq!(components("a/./b"), [Normal("a"), CurDir, Normal("b")]);
+        assert_eq!(components("../a"), [ParentDir, Normal("a")]);
+        assert_eq!(components(".hidden"), [Normal(".hidden")]);
+    }
+
+    #[test]
+    fn components_backward() {
+        use Component::*;
+
+        assert_eq!(components_rev("/"), [RootDir]);
+        assert_eq!(components_rev(""), []);
+        assert_eq!(
+            components_rev("/a//b/"),
+            [Normal("b"), Normal("a"), RootDir]
+        );
+        assert_eq!(components_rev("../a"), [Normal("a"), ParentDir]);
+    }
+
+    #[test]
+    fn components_from_both_ends() {
+        use Component::*;
+
+        let mut components = VPathStr::new("/a/b/c").components();
+        assert_eq!(components.next(), Some(RootDir));
+        assert_eq!(components.next_back(), Some(Normal("c")));
+        assert_eq!(components.as_path().as_str(), "a/b/");
+        assert_eq!(components.next(), Some(Normal("a")));
+        assert_eq!(components.next_back(), Some(Normal("b")));
+        assert_eq!(components.next(), None);
+        assert_eq!(components.next_back(), None);
+
+        let mut components = VPathStr::new("/a").components();
+        assert_eq!(components.next_back(), Some(Normal("a")));
+        assert_eq!(components.next_back(), Some(RootDir));
+        assert_eq!(components.next(), None);
+    }
+
+    #[test]
+    fn parent() {
+        assert_eq!(parent_of("/"), None);
+        assert_eq!(parent_of(""), None);
+        assert_eq!(parent_of("/a"), Some("/"));
+        assert_eq!(parent_of("/a/b"), Some("/a"));
+        assert_eq!(parent_of("/a/b/"), Some("/a"));
+        assert_eq!(parent_of("/a//b"), Some("/a"));
+        assert_eq!(parent_of("a"), Some(""));
+        assert_eq!(parent_of("../a"), Some(".."));
+        assert_eq!(parent_of("/.hidden"), Some("/"));
+    }
+
+    #[test]
+    fn relative_to() {
+        assert_eq!(relative_of("/a/b/c", "/a/d").as_deref(), Some("../b/c"));
+        assert_eq!(relative_of("/a/b", "/a/b/").as_deref(), Some(""));
+        assert_eq!(relative_of("/a/b/", "/a").as_deref(), Some("b"));
+        assert_eq!(relative_of("/", "/a/b").as_deref(), Some("../.."));
+        assert_eq!(relative_of("/a/./b/../c", "/a").as_deref(), Some("c"));
+        assert_eq!(relative_of("../a", "b").as_deref(), Some("../../a"));
+        assert_eq!(relative_of("a", "/a"), None);
+        assert_eq!(relative_of("/a", "a"), None);
+        assert_eq!(relative_of("a", ".."), None);
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalized("/"), "/");
+        assert_eq!(normalized(""), "");
+        assert_eq!(normalized("."), "");
+        assert_eq!(normalized("/a/./b/../c//"), "/a/c");
+        assert_eq!(normalized("/../a"), "/a");
+        assert_eq!(normalized("../a/.."), "..");
+        assert_eq!(normalized("a/../../b"), "../b");
+        assert_eq!(normalized("../../a"), "../../a");
+        assert_eq!(normalized("/.hidden/"), "/.hidden");
+    }
+
+    #[test]
+    fn resolve() {
+        let resolved = |base: &str, path: &str| VPathStr::new(base).resolve(path).into_string();
+        assert_eq!(resolved("", ""), "");
+        assert_eq!(resolved("", "/a/b/"), "a/b");
+        assert_eq!(resolved("a", "b/./c"), "a/b/c");
+        assert_eq!(resolved("a/b", "../c"), "a/c");
+        assert_eq!(resolved("a", "../../c"), "c");
+        assert_eq!(resolved("a/b", "/c"), "c");
+        assert_eq!(resolved("a", ".."), "");
+    }
+
+    #[test]
+    fn set_extension() {
+        assert_eq!(
+            with_extension("/a/file.txt", "md"),
+            (true, "/a/file.md".into())
+        );
+        assert_eq!(with_extension("/a/file.txt", ""), (true, "/a/file".into()));
+        assert_eq!(with_extension("/a/file", "gz"), (true, "/a/file.gz".into()));
+        assert_eq!(
+            with_extension("a.tar.gz", "zst"),
+            (true, "a.tar.zst".into())
+        );
+        assert_eq!(with_extension("/a/b/", "txt"), (true, "/a/b.txt".into()));
+        assert_eq!(with_extension("file.", ""), (true, "file".into()));
+        assert_eq!(
+            with_extension(".bashrc", "bak"),
+            (true, ".bashrc.bak".into())
+        );
+        assert_eq!(with_extension(".bashrc", ""), (true, ".bashrc".into()));
+        assert_eq!(with_extension("/", "txt"), (false, "/".into()));
+        assert_eq!(with_extension("/a/..", "txt"), (false, "/a/..".into()));
+        assert_eq!(with_extension("", "txt"), (false, "".into()));
+    }
+
+    #[test]
+    fn extension_of_dotfiles() {
+        let path = VPathStr::new("/home/.profile");
+        assert_eq!(path.file_name(), Some(".profile"));
+        assert_eq!(path.file_stem(), Some(".profile"));
+        assert_eq!(path.extension(), None);
+
+        let path = VPathStr::new("/home/.profile.old");
+        assert_eq!(path.file_stem(), Some(".profile"));
+        assert_eq!(path.extension(), Some("old"));
+
+        assert_eq!(VPathStr::new("file.").extension(), Some(""));
+    }
+
+    #[test]
+    fn validate() {
+        for path in ["/", "", "/a/b", "a/../b", "/a/./b/..", "/.hidden"] {
+            assert!(VPathStr::new(path).validate().is_ok(), "{path}");
+        }
+
+        for path in ["..", "/..", "../a", "/a/../..", "a/./../../b"] {
+            let err = VPathStr::new(path).validate().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{path}");
+        }
+
+        let err = VPathStr::new("/a/b\0c").validate().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidFilename);
+    }
+
+    #[test]
+    fn push_and_collect() {
+        let mut buf = VPathBuf::from("/a");
+        buf.push("b/");
+        buf.push("c");
+        assert_eq!(buf.as_str(), "/a/b/c");
+        buf.push("/d");
+        assert_eq!(buf.as_str(), "/d");
+
+        let buf: VPathBuf = vec!["/", "a", "b"].into_iter().collect();
+        assert_eq!(buf.as_str(), "/a/b");
+    }
+}
@@ -0,0 +1,292 @@
+#[cfg(feature = "std")]
+pub use self::hub::*;
+#[cfg(feature = "alloc")]
+pub use self::poll::*;
+
+/// A change reported by [`VPath::watch`](crate::VPath::watch)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent<P> {
+    Create(P),
+    Modify(P),
+    Remove(P),
+    Rename { from: P, to: P },
+}
+
+impl<P> WatchEvent<P> {
+    /// The path affected by the event. For renames this is the new location
+    pub fn path(&self) -> &P {
+        match self {
+            WatchEvent::Create(path) | WatchEvent::Modify(path) | WatchEvent::Remove(path) => path,
+            WatchEvent::Rename { to, .. } => to,
+        }
+    }
+
+    /// Convert the paths of the event
+    pub fn map<T>(self, mut func: impl FnMut(P) -> T) -> WatchEvent<T> {
+        match self {
+            WatchEvent::Create(path) => WatchEvent::Create(func(path)),
+            WatchEvent::Modify(path) => WatchEvent::Modify(func(path)),
+            WatchEvent::Remove(path) => WatchEvent::Remove(func(path)),
+            WatchEvent::Rename { from, to } => WatchEvent::Rename {
+                from: func(from),
+                to: func(to),
+            },
+        }
+    }
+}
+
+/// Returns true if a change to `path` should be reported to a watcher on `root`
+#[cfg(feature = "std")]
+fn is_watched(root: &str, recursive: bool, path: &str) -> bool {
+    if path == root {
+        return true;
+    }
+
+    let Some(rest) = path.strip_prefix(root) else {
+        return false;
+    };
+
+    let rest = if root.ends_with('/') {
+        rest
+    } else if let Some(rest) = rest.strip_prefix('/') {
+        rest
+    } else {
+        return false;
+    };
+
+    recursive || !rest.contains('/')
+}
+
+#[cfg(feature = "alloc")]
+mod poll {
+    use alloc::{collections::BTreeMap, collections::VecDeque, string::String, vec, vec::Vec};
+    use core::pin::pin;
+
+    use futures::{StreamExt, TryStreamExt};
+    use futures_core::{Stream, stream::BoxStream};
+
+    use super::WatchEvent;
+    use crate::{Error, ErrorKind, FileType, Metadata, VPath};
+
+    type Snapshot<P> = BTreeMap<String, (P, Metadata)>;
+
+    /// Watch `path` by comparing `read_dir` and `metadata` snapshots each time `ticks` yields.
+    ///
+    /// This works with any backend, but cannot detect renames, which are reported
+    /// as a removal followed by a creation.
+    pub fn poll_watch<P, T>(
+        path: P,
+        recursive: bool,
+        ticks: T,
+    ) -> BoxStream<'static, Result<WatchEvent<P>, Error>>
+    where
+        P: VPath + Clone + Send + Sync + 'static,
+        P::Metadata: Send,
+        P::ReadDir: Send,
+        P::ListDir: Send,
+        T: Stream<Item = ()> + Send + 'static,
+    {
+        let state = PollState {
+            path,
+            recursive,
+            ticks: ticks.boxed(),
+            snapshot: None,
+            queue: VecDeque::new(),
+        };
+
+        futures::stream::unfold(state, |mut state| async move {
+            loop {
+                if let Some(event) = state.queue.pop_front() {
+                    return Some((Ok(event), state));
+                }
+
+                if state.snapshot.is_some() {
+                    state.ticks.next().await?;
+                }
+
+                let next = match snapshot(&state.path, state.recursive).await {
+                    Ok(next) => next,
+                    Err(err) => return Some((Err(err), state)),
+                };
+
+                if let Some(prev) = &state.snapshot {
+                    diff(prev, &next, &mut state.queue);
+                }
+
+                state.snapshot = Some(next);
+            }
+        })
+        .boxed()
+    }
+
+    struct PollState<P> {
+        path: P,
+        recursive: bool,
+        ticks: BoxStream<'static, ()>,
+        snapshot: Option<Snapshot<P>>,
+        queue: VecDeque<WatchEvent<P>>,
+    }
+
+    async fn snapshot<P>(root: &P, recursive: bool) -> Result<Snapshot<P>, Error>
+    where
+        P: VPath + Clone,
+    {
+        let mut snapshot = Snapshot::new();
+
+        let metadata = match root.metadata().await {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(snapshot),
+            Err(err) => return Err(err),
+        };
+
+        snapshot.insert(root.to_string(), (root.clone(), metadata));
+
+        if !metadata.is_dir() {
+            return Ok(snapshot);
+        }
+
+        let mut queue: Vec<P> = vec![root.clone()];
+
+        while let Some(dir) = queue.pop() {
+            let mut entries = pin!(dir.read_dir().await?);
+            while let Some(entry) = entries.try_next().await? {
+                let metadata = match entry.metadata().await {
+                    Ok(metadata) => metadata,
+                    // Removed while we were listing
+                    Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err),
+                };
+
+                // Decided by the type of the entry itself, so symlinks to
+                // directories are not followed, and cannot loop
+                if recursive && entry.file_type() == FileType::Dir {
+                    queue.push(entry.path().clone());
+                }
+
+                let path = entry.into_path();
+                snapshot.insert(path.to_string(), (path, metadata));
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+    fn diff<P: Clone>(
+        prev: &Snapshot<P>,
+        next: &Snapshot<P>,
+        events: &mut VecDeque<WatchEvent<P>>,
+    ) {
+        for (key, (path, _)) in prev {
+            if !next.contains_key(key) {
+                events.push_back(WatchEvent::Remove(path.clone()));
+            }
+        }
+
+        for (key, (path, metadata)) in next {
+            match prev.get(key) {
+                None => events.push_back(WatchEvent::Create(path.clone())),
+                Some((_, old)) if is_modified(old, metadata) => {
+                    events.push_back(WatchEvent::Modify(path.clone()))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn is_modified(old: &Metadata, new: &Metadata) -> bool {
+        // Directory timestamps change whenever an entry is added or removed,
+        // which is already reported through the entries themselves
+        if old.is_dir() && new.is_dir() {
+            return false;
+        }
+
+        old.kind != new.kind || old.size != new.size || old.modified != new.modified
+    }
+}
+
+#[cfg(feature = "std")]
+mod hub {
+    use alloc::{string::String, sync::Arc, vec::Vec};
+    use std::sync::Mutex;
+
+    use futures::channel::mpsc;
+
+    use super::{WatchEvent, is_watched};
+    use crate::{Error, VPath};
+
+    pub type HubWatcher<P> = mpsc::UnboundedReceiver<Result<WatchEvent<P>, Error>>;
+
+    struct Subscriber<P> {
+        root: String,
+        recursive: bool,
+        sender: mpsc::UnboundedSender<Result<WatchEvent<P>, Error>>,
+    }
+
+    /// Fan out of change events for backends which perform mutations themselves,
+    /// like in-memory or wrapping filesystems.
+    ///
+    /// The backend calls [`WatchHub::emit`] after each mutation and implements
+    /// [`VPath::watch`] with [`WatchHub::subscribe`].
+    pub struct WatchHub<P> {
+        subscribers: Arc<Mutex<Vec<Subscriber<P>>>>,
+    }
+
+    impl<P> Clone for WatchHub<P> {
+        fn clone(&self) -> Self {
+            WatchHub {
+                subscribers: self.subscribers.clone(),
+            }
+        }
+    }
+
+    impl<P> Default for WatchHub<P> {
+        fn default() -> Self {
+            WatchHub {
+                subscribers: Default::default(),
+            }
+        }
+    }
+
+    impl<P> core::fmt::Debug for WatchHub<P> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.debug_struct("WatchHub").finish_non_exhaustive()
+        }
+    }
+
+    impl<P: VPath + Clone> WatchHub<P> {
+        pub fn new() -> WatchHub<P> {
+            Default::default()
+        }
+
+        /// Receive events for `path`, and its descendants if `recursive` is true
+        pub fn subscribe(&self, path: &P, recursive: bool) -> HubWatcher<P> {
+            let (sender, receiver) = mpsc::unbounded();
+            self.subscribers.lock().unwrap().push(Subscriber {
+                root: path.to_string(),
+                recursive,
+                sender,
+            });
+            receiver
+        }
+
+        /// Deliver an event to all interested subscribers
+        pub fn emit(&self, event: WatchEvent<P>) {
+            let mut subscribers = self.subscribers.lock().unwrap();
+            subscribers.retain(|sub| !sub.sender.is_closed());
+
+            let paths: Vec<String> = match &event {
+                WatchEvent::Rename { from, to } => [from.to_string(), to.to_string()].into(),
+                event => [event.path().to_string()].into(),
+            };
+
+            for sub in subscribers.iter() {
+                if paths
+                    .iter()
+                    .any(|path| is_watched(&sub.root, sub.recursive, path))
+                {
+                    sub.sender.unbounded_send(Ok(event.clone())).ok();
+                }
+            }
+        }
+    }
+}